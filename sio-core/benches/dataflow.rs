@@ -0,0 +1,53 @@
+// Wake-up latency of dataflow variables: a relay of threads, each waiting on
+// the variable bound by the previous one before binding the next. The program
+// only uses the public `Runtime` API, so the same file measures any store
+// implementation. Run with `cargo bench --bench dataflow` on both trees to
+// compare them.
+
+use std::time::Instant;
+use sio_core::scheduler::{ExecutorPool, Runtime};
+
+const HOPS: usize = 200;
+const ROUNDS: u32 = 20;
+
+fn source() -> String {
+    let names: Vec<String> = (0..=HOPS).map(|i| format!("x{}", i)).collect();
+    let mut main = format!("let {};\n", names.join(", "));
+    // spawned last hop first, so every thread starts out waiting
+    for hop in (0..HOPS).rev() {
+        main.push_str(&format!("thread {{ {} = {} + 1; }}\n", names[hop + 1], names[hop]));
+    }
+    main.push_str("x0 = 0;\n");
+    main.push_str(&format!("{} = {};\n", names[HOPS], HOPS));
+    format!(
+        "corporal bench::Relay {{
+            pub main :: () {{
+                {}
+            }}
+        }}",
+        main
+    )
+}
+
+fn main() {
+    let src = source();
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    for workers in [1, cores] {
+        let pool = ExecutorPool::new(workers);
+        let runtime = Runtime::new(pool.executor());
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            let mut process = pool.block_on(runtime.create(src.clone(), "dataflow.sio".to_string()))
+                .expect("the benchmark compiles");
+            pool.block_on(process.run()).expect("the benchmark runs");
+        }
+        let elapsed = start.elapsed() / ROUNDS;
+        println!(
+            "{:>3} workers: {:>10.3?} per run, {:>8.3?} per hop",
+            workers,
+            elapsed,
+            elapsed / HOPS as u32
+        );
+        pool.shutdown();
+    }
+}
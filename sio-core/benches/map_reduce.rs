@@ -0,0 +1,60 @@
+// CPU-bound map-reduce: eight mapper threads each count down from N, the main
+// thread reduces their results. Run with `cargo bench --bench map_reduce` and
+// compare the time per worker count.
+
+use std::time::Instant;
+use sio_core::scheduler::{ExecutorPool, Runtime};
+
+const MAPPERS: usize = 8;
+const N: usize = 20_000;
+const ROUNDS: u32 = 5;
+
+fn source() -> String {
+    let names: Vec<String> = (0..MAPPERS).map(|i| format!("m{}", i)).collect();
+    let mut main = format!("let {};\n", names.join(", "));
+    for name in names.iter() {
+        main.push_str(&format!("thread {{ {} = count({}); }}\n", name, N));
+    }
+    main.push_str(&format!("let total = {};\n", names.join(" + ")));
+    main.push_str(&format!("total = {};\n", MAPPERS * N));
+    format!(
+        "corporal bench::MapReduce {{
+            pub main :: () {{
+                {}
+            }}
+            count :: (n) {{
+                if n == 0 {{ 0; }} else {{ 1 + count(n - 1); }}
+            }}
+        }}",
+        main
+    )
+}
+
+fn main() {
+    let src = source();
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut workers = vec![1];
+    while workers[workers.len() - 1] * 2 <= cores {
+        workers.push(workers[workers.len() - 1] * 2);
+    }
+    let mut baseline = None;
+    for workers in workers {
+        let pool = ExecutorPool::new(workers);
+        let runtime = Runtime::new(pool.executor());
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            let mut process = pool.block_on(runtime.create(src.clone(), "map_reduce.sio".to_string()))
+                .expect("the benchmark compiles");
+            pool.block_on(process.run()).expect("the benchmark runs");
+        }
+        let elapsed = start.elapsed() / ROUNDS;
+        let baseline = *baseline.get_or_insert(elapsed);
+        println!(
+            "{:>3} workers: {:>10.3?} per run, speedup {:.2}x",
+            workers,
+            elapsed,
+            baseline.as_secs_f64() / elapsed.as_secs_f64()
+        );
+        pool.shutdown();
+    }
+}
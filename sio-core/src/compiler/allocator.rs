@@ -1,6 +1,9 @@
 use crate::compiler::value::{ThreadValue};
 use werbolg_exec::WAllocator;
 
+// Values are reference counted and shared between threads through the store,
+// they are not allocated per thread: the memory quota of a process counts the
+// values its store holds, see `scheduler::quota`.
 pub struct ThreadAllocator;
 impl WAllocator for ThreadAllocator {
     type Value = ThreadValue;
@@ -0,0 +1,400 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+use werbolg_exec::{ExecutionError, Valuable};
+use crate::compiler::value::{ThreadValue, ValueInt, KEY_KIND, NO_VARIABLE};
+
+// A persistent hash array mapped trie. Every update copies only the nodes on
+// the path from the root to the touched entry, the rest of the trie is shared
+// through `Arc`, so handing a map to another dataflow thread and updating it
+// there never copies the whole map.
+
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+/// Keys a map can be indexed by: the scalar thread values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MapKey {
+    Unit,
+    Bool(bool),
+    Integer(ValueInt),
+}
+
+impl MapKey {
+    pub fn from_value(value: &ThreadValue) -> Result<Self, ExecutionError> {
+        match value {
+            ThreadValue::Unit => Ok(MapKey::Unit),
+            ThreadValue::Bool(_, b) => Ok(MapKey::Bool(*b)),
+            ThreadValue::Integer(_, n) => Ok(MapKey::Integer(*n)),
+            _ => Err(ExecutionError::ValueKindUnexpected {
+                value_expected: KEY_KIND,
+                value_got: value.descriptor(),
+            }),
+        }
+    }
+
+    pub fn to_value(&self) -> ThreadValue {
+        match self {
+            MapKey::Unit => ThreadValue::Unit,
+            MapKey::Bool(b) => ThreadValue::Bool(NO_VARIABLE, *b),
+            MapKey::Integer(n) => ThreadValue::Integer(NO_VARIABLE, *n),
+        }
+    }
+
+    // FNV-1a, stable across runs so iteration order is deterministic
+    fn hash(&self) -> u64 {
+        let (tag, payload) = match self {
+            MapKey::Unit => (0u8, 0),
+            MapKey::Bool(b) => (1u8, *b as u64),
+            MapKey::Integer(n) => (2u8, *n),
+        };
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in core::iter::once(tag).chain(payload.to_le_bytes()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Entry {
+    Leaf(u64, MapKey, ThreadValue),
+    Collision(u64, Arc<Vec<(MapKey, ThreadValue)>>),
+    Node(Arc<Node>),
+}
+
+impl Entry {
+    fn len(&self) -> usize {
+        match self {
+            Entry::Leaf(_, _, _) => 1,
+            Entry::Collision(_, items) => items.len(),
+            Entry::Node(node) => node.len,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    bitmap: u32,
+    len: usize,
+    entries: Vec<Entry>,
+}
+
+impl Node {
+    fn empty() -> Self {
+        Node { bitmap: 0, len: 0, entries: Vec::new() }
+    }
+
+    fn position(&self, bit: u32) -> usize {
+        (self.bitmap & (bit - 1)).count_ones() as usize
+    }
+
+    fn get(&self, shift: u32, hash: u64, key: &MapKey) -> Option<&ThreadValue> {
+        let bit = 1 << ((hash >> shift) & MASK);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        match &self.entries[self.position(bit)] {
+            Entry::Leaf(_, k, v) => if k == key { Some(v) } else { None },
+            Entry::Collision(_, items) => items.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            Entry::Node(node) => node.get(shift + BITS, hash, key),
+        }
+    }
+
+    // returns the new node and whether a new key was added
+    fn insert(&self, shift: u32, hash: u64, key: MapKey, value: ThreadValue) -> (Node, bool) {
+        let bit = 1 << ((hash >> shift) & MASK);
+        let pos = self.position(bit);
+        let mut node = self.clone();
+        if self.bitmap & bit == 0 {
+            node.bitmap |= bit;
+            node.entries.insert(pos, Entry::Leaf(hash, key, value));
+            node.len += 1;
+            return (node, true);
+        }
+        let (entry, added) = match &self.entries[pos] {
+            Entry::Leaf(h, k, _) if *k == key => (Entry::Leaf(*h, key, value), false),
+            Entry::Leaf(h, k, v) if *h == hash => {
+                let items = vec![(k.clone(), v.clone()), (key, value)];
+                (Entry::Collision(hash, Arc::new(items)), true)
+            }
+            Entry::Collision(h, items) if *h == hash => {
+                let mut items = (**items).clone();
+                let added = match items.iter_mut().find(|(k, _)| *k == key) {
+                    Some(item) => {
+                        item.1 = value;
+                        false
+                    }
+                    None => {
+                        items.push((key, value));
+                        true
+                    }
+                };
+                (Entry::Collision(hash, Arc::new(items)), added)
+            }
+            Entry::Node(child) => {
+                let (child, added) = child.insert(shift + BITS, hash, key, value);
+                (Entry::Node(Arc::new(child)), added)
+            }
+            existing => {
+                let existing_hash = match existing {
+                    Entry::Leaf(h, _, _) | Entry::Collision(h, _) => *h,
+                    Entry::Node(_) => unreachable!(),
+                };
+                let split = Node::split(shift + BITS, existing.clone(), existing_hash, Entry::Leaf(hash, key, value), hash);
+                (Entry::Node(Arc::new(split)), true)
+            }
+        };
+        node.entries[pos] = entry;
+        if added {
+            node.len += 1;
+        }
+        (node, added)
+    }
+
+    // build the smallest subtree holding two entries whose hashes differ
+    fn split(shift: u32, e1: Entry, h1: u64, e2: Entry, h2: u64) -> Node {
+        let len = e1.len() + e2.len();
+        let i1 = (h1 >> shift) & MASK;
+        let i2 = (h2 >> shift) & MASK;
+        if i1 == i2 {
+            let child = Node::split(shift + BITS, e1, h1, e2, h2);
+            Node { bitmap: 1 << i1, len, entries: vec![Entry::Node(Arc::new(child))] }
+        } else {
+            let entries = if i1 < i2 { vec![e1, e2] } else { vec![e2, e1] };
+            Node { bitmap: (1 << i1) | (1 << i2), len, entries }
+        }
+    }
+
+    // None when the key is absent, otherwise the node without the key
+    fn remove(&self, shift: u32, hash: u64, key: &MapKey) -> Option<Node> {
+        let bit = 1 << ((hash >> shift) & MASK);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        let pos = self.position(bit);
+        let replacement = match &self.entries[pos] {
+            Entry::Leaf(_, k, _) if k == key => None,
+            Entry::Leaf(_, _, _) => return None,
+            Entry::Collision(h, items) => {
+                let index = items.iter().position(|(k, _)| k == key)?;
+                let mut items = (**items).clone();
+                items.remove(index);
+                if items.len() == 1 {
+                    let (k, v) = items.pop().unwrap();
+                    Some(Entry::Leaf(*h, k, v))
+                } else {
+                    Some(Entry::Collision(*h, Arc::new(items)))
+                }
+            }
+            Entry::Node(child) => {
+                let child = child.remove(shift + BITS, hash, key)?;
+                match child.entries.len() {
+                    0 => None,
+                    // pull a lone leaf up so the trie stays shallow
+                    1 if !matches!(child.entries[0], Entry::Node(_)) => Some(child.entries[0].clone()),
+                    _ => Some(Entry::Node(Arc::new(child))),
+                }
+            }
+        };
+        let mut node = self.clone();
+        node.len -= 1;
+        match replacement {
+            Some(entry) => node.entries[pos] = entry,
+            None => {
+                node.bitmap &= !bit;
+                node.entries.remove(pos);
+            }
+        }
+        Some(node)
+    }
+
+    fn nth(&self, mut index: usize) -> Option<(&MapKey, &ThreadValue)> {
+        for entry in self.entries.iter() {
+            let len = entry.len();
+            if index >= len {
+                index -= len;
+                continue;
+            }
+            return match entry {
+                Entry::Leaf(_, k, v) => Some((k, v)),
+                Entry::Collision(_, items) => items.get(index).map(|(k, v)| (k, v)),
+                Entry::Node(node) => node.nth(index),
+            };
+        }
+        None
+    }
+}
+
+/// Immutable map from scalar keys to thread values.
+#[derive(Clone, Debug)]
+pub struct ValueMap {
+    root: Arc<Node>,
+}
+
+impl ValueMap {
+    pub fn new() -> Self {
+        ValueMap { root: Arc::new(Node::empty()) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.root.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.len == 0
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&ThreadValue> {
+        self.root.get(0, key.hash(), key)
+    }
+
+    pub fn contains_key(&self, key: &MapKey) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns a new map with `key` bound to `value`, sharing everything else with `self`.
+    pub fn insert(&self, key: MapKey, value: ThreadValue) -> Self {
+        let hash = key.hash();
+        let (root, _) = self.root.insert(0, hash, key, value);
+        ValueMap { root: Arc::new(root) }
+    }
+
+    /// Returns a new map without `key`, or a cheap copy of `self` if the key is absent.
+    pub fn remove(&self, key: &MapKey) -> Self {
+        match self.root.remove(0, key.hash(), key) {
+            Some(root) => ValueMap { root: Arc::new(root) },
+            None => self.clone(),
+        }
+    }
+
+    /// Entry at `index` in the (stable) iteration order of the map.
+    pub fn nth(&self, index: usize) -> Option<(&MapKey, &ThreadValue)> {
+        self.root.nth(index)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { stack: vec![(&*self.root, 0)], collision: None }
+    }
+
+    /// Whether both maps are the very same trie, which is the common case when
+    /// a map is passed unchanged between threads.
+    pub fn ptr_eq(&self, other: &ValueMap) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+
+    /// Whether both maps hold the same keys bound to equal values.
+    pub fn equals(&self, other: &ValueMap) -> bool {
+        self.ptr_eq(other)
+            || (self.len() == other.len()
+                && self.iter().all(|(key, value)| {
+                    other.get(key).map_or(false, |other| value.equals(other))
+                }))
+    }
+}
+
+impl Default for ValueMap {
+    fn default() -> Self {
+        ValueMap::new()
+    }
+}
+
+pub struct Iter<'a> {
+    stack: Vec<(&'a Node, usize)>,
+    collision: Option<(&'a [(MapKey, ThreadValue)], usize)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a MapKey, &'a ThreadValue);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((items, index)) = self.collision {
+                if let Some((k, v)) = items.get(index) {
+                    self.collision = Some((items, index + 1));
+                    return Some((k, v));
+                }
+                self.collision = None;
+            }
+            let (node, index) = match self.stack.last_mut() {
+                Some(top) => {
+                    top.1 += 1;
+                    (top.0, top.1 - 1)
+                }
+                None => return None,
+            };
+            match node.entries.get(index) {
+                None => {
+                    self.stack.pop();
+                }
+                Some(Entry::Leaf(_, k, v)) => return Some((k, v)),
+                Some(Entry::Collision(_, items)) => self.collision = Some((items.as_slice(), 0)),
+                Some(Entry::Node(child)) => self.stack.push((&**child, 0)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: ValueInt) -> ThreadValue {
+        ThreadValue::Integer(0, n)
+    }
+
+    fn get_int(map: &ValueMap, key: ValueInt) -> Option<ValueInt> {
+        map.get(&MapKey::Integer(key)).map(|v| v.int().unwrap().1)
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = ValueMap::new();
+        for n in 0..1000 {
+            map = map.insert(MapKey::Integer(n), int(n * 2));
+        }
+        assert_eq!(map.len(), 1000);
+        assert_eq!(get_int(&map, 10), Some(20));
+        assert_eq!(get_int(&map, 1000), None);
+
+        let map = map.insert(MapKey::Integer(10), int(0));
+        assert_eq!(map.len(), 1000);
+        assert_eq!(get_int(&map, 10), Some(0));
+
+        let mut smaller = map.clone();
+        for n in 0..500 {
+            smaller = smaller.remove(&MapKey::Integer(n));
+        }
+        assert_eq!(smaller.len(), 500);
+        assert_eq!(get_int(&smaller, 10), None);
+        assert_eq!(get_int(&smaller, 700), Some(1400));
+        assert_eq!(smaller.remove(&MapKey::Integer(1)).len(), 500);
+    }
+
+    #[test]
+    fn updates_do_not_affect_older_versions() {
+        let v1 = ValueMap::new().insert(MapKey::Bool(true), int(1));
+        let v2 = v1.insert(MapKey::Bool(false), int(2));
+        let v3 = v2.remove(&MapKey::Bool(true));
+        assert_eq!(v1.len(), 1);
+        assert_eq!(v2.len(), 2);
+        assert_eq!(v3.len(), 1);
+        assert!(v1.get(&MapKey::Bool(false)).is_none());
+        assert!(v3.get(&MapKey::Bool(true)).is_none());
+        assert!(v2.contains_key(&MapKey::Bool(true)));
+    }
+
+    #[test]
+    fn iteration_and_nth_agree() {
+        let mut map = ValueMap::new().insert(MapKey::Unit, int(0));
+        for n in 0..300 {
+            map = map.insert(MapKey::Integer(n), int(n));
+        }
+        let keys = map.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>();
+        assert_eq!(keys.len(), map.len());
+        for (index, key) in keys.iter().enumerate() {
+            assert_eq!(map.nth(index).map(|(k, _)| k), Some(key));
+        }
+        assert!(map.nth(map.len()).is_none());
+        assert!(keys.contains(&MapKey::Unit));
+    }
+}
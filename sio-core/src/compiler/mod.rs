@@ -2,8 +2,27 @@
 
 extern crate alloc;
 
+#[cfg(feature = "std")]
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "std")]
+use crate::compiler::value::VariableId;
+#[cfg(feature = "std")]
+use crate::scheduler::{Clock, Operation, ProcessRole, Suspension, ThreadId, store::Store};
+#[cfg(feature = "std")]
+use crate::scheduler::{ExternalCall, Mail, Mailboxes};
+
+// The values and maps build without `std`, for the dataflow store. The NIFs,
+// the prelude they are compiled with and the state of a running thread go
+// with the scheduler, and need it.
+
 pub mod allocator;
+pub mod map;
+#[cfg(feature = "std")]
 pub mod nifs;
+#[cfg(feature = "std")]
+pub mod prelude;
 pub mod value;
 
 #[derive(Debug)]
@@ -11,7 +30,6 @@ pub enum CompilerError {
     UnsupportedFeature,
     SyntaxError,
     TypeError,
-    OutOfMemory,
     InvalidArgument,
     UnresolvedReference,
     InvalidOperation,
@@ -24,13 +42,94 @@ pub enum CompilerError {
 pub use self::{
     allocator::{ThreadAllocator},
     value::{ThreadValue},
-    nifs::{ThreadLiteral, thread_literal_mapper, thread_literal_to_value, create_thread_env},
 };
+#[cfg(feature = "std")]
+pub use self::nifs::{ThreadLiteral, thread_literal_mapper, thread_literal_to_value, create_thread_env};
 
+/// What the code of a program depends on besides its source: the prelude and
+/// the NIFs. Builds with the same fingerprint compile a source to the same
+/// instruction addresses and function ids.
+#[cfg(feature = "std")]
+pub(crate) fn fingerprint() -> u64 {
+    let nifs = nifs::nif_table();
+    let nifs = nifs.iter().flat_map(|(name, arity)| name.bytes().chain([0, *arity as u8]));
+    // FNV-1a, as the keys of maps
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in prelude::PRELUDE.bytes().chain(nifs) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(feature = "std")]
 pub type ThreadNIF = werbolg_exec::NIF<ThreadAllocator, ThreadLiteral, RunningThreadState, ThreadValue>;
+#[cfg(feature = "std")]
 pub type ThreadEnvironment = werbolg_compile::Environment<ThreadNIF, ThreadValue>;
+#[cfg(feature = "std")]
+pub type ThreadExecutionEnviron =
+    werbolg_exec::ExecutionEnviron<ThreadAllocator, ThreadLiteral, RunningThreadState, ThreadValue>;
+#[cfg(feature = "std")]
 pub type ThreadExecutionMachine =
     werbolg_exec::ExecutionMachine<ThreadAllocator, ThreadLiteral, RunningThreadState, ThreadValue>;
 
+/// The part of a thread the NIFs can see through the execution machine.
+#[cfg(feature = "std")]
 #[derive(Clone)]
-pub struct RunningThreadState {}
\ No newline at end of file
+pub struct RunningThreadState {
+    pub thread_id: ThreadId,
+    // the role of the process, checked by the privileged NIFs
+    pub role: ProcessRole,
+    // shared by every thread of a process, so variables are process-unique
+    pub next_variable: Arc<AtomicU64>,
+    // the dataflow store of the process, NIFs bind and read it directly
+    pub store: Arc<Store>,
+    // the clock of the process timers, deadlines are taken when asked for
+    pub clock: Arc<dyn Clock>,
+    // the mailbox of the process and those `send` posts to
+    pub mailboxes: Mailboxes,
+    // set by a NIF that needs the scheduler: an operation for the process
+    pub pending: Option<Operation>,
+    // set by `send` and `receive`, which the thread waits on the mailboxes for
+    pub mail: Option<Mail>,
+    // set by `external`, which the thread runs on a machine of its own
+    pub call: Option<ExternalCall>,
+    // set by a NIF that cannot go on yet: what the thread waits for before
+    // the step is replayed
+    pub suspension: Option<Suspension>,
+}
+
+#[cfg(feature = "std")]
+impl RunningThreadState {
+    pub fn new(
+        thread_id: ThreadId,
+        role: ProcessRole,
+        next_variable: Arc<AtomicU64>,
+        store: Arc<Store>,
+        clock: Arc<dyn Clock>,
+        mailboxes: Mailboxes,
+    ) -> Self {
+        Self {
+            thread_id,
+            role,
+            next_variable,
+            store,
+            clock,
+            mailboxes,
+            pending: None,
+            mail: None,
+            call: None,
+            suspension: None,
+        }
+    }
+
+    pub fn fresh_variable(&self) -> VariableId {
+        self.next_variable.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Follows the bindings of the store. The result is either a determined
+    /// value or the last variable of the chain, unbound for now.
+    pub fn deref(&self, value: &ThreadValue) -> ThreadValue {
+        self.store.deref(value)
+    }
+}
@@ -1,20 +1,71 @@
-use crate::compiler::value::{ThreadValue as Value, ValueInt, VariableId};
+use crate::compiler::value::{Closure, Record, ThreadValue as Value, ValueInt, VariableId, FUN_KIND, NO_VARIABLE};
+use crate::compiler::map::{MapKey, ValueMap};
 use werbolg_compile::{CompilationError, Environment, CallArity};
-use werbolg_core::{AbsPath, Ident, Literal, Namespace, Span};
-use werbolg_exec::{ExecutionError, NIFCall, WAllocator};
+use werbolg_core::{AbsPath, Ident, Literal, Namespace, Span, ValueFun};
+use werbolg_exec::{ExecutionError, NIFCall, Valuable, WAllocator};
 use crate::compiler::{ThreadExecutionMachine, ThreadNIF};
-use alloc::string::ToString;
+use crate::scheduler::{Capability, CapabilityDenied, ExternalCall, Mail, Operation, Suspension, ThreadEntry};
+use alloc::{format, string::ToString, sync::Arc, vec::Vec};
 
+// a fresh dataflow variable per `let x;`, unique within the process. The
+// lowering passes the span of the declaration along for diagnostics.
 fn nif_unbound(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
     let (_, args) = em.stack.get_call_and_args(em.current_arity);
-    if args.is_empty() {
-        Ok(Value::Unit)
-    } else {
-        Err(ExecutionError::UserPanic {
-            message: "`nil' function does not need any arguments".to_string(),
-        })
+    let (_, start) = args[0].int()?;
+    let (_, end) = args[1].int()?;
+    let variable = em.userdata.fresh_variable();
+    em.userdata.store.declare(variable, start as usize..end as usize);
+    Ok(Value::Unbound(variable))
+}
+
+// Suspends the thread on what it waits for. The error only unwinds the step,
+// the thread sees the suspension instead and replays the call that suspended
+// once it can go on.
+fn suspend(em: &mut ThreadExecutionMachine, suspension: Suspension) -> ExecutionError {
+    em.userdata.suspension = Some(suspension);
+    ExecutionError::UserPanic {
+        message: format!("suspended on variable {}", suspension.variable()),
+    }
+}
+
+// `read(x)` is x's value, suspending the thread until x is bound
+fn nif_read(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let value = args[0].clone();
+    match em.userdata.deref(&value) {
+        Value::Unbound(variable) => Err(suspend(em, Suspension::Bound(variable))),
+        value => Ok(value),
     }
 }
+
+// `print x;` writes x on a line of the standard output once it is bound, the
+// variables still unbound inside it as `_`
+fn nif_print(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let value = args[0].clone();
+    let value = match em.userdata.deref(&value) {
+        Value::Unbound(variable) => return Err(suspend(em, Suspension::Bound(variable))),
+        // a cell cannot be resolved, its content can change
+        value => em.userdata.store.resolve(&value).unwrap_or(value),
+    };
+    std::println!("{}", value);
+    Ok(Value::Unit)
+}
+
+// `x = v`: binding an unbound variable never waits, `v` can be another
+// unbound variable or a partial value such as `[a | tail]`. Bound values are
+// unified in the store, a mismatch fails the thread and with it the process.
+fn nif_bind(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let (target, value) = (args[0].clone(), args[1].clone());
+    match em.userdata.store.unify(&target, &value) {
+        Ok(_) => Ok(em.userdata.deref(&target)),
+        Err(error) => Err(ExecutionError::UserPanic {
+            message: format!("{}", error),
+        }),
+    }
+}
+
 fn nif_plus<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
     let (i1, n1) = args[0].int()?;
     let (_, n2) = args[1].int()?;
@@ -42,6 +93,19 @@ fn nif_mul<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError
     Ok(ret)
 }
 
+fn nif_div<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (i1, n1) = args[0].int()?;
+    let (_, n2) = args[1].int()?;
+
+    let Some(ret) = n1.checked_div(n2) else {
+        return Err(ExecutionError::UserPanic {
+            message: "division by zero".to_string(),
+        });
+    };
+
+    Ok(Value::Integer(i1, ret))
+}
+
 fn nif_neg<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
     let (i1, n1) = args[0].int()?;
 
@@ -59,6 +123,15 @@ fn nif_eq<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError>
     Ok(Value::Bool(i1, ret))
 }
 
+fn nif_neq<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (i1, n1) = args[0].int()?;
+    let (_, n2) = args[1].int()?;
+
+    let ret = n1 != n2;
+
+    Ok(Value::Bool(i1, ret))
+}
+
 fn nif_le<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
     let (i1, n1) = args[0].int()?;
     let (_, n2) = args[1].int()?;
@@ -68,33 +141,442 @@ fn nif_le<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError>
     Ok(Value::Bool(i1, ret))
 }
 
+fn nif_lt<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (i1, n1) = args[0].int()?;
+    let (_, n2) = args[1].int()?;
+
+    let ret = n1 < n2;
+
+    Ok(Value::Bool(i1, ret))
+}
+
+fn nif_ge<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (i1, n1) = args[0].int()?;
+    let (_, n2) = args[1].int()?;
+
+    let ret = n1 >= n2;
+
+    Ok(Value::Bool(i1, ret))
+}
+
+fn nif_gt<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (i1, n1) = args[0].int()?;
+    let (_, n2) = args[1].int()?;
+
+    let ret = n1 > n2;
+
+    Ok(Value::Bool(i1, ret))
+}
+
+fn nif_not<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (i1, b) = args[0].bool()?;
+
+    Ok(Value::Bool(i1, !b))
+}
+
+fn nif_nil<A: WAllocator>(_: &A, _args: &[Value]) -> Result<Value, ExecutionError> {
+    Ok(Value::Unit)
+}
+
+fn nif_map_new<A: WAllocator>(_: &A, _args: &[Value]) -> Result<Value, ExecutionError> {
+    Ok(Value::Map(NO_VARIABLE, ValueMap::new()))
+}
+
+fn nif_map_get<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (_, map) = args[0].map()?;
+    let key = MapKey::from_value(&args[1])?;
+
+    match map.get(&key) {
+        Some(value) => Ok(value.clone()),
+        None => Err(ExecutionError::UserPanic {
+            message: format!("key {:?} not found in map", key),
+        }),
+    }
+}
+
+fn nif_map_contains<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (i1, map) = args[0].map()?;
+    let key = MapKey::from_value(&args[1])?;
+
+    Ok(Value::Bool(i1, map.contains_key(&key)))
+}
+
+fn nif_map_insert<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (i1, map) = args[0].map()?;
+    let key = MapKey::from_value(&args[1])?;
+
+    Ok(Value::Map(i1, map.insert(key, args[2].clone())))
+}
+
+fn nif_map_remove<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (i1, map) = args[0].map()?;
+    let key = MapKey::from_value(&args[1])?;
+
+    Ok(Value::Map(i1, map.remove(&key)))
+}
+
+fn nif_map_size<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (i1, map) = args[0].map()?;
+
+    Ok(Value::Integer(i1, map.len() as ValueInt))
+}
+
+// iteration is positional so that sio code can walk a map with a counter:
+// `map_key_at(m, i)` / `map_value_at(m, i)` for `i` in `0..map_size(m)`
+fn map_entry_at(args: &[Value]) -> Result<(&MapKey, &Value), ExecutionError> {
+    let (_, map) = args[0].map()?;
+    let (_, index) = args[1].int()?;
+
+    map.nth(index as usize).ok_or_else(|| ExecutionError::UserPanic {
+        message: format!("index {} out of bounds for map of size {}", index, map.len()),
+    })
+}
+
+fn nif_map_key_at<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (key, _) = map_entry_at(args)?;
+
+    Ok(key.to_value())
+}
+
+fn nif_map_value_at<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (_, value) = map_entry_at(args)?;
+
+    Ok(value.clone())
+}
+
+// Lists are cons cells ending in nil, so a list can be built with an unbound
+// tail that another thread binds later: `xs = [1 | rest]`.
+fn nif_cons<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    Ok(Value::Cons(NO_VARIABLE, Arc::new((args[0].clone(), args[1].clone()))))
+}
+
+// `Point(x, y)` is lowered to `record(Point, [x, y])`: the label of the atom
+// `Point` and the items of the list as fields
+fn nif_record<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (_, atom) = args[0].record()?;
+    let mut fields = Vec::new();
+    let mut list = &args[1];
+    while let Value::Cons(_, cell) = list {
+        fields.push(cell.0.clone());
+        list = &cell.1;
+    }
+
+    Ok(Value::Record(NO_VARIABLE, Arc::new(Record { label: atom.label.clone(), fields })))
+}
+
+fn nif_head<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (_, head, _) = args[0].cons()?;
+
+    Ok(head.clone())
+}
+
+fn nif_tail<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (_, _, tail) = args[0].cons()?;
+
+    Ok(tail.clone())
+}
+
+fn nif_is_cons<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    Ok(Value::Bool(NO_VARIABLE, matches!(args[0], Value::Cons(_, _))))
+}
+
+// `wait_needed(x)` suspends the thread until some thread waits on x, so a
+// lazy producer only computes what its consumers ask for
+fn nif_wait_needed(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let value = args[0].clone();
+    match em.userdata.deref(&value) {
+        Value::Unbound(variable) if !em.userdata.store.is_needed(variable) => {
+            Err(suspend(em, Suspension::Needed(variable)))
+        }
+        _ => Ok(Value::Unit),
+    }
+}
+
+// `timer(ms)` is a variable the process binds to nil once `ms` milliseconds
+// have passed since the call, the prelude `sleep(ms)` reads one
+fn nif_timer(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let (_, duration) = args[0].int()?;
+    let deadline = em.userdata.clock.now().saturating_add(duration);
+    let variable = em.userdata.fresh_variable();
+    em.userdata.pending = Some(Operation::Timer(em.userdata.thread_id, deadline, variable));
+    Ok(Value::Unbound(variable))
+}
+
+// `die_after(ms)` fails the process once `ms` milliseconds have passed, unless
+// it ended before
+fn nif_die_after(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let (_, duration) = args[0].int()?;
+    let deadline = em.userdata.clock.now().saturating_add(duration);
+    em.userdata.pending = Some(Operation::Timer(em.userdata.thread_id, deadline, NO_VARIABLE));
+    Ok(Value::Unit)
+}
+
+// `self()` is the id of the process, which other processes send messages to
+fn nif_self(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    Ok(Value::Integer(NO_VARIABLE, em.userdata.mailboxes.process_id()))
+}
+
+// `send(p, m)` puts a copy of `m` in the mailbox of the process `p`, the
+// thread waiting while it is full. A process that ended drops it.
+fn nif_send(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let (process, message) = (args[0].clone(), args[1].clone());
+    let (_, process) = process.int()?;
+    let Some(message) = em.userdata.store.ground(&message) else {
+        return Err(ExecutionError::UserPanic {
+            message: "a message has to be determined when it is sent".to_string(),
+        });
+    };
+    let Some(mailbox) = em.userdata.mailboxes.mailbox(process) else {
+        return Err(ExecutionError::UserPanic {
+            message: format!("process {} does not exist", process),
+        });
+    };
+    em.userdata.mail = Some(Mail::Send(mailbox, message));
+    Ok(Value::Unit)
+}
+
+// `receive x { ... }` is lowered to `receive()`, a variable the thread binds
+// to the next message of its process before it goes on
+fn nif_receive(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let variable = em.userdata.fresh_variable();
+    em.userdata.mail = Some(Mail::Receive(variable, None));
+    Ok(Value::Unbound(variable))
+}
+
+// with `after ms { ... }`, to `receive_within(ms)`: the list of the next
+// message, or nil when none came within `ms` milliseconds of the call
+fn nif_receive_within(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let (_, duration) = args[0].int()?;
+    let deadline = em.userdata.clock.now().saturating_add(duration);
+    let variable = em.userdata.fresh_variable();
+    em.userdata.mail = Some(Mail::Receive(variable, Some(deadline)));
+    Ok(Value::Unbound(variable))
+}
+
+// `xs[i]` walks the list, waiting on the tails that are not bound yet
+fn nif_list_get(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let (list, index) = (args[0].clone(), args[1].clone());
+    let (_, index) = em.userdata.deref(&index).int()?;
+    let mut list = em.userdata.deref(&list);
+    for _ in 0..index {
+        let tail = match &list {
+            Value::Unbound(variable) => return Err(suspend(em, Suspension::Bound(*variable))),
+            value => value.cons()?.2.clone(),
+        };
+        list = em.userdata.deref(&tail);
+    }
+    match &list {
+        Value::Unbound(variable) => Err(suspend(em, Suspension::Bound(*variable))),
+        value => Ok(value.cons()?.1.clone()),
+    }
+}
+
+// `thread { ... }` is lowered to `spawn(closure)`, the process starts a new
+// thread running the closure
+fn nif_spawn(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let closure = args[0].clone();
+    closure.closure()?;
+    em.userdata.pending = Some(Operation::ThreadSpawn(em.userdata.thread_id, ThreadEntry::Closure(closure)));
+    Ok(Value::Unit)
+}
+
+// The lowering rejects the privileged calls a module makes by name, calls
+// through a function value are checked here, against the role of the process.
+fn allowed(em: &ThreadExecutionMachine, capability: Capability) -> Result<(), ExecutionError> {
+    let role = em.userdata.role;
+    if role.can(capability) {
+        Ok(())
+    } else {
+        Err(ExecutionError::UserPanic {
+            message: CapabilityDenied { role, capability }.to_string(),
+        })
+    }
+}
+
+// `spawn_process(f)` starts a child process running the closure, with its own
+// store: what the closure captured is copied over, and has to be determined.
+// The result is a variable the process binds to the id of the child, which
+// runs a rank below the process.
+fn nif_spawn_process(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    allowed(em, Capability::SpawnProcess)?;
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let Some(closure) = em.userdata.store.ground(&args[0]) else {
+        return Err(ExecutionError::UserPanic {
+            message: "a process can only be started with determined values".to_string(),
+        });
+    };
+    let (_, fun) = closure.closure()?;
+    if !matches!(fun.fun, ValueFun::Fun(_)) {
+        return Err(ExecutionError::UserPanic { message: "a process cannot start in a native function".to_string() });
+    }
+    let process = em.userdata.fresh_variable();
+    em.userdata.pending = Some(Operation::ProcessSpawn(em.userdata.thread_id, closure, process));
+    Ok(Value::Unbound(process))
+}
+
+// `supervise(p)` is a variable bound once the child process `p` ended, to
+// true when it completed and false when it failed
+fn nif_supervise(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    allowed(em, Capability::Supervise)?;
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let (_, process) = args[0].int()?;
+    let exit = em.userdata.fresh_variable();
+    em.userdata.pending = Some(Operation::Supervise(em.userdata.thread_id, process, exit));
+    Ok(Value::Unbound(exit))
+}
+
+// `external(i, args)` is what `Module.function(args)` is lowered to: the
+// thread runs the function in the current version of the module, and binds
+// the variable returned to its result
+fn nif_external(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let (_, external) = args[0].int()?;
+    let mut call_args = Vec::new();
+    let mut list = args[1].clone();
+    while let Value::Cons(_, cell) = list {
+        call_args.push(cell.0.clone());
+        list = cell.1.clone();
+    }
+    let result = em.userdata.fresh_variable();
+    em.userdata.call = Some(ExternalCall { external: external as usize, args: call_args, result });
+    Ok(Value::Unbound(result))
+}
+
+// `deploy(f)` is `spawn_process(f)` on another node. What the closure
+// captured may not be bound yet: the variables are then shared with the node.
+fn nif_deploy(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    allowed(em, Capability::Deploy)?;
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let closure = args[0].clone();
+    closure.closure()?;
+    let process = em.userdata.fresh_variable();
+    em.userdata.pending = Some(Operation::Deploy(em.userdata.thread_id, None, closure, process));
+    Ok(Value::Unbound(process))
+}
+
+// `deploy_to(node, f)` is `deploy(f)` to the node given, which fails the
+// thread when the runtime is not connected to it
+fn nif_deploy_to(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    allowed(em, Capability::Deploy)?;
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let (_, node) = args[0].int()?;
+    let closure = args[1].clone();
+    closure.closure()?;
+    let process = em.userdata.fresh_variable();
+    em.userdata.pending = Some(Operation::Deploy(em.userdata.thread_id, Some(node), closure, process));
+    Ok(Value::Unbound(process))
+}
+
+// Cells are the one mutable value. The content lives in the process, which
+// applies the exchanges of all its threads one at a time; the old content
+// comes back as a dataflow variable the process binds, so the thread keeps
+// running until it actually reads it.
+fn nif_cell(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let content = args[0].clone();
+    let cell = em.userdata.fresh_variable();
+    em.userdata.pending = Some(Operation::NewCell(em.userdata.thread_id, cell, content));
+    Ok(Value::Cell(NO_VARIABLE, cell))
+}
+
+fn cell_exchange(em: &mut ThreadExecutionMachine, content: Option<Value>) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let cell = args[0].clone();
+    let (_, cell) = match em.userdata.deref(&cell) {
+        Value::Unbound(variable) => return Err(suspend(em, Suspension::Bound(variable))),
+        cell => cell.cell()?,
+    };
+    let old = em.userdata.fresh_variable();
+    em.userdata.pending = Some(Operation::Exchange(em.userdata.thread_id, cell, content, old));
+    Ok(Value::Unbound(old))
+}
+
+// `exchange(c, new)` is the old content of `c`
+fn nif_exchange(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let content = args[1].clone();
+    cell_exchange(em, Some(content))
+}
+
+fn nif_cell_get(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    cell_exchange(em, None)
+}
+
+fn nif_cell_set(em: &mut ThreadExecutionMachine) -> Result<Value, ExecutionError> {
+    let (_, args) = em.stack.get_call_and_args(em.current_arity);
+    let content = args[1].clone();
+    cell_exchange(em, Some(content))?;
+    Ok(Value::Unit)
+}
+
+// closures are built by the lowering: `closure_new(f)` followed by one
+// `closure_capture` per free variable, which the lifted body of `f` reads back
+// with `closure_env(self, index)`
+fn nif_closure_new<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let Some(fun) = args[0].fun() else {
+        return Err(ExecutionError::ValueKindUnexpected {
+            value_expected: FUN_KIND,
+            value_got: args[0].descriptor(),
+        });
+    };
+
+    Ok(Value::Closure(NO_VARIABLE, Arc::new(Closure { fun, env: Vec::new() })))
+}
+
+fn nif_closure_capture<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (i1, closure) = args[0].closure()?;
+
+    let mut closure = (**closure).clone();
+    closure.env.push(args[1].clone());
+
+    Ok(Value::Closure(i1, Arc::new(closure)))
+}
+
+fn nif_closure_env<A: WAllocator>(_: &A, args: &[Value]) -> Result<Value, ExecutionError> {
+    let (_, closure) = args[0].closure()?;
+    let (_, index) = args[1].int()?;
+
+    closure.env.get(index as usize).cloned().ok_or_else(|| ExecutionError::UserPanic {
+        message: format!("closure has no captured variable {}", index),
+    })
+}
+
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub enum ThreadLiteral {
     Bool(VariableId, bool),
     Integer(VariableId, ValueInt),
+    Atom(Arc<str>),
 }
 
 pub fn thread_literal_to_value(lit: &ThreadLiteral) -> Value {
     match lit {
         ThreadLiteral::Bool(variable_id, b) => Value::Bool(*variable_id, *b),
         ThreadLiteral::Integer(variable_id, n) => Value::Integer(*variable_id, *n),
+        ThreadLiteral::Atom(label) => Value::Record(NO_VARIABLE, Arc::new(Record::atom(label))),
     }
 }
 
-// only support bool and number from the werbolg core literal
+// only support bool, number and string from the werbolg core literal. A string
+// is an atom, the record with that label and no fields, as in Oz.
 pub fn thread_literal_mapper(span: Span, lit: Literal) -> Result<ThreadLiteral, CompilationError> {
     match lit {
         Literal::Bool(b) => {
             let b = b.as_ref() == "true";
-            Ok(ThreadLiteral::Bool(0, b))
-        }
-        Literal::Number(s) => {
-            let Ok(v) = ValueInt::from_str_radix(s.as_ref(), 10) else {
-                todo!()
-            };
-            Ok(ThreadLiteral::Integer(0, v))
+            Ok(ThreadLiteral::Bool(NO_VARIABLE, b))
         }
-        Literal::String(_) => Err(CompilationError::LiteralNotSupported(span, lit)),
+        Literal::Number(s) => match ValueInt::from_str_radix(s.as_ref(), 10) {
+            Ok(v) => Ok(ThreadLiteral::Integer(NO_VARIABLE, v)),
+            // not a number an integer holds
+            Err(_) => Err(CompilationError::LiteralNotSupported(span, Literal::Number(s))),
+        },
+        Literal::String(s) => Ok(ThreadLiteral::Atom(s.as_ref().into())),
         Literal::Decimal(_) => Err(CompilationError::LiteralNotSupported(span, lit)),
         Literal::Bytes(_) => Err(CompilationError::LiteralNotSupported(span, lit)),
     }
@@ -102,11 +584,24 @@ pub fn thread_literal_mapper(span: Span, lit: Literal) -> Result<ThreadLiteral,
 
 pub fn create_thread_env(
 ) -> Environment<ThreadNIF, Value> {
+    thread_env().0
+}
+
+/// The name and arity of each NIF, in the order of their ids.
+pub(crate) fn nif_table() -> Vec<(&'static str, usize)> {
+    thread_env().1
+}
+
+fn thread_env() -> (Environment<ThreadNIF, Value>, Vec<(&'static str, usize)>) {
+    let mut env = Environment::new();
+    // declared before the macros, which push to it
+    let mut table = Vec::new();
     macro_rules! add_raw_nif {
         ($env:ident, $i:literal, $arity:literal, $e:expr) => {
             let nif = NIFCall::Raw($e).info($i, CallArity::try_from($arity as usize).unwrap());
             let path = AbsPath::new(&Namespace::root(), &Ident::from($i));
             let _ = $env.add_nif(&path, nif);
+            table.push(($i, $arity));
         };
     }
     macro_rules! add_pure_nif {
@@ -114,15 +609,59 @@ pub fn create_thread_env(
             let nif = NIFCall::Pure($e).info($i, CallArity::try_from($arity as usize).unwrap());
             let path = AbsPath::new(&Namespace::root(), &Ident::from($i));
             let _ = $env.add_nif(&path, nif);
+            table.push(($i, $arity));
         };
     }
-    let mut env = Environment::new();
-    add_raw_nif!(env, "unbound", 0, nif_unbound);
+    add_raw_nif!(env, "unbound", 2, nif_unbound);
+    add_raw_nif!(env, "read", 1, nif_read);
+    add_raw_nif!(env, "print", 1, nif_print);
+    add_raw_nif!(env, "bind", 2, nif_bind);
+    add_raw_nif!(env, "wait_needed", 1, nif_wait_needed);
+    add_raw_nif!(env, "timer", 1, nif_timer);
+    add_raw_nif!(env, "die_after", 1, nif_die_after);
+    add_raw_nif!(env, "self", 0, nif_self);
+    add_raw_nif!(env, "send", 2, nif_send);
+    add_raw_nif!(env, "receive", 0, nif_receive);
+    add_raw_nif!(env, "receive_within", 1, nif_receive_within);
     add_pure_nif!(env, "+", 2, nif_plus);
     add_pure_nif!(env, "-", 2, nif_sub);
     add_pure_nif!(env, "*", 2, nif_mul);
     add_pure_nif!(env, "==", 2, nif_eq);
+    add_pure_nif!(env, "/", 2, nif_div);
+    add_pure_nif!(env, "!=", 2, nif_neq);
     add_pure_nif!(env, "<=", 2, nif_le);
+    add_pure_nif!(env, "<", 2, nif_lt);
+    add_pure_nif!(env, ">=", 2, nif_ge);
+    add_pure_nif!(env, ">", 2, nif_gt);
     add_pure_nif!(env, "neg", 1, nif_neg);
-    env
+    add_pure_nif!(env, "not", 1, nif_not);
+    add_pure_nif!(env, "nil", 0, nif_nil);
+    add_pure_nif!(env, "closure_new", 1, nif_closure_new);
+    add_pure_nif!(env, "closure_capture", 2, nif_closure_capture);
+    add_pure_nif!(env, "closure_env", 2, nif_closure_env);
+    add_pure_nif!(env, "cons", 2, nif_cons);
+    add_pure_nif!(env, "record", 2, nif_record);
+    add_pure_nif!(env, "head", 1, nif_head);
+    add_pure_nif!(env, "tail", 1, nif_tail);
+    add_pure_nif!(env, "is_cons", 1, nif_is_cons);
+    add_raw_nif!(env, "list_get", 2, nif_list_get);
+    add_raw_nif!(env, "spawn", 1, nif_spawn);
+    add_raw_nif!(env, "spawn_process", 1, nif_spawn_process);
+    add_raw_nif!(env, "supervise", 1, nif_supervise);
+    add_raw_nif!(env, "deploy", 1, nif_deploy);
+    add_raw_nif!(env, "deploy_to", 2, nif_deploy_to);
+    add_raw_nif!(env, "external", 2, nif_external);
+    add_raw_nif!(env, "cell", 1, nif_cell);
+    add_raw_nif!(env, "exchange", 2, nif_exchange);
+    add_raw_nif!(env, "cell_get", 1, nif_cell_get);
+    add_raw_nif!(env, "cell_set", 2, nif_cell_set);
+    add_pure_nif!(env, "map_new", 0, nif_map_new);
+    add_pure_nif!(env, "map_get", 2, nif_map_get);
+    add_pure_nif!(env, "map_contains", 2, nif_map_contains);
+    add_pure_nif!(env, "map_insert", 3, nif_map_insert);
+    add_pure_nif!(env, "map_remove", 2, nif_map_remove);
+    add_pure_nif!(env, "map_size", 1, nif_map_size);
+    add_pure_nif!(env, "map_key_at", 2, nif_map_key_at);
+    add_pure_nif!(env, "map_value_at", 2, nif_map_value_at);
+    (env, table)
 }
@@ -0,0 +1,75 @@
+use alloc::{boxed::Box, format, string::{String, ToString}, vec::Vec};
+use core::error::Error;
+use hashbrown::{HashMap, HashSet};
+use werbolg_core::ir::{Module, Statement};
+use werbolg_lang_common::Source;
+use crate::frontend;
+
+// The standard library ships as sio source, compiled with every program.
+pub(crate) const PRELUDE: &str = include_str!("prelude.sio");
+
+/// The functions of the standard library that `code` calls, directly or
+/// through each other, to compile in the root namespace. The others are left
+/// out, so a program does not pay for the helpers it does not use.
+pub fn prelude(code: &str) -> Result<Module, Box<dyn Error>> {
+    let source = Source::from_string("prelude.sio".to_string(), PRELUDE.to_string());
+    let (_, mut module) = frontend::module_with_kind(&source.file_unit).map_err(|es| {
+        let messages: Vec<String> = es.into_iter().map(|e| e.message).collect();
+        format!("the prelude does not parse: {}", messages.join(", "))
+    })?;
+    // what each function mentions, the lambdas lowered out of it included
+    let calls: HashMap<String, HashSet<String>> = module
+        .statements
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Function(span, def, _) => Some((def.name.to_string(), frontend::identifiers(&PRELUDE[span.clone()]))),
+            _ => None,
+        })
+        .collect();
+    let mut used = HashSet::new();
+    let mut mentioned: Vec<String> = frontend::identifiers(code).into_iter().collect();
+    while let Some(name) = mentioned.pop() {
+        if let Some(names) = calls.get(&name) {
+            if used.insert(name) {
+                mentioned.extend(names.iter().cloned());
+            }
+        }
+    }
+    // `f$lambda0` and `f$closure` go with `f`
+    module.statements.retain(|statement| match statement {
+        Statement::Function(_, def, _) => {
+            let name = def.name.to_string();
+            used.contains(name.split('$').next().unwrap_or(&name))
+        }
+        _ => true,
+    });
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn functions(module: &Module) -> Vec<String> {
+        let mut names: Vec<String> = module
+            .statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Function(_, def, _) if !def.name.to_string().contains('$') => Some(def.name.to_string()),
+                _ => None,
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn only_the_helpers_a_program_uses_are_compiled() {
+        assert!(functions(&prelude("main :: () { 1; }").unwrap()).is_empty());
+        assert_eq!(functions(&prelude("main :: () { wait(1); }").unwrap()), ["sleep", "wait"]);
+        assert_eq!(
+            functions(&prelude("main :: () { stream_buffer(stream_range(0, 9), 2); }").unwrap()),
+            ["stream_buffer", "stream_buffer_from", "stream_drop", "stream_range"]
+        );
+    }
+}
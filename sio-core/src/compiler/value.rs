@@ -1,8 +1,18 @@
 use werbolg_core::{ConstrId, ValueFun};
 use werbolg_exec::{ExecutionError, Valuable, ValueKind};
+use crate::compiler::map::ValueMap;
+use alloc::{sync::Arc, vec::Vec};
+use core::fmt;
 
 pub type ValueInt = u64;
 pub type VariableId = u64;
+pub type CellId = u64;
+
+/// The tag carried by values that are not (yet) the value of a dataflow
+/// variable: literals and the results of computations. Fresh variables are
+/// numbered from `FIRST_VARIABLE` by their process.
+pub const NO_VARIABLE: VariableId = 0;
+pub const FIRST_VARIABLE: VariableId = 1;
 
 #[derive(Clone, Debug)]
 pub enum ThreadValue {
@@ -11,6 +21,35 @@ pub enum ThreadValue {
     Bool(VariableId, bool),
     Integer(VariableId, ValueInt),
     Fun(VariableId, ValueFun),
+    Map(VariableId, ValueMap),
+    Closure(VariableId, Arc<Closure>),
+    Cons(VariableId, Arc<(ThreadValue, ThreadValue)>),
+    Cell(VariableId, CellId),
+    Record(VariableId, Arc<Record>),
+}
+
+/// A constructor applied to its fields, `Point(x, y)`, as the records of Oz.
+/// A bare `Point` is the record with no fields. Fields can be unbound
+/// variables, bound later on by unification.
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub label: Arc<str>,
+    pub fields: Vec<ThreadValue>,
+}
+
+impl Record {
+    pub fn atom(label: &str) -> Self {
+        Self { label: label.into(), fields: Vec::new() }
+    }
+}
+
+/// A function value together with the variables it captured when it was
+/// created. Captured dataflow variables are stored as they were, bound or
+/// not, and are dereferenced when the closure body reads them.
+#[derive(Clone, Debug)]
+pub struct Closure {
+    pub fun: ValueFun,
+    pub env: Vec<ThreadValue>,
 }
 
 impl ThreadValue {
@@ -21,6 +60,11 @@ impl ThreadValue {
             ThreadValue::Bool(_,_) => BOOL_KIND,
             ThreadValue::Integer(_,_) => INT_KIND,
             ThreadValue::Fun(_,_) => FUN_KIND,
+            ThreadValue::Map(_,_) => MAP_KIND,
+            ThreadValue::Closure(_,_) => CLOSURE_KIND,
+            ThreadValue::Cons(_,_) => CONS_KIND,
+            ThreadValue::Cell(_,_) => CELL_KIND,
+            ThreadValue::Record(_,_) => RECORD_KIND,
         }
     }
 }
@@ -30,6 +74,13 @@ pub const UNBOUND_KIND: ValueKind = " unbound";
 pub const BOOL_KIND: ValueKind = "    bool";
 pub const INT_KIND: ValueKind = "     int";
 pub const FUN_KIND: ValueKind = "     fun";
+pub const MAP_KIND: ValueKind = "     map";
+// not a value of its own: the scalar kinds a map can be indexed by
+pub const KEY_KIND: ValueKind = "     key";
+pub const CLOSURE_KIND: ValueKind = " closure";
+pub const CONS_KIND: ValueKind = "    cons";
+pub const CELL_KIND: ValueKind = "    cell";
+pub const RECORD_KIND: ValueKind = "  record";
 
 impl Valuable for ThreadValue {
     fn descriptor(&self) -> werbolg_exec::ValueKind {
@@ -46,6 +97,7 @@ impl Valuable for ThreadValue {
     fn fun(&self) -> Option<ValueFun> {
         match self {
             Self::Fun(variable_id, valuefun) => Some(*valuefun),
+            Self::Closure(_, closure) => Some(closure.fun),
             _ => None,
         }
     }
@@ -59,7 +111,7 @@ impl Valuable for ThreadValue {
     }
 
     fn make_fun(fun: ValueFun) -> Self {
-        ThreadValue::Fun(0, fun)
+        ThreadValue::Fun(NO_VARIABLE, fun)
     }
 
     fn make_dummy() -> Self {
@@ -77,4 +129,223 @@ impl ThreadValue {
             }),
         }
     }
+
+    pub fn bool(&self) -> Result<(VariableId, bool), ExecutionError> {
+        match self {
+            ThreadValue::Bool(index, value) => Ok((*index, *value)),
+            _ => Err(ExecutionError::ValueKindUnexpected {
+                value_expected: BOOL_KIND,
+                value_got: self.descriptor(),
+            }),
+        }
+    }
+
+    pub fn closure(&self) -> Result<(VariableId, &Arc<Closure>), ExecutionError> {
+        match self {
+            ThreadValue::Closure(index, closure) => Ok((*index, closure)),
+            _ => Err(ExecutionError::ValueKindUnexpected {
+                value_expected: CLOSURE_KIND,
+                value_got: self.descriptor(),
+            }),
+        }
+    }
+
+    pub fn map(&self) -> Result<(VariableId, &ValueMap), ExecutionError> {
+        match self {
+            ThreadValue::Map(index, map) => Ok((*index, map)),
+            _ => Err(ExecutionError::ValueKindUnexpected {
+                value_expected: MAP_KIND,
+                value_got: self.descriptor(),
+            }),
+        }
+    }
+
+    pub fn cons(&self) -> Result<(VariableId, &ThreadValue, &ThreadValue), ExecutionError> {
+        match self {
+            ThreadValue::Cons(index, cell) => Ok((*index, &cell.0, &cell.1)),
+            _ => Err(ExecutionError::ValueKindUnexpected {
+                value_expected: CONS_KIND,
+                value_got: self.descriptor(),
+            }),
+        }
+    }
+
+    pub fn record(&self) -> Result<(VariableId, &Arc<Record>), ExecutionError> {
+        match self {
+            ThreadValue::Record(index, record) => Ok((*index, record)),
+            _ => Err(ExecutionError::ValueKindUnexpected {
+                value_expected: RECORD_KIND,
+                value_got: self.descriptor(),
+            }),
+        }
+    }
+
+    pub fn cell(&self) -> Result<(VariableId, CellId), ExecutionError> {
+        match self {
+            ThreadValue::Cell(index, cell) => Ok((*index, *cell)),
+            _ => Err(ExecutionError::ValueKindUnexpected {
+                value_expected: CELL_KIND,
+                value_got: self.descriptor(),
+            }),
+        }
+    }
+
+    /// The dataflow variable this value is, or is the value of.
+    pub fn variable(&self) -> Option<VariableId> {
+        match self {
+            ThreadValue::Unit => None,
+            ThreadValue::Unbound(index)
+            | ThreadValue::Bool(index, _)
+            | ThreadValue::Integer(index, _)
+            | ThreadValue::Fun(index, _)
+            | ThreadValue::Map(index, _)
+            | ThreadValue::Closure(index, _)
+            | ThreadValue::Cons(index, _)
+            | ThreadValue::Cell(index, _)
+            | ThreadValue::Record(index, _) => Some(*index).filter(|index| *index != NO_VARIABLE),
+        }
+    }
+
+    /// The same value, tagged as the value of `variable`. An unbound variable
+    /// stays itself: `variable` is then bound to that other variable.
+    pub fn with_variable(self, variable: VariableId) -> Self {
+        match self {
+            ThreadValue::Unit => ThreadValue::Unit,
+            ThreadValue::Unbound(other) => ThreadValue::Unbound(other),
+            ThreadValue::Bool(_, b) => ThreadValue::Bool(variable, b),
+            ThreadValue::Integer(_, n) => ThreadValue::Integer(variable, n),
+            ThreadValue::Fun(_, fun) => ThreadValue::Fun(variable, fun),
+            ThreadValue::Map(_, map) => ThreadValue::Map(variable, map),
+            ThreadValue::Closure(_, closure) => ThreadValue::Closure(variable, closure),
+            ThreadValue::Cons(_, cell) => ThreadValue::Cons(variable, cell),
+            ThreadValue::Cell(_, cell) => ThreadValue::Cell(variable, cell),
+            ThreadValue::Record(_, record) => ThreadValue::Record(variable, record),
+        }
+    }
+
+    /// Whether both values are the same term. Variable tags are not compared,
+    /// `1` bound to `x` is the same value as the literal `1`, and unbound
+    /// variables are only equal to themselves.
+    pub fn equals(&self, other: &ThreadValue) -> bool {
+        let (mut left, mut right) = (self, other);
+        // walk lists along their tails rather than recursing into them
+        while let (ThreadValue::Cons(_, a), ThreadValue::Cons(_, b)) = (left, right) {
+            if Arc::ptr_eq(a, b) {
+                return true;
+            }
+            if !a.0.equals(&b.0) {
+                return false;
+            }
+            (left, right) = (&a.1, &b.1);
+        }
+        match (left, right) {
+            (ThreadValue::Unit, ThreadValue::Unit) => true,
+            (ThreadValue::Unbound(a), ThreadValue::Unbound(b)) => a == b,
+            (ThreadValue::Bool(_, a), ThreadValue::Bool(_, b)) => a == b,
+            (ThreadValue::Integer(_, a), ThreadValue::Integer(_, b)) => a == b,
+            (ThreadValue::Fun(_, a), ThreadValue::Fun(_, b)) => a == b,
+            // cells have identity, their content can change
+            (ThreadValue::Cell(_, a), ThreadValue::Cell(_, b)) => a == b,
+            (ThreadValue::Map(_, a), ThreadValue::Map(_, b)) => a.equals(b),
+            (ThreadValue::Record(_, a), ThreadValue::Record(_, b)) => {
+                Arc::ptr_eq(a, b)
+                    || (a.label == b.label
+                        && a.fields.len() == b.fields.len()
+                        && a.fields.iter().zip(b.fields.iter()).all(|(a, b)| a.equals(b)))
+            }
+            (ThreadValue::Closure(_, a), ThreadValue::Closure(_, b)) => {
+                Arc::ptr_eq(a, b)
+                    || (a.fun == b.fun
+                        && a.env.len() == b.env.len()
+                        && a.env.iter().zip(b.env.iter()).all(|(a, b)| a.equals(b)))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A value as sio source writes it. An unbound variable, as the tail of a
+/// partial list or the field of a record, is `_`.
+impl fmt::Display for ThreadValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let elements = |f: &mut fmt::Formatter<'_>, values: &[ThreadValue]| {
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", value)?;
+            }
+            Ok(())
+        };
+        match self {
+            ThreadValue::Unit => write!(f, "nil"),
+            ThreadValue::Unbound(_) => write!(f, "_"),
+            ThreadValue::Bool(_, b) => write!(f, "{}", b),
+            ThreadValue::Integer(_, n) => write!(f, "{}", n),
+            ThreadValue::Fun(..) | ThreadValue::Closure(..) => write!(f, "<function>"),
+            ThreadValue::Cell(..) => write!(f, "<cell>"),
+            ThreadValue::Map(_, map) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in map.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key.to_value(), value)?;
+                }
+                write!(f, "}}")
+            }
+            ThreadValue::Record(_, record) if record.fields.is_empty() => write!(f, "{}", record.label),
+            ThreadValue::Record(_, record) => {
+                write!(f, "{}(", record.label)?;
+                elements(f, &record.fields)?;
+                write!(f, ")")
+            }
+            ThreadValue::Cons(..) => {
+                write!(f, "[")?;
+                // the spine walked without recursing
+                let mut value = self;
+                let mut first = true;
+                while let ThreadValue::Cons(_, cell) = value {
+                    if !first {
+                        write!(f, ", ")?;
+                    }
+                    first = false;
+                    write!(f, "{}", cell.0)?;
+                    value = &cell.1;
+                }
+                match value {
+                    ThreadValue::Unit => write!(f, "]"),
+                    tail => write!(f, " | {}]", tail),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::ToString, vec};
+    use crate::compiler::map::MapKey;
+
+    fn int(n: ValueInt) -> ThreadValue {
+        ThreadValue::Integer(NO_VARIABLE, n)
+    }
+
+    fn cons(head: ThreadValue, tail: ThreadValue) -> ThreadValue {
+        ThreadValue::Cons(NO_VARIABLE, Arc::new((head, tail)))
+    }
+
+    #[test]
+    fn values_display_as_source() {
+        let list = cons(int(1), cons(int(2), ThreadValue::Unit));
+        assert_eq!(list.to_string(), "[1, 2]");
+        assert_eq!(cons(int(1), ThreadValue::Unbound(3)).to_string(), "[1 | _]");
+        let point = Record { label: "Point".into(), fields: vec![int(1), ThreadValue::Unbound(4)] };
+        assert_eq!(ThreadValue::Record(NO_VARIABLE, Arc::new(point)).to_string(), "Point(1, _)");
+        assert_eq!(ThreadValue::Record(NO_VARIABLE, Arc::new(Record::atom("Done"))).to_string(), "Done");
+        let map = ValueMap::new().insert(MapKey::Integer(1), ThreadValue::Bool(NO_VARIABLE, true));
+        assert_eq!(ThreadValue::Map(NO_VARIABLE, map).to_string(), "{1: true}");
+        assert_eq!(ThreadValue::Unit.to_string(), "nil");
+    }
 }
@@ -10,6 +10,8 @@ pub type Identifier = String;
 pub enum UrlComponent {
     Identifier(WithSpan<Identifier>),
     String(WithSpan<String>),
+    /// a public key, in its canonical form
+    PublicKey(WithSpan<String>),
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -57,6 +59,7 @@ pub enum Expr {
     ListGet(Box<WithSpan<Expr>>, Box<WithSpan<Expr>>),
     ListSet(Box<WithSpan<Expr>>, Box<WithSpan<Expr>>, Box<WithSpan<Expr>>),
     ListAppend(Box<WithSpan<Expr>>, Box<WithSpan<Expr>>),
+    Map(Vec<(WithSpan<Expr>, WithSpan<Expr>)>),
     Function(Function),
 }
 #[derive(Debug, PartialEq, Clone)]
@@ -70,6 +73,8 @@ pub enum Stmt {
     Let(WithSpan<Identifier>, Option<WithSpan<Expr>>),
     LetMultiple(Vec<WithSpan<Identifier>>),
     Thread(Vec<WithSpan<Stmt>>),
+    /// `receive x { ... }`, with the statements of `after ms { ... }`
+    Receive(WithSpan<Identifier>, Vec<WithSpan<Stmt>>, Option<(Box<WithSpan<Expr>>, Vec<WithSpan<Stmt>>)>),
     Function(Function),
     Module(Module),
     Return(Box<WithSpan<Expr>>),
@@ -79,16 +84,67 @@ pub enum Stmt {
 pub enum UseItem {
     Simple { path: Vec<WithSpan<String>> },
     Nested { path: Vec<WithSpan<String>>, items: Vec<UseItem> },
+    /// `path::*`, every public function of the module
+    Glob { path: Vec<WithSpan<String>> },
 }
 
 pub type Ast = Vec<WithSpan<Stmt>>;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Module {
-    Corporal { name: Vec<WithSpan<UrlComponent>>, stmts: Vec<WithSpan<Stmt>> },
-    Major { name: Vec<WithSpan<UrlComponent>>, stmts: Vec<WithSpan<Stmt>> },
-    Brigadier { name: Vec<WithSpan<UrlComponent>>, stmts: Vec<WithSpan<Stmt>>},
-    General { name: Vec<WithSpan<UrlComponent>>, stmts: Vec<WithSpan<Stmt>>},
+    Corporal { name: Vec<WithSpan<UrlComponent>>, version: Option<u64>, stmts: Vec<WithSpan<Stmt>> },
+    Major { name: Vec<WithSpan<UrlComponent>>, version: Option<u64>, stmts: Vec<WithSpan<Stmt>> },
+    Brigadier { name: Vec<WithSpan<UrlComponent>>, version: Option<u64>, stmts: Vec<WithSpan<Stmt>>},
+    General { name: Vec<WithSpan<UrlComponent>>, version: Option<u64>, stmts: Vec<WithSpan<Stmt>>},
+}
+
+/// The rank a module is declared with, which decides the role of the process
+/// running it.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ModuleKind {
+    Corporal,
+    Major,
+    Brigadier,
+    General,
+}
+
+impl Module {
+    pub fn name(&self) -> &Vec<WithSpan<UrlComponent>> {
+        match self {
+            Module::Corporal { name, .. }
+            | Module::Major { name, .. }
+            | Module::Brigadier { name, .. }
+            | Module::General { name, .. } => name,
+        }
+    }
+
+    pub fn stmts(&self) -> &Vec<WithSpan<Stmt>> {
+        match self {
+            Module::Corporal { stmts, .. }
+            | Module::Major { stmts, .. }
+            | Module::Brigadier { stmts, .. }
+            | Module::General { stmts, .. } => stmts,
+        }
+    }
+
+    /// The version the module was declared with, `corporal app::A 2 { ... }`.
+    pub fn version(&self) -> Option<u64> {
+        match self {
+            Module::Corporal { version, .. }
+            | Module::Major { version, .. }
+            | Module::Brigadier { version, .. }
+            | Module::General { version, .. } => *version,
+        }
+    }
+
+    pub fn kind(&self) -> ModuleKind {
+        match self {
+            Module::Corporal { .. } => ModuleKind::Corporal,
+            Module::Major { .. } => ModuleKind::Major,
+            Module::Brigadier { .. } => ModuleKind::Brigadier,
+            Module::General { .. } => ModuleKind::General,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -106,6 +162,9 @@ pub struct Param {
 #[derive(Debug, PartialEq, Clone)]
 pub struct Function {
     pub visibility: Visibility,
+    // declared `stateful`: it may use cells, directly or through the
+    // functions it calls
+    pub stateful: bool,
     pub name: Option<WithSpan<Identifier>>,
     pub params: Vec<Param>,
     pub body: Box<WithSpan<Stmt>>,
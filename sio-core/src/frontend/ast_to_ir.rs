@@ -1,203 +1,1128 @@
-use werbolg_core::{Statement, Module};
-use crate::{
-    Ast,
-    ast::Stmt,
+use werbolg_core::{ir, Ident, Literal};
+use crate::frontend::{
+    ast::*,
+    imports::{Imported, Imports},
+    position::{Diagnostic, Span, WithSpan},
+    url_resolver,
 };
+use crate::scheduler::{Capability, ProcessRole};
 use alloc::{
     boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
     vec::Vec,
 };
-/*
-pub fn convert_ast_to_module(ast: Ast) -> Module {
-    let mut statements = Vec::new();
-    
-    for with_span_stmt in ast {
-        // Extract the inner Stmt
-        let stmt = with_span_stmt.value;
-        // Convert Stmt to Statement and append to main statements vector
-        convert_and_append_stmt(&stmt, &mut statements);
-    }
-    
-    // Create the Module struct with the collected statements
-    Module {
-        statements,
-    }
-}
-
-fn convert_and_append_stmt(stmt: &Stmt, statements: &mut Vec<Statement>) {
-    match stmt {
-        Stmt::Url(identifier, hierarchical_name) => {
-            statements.push(Statement::Url((*identifier.value).clone(), hierarchical_name.clone()));
-        }
-        Stmt::Expression(expr) => {
-            statements.push(Statement::Expression((*expr.value).clone()));
-        }
-        Stmt::Print(expr) => {
-            statements.push(Statement::Print((*expr.value).clone()));
-        }
-        Stmt::If(cond, then_block, else_block) => {
-            statements.push(Statement::If(
-                (*cond.value).clone(),
-                Box::new(convert_stmt_to_statement(&then_block.value)),
-                else_block.as_ref().map(|else_block| Box::new(convert_stmt_to_statement(&else_block.value))),
-            ));
+use hashbrown::{HashMap, HashSet};
+
+// Lower the sio AST of a file, which declares one module, into a werbolg
+// module. Every function of the module becomes a werbolg function, statement sequences become
+// nested `let`s and operators become calls to the thread NIFs.
+//
+// Anonymous functions are closure converted: each one is lifted into a module
+// function taking the closure itself as an extra first parameter, its free
+// variables are read back from the closure environment with `closure_env`, and
+// the creation site builds the closure with `closure_new`/`closure_capture`.
+// Calls through a local variable pass the callee as that first argument.
+//
+// `Module.function(...)`, where `Module` is a module the file imports or the
+// one it declares, is an external call: it goes through the `external` NIF
+// with the index of the module, by url, and function in the table of external calls,
+// and the arguments in a list, so the runtime can run the current version of
+// the module. Calls to what the module imports are external calls as well.
+
+const CLOSURE_SELF: &str = "$self";
+
+// natively implemented functions about the variables themselves rather than
+// their values, their arguments are not read
+const VARIABLE_NIFS: &[&str] = &["wait_needed"];
+
+// only a function declared `stateful` may call these, or call one that is
+const CELL_NIFS: &[&str] = &["cell", "exchange", "cell_get", "cell_set"];
+
+/// A lowered module and the external calls it makes, as (module, function)
+/// pairs indexed like the `external` NIF calls.
+pub struct Lowered {
+    pub module: ir::Module,
+    pub externals: Vec<(String, String)>,
+}
+
+pub fn convert_ast_to_module(ast: Ast) -> Result<ir::Module, Vec<Diagnostic>> {
+    lower_ast(ast).map(|lowered| lowered.module)
+}
+
+pub fn lower_ast(ast: Ast) -> Result<Lowered, Vec<Diagnostic>> {
+    let urls = url_resolver::resolve(&ast)?;
+    let url = urls.modules.first().map(|path| path.join("::")).unwrap_or_default();
+    lower_ast_with(ast, url, Imports::default())
+}
+
+/// Lowers `ast`, whose module is at `url` and imports `imports`.
+pub fn lower_ast_with(ast: Ast, url: String, imports: Imports) -> Result<Lowered, Vec<Diagnostic>> {
+    let mut lowering = Lowering::new();
+    lowering.url = url;
+    lowering.imports = imports;
+    for stmt in ast.iter() {
+        lowering.collect_globals(stmt);
+    }
+    for stmt in ast.iter() {
+        lowering.lower_declaration(stmt);
+    }
+    if lowering.diagnostics.is_empty() {
+        Ok(Lowered {
+            module: ir::Module { statements: lowering.statements },
+            externals: lowering.externals,
+        })
+    } else {
+        Err(lowering.diagnostics)
+    }
+}
+
+fn span(span: Span) -> werbolg_core::Span {
+    span.start.0 as usize..span.end.0 as usize
+}
+
+fn spanned(expr: ir::Expr, s: Span) -> Box<ir::Spanned<ir::Expr>> {
+    Box::new(ir::Spanned::new(span(s), expr))
+}
+
+fn ident(name: &str) -> Ident {
+    Ident::from(name)
+}
+
+fn local(name: &WithSpan<Identifier>) -> ir::Expr {
+    ir::Expr::Path(span(name.span), ir::Path::relative(ident(&name.value)))
+}
+
+fn nif_call(s: Span, nif: &str, args: Vec<ir::Expr>) -> ir::Expr {
+    let mut exprs = vec![ir::Expr::Path(span(s), ir::Path::absolute(ident(nif)))];
+    exprs.extend(args);
+    ir::Expr::Call(span(s), exprs)
+}
+
+// a fresh dataflow variable, knowing where it was declared
+fn unbound(s: Span) -> ir::Expr {
+    let declared = span(s);
+    nif_call(s, "unbound", vec![number(s, declared.start), number(s, declared.end)])
+}
+
+fn nil(s: Span) -> ir::Expr {
+    nif_call(s, "nil", vec![])
+}
+
+// a capitalized name that is not bound anywhere is a constructor
+fn is_constructor(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+// the record with no fields labelled `label`, an atom
+fn atom(s: Span, label: &str) -> ir::Expr {
+    ir::Expr::Literal(span(s), Literal::String(label.into()))
+}
+
+fn boolean(s: Span, b: bool) -> ir::Expr {
+    ir::Expr::Literal(span(s), Literal::Bool(b.to_string().into()))
+}
+
+fn params(params: &[Param]) -> Vec<ir::Variable> {
+    params
+        .iter()
+        .map(|param| ir::Variable(ir::Spanned::new(span(param.name.span), ident(&param.name.value))))
+        .collect()
+}
+
+fn number(s: Span, n: usize) -> ir::Expr {
+    ir::Expr::Literal(span(s), Literal::Number(n.to_string().into()))
+}
+
+enum Resolved {
+    // bound by a parameter or a `let` of the function being lowered,
+    // including captured variables which are rebound on entry
+    Local,
+    // a function of the module
+    Global,
+    // a module or a function of one, which `use` brought in
+    Imported(Imported),
+    // anything else is looked up in the root namespace, where the NIFs live
+    Root,
+}
+
+// the variables in scope in one function, innermost block last
+struct FunctionScope {
+    blocks: Vec<Vec<String>>,
+    captures: Vec<String>,
+    // whether the statement being lowered is the last one the function
+    // runs, where a `return` can be
+    tail: bool,
+}
+
+impl FunctionScope {
+    fn new(params: &[Param]) -> Self {
+        let params = params.iter().map(|p| p.name.value.clone()).collect();
+        FunctionScope { blocks: vec![params], captures: Vec::new(), tail: true }
+    }
+
+    fn binds(&self, name: &str) -> bool {
+        self.blocks.iter().any(|block| block.iter().any(|n| n == name))
+            || self.captures.iter().any(|n| n == name)
+    }
+}
+
+struct Lowering {
+    statements: Vec<ir::Statement>,
+    diagnostics: Vec<Diagnostic>,
+    // the functions of the module and their arity
+    globals: HashMap<String, usize>,
+    // the module functions declared `stateful`
+    stateful: HashSet<String>,
+    // functions being lowered, enclosing function first
+    scopes: Vec<FunctionScope>,
+    current_function: String,
+    // whether the function being lowered, or the one enclosing the closure
+    // being lowered, is declared `stateful`
+    current_stateful: bool,
+    lambda_count: usize,
+    trampolines: HashSet<String>,
+    temporary_count: usize,
+    // the role of the module being lowered, which bounds what it may call
+    role: ProcessRole,
+    // the name of the module, which it calls itself by, and its url, which
+    // those calls go through
+    module: String,
+    url: String,
+    externals: Vec<(String, String)>,
+    // what the module imports
+    imports: Imports,
+}
+
+impl Lowering {
+    fn new() -> Self {
+        Lowering {
+            statements: Vec::new(),
+            diagnostics: Vec::new(),
+            globals: HashMap::new(),
+            stateful: HashSet::new(),
+            scopes: Vec::new(),
+            current_function: String::new(),
+            current_stateful: false,
+            lambda_count: 0,
+            trampolines: HashSet::new(),
+            temporary_count: 0,
+            role: ProcessRole::Corporal,
+            module: String::new(),
+            url: String::new(),
+            externals: Vec::new(),
+            imports: Imports::default(),
         }
-        Stmt::Block(stmts) => {
-            for stmt in stmts {
-                convert_and_append_stmt(&stmt.value, statements);
+    }
+
+    fn collect_globals(&mut self, stmt: &WithSpan<Stmt>) {
+        match &stmt.value {
+            Stmt::Module(module) => {
+                if let Some(component) = module.name().last() {
+                    let (UrlComponent::Identifier(name) | UrlComponent::String(name) | UrlComponent::PublicKey(name)) =
+                        &component.value;
+                    self.module = name.value.clone();
+                }
+                for stmt in module.stmts().iter() {
+                    self.collect_globals(stmt);
+                }
+            }
+            Stmt::Function(Function { name: Some(name), params, stateful, .. }) => {
+                // a call names the function alone, whatever its arity
+                if self.globals.insert(name.value.clone(), params.len()).is_some() {
+                    self.diagnostics.push(Diagnostic {
+                        message: format!("`{}` is declared twice in {}", name.value, self.module),
+                        span: name.span,
+                    });
+                }
+                if *stateful {
+                    self.stateful.insert(name.value.clone());
+                }
             }
+            _ => {}
+        }
+    }
+
+    fn bind_local(&mut self, name: &str) {
+        if let Some(block) = self.scopes.last_mut().and_then(|scope| scope.blocks.last_mut()) {
+            block.push(name.to_string());
+        }
+    }
+
+    fn resolve(&mut self, name: &str) -> Resolved {
+        match self.resolve_at(self.scopes.len(), name) {
+            Some(()) => Resolved::Local,
+            None if self.globals.contains_key(name) => Resolved::Global,
+            None => match self.imports.get(name) {
+                Some(imported) => Resolved::Imported(imported.clone()),
+                None => Resolved::Root,
+            },
+        }
+    }
+
+    // a name bound in an enclosing function is captured by every function
+    // between that one and the innermost
+    fn resolve_at(&mut self, depth: usize, name: &str) -> Option<()> {
+        if depth == 0 {
+            return None;
         }
-        Stmt::Let(identifier, expr) => {
-            statements.push(Statement::Let(identifier.value.clone(), expr.as_ref().map(|expr| (*expr.value).clone())));
+        if self.scopes[depth - 1].binds(name) {
+            return Some(());
         }
-        Stmt::Thread(stmts) => {
-            for stmt in stmts {
-                convert_and_append_stmt(&stmt.value, statements);
+        self.resolve_at(depth - 1, name)?;
+        self.scopes[depth - 1].captures.push(name.to_string());
+        Some(())
+    }
+
+    fn lower_variable(&mut self, name: &WithSpan<Identifier>) -> ir::Expr {
+        match self.resolve(&name.value) {
+            Resolved::Local => local(name),
+            Resolved::Global => self.trampoline(name),
+            Resolved::Imported(_) => self.unsupported("using an imported name as a value", name.span),
+            Resolved::Root if is_constructor(&name.value) => atom(name.span, &name.value),
+            Resolved::Root => ir::Expr::Path(span(name.span), ir::Path::absolute(ident(&name.value))),
+        }
+    }
+
+    // a module function used as a value is wrapped in a closure, so that every
+    // function value can be called the same way
+    fn trampoline(&mut self, name: &WithSpan<Identifier>) -> ir::Expr {
+        let trampoline = format!("{}$closure", name.value);
+        if self.trampolines.insert(trampoline.clone()) {
+            let arity = self.globals[&name.value];
+            let params = (0..arity).map(|i| format!("$arg{}", i)).collect::<Vec<_>>();
+            let mut call = vec![local(name)];
+            call.extend(params.iter().map(|p| ir::Expr::Path(span(name.span), ir::Path::relative(ident(p)))));
+            let mut vars = vec![ir::Variable(ir::Spanned::new(span(name.span), ident(CLOSURE_SELF)))];
+            vars.extend(params.iter().map(|p| ir::Variable(ir::Spanned::new(span(name.span), ident(p)))));
+            self.statements.push(ir::Statement::Function(
+                span(name.span),
+                ir::FunDef {
+                    privacy: ir::Privacy::Private,
+                    name: ident(&trampoline),
+                },
+                ir::FunImpl { vars, body: ir::Expr::Call(span(name.span), call) },
+            ));
+        }
+        let fun = ir::Expr::Path(span(name.span), ir::Path::relative(ident(&trampoline)));
+        nif_call(name.span, "closure_new", vec![fun])
+    }
+
+    // cells show in the signature of the functions using them
+    fn check_stateful(&mut self, callee: &str, s: Span) {
+        if !self.current_stateful {
+            self.diagnostics.push(Diagnostic {
+                message: format!(
+                    "`{}` uses cells, `{}` must be declared `stateful`",
+                    callee, self.current_function
+                ),
+                span: s,
+            });
+        }
+    }
+
+    fn unsupported(&mut self, what: &str, s: Span) -> ir::Expr {
+        self.diagnostics.push(Diagnostic {
+            message: format!("{} is not supported yet", what),
+            span: s,
+        });
+        nil(s)
+    }
+
+    fn lower_declaration(&mut self, stmt: &WithSpan<Stmt>) {
+        match &stmt.value {
+            // url aliases and imports are resolved before lowering
+            Stmt::Url(_, _) | Stmt::Use(_, _) => {}
+            Stmt::Module(module) => {
+                self.role = ProcessRole::from(module.kind());
+                for stmt in module.stmts().iter() {
+                    self.lower_declaration(stmt);
+                }
+            }
+            Stmt::Function(function) => self.lower_function(function, stmt.span),
+            _ => {
+                self.unsupported("a statement outside of a function", stmt.span);
             }
         }
-        Stmt::Function(function) => {
-            // Assume function can be differentiated as either definition or implementation
-            if function.is_definition() {
-                statements.push(Statement::FunDef(function.clone()));
-            } else {
-                statements.push(Statement::FunImpl(function.clone()));
+    }
+
+    fn lower_function(&mut self, function: &Function, s: Span) {
+        let Some(name) = function.name.as_ref() else {
+            self.unsupported("an anonymous function declaration", s);
+            return;
+        };
+        let privacy = match function.visibility {
+            Visibility::Public => ir::Privacy::Public,
+            Visibility::Private => ir::Privacy::Private,
+        };
+        self.current_function = name.value.clone();
+        self.current_stateful = function.stateful;
+        self.lambda_count = 0;
+        self.scopes.push(FunctionScope::new(&function.params));
+        let body = self.lower_stmt(&function.body);
+        self.scopes.pop();
+        self.statements.push(ir::Statement::Function(
+            span(s),
+            ir::FunDef {
+                privacy,
+                name: ident(&name.value),
+            },
+            ir::FunImpl { vars: params(&function.params), body },
+        ));
+    }
+
+    fn lower_lambda(&mut self, function: &Function, s: Span) -> ir::Expr {
+        let name = format!("{}$lambda{}", self.current_function, self.lambda_count);
+        self.lambda_count += 1;
+
+        self.scopes.push(FunctionScope::new(&function.params));
+        let mut body = self.lower_stmt(&function.body);
+        let scope = self.scopes.pop().unwrap();
+
+        let closure_self = || ir::Expr::Path(span(s), ir::Path::relative(ident(CLOSURE_SELF)));
+        for (index, captured) in scope.captures.iter().enumerate().rev() {
+            let value = nif_call(s, "closure_env", vec![closure_self(), number(s, index)]);
+            body = ir::Expr::Let(ir::Binder::Ident(ident(captured)), Box::new(value), Box::new(body));
+        }
+        let mut vars = vec![ir::Variable(ir::Spanned::new(span(s), ident(CLOSURE_SELF)))];
+        vars.extend(params(&function.params));
+        self.statements.push(ir::Statement::Function(
+            span(s),
+            ir::FunDef {
+                privacy: ir::Privacy::Private,
+                name: ident(&name),
+            },
+            ir::FunImpl { vars, body },
+        ));
+
+        let fun = ir::Expr::Path(span(s), ir::Path::relative(ident(&name)));
+        let mut closure = nif_call(s, "closure_new", vec![fun]);
+        for captured in scope.captures.iter() {
+            // resolving registers the capture in the enclosing function too
+            let captured = WithSpan::new(captured.clone(), s);
+            let value = self.lower_variable(&captured);
+            closure = nif_call(s, "closure_capture", vec![closure, value]);
+        }
+        closure
+    }
+
+    fn lower_external(&mut self, module: &str, function: &str, args: &[WithSpan<Expr>], s: Span) -> ir::Expr {
+        let external = (module.to_string(), function.to_string());
+        let index = match self.externals.iter().position(|e| *e == external) {
+            Some(index) => index,
+            None => {
+                self.externals.push(external);
+                self.externals.len() - 1
             }
+        };
+        let mut lowered_args = Vec::new();
+        for arg in args.iter() {
+            lowered_args.push(self.lower_expr(arg));
         }
-        Stmt::Use(module_name, identifiers) => {
-            statements.push(Statement::Use(module_name.value.clone(), identifiers.clone().map(|ids| ids.iter().map(|id| id.value.clone()).collect())));
+        let mut list = nil(s);
+        for arg in lowered_args.into_iter().rev() {
+            list = nif_call(s, "cons", vec![arg, list]);
         }
-        Stmt::CorporalModule(module) => {
-            for stmt in &module.statements {
-                convert_and_append_stmt(&stmt.value, statements);
+        nif_call(s, "external", vec![number(s, index), list])
+    }
+
+    fn lower_call(&mut self, callee: &WithSpan<Expr>, args: &[WithSpan<Expr>], s: Span) -> ir::Expr {
+        if let Expr::Get(object, function) = &callee.value {
+            if let Expr::Variable(module) = &object.value {
+                match self.resolve(&module.value) {
+                    Resolved::Root if module.value == self.module => {
+                        let url = self.url.clone();
+                        return self.lower_external(&url, &function.value, args, s);
+                    }
+                    Resolved::Root => {
+                        self.diagnostics.push(Diagnostic {
+                            message: format!("{} is not in scope, a module is imported with `use`", module.value),
+                            span: module.span,
+                        });
+                        return nil(s);
+                    }
+                    Resolved::Imported(Imported::Module(name)) => {
+                        return self.lower_external(&name, &function.value, args, s)
+                    }
+                    _ => {}
+                }
             }
         }
-        Stmt::MajorModule(module) => {
-            for stmt in &module.statements {
-                convert_and_append_stmt(&stmt.value, statements);
+        if let Expr::Variable(name) = &callee.value {
+            match self.resolve(&name.value) {
+                Resolved::Local => {}
+                Resolved::Imported(Imported::Function(module, function)) => {
+                    return self.lower_external(&module, &function, args, s);
+                }
+                Resolved::Imported(Imported::Module(_)) => {
+                    self.diagnostics.push(Diagnostic {
+                        message: format!("{} is a module, not a function", name.value),
+                        span: name.span,
+                    });
+                    return nil(s);
+                }
+                Resolved::Global => {
+                    if self.stateful.contains(&name.value) {
+                        self.check_stateful(&name.value, s);
+                    }
+                    let mut exprs = vec![local(name)];
+                    for arg in args.iter() {
+                        exprs.push(self.lower_expr(arg));
+                    }
+                    return ir::Expr::Call(span(s), exprs);
+                }
+                // `Point(x, y)` is `record(Point, [x, y])`, the fields are
+                // stored as they are like the items of a list
+                Resolved::Root if is_constructor(&name.value) => {
+                    let mut fields = nil(s);
+                    for arg in args.iter().rev() {
+                        let field = self.lower_expr(arg);
+                        fields = nif_call(arg.span, "cons", vec![field, fields]);
+                    }
+                    return nif_call(s, "record", vec![atom(name.span, &name.value), fields]);
+                }
+                // natively implemented functions work on values, not variables
+                Resolved::Root => {
+                    // calls through a function value are checked when run
+                    if let Some(capability) = Capability::of(&name.value) {
+                        if !self.role.can(capability) {
+                            self.diagnostics.push(Diagnostic {
+                                message: format!(
+                                    "a {} cannot {}: `{}` needs a {} or above",
+                                    self.role,
+                                    capability,
+                                    name.value,
+                                    capability.rank()
+                                ),
+                                span: s,
+                            });
+                        }
+                    }
+                    if CELL_NIFS.contains(&name.value.as_str()) {
+                        self.check_stateful(&name.value, s);
+                    }
+                    let on_variables = VARIABLE_NIFS.contains(&name.value.as_str());
+                    let mut lowered_args = Vec::new();
+                    for arg in args.iter() {
+                        if on_variables {
+                            lowered_args.push(self.lower_expr(arg));
+                        } else {
+                            lowered_args.push(self.lower_read(arg));
+                        }
+                    }
+                    return nif_call(s, &name.value, lowered_args);
+                }
             }
         }
-        Stmt::BrigadierModule(module) => {
-            for stmt in &module.statements {
-                convert_and_append_stmt(&stmt.value, statements);
+        let mut lowered_args = Vec::new();
+        for arg in args.iter() {
+            lowered_args.push(self.lower_expr(arg));
+        }
+        // any other callee evaluates to a closure, bind it to pass it along
+        let temporary = format!("$callee{}", self.temporary_count);
+        self.temporary_count += 1;
+        let callee_expr = self.lower_read(callee);
+        let temporary_path = || ir::Expr::Path(span(callee.span), ir::Path::relative(ident(&temporary)));
+        let mut exprs = vec![temporary_path(), temporary_path()];
+        exprs.extend(lowered_args);
+        ir::Expr::Let(
+            ir::Binder::Ident(ident(&temporary)),
+            Box::new(callee_expr),
+            Box::new(ir::Expr::Call(span(s), exprs)),
+        )
+    }
+
+    fn lower_block(&mut self, stmts: &[WithSpan<Stmt>], s: Span) -> ir::Expr {
+        let Some((first, rest)) = stmts.split_first() else {
+            return nil(s);
+        };
+        if rest.is_empty() {
+            return self.lower_stmt(first);
+        }
+        let rest_span = Span::union(&rest[0], &rest[rest.len() - 1]);
+        match &first.value {
+            Stmt::Let(name, init) => {
+                let value = match init {
+                    Some(expr) => self.lower_expr(expr),
+                    None => unbound(name.span),
+                };
+                self.bind_local(&name.value);
+                let then = self.lower_block(rest, rest_span);
+                ir::Expr::Let(ir::Binder::Ident(ident(&name.value)), Box::new(value), Box::new(then))
+            }
+            Stmt::LetMultiple(names) => {
+                for name in names.iter() {
+                    self.bind_local(&name.value);
+                }
+                let mut then = self.lower_block(rest, rest_span);
+                for name in names.iter().rev() {
+                    let value = unbound(name.span);
+                    then = ir::Expr::Let(ir::Binder::Ident(ident(&name.value)), Box::new(value), Box::new(then));
+                }
+                then
+            }
+            _ => {
+                let first = self.lower_inner(first);
+                let then = self.lower_block(rest, rest_span);
+                ir::Expr::Let(ir::Binder::Ignore, Box::new(first), Box::new(then))
             }
         }
     }
-}
 
-fn convert_stmt_to_statement(stmt: &Stmt) -> Statement {
-    match stmt {
-        Stmt::Url(identifier, hierarchical_name) => {
-            Statement::Url((*identifier.value).clone(), hierarchical_name.clone())
+    // a statement followed by others
+    fn lower_inner(&mut self, stmt: &WithSpan<Stmt>) -> ir::Expr {
+        let tail = self.scopes.last_mut().map(|scope| core::mem::replace(&mut scope.tail, false));
+        let lowered = self.lower_stmt(stmt);
+        if let (Some(scope), Some(tail)) = (self.scopes.last_mut(), tail) {
+            scope.tail = tail;
         }
-        Stmt::Expression(expr) => {
-            Statement::Expression((*expr.value).clone())
+        lowered
+    }
+
+    // the statements of a block, in a scope of their own that binds `name`
+    fn lower_scoped(&mut self, name: Option<&str>, stmts: &[WithSpan<Stmt>], s: Span) -> ir::Expr {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.blocks.push(Vec::new());
         }
-        Stmt::Print(expr) => {
-            Statement::Print((*expr.value).clone())
+        if let Some(name) = name {
+            self.bind_local(name);
         }
-        Stmt::If(cond, then_block, else_block) => {
-            Statement::If(
-                (*cond.value).clone(),
-                Box::new(convert_stmt_to_statement(&then_block.value)),
-                else_block.as_ref().map(|else_block| Box::new(convert_stmt_to_statement(&else_block.value))),
-            )
+        let block = self.lower_block(stmts, s);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.blocks.pop();
         }
-        Stmt::Let(identifier, expr) => {
-            Statement::Let(identifier.value.clone(), expr.as_ref().map(|expr| (*expr.value).clone()))
+        block
+    }
+
+    fn lower_stmt(&mut self, stmt: &WithSpan<Stmt>) -> ir::Expr {
+        match &stmt.value {
+            Stmt::Expression(expr) => self.lower_expr(expr),
+            Stmt::Print(expr) => {
+                let expr = self.lower_expr(expr);
+                nif_call(stmt.span, "print", vec![expr])
+            }
+            // the value of the function, as the last statement is: there is
+            // no jumping out of the middle of one
+            Stmt::Return(expr) => {
+                if !self.scopes.last().map_or(true, |scope| scope.tail) {
+                    self.diagnostics.push(Diagnostic {
+                        message: "`return` can only be the last statement of a function".to_string(),
+                        span: stmt.span,
+                    });
+                }
+                self.lower_expr(expr)
+            }
+            Stmt::Block(stmts) => self.lower_scoped(None, stmts, stmt.span),
+            Stmt::If(cond, then_branch, else_branch) => {
+                let cond_expr = self.lower_read(cond);
+                let then_expr = self.lower_stmt(then_branch);
+                let else_expr = match else_branch {
+                    Some(else_branch) => spanned(self.lower_stmt(else_branch), else_branch.span),
+                    None => spanned(nil(stmt.span), stmt.span),
+                };
+                ir::Expr::If {
+                    span: span(stmt.span),
+                    cond: spanned(cond_expr, cond.span),
+                    then_expr: spanned(then_expr, then_branch.span),
+                    else_expr,
+                }
+            }
+            // a declaration as the last statement of a block evaluates to nil
+            Stmt::Let(_, _) | Stmt::LetMultiple(_) => {
+                let block = vec![stmt.clone(), WithSpan::new(Stmt::Block(vec![]), stmt.span)];
+                self.lower_block(&block, stmt.span)
+            }
+            // the block becomes a closure over the variables it uses, which
+            // the new thread runs
+            Stmt::Thread(stmts) => {
+                let function = Function {
+                    visibility: Visibility::Private,
+                    stateful: false,
+                    name: None,
+                    params: Vec::new(),
+                    body: Box::new(WithSpan::new(Stmt::Block(stmts.clone()), stmt.span)),
+                };
+                let closure = self.lower_lambda(&function, stmt.span);
+                nif_call(stmt.span, "spawn", vec![closure])
+            }
+            // the thread binds `x` to the next message before it goes on;
+            // with `after`, to the list of that message, or to nil once the
+            // time is up
+            Stmt::Receive(name, stmts, after) => {
+                let s = stmt.span;
+                let body = self.lower_scoped(Some(&name.value), stmts, s);
+                let binder = ir::Binder::Ident(ident(&name.value));
+                let Some((timeout, after_stmts)) = after else {
+                    return ir::Expr::Let(binder, Box::new(nif_call(s, "receive", vec![])), Box::new(body));
+                };
+                let timeout = self.lower_read(timeout);
+                let after_body = self.lower_scoped(None, after_stmts, s);
+                let received = format!("$received{}", self.temporary_count);
+                self.temporary_count += 1;
+                let read_received = || nif_call(s, "read", vec![ir::Expr::Path(span(s), ir::Path::relative(ident(&received)))]);
+                let message = nif_call(s, "head", vec![read_received()]);
+                ir::Expr::Let(
+                    ir::Binder::Ident(ident(&received)),
+                    Box::new(nif_call(s, "receive_within", vec![timeout])),
+                    Box::new(ir::Expr::If {
+                        span: span(s),
+                        cond: spanned(nif_call(s, "is_cons", vec![read_received()]), s),
+                        then_expr: spanned(ir::Expr::Let(binder, Box::new(message), Box::new(body)), s),
+                        else_expr: spanned(after_body, s),
+                    }),
+                )
+            }
+            Stmt::Function(_) => self.unsupported("a nested function declaration", stmt.span),
+            Stmt::Url(_, _) | Stmt::Use(_, _) | Stmt::Module(_) => {
+                self.unsupported("a declaration inside a function", stmt.span)
+            }
         }
-        Stmt::Function(function) => {
-            if function.is_definition() {
-                Statement::FunDef(function.clone())
-            } else {
-                Statement::FunImpl(function.clone())
+    }
+
+    // Where a value is needed rather than a variable: operands, conditions,
+    // map keys and the arguments of natively implemented functions wait for
+    // their dataflow variables to be bound.
+    fn lower_read(&mut self, expr: &WithSpan<Expr>) -> ir::Expr {
+        match &expr.value {
+            Expr::Number(_) | Expr::Boolean(_) | Expr::String(_) | Expr::Nil => self.lower_expr(expr),
+            Expr::Binary(_, _, _) | Expr::Unary(_, _) | Expr::Logical(_, _, _) => self.lower_expr(expr),
+            Expr::Map(_) | Expr::Function(_) | Expr::List(_) | Expr::ListAppend(_, _) => self.lower_expr(expr),
+            Expr::Grouping(inner) => self.lower_read(inner),
+            _ => {
+                let value = self.lower_expr(expr);
+                nif_call(expr.span, "read", vec![value])
             }
         }
-        Stmt::Use(module_name, identifiers) => {
-            Statement::Use(module_name.value.clone(), identifiers.clone().map(|ids| ids.iter().map(|id| id.value.clone()).collect()))
+    }
+
+    fn lower_expr(&mut self, expr: &WithSpan<Expr>) -> ir::Expr {
+        let s = expr.span;
+        match &expr.value {
+            Expr::Number(n) => {
+                let literal = if n.fract() == 0.0 && *n >= 0.0 {
+                    Literal::Number(format!("{}", *n as u64).into())
+                } else {
+                    Literal::Decimal(format!("{}", n).into())
+                };
+                ir::Expr::Literal(span(s), literal)
+            }
+            Expr::Boolean(b) => boolean(s, *b),
+            Expr::String(string) => ir::Expr::Literal(span(s), Literal::String(string.as_str().into())),
+            Expr::Nil => nil(s),
+            Expr::Variable(name) => self.lower_variable(name),
+            Expr::Grouping(inner) => self.lower_expr(inner),
+            Expr::Binary(left, op, right) => {
+                let nif = match op.value {
+                    BinaryOperator::Slash => "/",
+                    BinaryOperator::Star => "*",
+                    BinaryOperator::Plus => "+",
+                    BinaryOperator::Minus => "-",
+                    BinaryOperator::Greater => ">",
+                    BinaryOperator::GreaterEqual => ">=",
+                    BinaryOperator::Less => "<",
+                    BinaryOperator::LessEqual => "<=",
+                    BinaryOperator::BangEqual => "!=",
+                    BinaryOperator::EqualEqual => "==",
+                };
+                let left = self.lower_read(left);
+                let right = self.lower_read(right);
+                nif_call(op.span, nif, vec![left, right])
+            }
+            Expr::Unary(op, right) => {
+                let nif = match op.value {
+                    UnaryOperator::Bang => "not",
+                    UnaryOperator::Minus => "neg",
+                };
+                let right = self.lower_read(right);
+                nif_call(op.span, nif, vec![right])
+            }
+            Expr::Logical(left, op, right) => {
+                let cond = spanned(self.lower_read(left), left.span);
+                let right_expr = spanned(self.lower_read(right), right.span);
+                let (then_expr, else_expr) = match op.value {
+                    LogicalOperator::And => (right_expr, spanned(boolean(op.span, false), op.span)),
+                    LogicalOperator::Or => (spanned(boolean(op.span, true), op.span), right_expr),
+                };
+                ir::Expr::If { span: span(s), cond, then_expr, else_expr }
+            }
+            Expr::Call(callee, args) => self.lower_call(callee, args, s),
+            Expr::Assign(name, value) => {
+                let variable = self.lower_variable(name);
+                let value = self.lower_expr(value);
+                nif_call(s, "bind", vec![variable, value])
+            }
+            Expr::Function(function) => self.lower_lambda(function, s),
+            Expr::Map(entries) => {
+                let mut map = nif_call(s, "map_new", vec![]);
+                for (key, value) in entries.iter() {
+                    let key_expr = self.lower_read(key);
+                    let value_expr = self.lower_expr(value);
+                    map = nif_call(Span::union(key, value), "map_insert", vec![map, key_expr, value_expr]);
+                }
+                map
+            }
+            Expr::Get(_, _) | Expr::Set(_, _, _) => self.unsupported("field access", s),
+            // `[a, b]` is `[a | [b | nil]]`, the items and the tail are stored
+            // as they are so a list can be built from unbound variables
+            Expr::List(items) => {
+                let mut list = nil(s);
+                for item in items.iter().rev() {
+                    let item_expr = self.lower_expr(item);
+                    list = nif_call(item.span, "cons", vec![item_expr, list]);
+                }
+                list
+            }
+            Expr::ListAppend(item, list) => {
+                let item = self.lower_expr(item);
+                let list = self.lower_expr(list);
+                nif_call(s, "cons", vec![item, list])
+            }
+            Expr::ListGet(list, index) => {
+                let list = self.lower_expr(list);
+                let index = self.lower_read(index);
+                nif_call(s, "list_get", vec![list, index])
+            }
+            Expr::ListSet(_, _, _) => self.unsupported("updating a list in place", s),
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::parse;
 
-pub fn module(fileunit: &FileUnit) -> Result<ir::Module, Vec<ParseError>> {
-    let m = parse::module(&fileunit.content).map_err(|errs| {
-        errs.into_iter()
-            .map(|err| simple_to_perr(err))
-            .collect::<Vec<_>>()
-    })?;
+    fn lower(src: &str) -> Result<ir::Module, Vec<Diagnostic>> {
+        convert_ast_to_module(parse(src)?)
+    }
 
-    let statements = m
-        .into_iter()
-        .map(|(n, span, fun)| {
-            let body = rewrite_expr(&fun.body);
-            Statement::Function(
-                span,
-                ir::FunDef {
-                    privacy: ir::Privacy::Public,
-                    name: ir::Ident::from(n),
-                },
-                ir::FunImpl {
-                    vars: fun.args,
-                    body,
-                },
+    #[test]
+    fn lowers_every_module_function() {
+        let module = lower(
+            "corporal app::Corporal {
+                pub main :: () {
+                    let m = {1: true, 2: false};
+                    helper(m);
+                }
+                helper :: (m) {
+                    map_size(m) + 1;
+                }
+            }",
+        )
+        .expect("lowering succeeds");
+        assert_eq!(module.statements.len(), 2);
+    }
+
+    fn function<'a>(module: &'a ir::Module, name: &str) -> &'a ir::FunImpl {
+        module
+            .statements
+            .iter()
+            .find_map(|stmt| match stmt {
+                ir::Statement::Function(_, def, fun_impl) if def.name == Ident::from(name) => Some(fun_impl),
+                _ => None,
+            })
+            .expect("function exists")
+    }
+
+    #[test]
+    fn lifts_closures_with_their_free_variables() {
+        let module = lower(
+            "corporal app::Corporal {
+                pub main :: () {
+                    let x;
+                    let offset = 1;
+                    let add = (y) {
+                        let inner = () { x + offset; };
+                        inner() + y;
+                    };
+                    apply(add, 2);
+                }
+                apply :: (f, arg) {
+                    f(arg);
+                }
+            }",
+        )
+        .expect("lowering succeeds");
+        // main, apply and the two lifted lambdas
+        assert_eq!(module.statements.len(), 4);
+        // the closure itself is the first parameter of a lifted lambda
+        assert_eq!(function(&module, "main$lambda0").vars.len(), 2);
+        assert_eq!(function(&module, "main$lambda1").vars.len(), 1);
+        assert_eq!(function(&module, "apply").vars.len(), 2);
+    }
+
+    #[test]
+    fn module_functions_used_as_values_get_a_trampoline() {
+        let module = lower(
+            "corporal app::Corporal {
+                pub main :: () {
+                    apply(double, 2);
+                }
+                apply :: (f, arg) {
+                    f(arg);
+                }
+                double :: (n) {
+                    n * 2;
+                }
+            }",
+        )
+        .expect("lowering succeeds");
+        assert_eq!(module.statements.len(), 4);
+        assert_eq!(function(&module, "double$closure").vars.len(), 2);
+    }
+
+    fn nif_calls(expr: &ir::Expr, nif: &str) -> usize {
+        match expr {
+            ir::Expr::Call(_, exprs) => {
+                let own = match exprs.first() {
+                    Some(ir::Expr::Path(_, path)) if *path == ir::Path::absolute(Ident::from(nif)) => 1,
+                    _ => 0,
+                };
+                own + exprs.iter().map(|expr| nif_calls(expr, nif)).sum::<usize>()
+            }
+            ir::Expr::Let(_, value, then) => nif_calls(value, nif) + nif_calls(then, nif),
+            ir::Expr::If { cond, then_expr, else_expr, .. } => {
+                nif_calls(&cond.inner, nif) + nif_calls(&then_expr.inner, nif) + nif_calls(&else_expr.inner, nif)
+            }
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn assignments_bind_dataflow_variables() {
+        let module = lower(
+            "corporal app::Corporal {
+                pub main :: () {
+                    let x, y;
+                    x = 1;
+                    y = x + 1;
+                    y;
+                }
+            }",
+        )
+        .expect("lowering succeeds");
+        let body = &function(&module, "main").body;
+        assert_eq!(nif_calls(body, "unbound"), 2);
+        assert_eq!(nif_calls(body, "bind"), 2);
+        // only the operand of `+` needs the value of a variable
+        assert_eq!(nif_calls(body, "read"), 1);
+    }
+
+    #[test]
+    fn thread_blocks_spawn_closures() {
+        let module = lower(
+            "corporal app::Corporal {
+                pub main :: () {
+                    let x;
+                    thread {
+                        x = 1;
+                    }
+                    x + 1;
+                }
+            }",
+        )
+        .expect("lowering succeeds");
+        assert_eq!(module.statements.len(), 2);
+        // the block only takes the closure holding x
+        assert_eq!(function(&module, "main$lambda0").vars.len(), 1);
+        assert_eq!(nif_calls(&function(&module, "main").body, "spawn"), 1);
+    }
+
+    #[test]
+    fn wait_needed_takes_the_variable_itself() {
+        let module = lower(
+            "corporal app::Corporal {
+                pub main :: () {
+                    let x;
+                    wait_needed(x);
+                }
+            }",
+        )
+        .expect("lowering succeeds");
+        let body = &function(&module, "main").body;
+        assert_eq!(nif_calls(body, "wait_needed"), 1);
+        assert_eq!(nif_calls(body, "read"), 0);
+    }
+
+    #[test]
+    fn constructors_build_records() {
+        let module = lower(
+            "corporal app::Corporal {
+                pub main :: () {
+                    let x, y;
+                    let p = Point(x, y);
+                    let none = None;
+                }
+            }",
+        )
+        .expect("lowering succeeds");
+        let body = &function(&module, "main").body;
+        assert_eq!(nif_calls(body, "record"), 1);
+        assert_eq!(nif_calls(body, "cons"), 2);
+        // the fields are the variables themselves
+        assert_eq!(nif_calls(body, "read"), 0);
+    }
+
+    #[test]
+    fn reports_unsupported_constructs() {
+        let diagnostics = lower(
+            "corporal app::Corporal {
+                pub main :: () {
+                    let l = [1, 2];
+                    l[0] = 3;
+                }
+            }",
+        )
+        .unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "updating a list in place is not supported yet");
+    }
+
+    #[test]
+    fn calls_to_the_module_itself_are_external() {
+        let lowered = lower_ast(
+            parse(
+                "corporal app::A {
+                    pub main :: () {
+                        A.twice(1) + A.twice(2);
+                    }
+                    pub twice :: (n) {
+                        n + n;
+                    }
+                }",
             )
-        })
-        .collect::<Vec<_>>();
-
-    Ok(ir::Module { statements })
-}
-
-fn rewrite_expr_spanbox(span_expr: &(Stmt, parse::Span)) -> Box<Spanned<ir::Expr>> {
-    let span = span_expr.1.clone();
-    let expr = rewrite_expr(span_expr);
-    Box::new(Spanned::new(span, expr))
-}
-
-fn rewrite_expr(span_expr: &(Stmt, parse::Span)) -> ir::Expr {
-    match &span_expr.0 {
-        Stmt::Error => todo!(),
-        Stmt::Literal(lit) => ir::Expr::Literal(span_expr.1.clone(), lit.clone()),
-        Stmt::List(list) => ir::Expr::Sequence(
-            span_expr.1.clone(),
-            list.iter().map(|se| rewrite_expr(se)).collect::<Vec<_>>(),
-        ),
-        Stmt::Local(l) => ir::Expr::Path(
-            span_expr.1.clone(),
-            ir::Path::relative(ir::Ident::from(l.as_str())),
-        ),
-        Stmt::Let(name, bind, then) => ir::Expr::Let(
-            ir::Binder::Ident(ir::Ident::from(name.as_str())),
-            Box::new(rewrite_expr(bind)),
-            Box::new(rewrite_expr(then)),
-        ),
-        Stmt::Then(first, second) => ir::Expr::Let(
-            ir::Binder::Ignore,
-            Box::new(rewrite_expr(first)),
-            Box::new(rewrite_expr(second)),
-        ),
-        Stmt::Binary(left, op, right) => ir::Expr::Call(
-            span_expr.1.clone(),
-            vec![
-                ir::Expr::Path(
-                    /* should be op's span */ span_expr.1.clone(),
-                    ir::Path::absolute(ir::Ident::from(op.as_str())),
-                ),
-                rewrite_expr(&left),
-                rewrite_expr(&right),
-            ],
-        ),
-        Stmt::Call(x, args) => {
-            let mut exprs = vec![rewrite_expr(x)];
-            for a in args {
-                exprs.push(rewrite_expr(a))
-            }
-            ir::Expr::Call(span_expr.1.clone(), exprs)
-        }
-        Stmt::If(cond, then_expr, else_expr) => ir::Expr::If {
-            span: span_expr.1.clone(),
-            cond: rewrite_expr_spanbox(cond),
-            then_expr: rewrite_expr_spanbox(then_expr),
-            else_expr: rewrite_expr_spanbox(else_expr),
-        },
-    }
-}*/
\ No newline at end of file
+            .unwrap(),
+        )
+        .expect("lowering succeeds");
+        assert_eq!(lowered.externals, [("app::A".to_string(), "twice".to_string())]);
+        assert_eq!(nif_calls(&function(&lowered.module, "main").body, "external"), 2);
+    }
+
+    #[test]
+    fn return_ends_a_function() {
+        lower(
+            "corporal app::A {
+                pub main :: () {
+                    let f = (x) {
+                        let y = x + 1;
+                        return y;
+                    };
+                    if f(1) == 2 {
+                        return true;
+                    } else {
+                        return false;
+                    }
+                }
+            }",
+        )
+        .expect("returns in tail position lower");
+        let diagnostics = lower(
+            "corporal app::A {
+                pub main :: () {
+                    if true {
+                        return 1;
+                    }
+                    2;
+                }
+            }",
+        )
+        .unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "`return` can only be the last statement of a function");
+    }
+
+    #[test]
+    fn functions_are_declared_once() {
+        let src = "corporal app::A {
+                pub main :: () {
+                    twice(1);
+                }
+                twice :: (n) {
+                    n + n;
+                }
+                twice :: (n, m) {
+                    n + m;
+                }
+            }";
+        let diagnostics = lower(src).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "`twice` is declared twice in A");
+        let second = src.rfind("twice ::").unwrap();
+        assert_eq!(diagnostics[0].span.start.0 as usize..diagnostics[0].span.end.0 as usize, second..second + 5);
+    }
+
+    #[test]
+    fn modules_are_called_once_imported() {
+        let diagnostics = lower(
+            "corporal app::A {
+                pub main :: () {
+                    B.x(1);
+                }
+            }",
+        )
+        .unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "B is not in scope, a module is imported with `use`");
+    }
+
+    #[test]
+    fn privileged_calls_need_the_rank_for_them() {
+        let diagnostics = lower(
+            "corporal app::Corporal {
+                pub main :: () {
+                    spawn_process(() { 1; });
+                }
+            }",
+        )
+        .unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "a corporal cannot spawn processes: `spawn_process` needs a major or above"
+        );
+
+        lower(
+            "major app::Major {
+                pub main :: () {
+                    spawn_process(() { 1; });
+                }
+            }",
+        )
+        .expect("majors can spawn processes");
+    }
+
+    #[test]
+    fn cells_show_in_signatures() {
+        let diagnostics = lower(
+            "corporal app::Corporal {
+                pub main :: () {
+                    let c = cell(0);
+                    increment(c);
+                }
+                stateful increment :: (c) {
+                    thread {
+                        cell_set(c, cell_get(c) + 1);
+                    }
+                }
+            }",
+        )
+        .unwrap_err();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].message, "`cell` uses cells, `main` must be declared `stateful`");
+        assert_eq!(diagnostics[1].message, "`increment` uses cells, `main` must be declared `stateful`");
+
+        lower(
+            "corporal app::Corporal {
+                pub stateful main :: () {
+                    let c = cell(0);
+                    increment(c);
+                }
+                stateful increment :: (c) {
+                    exchange(c, 1);
+                }
+            }",
+        )
+        .expect("cells are used by stateful functions only");
+    }
+}
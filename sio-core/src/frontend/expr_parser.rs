@@ -92,7 +92,7 @@ fn parse_infix(it: &mut Parser, left: WithSpan<Expr>) -> Result<WithSpan<Expr>,
 }
 
 fn parse_prefix(it: &mut Parser) -> Result<WithSpan<Expr>, ()> {
-    if it.check(TokenKind::LeftParen) {
+    if is_anonymous_function(it) {
         return parse_anonymous_function(it);
     }
     match it.peek() {
@@ -105,6 +105,7 @@ fn parse_prefix(it: &mut Parser) -> Result<WithSpan<Expr>, ()> {
         TokenKind::Bang | TokenKind::Minus => parse_unary(it),
         TokenKind::LeftParen => parse_grouping(it),
         TokenKind::LeftBracket => parse_list(it),
+        TokenKind::LeftBrace => parse_map(it),
         _ => {
             it.error(&format!("Unexpected {}", it.peek_token().value), it.peek_token().span);
             Err(())
@@ -156,6 +157,22 @@ fn parse_arguments(it: &mut Parser) -> Result<Vec<WithSpan<Expr>>, ()> {
     Ok(args)
 }
 
+// `(a, b) {` starts an anonymous function, anything else in parentheses is
+// a grouping
+fn is_anonymous_function(it: &Parser) -> bool {
+    if !it.check(TokenKind::LeftParen) {
+        return false;
+    }
+    let mut n = 1;
+    if it.peek_nth(n) == TokenKind::Identifier {
+        n += 1;
+        while it.peek_nth(n) == TokenKind::Comma && it.peek_nth(n + 1) == TokenKind::Identifier {
+            n += 2;
+        }
+    }
+    it.peek_nth(n) == TokenKind::RightParen && it.peek_nth(n + 1) == TokenKind::LeftBrace
+}
+
 fn parse_anonymous_function(it: &mut Parser) -> Result<WithSpan<Expr>, ()> {
     let begin_span = it.expect(TokenKind::LeftParen)?;
     let params = parse_params(it)?;
@@ -163,6 +180,7 @@ fn parse_anonymous_function(it: &mut Parser) -> Result<WithSpan<Expr>, ()> {
     let block_stmt = parse_block_statement(it)?;
     let function = Function {
         visibility: Visibility::Private,
+        stateful: false,
         name: None,
         params,
         body: Box::new(block_stmt.clone()),
@@ -219,13 +237,47 @@ fn parse_list_items(it: &mut Parser) -> Result<Vec<WithSpan<Expr>>, ()> {
 
 fn parse_list(it: &mut Parser) -> Result<WithSpan<Expr>, ()> {
     let left_bracket = it.expect(TokenKind::LeftBracket)?;
-    let items = parse_list_items(it)?;
+    let mut items = parse_list_items(it)?;
+    // `[head | tail]`
+    if items.len() == 1 && it.check(TokenKind::Pipe) {
+        it.expect(TokenKind::Pipe)?;
+        let tail = parse_expr(it, Precedence::None)?;
+        let right_bracket = it.expect(TokenKind::RightBracket)?;
+        let span = Span::union(left_bracket, right_bracket);
+        let head = items.remove(0);
+        return Ok(WithSpan::new(Expr::ListAppend(Box::new(head), Box::new(tail)), span));
+    }
     let right_bracket = it.expect(TokenKind::RightBracket)?;
 
     let span = Span::union(left_bracket, right_bracket);
     Ok(WithSpan::new(Expr::List(items), span))
 }
 
+fn parse_map_entries(it: &mut Parser) -> Result<Vec<(WithSpan<Expr>, WithSpan<Expr>)>, ()> {
+    let mut entries = Vec::new();
+    while !it.check(TokenKind::RightBrace) {
+        let key = parse_expr(it, Precedence::None)?;
+        it.expect(TokenKind::Colon)?;
+        let value = parse_expr(it, Precedence::None)?;
+        entries.push((key, value));
+        if it.check(TokenKind::Comma) {
+            it.expect(TokenKind::Comma)?;
+        } else {
+            break;
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_map(it: &mut Parser) -> Result<WithSpan<Expr>, ()> {
+    let left_brace = it.expect(TokenKind::LeftBrace)?;
+    let entries = parse_map_entries(it)?;
+    let right_brace = it.expect(TokenKind::RightBrace)?;
+
+    let span = Span::union(left_brace, right_brace);
+    Ok(WithSpan::new(Expr::Map(entries), span))
+}
+
 fn parse_grouping(it: &mut Parser) -> Result<WithSpan<Expr>, ()> {
     let left_paren = it.expect(TokenKind::LeftParen)?;
     let expr = parse_expr(it, Precedence::None)?;
@@ -320,7 +372,7 @@ pub fn parse(it: &mut Parser) -> Result<WithSpan<Expr>, ()> {
 
 #[cfg(test)]
 mod tests {
-    use crate::position::Diagnostic;
+    use crate::frontend::position::Diagnostic;
     use alloc::vec;
 
     use super::*;
@@ -328,7 +380,7 @@ mod tests {
         use super::super::tokenizer::*;
 
         let tokens = tokenize_with_context(data);
-        let mut parser = crate::parser::Parser::new(&tokens);
+        let mut parser = crate::frontend::parser::Parser::new(&tokens);
         match parse(&mut parser) {
             Ok(e) => Ok(e),
             Err(_) => Err(parser.diagnostics().to_vec()),
@@ -554,6 +606,10 @@ mod tests {
         ), 0..5);
         assert("(1+2)", expr);
 
+        let expr = wsg(ws(Expr::Variable(wsi("a", 1..2)), 1..2), 0..3);
+        assert("(a)", expr);
+        assert!(matches!(parse_str("(a) {}").map(|e| e.value), Ok(Expr::Function(_))));
+
         assert_errs("(1", &["Expected ')' got <EOF>"]);
         assert_errs("(1}", &["Expected ')' got '}'"]);
     }
@@ -724,5 +780,28 @@ mod tests {
         let value = ws(n(1.0), 5..6);
         let expr = ws(Expr::ListSet(Box::new(left), Box::new(right), Box::new(value)), 0..6);
         assert("x[0]=1", expr);
+
+        let head = ws(v("a", 1..2), 1..2);
+        let tail = ws(v("t", 5..6), 5..6);
+        let expr = ws(Expr::ListAppend(Box::new(head), Box::new(tail)), 0..7);
+        assert("[a | t]", expr);
+    }
+
+    #[test]
+    fn test_map() {
+        use help::assert;
+        use make::*;
+
+        let expr = ws(Expr::Map(Vec::new()), 0..2);
+        assert("{}", expr);
+
+        let entries = vec![
+            (wsn(1., 1..2), wsb(true, 4..8)),
+            (ws(v("x", 10..11), 10..11), wsn(2., 13..14)),
+        ];
+        let expr = ws(Expr::Map(entries), 0..16);
+        assert("{1: true, x: 2,}", expr);
+
+        assert_errs("{1 2}", &["Expected ':' got number"]);
     }
 }
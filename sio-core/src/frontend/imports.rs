@@ -0,0 +1,182 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use hashbrown::HashMap;
+use crate::frontend::position::{Diagnostic, Span};
+use crate::frontend::url_resolver::Import;
+
+// `use` binds names of other modules in the module it is written in, and only
+// there, looked up by url among the modules the file is compiled with:
+// `use lib::B;` and
+// `use lib::B::{self}` bind the module `B`, `use lib::B::x;` its function `x`
+// and `use lib::B::*;` all of its public functions. A name declared by the
+// module itself wins over a glob import, and the same name imported from two
+// places, or both imported and declared, is an error. What an import names
+// is called like any other function, and runs as an external call.
+
+/// What a module offers to the modules importing it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interface {
+    /// its fully qualified url, its name last
+    pub path: Vec<String>,
+    /// its functions, and whether each one is public
+    pub functions: Vec<(String, bool)>,
+}
+
+impl Interface {
+    /// What the module is called by in messages.
+    pub fn name(&self) -> &str {
+        self.path.last().map(String::as_str).unwrap_or_default()
+    }
+
+    /// What external calls to the module go through, and what the runtime
+    /// keeps it under: its path joined, as two modules may share a name.
+    pub fn url(&self) -> String {
+        self.path.join("::")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Imported {
+    /// a module, by url
+    Module(String),
+    /// a module by url, and one of its functions
+    Function(String, String),
+}
+
+/// The names a module imports.
+#[derive(Debug, Clone, Default)]
+pub struct Imports {
+    // and whether each one comes from a glob
+    names: HashMap<String, (Imported, bool)>,
+}
+
+impl Imports {
+    pub fn get(&self, name: &str) -> Option<&Imported> {
+        self.names.get(name).map(|(imported, _)| imported)
+    }
+}
+
+/// Binds the names `imports` bring in a module declaring the functions
+/// `declared`.
+pub fn resolve(imports: &[Import], declared: &[String], interfaces: &[Interface]) -> Result<Imports, Vec<Diagnostic>> {
+    let mut resolving = Resolving { imports: Imports::default(), declared, diagnostics: Vec::new() };
+    let find = |path: &[String]| interfaces.iter().find(|interface| interface.path == path);
+    for import in imports {
+        let url = import.path.join("::");
+        if import.glob {
+            match find(&import.path) {
+                Some(module) => {
+                    for (function, _) in module.functions.iter().filter(|(_, public)| *public) {
+                        let imported = Imported::Function(module.url(), function.clone());
+                        resolving.bind(function, imported, true, import.span);
+                    }
+                }
+                None => resolving.error(format!("no module {} is loaded", url), import.span),
+            }
+            continue;
+        }
+        if let Some(module) = find(&import.path) {
+            resolving.bind(module.name(), Imported::Module(module.url()), false, import.span);
+            continue;
+        }
+        let (function, path) = import.path.split_last().expect("an import names something");
+        let Some(module) = find(path) else {
+            let missing = if path.is_empty() { url } else { path.join("::") };
+            resolving.error(format!("no module {} is loaded", missing), import.span);
+            continue;
+        };
+        match module.functions.iter().find(|(name, _)| name == function) {
+            Some((_, true)) => {
+                let imported = Imported::Function(module.url(), function.clone());
+                resolving.bind(function, imported, false, import.span);
+            }
+            Some((_, false)) => resolving.error(format!("{} is private to {}", function, path.join("::")), import.span),
+            None => resolving.error(format!("{} has no function {}", path.join("::"), function), import.span),
+        }
+    }
+    if resolving.diagnostics.is_empty() {
+        Ok(resolving.imports)
+    } else {
+        Err(resolving.diagnostics)
+    }
+}
+
+struct Resolving<'a> {
+    imports: Imports,
+    declared: &'a [String],
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolving<'_> {
+    fn error(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic { message, span });
+    }
+
+    fn bind(&mut self, name: &str, imported: Imported, glob: bool, span: Span) {
+        if self.declared.iter().any(|declared| declared == name) {
+            if !glob {
+                self.error(format!("{} is both imported and declared", name), span);
+            }
+            return;
+        }
+        match self.imports.names.get(name) {
+            Some((existing, _)) if *existing == imported => {}
+            // an explicit import wins over a glob one
+            Some((_, true)) if !glob => {}
+            Some((_, false)) if glob => return,
+            Some(_) => {
+                self.error(format!("{} is imported twice", name), span);
+                return;
+            }
+            None => {}
+        }
+        self.imports.names.insert(name.into(), (imported, glob));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn interface(path: &str, functions: &[(&str, bool)]) -> Interface {
+        Interface {
+            path: path.split("::").map(ToString::to_string).collect(),
+            functions: functions.iter().map(|(name, public)| (name.to_string(), *public)).collect(),
+        }
+    }
+
+    fn import(path: &str, glob: bool) -> Import {
+        Import { path: path.split("::").map(ToString::to_string).collect(), glob, span: Span::empty() }
+    }
+
+    fn messages(result: Result<Imports, Vec<Diagnostic>>) -> Vec<String> {
+        result.unwrap_err().into_iter().map(|diagnostic| diagnostic.message).collect()
+    }
+
+    #[test]
+    fn imports_bind_modules_and_functions() {
+        let loaded = [interface("lib::B", &[("x", true), ("y", true), ("hidden", false)]), interface("lib::C", &[("x", true)])];
+        let imports = resolve(&[import("lib::B", true), import("lib::C::x", false), import("lib::C", false)], &[], &loaded)
+            .expect("imports resolve");
+        assert_eq!(imports.get("x"), Some(&Imported::Function("lib::C".into(), "x".into())));
+        assert_eq!(imports.get("y"), Some(&Imported::Function("lib::B".into(), "y".into())));
+        assert_eq!(imports.get("C"), Some(&Imported::Module("lib::C".into())));
+        assert_eq!(imports.get("hidden"), None);
+    }
+
+    #[test]
+    fn bad_imports_are_reported() {
+        let loaded = [interface("lib::B", &[("x", true), ("hidden", false)]), interface("lib::C", &[("x", true)])];
+        assert_eq!(messages(resolve(&[import("lib::B::hidden", false)], &[], &loaded)), ["hidden is private to lib::B"]);
+        assert_eq!(messages(resolve(&[import("lib::B::z", false)], &[], &loaded)), ["lib::B has no function z"]);
+        assert_eq!(messages(resolve(&[import("lib::D::x", false)], &[], &loaded)), ["no module lib::D is loaded"]);
+        assert_eq!(
+            messages(resolve(&[import("lib::B::x", false), import("lib::C::x", false)], &[], &loaded)),
+            ["x is imported twice"]
+        );
+        assert_eq!(messages(resolve(&[import("lib::B::x", false)], &["x".into()], &loaded)), ["x is both imported and declared"]);
+        assert!(resolve(&[import("lib::B", true)], &["x".into()], &loaded).is_ok());
+    }
+}
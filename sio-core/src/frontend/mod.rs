@@ -2,7 +2,7 @@
 extern crate alloc;
 extern crate proc_macro;
 
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 
 pub mod position;
 pub mod ast;
@@ -14,13 +14,13 @@ mod token;
 mod tokenizer;
 mod stmt_parser;
 mod expr_parser;
-mod hierarchical_name;
-//mod ast_to_ir;
+mod ast_to_ir;
 mod url_resolver;
+pub mod imports;
 
-use werbolg_ir_write::module;
 use werbolg_lang_common::{FileUnit};
-use ast::Ast;
+use ast::{Ast, Function, ModuleKind, Stmt, UrlComponent, Visibility};
+use imports::Interface;
 use position::Diagnostic;
 
 fn parse(code: &str) -> Result<Ast, Vec<Diagnostic>> {
@@ -35,16 +35,150 @@ fn parse(code: &str) -> Result<Ast, Vec<Diagnostic>> {
     }
 }
 
+/// The identifiers `code` mentions, whatever they name.
+pub fn identifiers(code: &str) -> hashbrown::HashSet<String> {
+    tokenizer::tokenize_with_context(code)
+        .into_iter()
+        .filter_map(|token| match token.value {
+            token::Token::Identifier(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
 #[allow(dead_code)]
 pub fn module(file_unit: &FileUnit) -> Result<werbolg_core::Module, Vec<Diagnostic>> {
+    let (_, module) = module_with_kind(file_unit)?;
+    Ok(module)
+}
+
+/// Like `module`, along with the kind of the module the file declares, a
+/// file without module declaration being a corporal.
+pub fn module_with_kind(file_unit: &FileUnit) -> Result<(ModuleKind, werbolg_core::Module), Vec<Diagnostic>> {
+    let lowered = lower(file_unit)?;
+    Ok((lowered.kind, lowered.module))
+}
+
+/// What the frontend makes of a file, for the runtime to load it.
+pub struct Lowered {
+    pub kind: ModuleKind,
+    /// the last component of the name of the module the file declares, what
+    /// messages call it
+    pub name: String,
+    pub version: Option<u64>,
+    /// the fully qualified url of that module, its url aliases resolved,
+    /// which its functions are compiled under
+    pub url: Option<werbolg_core::Namespace>,
+    /// what the module offers to those importing it
+    pub interface: Option<Interface>,
+    pub module: werbolg_core::Module,
+    /// the module, by url, and function of each external call
+    pub externals: Vec<(String, String)>,
+}
+
+/// A file parsed, its urls resolved, to be lowered once the modules it
+/// imports are known.
+pub struct Parsed {
+    ast: Ast,
+    urls: url_resolver::Urls,
+}
+
+/// Parses a file, which declares at most one module: the runtime loads
+/// modules by url, so each one goes in a file of its own.
+pub fn parse_file(file_unit: &FileUnit) -> Result<Parsed, Vec<Diagnostic>> {
     let ast = parse(&file_unit.content)?;
-   // let ir = ast_to_ir::convert_ast_to_module(ast);
-    //Ok(ir)
-    Ok(module! {
-        fn main() {
-            1
+    let extra = ast
+        .iter()
+        .filter(|stmt| matches!(stmt.value, Stmt::Module(_)))
+        .skip(1)
+        .map(|stmt| Diagnostic {
+            message: "a file declares one module, this one needs a file of its own".into(),
+            span: stmt.span,
+        })
+        .collect::<Vec<_>>();
+    if !extra.is_empty() {
+        return Err(extra);
+    }
+    let urls = url_resolver::resolve(&ast)?;
+    Ok(Parsed { ast, urls })
+}
+
+impl Parsed {
+    /// What the module the file declares offers to those importing it.
+    pub fn interface(&self) -> Option<Interface> {
+        let path = self.urls.modules.first()?.clone();
+        let functions = self.functions().map(|(name, public)| (name.clone(), public)).collect();
+        Some(Interface { path, functions })
+    }
+
+    fn functions(&self) -> impl Iterator<Item = (&String, bool)> {
+        self.ast
+            .iter()
+            .filter_map(|stmt| match &stmt.value {
+                Stmt::Module(module) => Some(module.stmts()),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|stmt| match &stmt.value {
+                Stmt::Function(Function { name: Some(name), visibility, .. }) => {
+                    Some((&name.value, *visibility == Visibility::Public))
+                }
+                _ => None,
+            })
+    }
+}
+
+/// Why a parsed file could not be lowered.
+#[derive(Debug)]
+pub enum Unlowered {
+    /// what the file imports is not loaded, private, or clashes with another
+    /// name
+    Resolution(Vec<Diagnostic>),
+    /// the file imports what it needs, but is not a program the runtime runs
+    Lowering(Vec<Diagnostic>),
+}
+
+impl Unlowered {
+    pub fn diagnostics(self) -> Vec<Diagnostic> {
+        match self {
+            Unlowered::Resolution(diagnostics) | Unlowered::Lowering(diagnostics) => diagnostics,
         }
-    })
+    }
+}
+
+/// Lowers a file that imports nothing.
+pub fn lower(file_unit: &FileUnit) -> Result<Lowered, Vec<Diagnostic>> {
+    lower_parsed(parse_file(file_unit)?, &[]).map_err(Unlowered::diagnostics)
+}
+
+/// Lowers a file, what its module imports resolved against `interfaces`.
+pub fn lower_parsed(parsed: Parsed, interfaces: &[Interface]) -> Result<Lowered, Unlowered> {
+    let interface = parsed.interface();
+    let declared = parsed.functions().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+    let imports = match parsed.urls.imports.first() {
+        Some(module_imports) => imports::resolve(module_imports, &declared, interfaces).map_err(Unlowered::Resolution)?,
+        None => imports::Imports::default(),
+    };
+    let Parsed { ast, urls } = parsed;
+    let declared = ast.iter().find_map(|stmt| match &stmt.value {
+        Stmt::Module(module) => Some(module),
+        _ => None,
+    });
+    let kind = declared.map(|module| module.kind()).unwrap_or(ModuleKind::Corporal);
+    let version = declared.and_then(|module| module.version());
+    let name = declared
+        .and_then(|module| module.name().last())
+        .map(|component| match &component.value {
+            UrlComponent::Identifier(name) => name.value.clone(),
+            UrlComponent::String(name) => name.value.clone(),
+            UrlComponent::PublicKey(key) => key.value.clone(),
+        })
+        .unwrap_or_default();
+    let path = urls.modules.first();
+    let url = path.map(|path| url_resolver::namespace(path));
+    let own = path.map(|path| path.join("::")).unwrap_or_default();
+    let lowered = ast_to_ir::lower_ast_with(ast, own, imports).map_err(Unlowered::Lowering)?;
+    Ok(Lowered { kind, name, version, url, interface, module: lowered.module, externals: lowered.externals })
 }
 
 
@@ -54,9 +188,9 @@ mod tests {
     use alloc::vec::Vec;
     use alloc::vec;
     use alloc::string::ToString;
-    use crate::token::Token;
+    use crate::frontend::token::Token;
     fn tokenize(buf: &str) -> Vec<Token> {
-        use crate::tokenizer::tokenize_with_context;
+        use crate::frontend::tokenizer::tokenize_with_context;
         tokenize_with_context(buf)
             .iter()
             .map(|tc| tc.value.clone())
@@ -116,4 +250,69 @@ mod tests {
             ]
         );
     }
+    #[test]
+    fn modules_are_loaded_under_their_url() {
+        let source = werbolg_lang_common::Source::from_string(
+            "b.sio".to_string(),
+            "corporal lib::B 2 { pub x :: () { 2; } }".to_string(),
+        );
+        let lowered = super::lower(&source.file_unit).expect("a module");
+        assert_eq!((lowered.name.as_str(), lowered.version), ("B", Some(2)));
+        assert_eq!(lowered.interface.expect("an interface").url(), "lib::B");
+        let unversioned = werbolg_lang_common::Source::from_string(
+            "b.sio".to_string(),
+            "corporal lib::B { pub x :: () { 1; } }".to_string(),
+        );
+        assert_eq!(super::lower(&unversioned.file_unit).expect("a module").version, None);
+    }
+    #[test]
+    fn imported_functions_are_called_externally() {
+        let lib = werbolg_lang_common::Source::from_string(
+            "b.sio".to_string(),
+            "url lib : \"lib\"; corporal lib::B { pub x :: () { 1; } y :: () { 2; } }".to_string(),
+        );
+        let interface = super::parse_file(&lib.file_unit).expect("parses").interface().expect("a module");
+        assert_eq!(interface.path, ["lib", "B"]);
+        let app = werbolg_lang_common::Source::from_string(
+            "a.sio".to_string(),
+            "corporal app::A { use lib::B::{x}; pub main :: () { x(); } }".to_string(),
+        );
+        let parsed = super::parse_file(&app.file_unit).expect("parses");
+        let lowered = super::lower_parsed(parsed, &[interface.clone()]).expect("lowers");
+        assert_eq!(lowered.externals, [("lib::B".to_string(), "x".to_string())]);
+        let private = werbolg_lang_common::Source::from_string(
+            "a.sio".to_string(),
+            "corporal app::A { use lib::B::y; pub main :: () { y(); } }".to_string(),
+        );
+        let parsed = super::parse_file(&private.file_unit).expect("parses");
+        let Some(super::Unlowered::Resolution(diagnostics)) = super::lower_parsed(parsed, &[interface.clone()]).err() else {
+            panic!("y is private")
+        };
+        assert_eq!(diagnostics[0].message, "y is private to lib::B");
+        let unimported = werbolg_lang_common::Source::from_string(
+            "c.sio".to_string(),
+            "corporal app::C { pub other :: () { B.x(); } }".to_string(),
+        );
+        let parsed = super::parse_file(&unimported.file_unit).expect("parses");
+        let Some(super::Unlowered::Lowering(diagnostics)) = super::lower_parsed(parsed, &[interface]).err() else {
+            panic!("C does not import B")
+        };
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "B is not in scope, a module is imported with `use`");
+    }
+    #[test]
+    fn a_file_declares_one_module() {
+        let two = werbolg_lang_common::Source::from_string(
+            "a.sio".to_string(),
+            "corporal app::A { pub main :: () { 1; } }
+             corporal app::C { pub other :: () { 2; } }"
+                .to_string(),
+        );
+        let Err(diagnostics) = super::parse_file(&two.file_unit) else {
+            panic!("C needs a file of its own")
+        };
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "a file declares one module, this one needs a file of its own");
+        assert_eq!(&two.file_unit.content[diagnostics[0].span.start.0 as usize..][..8], "corporal");
+    }
 }
@@ -4,7 +4,6 @@ use crate::alloc::string::ToString;
 use crate::frontend::{
     position::{WithSpan, Diagnostic, Span},
     token::{Token, TokenKind},
-    url_resolver::UrlResolver,
 };
 
 static EOF_TOKEN: WithSpan<Token> = WithSpan::empty(Token::Eof);
@@ -13,12 +12,23 @@ pub struct Parser<'a> {
     tokens: &'a [WithSpan<Token>],
     cursor: usize,
     diagnostics: Vec<Diagnostic>,
-    pub url_resolver: UrlResolver,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: &'a [WithSpan<Token>]) -> Self {
-        Parser { tokens, cursor: 0, diagnostics: Vec::new(), url_resolver: UrlResolver::new() }
+        // the lexer leaves invalid public keys in, to be reported here with
+        // what is wrong with them
+        let diagnostics = tokens
+            .iter()
+            .filter_map(|token| match &token.value {
+                Token::InvalidPublicKey(error) => Some(Diagnostic {
+                    message: format!("invalid public key: {}", error),
+                    span: token.span,
+                }),
+                _ => None,
+            })
+            .collect();
+        Parser { tokens, cursor: 0, diagnostics }
     }
 
     pub fn diagnostics(&self) -> &[Diagnostic] {
@@ -58,6 +68,14 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // the kind of the token `n` places after the current one
+    pub fn peek_nth(&self, n: usize) -> TokenKind {
+        match self.tokens.get(self.cursor + n) {
+            Some(t) => t.into(),
+            None => TokenKind::Eof,
+        }
+    }
+
     pub fn check(&self, match_token: TokenKind) -> bool {
         let token = self.peek();
         token == match_token
@@ -3,7 +3,6 @@ use alloc::vec::Vec;
 use alloc::boxed::Box;
 use alloc::format;
 use alloc::string::String;
-use crate::alloc::string::ToString;
 use crate::frontend::{
     ast::*,
     token::*,
@@ -49,80 +48,89 @@ fn parse_url_declaration(p: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
 }
 
 fn parse_hierarchical_components(p: &mut Parser) -> Result<Vec<WithSpan<UrlComponent>>, ()> {
-    let mut components = Vec::new();
+    let mut components = vec![parse_hierarchical_component(p)?];
+    while p.peek() == TokenKind::ColonColon {
+        p.expect(TokenKind::ColonColon)?;
+        components.push(parse_hierarchical_component(p)?);
+    }
+    Ok(components)
+}
 
-    // Expect the first component of the URL
+fn parse_hierarchical_component(p: &mut Parser) -> Result<WithSpan<UrlComponent>, ()> {
     match p.peek() {
         TokenKind::String => {
-            let string = p.expect(TokenKind::String)?;
-            let string_value = WithSpan::new(string.value.to_string(), string.span);
-            components.push(WithSpan::new(UrlComponent::String(string_value), string.span));
+            let string = expect_string(p)?;
+            let span = string.span;
+            Ok(WithSpan::new(UrlComponent::String(string), span))
         }
         TokenKind::Identifier => {
-            let identifier = p.expect(TokenKind::Identifier)?;
-            let identifier_value = WithSpan::new(identifier.value.to_string(), identifier.span);
-            components.push(WithSpan::new(UrlComponent::Identifier(identifier_value), identifier.span));
+            let identifier = expect_identifier(p)?;
+            let span = identifier.span;
+            Ok(WithSpan::new(UrlComponent::Identifier(identifier), span))
         }
-        _ => return Err(()),
-    }
-
-    while p.peek() == TokenKind::ColonColon {
-        p.expect(TokenKind::ColonColon)?;
-        match p.peek() {
-            TokenKind::String => {
-                let string = p.expect(TokenKind::String)?;
-                let string_value = WithSpan::new(string.value.to_string(), string.span);
-                components.push(WithSpan::new(UrlComponent::String(string_value), string.span));
-            }
-            TokenKind::Identifier => {
-                let identifier = p.expect(TokenKind::Identifier)?;
-                let identifier_value = WithSpan::new(identifier.value.to_string(), identifier.span);
-                components.push(WithSpan::new(UrlComponent::Identifier(identifier_value), identifier.span));
+        TokenKind::PublicKey => {
+            let token = p.advance();
+            match &token.value {
+                Token::PublicKey(key) => Ok(WithSpan::new(UrlComponent::PublicKey(WithSpan::new(key.clone(), token.span)), token.span)),
+                _ => Err(()),
             }
-            _ => return Err(()),
         }
+        _ => Err(()),
     }
-
-    Ok(components)
 }
 
 fn parse_module<F>( p: &mut Parser, token_kind: TokenKind, create_module: F) -> Result<WithSpan<Stmt>, ()>
 where
-    F: FnOnce(Vec<WithSpan<UrlComponent>>, Vec<WithSpan<Stmt>>) -> Module,
+    F: FnOnce(Vec<WithSpan<UrlComponent>>, Option<u64>, Vec<WithSpan<Stmt>>) -> Module,
 {
     let begin_span = p.expect(token_kind)?;
     let name = parse_hierarchical_components(p)?;
+    let version = parse_module_version(p)?;
     p.expect(TokenKind::LeftBrace)?;
     let statements = parse_module_declarations(p)?;
     let end_span = p.expect(TokenKind::RightBrace)?;
     Ok(WithSpan::new(
-        Stmt::Module(create_module(name, statements)), 
+        Stmt::Module(create_module(name, version, statements)), 
         Span::union(&begin_span, &end_span),
     ))
 }
+// an optional whole number between the name of a module and its body
+fn parse_module_version(p: &mut Parser) -> Result<Option<u64>, ()> {
+    if p.peek() != TokenKind::Number {
+        return Ok(None);
+    }
+    let token = p.advance();
+    match &token.value {
+        Token::Number(n) if n.fract() == 0.0 && *n >= 0.0 => Ok(Some(*n as u64)),
+        _ => {
+            p.error(&format!("Expected a module version got {}", token.value), token.span);
+            Err(())
+        }
+    }
+}
 fn parse_general_declaration(p: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
-    let general_module = | name: Vec<WithSpan<UrlComponent>>, stmts: Vec<WithSpan<Stmt>> | { 
-        Module::General{ name, stmts }
+    let general_module = | name: Vec<WithSpan<UrlComponent>>, version: Option<u64>, stmts: Vec<WithSpan<Stmt>> | { 
+        Module::General{ name, version, stmts }
     };
     parse_module(p, TokenKind::General, general_module)
 }
 fn parse_brigadier_declaration(p: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
-    let brigadier_module = | name: Vec<WithSpan<UrlComponent>>, stmts: Vec<WithSpan<Stmt>> | { 
-        Module::Brigadier{ name, stmts }
+    let brigadier_module = | name: Vec<WithSpan<UrlComponent>>, version: Option<u64>, stmts: Vec<WithSpan<Stmt>> | { 
+        Module::Brigadier{ name, version, stmts }
     };
     parse_module(p, TokenKind::Brigadier, brigadier_module)
 }
 
 fn parse_major_declaration(p: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
-    let major_module = | name: Vec<WithSpan<UrlComponent>>, stmts: Vec<WithSpan<Stmt>> | { 
-        Module::Major{ name, stmts }
+    let major_module = | name: Vec<WithSpan<UrlComponent>>, version: Option<u64>, stmts: Vec<WithSpan<Stmt>> | { 
+        Module::Major{ name, version, stmts }
     };
     parse_module(p, TokenKind::Major, major_module)
 }
 
 fn parse_corporal_declaration(p: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
-    let corporal_module = | name: Vec<WithSpan<UrlComponent>>, stmts: Vec<WithSpan<Stmt>> | { 
-        Module::Corporal{ name, stmts }
+    let corporal_module = | name: Vec<WithSpan<UrlComponent>>, version: Option<u64>, stmts: Vec<WithSpan<Stmt>> | { 
+        Module::Corporal{ name, version, stmts }
     };
     parse_module(p, TokenKind::Corporal, corporal_module)
 }
@@ -135,7 +143,7 @@ fn parse_module_declarations(p: &mut Parser) -> Result<Vec<WithSpan<Stmt>>, ()>
                 statements.push(parse_url_declaration(p)?);
             }
             TokenKind::Use => statements.push(parse_use_statement(p)?),
-            TokenKind::Pub | TokenKind::Identifier => {
+            TokenKind::Pub | TokenKind::Stateful | TokenKind::Identifier => {
                 statements.push(parse_function_declaration(p)?);
             }
             _ => {
@@ -171,6 +179,7 @@ pub fn parse_statement(it: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
         TokenKind::Use => parse_use_statement(it),
         TokenKind::Let => parse_let_statement(it),
         TokenKind::Thread => parse_thread_statement(it),
+        TokenKind::Receive => parse_receive_statement(it),
         _ => parse_expr_statement(it),
     }
 }
@@ -178,22 +187,21 @@ pub fn parse_statement(it: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
 fn parse_use_statement(it: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
     let begin_span = it.expect(TokenKind::Use)?;
     let path = parse_use_path(it)?;
-    let items = if it.check(TokenKind::LeftBrace) {
+    let items = if it.check(TokenKind::Star) {
+        it.expect(TokenKind::Star)?;
+        vec![UseItem::Glob { path: vec![] }]
+    } else if it.check(TokenKind::LeftBrace) {
         it.expect(TokenKind::LeftBrace)?;
         let sub_items = parse_use_items(it)?;
         it.expect(TokenKind::RightBrace)?;
         sub_items
     } else {
-        vec![UseItem::Simple { path: path.clone() }]
+        // the path itself
+        vec![]
     };
     let end_span = it.expect(TokenKind::Semicolon)?;
-    let use_stmt = if items.len() == 1 && matches!(items[0], UseItem::Simple { .. }) {
-        Stmt::Use(path, vec![])
-    } else {
-        Stmt::Use(path, items)
-    };
     let span = Span::union(&begin_span, &end_span);
-    Ok(WithSpan::new(use_stmt, span))
+    Ok(WithSpan::new(Stmt::Use(path, items), span))
 }
 
 fn parse_use_path(it: &mut Parser) -> Result<Vec<WithSpan<String>>, ()> {
@@ -216,7 +224,10 @@ fn parse_use_items(it: &mut Parser) -> Result<Vec<UseItem>, ()> {
             break;
         }
         let item_path = parse_use_path(it)?;
-        if it.check(TokenKind::LeftBrace) {
+        if it.check(TokenKind::Star) {
+            it.expect(TokenKind::Star)?;
+            items.push(UseItem::Glob { path: item_path });
+        } else if it.check(TokenKind::LeftBrace) {
             it.expect(TokenKind::LeftBrace)?;
             let sub_items = parse_use_items(it)?;
             it.expect(TokenKind::RightBrace)?;
@@ -240,8 +251,12 @@ fn parse_function_declaration(it: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
     } else {
         Visibility::Private
     };
+    let stateful = it.optionally(TokenKind::Stateful)?;
 
     let name = expect_identifier(it)?;
+    if it.check(TokenKind::Less) {
+        skip_type_arguments(it)?;
+    }
 
     if !it.check(TokenKind::ColonColon) {
         let token = it.advance();
@@ -252,11 +267,15 @@ fn parse_function_declaration(it: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
     it.expect(TokenKind::LeftParen)?;
     let params = parse_params(it)?;
     it.expect(TokenKind::RightParen)?;
+    if it.optionally(TokenKind::Arrow)? {
+        skip_type(it)?;
+    }
 
     let block_stmt = parse_block_statement(it)?;
 
     let function = Function {
         visibility,
+        stateful,
         name: Some(name.clone()),
         params,
         body: Box::new(block_stmt.clone()),
@@ -334,11 +353,77 @@ pub fn parse_params(it: &mut Parser) -> Result<Vec<Param>, ()> {
 
 fn parse_param(it: &mut Parser) -> Result<Param, ()> {
     let name = expect_identifier(it)?;
-    //it.expect(TokenKind::Colon)?;
-    //let param_type = expect_identifier(it)?;
+    if it.optionally(TokenKind::Colon)? {
+        skip_type(it)?;
+    }
     Ok(Param { name })
 }
 
+// types are not checked yet: a signature may carry them, and they are parsed
+// then dropped. A type is a name with optional `<...>` arguments, a tuple
+// `(A, B)`, a list `[A]`, a sized list `[|3; A|]` or a map `{K: V}`, and any
+// of them followed by `-> T` is a function type
+fn skip_type(it: &mut Parser) -> Result<(), ()> {
+    match it.peek() {
+        TokenKind::Identifier => {
+            expect_identifier(it)?;
+            if it.check(TokenKind::Less) {
+                skip_type_arguments(it)?;
+            }
+        }
+        TokenKind::LeftParen => {
+            it.expect(TokenKind::LeftParen)?;
+            if !it.check(TokenKind::RightParen) {
+                skip_types(it)?;
+            }
+            it.expect(TokenKind::RightParen)?;
+        }
+        TokenKind::LeftBracket => {
+            it.expect(TokenKind::LeftBracket)?;
+            if it.optionally(TokenKind::Pipe)? {
+                it.expect(TokenKind::Number)?;
+                it.expect(TokenKind::Semicolon)?;
+                skip_type(it)?;
+                it.expect(TokenKind::Pipe)?;
+            } else {
+                skip_type(it)?;
+            }
+            it.expect(TokenKind::RightBracket)?;
+        }
+        TokenKind::LeftBrace => {
+            it.expect(TokenKind::LeftBrace)?;
+            skip_type(it)?;
+            it.expect(TokenKind::Colon)?;
+            skip_type(it)?;
+            it.expect(TokenKind::RightBrace)?;
+        }
+        _ => {
+            let token = it.advance();
+            it.error(&format!("Expected a type got {}", token.value), token.span);
+            return Err(());
+        }
+    }
+    if it.optionally(TokenKind::Arrow)? {
+        skip_type(it)?;
+    }
+    Ok(())
+}
+
+fn skip_types(it: &mut Parser) -> Result<(), ()> {
+    skip_type(it)?;
+    while it.optionally(TokenKind::Comma)? {
+        skip_type(it)?;
+    }
+    Ok(())
+}
+
+fn skip_type_arguments(it: &mut Parser) -> Result<(), ()> {
+    it.expect(TokenKind::Less)?;
+    skip_types(it)?;
+    it.expect(TokenKind::Greater)?;
+    Ok(())
+}
+
 fn parse_expr_statement(it: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
     let expr = parse_expr(it)?;
     let end_span = it.expect(TokenKind::Semicolon)?;
@@ -411,6 +496,34 @@ fn parse_thread_statement(it: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
     Ok(WithSpan::new(stmt, span))
 }
 
+fn parse_receive_statement(it: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
+    let receive_span = it.expect(TokenKind::Receive)?;
+    let name = expect_identifier(it)?;
+    let (statements, mut end_span) = parse_braced_statements(it)?;
+    let after = if it.check(TokenKind::After) {
+        it.expect(TokenKind::After)?;
+        let timeout = parse_expr(it)?;
+        let (after_statements, after_end_span) = parse_braced_statements(it)?;
+        end_span = after_end_span;
+        Some((Box::new(timeout), after_statements))
+    } else {
+        None
+    };
+    let stmt = Stmt::Receive(name, statements, after);
+    let span = Span::union(&receive_span, end_span);
+    Ok(WithSpan::new(stmt, span))
+}
+
+// the statements between braces, with the closing one
+fn parse_braced_statements<'a>(it: &mut Parser<'a>) -> Result<(Vec<WithSpan<Stmt>>, &'a WithSpan<Token>), ()> {
+    it.expect(TokenKind::LeftBrace)?;
+    let mut statements = Vec::new();
+    while !it.check(TokenKind::RightBrace) && !it.check(TokenKind::Eof) {
+        statements.push(parse_statement(it)?);
+    }
+    let end_span = it.expect(TokenKind::RightBrace)?;
+    Ok((statements, end_span))
+}
 
 fn parse_let_statement(it: &mut Parser) -> Result<WithSpan<Stmt>, ()> {
     let begin_span = it.expect(TokenKind::Let)?;
@@ -459,20 +572,34 @@ mod tests {
     use core::ops::Range;
     use alloc::vec;
     use alloc::string::String;
-    use crate::position::Diagnostic;
+    use crate::frontend::position::Diagnostic;
     //use crate::alloc::string::ToString;
 
     use super::super::tokenizer::*;
     use super::*;
     fn parse_str(data: &str) -> Result<Vec<WithSpan<Stmt>>, Vec<Diagnostic>> {
         let tokens = tokenize_with_context(data);
-        let mut parser = crate::parser::Parser::new(&tokens);
+        let mut parser = crate::frontend::parser::Parser::new(&tokens);
         match parse(&mut parser) {
             Ok(ast) => Ok(ast),
             Err(_) => Err(parser.diagnostics().to_vec()),
         }
     }
 
+    // the statement tests below sit outside any module declaration
+    fn parse_statements(data: &str) -> Result<Vec<WithSpan<Stmt>>, Vec<Diagnostic>> {
+        let tokens = tokenize_with_context(data);
+        let mut parser = crate::frontend::parser::Parser::new(&tokens);
+        let mut statements = Vec::new();
+        while !parser.is_eof() {
+            match parse_statement(&mut parser) {
+                Ok(statement) => statements.push(statement),
+                Err(_) => return Err(parser.diagnostics().to_vec()),
+            }
+        }
+        Ok(statements)
+    }
+
     pub fn ws<T>(value: T, range: Range<u32>) -> WithSpan<T> {
         unsafe { WithSpan::new_unchecked(value, range.start, range.end) }
     }
@@ -492,9 +619,9 @@ mod tests {
             parse_str("url this : \"that\";"),
             Ok(vec![
                 ws(Stmt::Url(
-                    Box::new(ws("this".into(), 0..4)),
-                    HierarchicalName::new(vec![ws("that".into(), 4..8)]),
-                ), 0..23),
+                    Box::new(ws("this".into(), 4..8)),
+                    vec![ws(UrlComponent::String(ws("that".into(), 11..17)), 11..17)],
+                ), 0..18),
             ])
         );
     }
@@ -505,23 +632,40 @@ mod tests {
             parse_str("url this : that::that2;"),
             Ok(vec![
                 ws(Stmt::Url(
-                    Box::new(ws("this".into(), 0..4)),
-                    HierarchicalName::new(vec![ws("that".into(), 4..8), ws("that2".into(), 8..12)]),
+                    Box::new(ws("this".into(), 4..8)),
+                    vec![
+                        ws(UrlComponent::Identifier(ws("that".into(), 11..15)), 11..15),
+                        ws(UrlComponent::Identifier(ws("that2".into(), 17..22)), 17..22),
+                    ],
                 ), 0..23),
             ])
         );
     }
 
+    #[test]
+    fn test_url_stmt_public_key() {
+        let key = "spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp06";
+        let stmts = parse_str(&alloc::format!("url pk : {}::app;", key)).expect("a url");
+        match &stmts[0].value {
+            Stmt::Url(_, components) => assert!(matches!(&components[0].value, UrlComponent::PublicKey(k) if k.value == key)),
+            stmt => panic!("{:?}", stmt),
+        }
+        assert_errs(
+            "url pk : spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp07;",
+            &["invalid public key: the checksum does not match, the key is mistyped"],
+        );
+    }
+
     #[test]
     fn test_expr_stmt() {
         assert_eq!(
-            parse_str("nil;"),
+            parse_statements("nil;"),
             Ok(vec![
                 ws(Stmt::Expression(Box::new(ws(Expr::Nil, 0..3))), 0..4)
             ])
         );
         assert_eq!(
-            parse_str("nil;nil;"),
+            parse_statements("nil;nil;"),
             Ok(vec![
                 ws(Stmt::Expression(Box::new(ws(Expr::Nil, 0..3))), 0..4),
                 ws(Stmt::Expression(Box::new(ws(Expr::Nil, 4..7))), 4..8),
@@ -532,7 +676,7 @@ mod tests {
     #[test]
     fn test_print_stmt() {
         assert_eq!(
-            parse_str("print nil;"),
+            parse_statements("print nil;"),
             Ok(vec![
                 ws(Stmt::Print(Box::new(ws(Expr::Nil, 6..9))), 0..10),
             ])
@@ -546,13 +690,13 @@ mod tests {
     #[test]
     fn test_var_decl() {
         assert_eq!(
-            parse_str("let beverage;"),
+            parse_statements("let beverage;"),
             Ok(vec![
                 ws(Stmt::Let(make_span_string("beverage", 4), None), 0..13),
             ])
         );
         assert_eq!(
-            parse_str("let beverage = nil;"),
+            parse_statements("let beverage = nil;"),
             Ok(vec![
                 ws(Stmt::Let(
                     make_span_string("beverage", 4),
@@ -563,7 +707,7 @@ mod tests {
 
         unsafe {
             assert_eq!(
-                parse_str("let beverage = x = nil;"),
+                parse_statements("let beverage = x = nil;"),
                 Ok(vec![
                     ws(Stmt::Let(
                         make_span_string("beverage", 4),
@@ -576,13 +720,14 @@ mod tests {
             );
         }
 
-        assert_errs("if (nil) let beverage = nil;", &["Unexpected 'var'"]);
+        let diagnostics = parse_statements("if (nil) let = nil;").unwrap_err();
+        assert_eq!(diagnostics[0].message, "Expected identifier got '='");
     }
 
     #[test]
     fn test_if_stmt() {
         assert_eq!(
-            parse_str("if(nil) print nil;"),
+            parse_statements("if(nil) print nil;"),
             Ok(vec![
                 ws(Stmt::If(
                     Box::new(ws(Expr::Nil, 3..6)),
@@ -592,7 +737,7 @@ mod tests {
             ])
         );
         assert_eq!(
-            parse_str("if(nil) print nil; else print false;"),
+            parse_statements("if(nil) print nil; else print false;"),
             Ok(vec![
                 ws(Stmt::If(
                     Box::new(ws(Expr::Nil, 3..6)),
@@ -605,13 +750,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_receive_stmt() {
+        assert_eq!(
+            parse_statements("receive m {}"),
+            Ok(vec![ws(Stmt::Receive(ws("m".into(), 8..9), vec![], None), 0..12)])
+        );
+        assert_eq!(
+            parse_statements("receive m { m; } after 10 { nil; }"),
+            Ok(vec![
+                ws(Stmt::Receive(
+                    ws("m".into(), 8..9),
+                    vec![ws(Stmt::Expression(Box::new(ws(Expr::Variable(ws("m".into(), 12..13)), 12..13))), 12..14)],
+                    Some((
+                        Box::new(ws(Expr::Number(10.0), 23..25)),
+                        vec![ws(Stmt::Expression(Box::new(ws(Expr::Nil, 28..31))), 28..32)],
+                    )),
+                ), 0..34),
+            ])
+        );
+        assert!(parse_statements("receive m { m; } after { nil; }").is_err());
+    }
+
     #[test]
     fn test_block_stmt() {
-        assert_eq!(parse_str("{}"), Ok(vec![
+        assert_eq!(parse_statements("{}"), Ok(vec![
             ws(Stmt::Block(vec![]), 0..2),
         ]));
         assert_eq!(
-            parse_str("{nil;}"),
+            parse_statements("{nil;}"),
             Ok(vec![
                 ws(Stmt::Block(vec![
                     ws(Stmt::Expression(Box::new(
@@ -621,7 +788,7 @@ mod tests {
             ])
         );
         assert_eq!(
-            parse_str("{nil;nil;}"),
+            parse_statements("{nil;nil;}"),
             Ok(vec![
                 ws(Stmt::Block(vec![
                     ws(Stmt::Expression(Box::new(ws(Expr::Nil, 1..4))), 1..5),
@@ -630,6 +797,27 @@ mod tests {
             ])
         );
     }
+
+    #[test]
+    fn test_type_annotations_are_ignored() {
+        let typed = "corporal app::Typed {
+            name :: () -> {string: Hi} { nil; }
+            name :: (a: () -> Hi) { nil; }
+            name :: (a: ([B]) -> [Hi], b: (C) -> [|3; Hi|]) { nil; }
+            name<A> :: (a: (A, A) -> (A) -> bool, b: (A, A) -> (A) -> Hi) -> Hi { nil; }
+            name<A, B, C> :: (a: (A, A) -> (C) -> bool,
+                              b: (A, B) -> (C) -> bool) -> (A) -> bool { nil; }
+        }";
+        let ast = parse_str(typed).unwrap();
+        let Stmt::Module(module) = &ast[0].value else { panic!("not a module") };
+        let params: Vec<Vec<&str>> = module.stmts().iter().map(|stmt| match &stmt.value {
+            Stmt::Function(function) => function.params.iter().map(|param| param.name.value.as_str()).collect(),
+            _ => panic!("not a function"),
+        }).collect();
+        assert_eq!(params, vec![vec![], vec!["a"], vec!["a", "b"], vec!["a", "b"], vec!["a", "b"]]);
+
+        assert_errs("corporal app::Typed { name :: (a: ) { nil; } }", &["Expected a type got ')'"]);
+    }
 /*
     #[test]
     fn test_use_stmt() {
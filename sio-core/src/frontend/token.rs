@@ -1,5 +1,6 @@
 use alloc::fmt::Display;
 use alloc::string::String;
+use crate::public_key::PublicKeyError;
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Token {
@@ -51,8 +52,11 @@ pub enum Token {
     Majors,
     Corporals,
     Pub,
+    Stateful,
     Let,
     Thread,
+    Receive,
+    After,
     Url,
     Use,
     If,
@@ -63,6 +67,7 @@ pub enum Token {
     // Other.
     Eof,
     UnterminatedString,
+    InvalidPublicKey(PublicKeyError),
     Unknown(char),
 }
 
@@ -116,8 +121,11 @@ pub enum TokenKind {
     Majors,
     Corporals,
     Pub,
+    Stateful,
     Let,
     Thread,
+    Receive,
+    After,
     Use,
     Url,
     If,
@@ -128,6 +136,7 @@ pub enum TokenKind {
     // Other.
     Eof,
     UnterminatedString,
+    InvalidPublicKey,
     Unknown,
 }
 
@@ -189,8 +198,11 @@ impl From<&Token> for TokenKind {
             Token::Majors=> TokenKind::Majors,
             Token::Corporals=> TokenKind::Corporals,
             Token::Pub => TokenKind::Pub,
+            Token::Stateful => TokenKind::Stateful,
             Token::Let => TokenKind::Let,
             Token::Thread => TokenKind::Thread,
+            Token::Receive => TokenKind::Receive,
+            Token::After => TokenKind::After,
             Token::Use => TokenKind::Use,
             Token::If => TokenKind::If,
             Token::Else => TokenKind::Else,
@@ -198,6 +210,7 @@ impl From<&Token> for TokenKind {
             Token::Fun => TokenKind::Fun,
             Token::Eof => TokenKind::Eof,
             Token::UnterminatedString => TokenKind::UnterminatedString,
+            Token::InvalidPublicKey(_) => TokenKind::InvalidPublicKey,
             Token::Unknown(_) => TokenKind::Unknown,
         }
     }
@@ -249,14 +262,18 @@ impl Display for TokenKind {
             TokenKind::Majors=> "'majors'",
             TokenKind::Corporals => "'corporals'",
             TokenKind::Pub => "'pub'",
+            TokenKind::Stateful => "'stateful'",
             TokenKind::Let => "'let'",
             TokenKind::Thread => "'thread'",
+            TokenKind::Receive => "'receive'",
+            TokenKind::After => "'after'",
             TokenKind::If => "'if'",
             TokenKind::Else => "'else'",
             TokenKind::Print => "'print'",
             TokenKind::Fun => "'fn'",
             TokenKind::Eof => "<EOF>",
             TokenKind::UnterminatedString => "<Unterminated String>",
+            TokenKind::InvalidPublicKey => "<Invalid Public Key>",
             TokenKind::Unknown => "<Unknown>",
         })
     }
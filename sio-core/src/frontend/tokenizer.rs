@@ -1,5 +1,6 @@
 use super::token::Token;
 use crate::frontend::position::*;
+use crate::public_key::PublicKey;
 use core::iter::Peekable;
 use alloc::str;
 use alloc::string::String;
@@ -119,7 +120,13 @@ impl<'a> Lexer<'a> {
             '-' => Some(self.either('>', Token::Arrow, Token::Minus)),
             x if x.is_numeric() => self.number(x),
             x if x.is_ascii_alphabetic() || x == '_' => self.identifier(x),
+            '!' => Some(self.either('=', Token::BangEqual, Token::Bang)),
+            '<' => Some(self.either('=', Token::LessEqual, Token::Less)),
+            '>' => Some(self.either('=', Token::GreaterEqual, Token::Greater)),
             ',' => Some(Token::Comma),
+            '.' => Some(Token::Dot),
+            '+' => Some(Token::Plus),
+            '*' => Some(Token::Star),
             '[' => Some(Token::LeftBracket),
             ']' => Some(Token::RightBracket),
             '{' => Some(Token::LeftBrace),
@@ -183,13 +190,19 @@ impl<'a> Lexer<'a> {
         keywords.insert("majors", Token::Majors);
         keywords.insert("corporals", Token::Corporals);
         keywords.insert("pub", Token::Pub);
+        keywords.insert("stateful", Token::Stateful);
         keywords.insert("let", Token::Let);
         keywords.insert("thread", Token::Thread);
+        keywords.insert("receive", Token::Receive);
+        keywords.insert("after", Token::After);
         keywords.insert("if", Token::If);
         keywords.insert("else", Token::Else);
         keywords.insert("true", Token::True);
         keywords.insert("false", Token::False);
         keywords.insert("print", Token::Print);
+        keywords.insert("nil", Token::Nil);
+        keywords.insert("and", Token::And);
+        keywords.insert("or", Token::Or);
         match keywords.get(identifier) {
             None => None,
             Some(token) => Some(token.clone()),
@@ -205,12 +218,13 @@ impl<'a> Lexer<'a> {
             .into_iter()
             .collect();
         identifier.push_str(rest.as_str());
-        if identifier.starts_with("spub1") {
-            if identifier.len() == 69 {
-                if !identifier.contains('_') {
-                    return Some(Token::PublicKey(identifier));
-                }
-            }
+        // a public key is written in its canonical form, which the lexer
+        // checks so that a mistyped one is told right where it is
+        if identifier.to_ascii_lowercase().starts_with("spub1") && !identifier.contains('_') {
+            return Some(match PublicKey::decode(&identifier) {
+                Ok(key) => Token::PublicKey(key.encode()),
+                Err(error) => Token::InvalidPublicKey(error),
+            });
         }
         match self.keyword(&identifier) {
             None => Some(Token::Identifier(identifier)),
@@ -247,6 +261,7 @@ pub fn tokenize_with_context(buf: &str) -> Vec<WithSpan<Token>> {
 #[cfg(test)]
 mod tests {
     use super::Token;
+    use crate::public_key::{PublicKey, PublicKeyError};
     use alloc::vec::Vec;
     use alloc::vec;
     use crate::alloc::string::ToString;
@@ -261,6 +276,13 @@ mod tests {
     #[test]
     fn test_errors() {
         assert_eq!(tokenize("\"test"), vec![Token::UnterminatedString]);
+        assert_eq!(tokenize("spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp07"),
+            vec![Token::InvalidPublicKey(PublicKeyError::Checksum)]);
+        assert_eq!(tokenize("spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp"),
+            vec![Token::InvalidPublicKey(PublicKeyError::Length(61))]);
+        let legacy = "spub179f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03aba";
+        assert_eq!(tokenize(legacy),
+            vec![Token::InvalidPublicKey(PublicKeyError::Legacy(PublicKey::from_legacy(legacy).unwrap()))]);
         assert_eq!(tokenize("spub1_9f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03aba"),
             vec![Token::Identifier("spub1_9f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03aba".to_string())]);
     }
@@ -278,20 +300,29 @@ mod tests {
                   Token::String("3".to_string()),
                   Token::RightBracket]);
         assert_eq!(tokenize("//test"), vec![]);
+        assert_eq!(tokenize("B.x() + 1.5 <= !y"),
+            vec![Token::Identifier("B".to_string()), Token::Dot, Token::Identifier("x".to_string()),
+                 Token::LeftParen, Token::RightParen, Token::Plus, Token::Number(1.5),
+                 Token::LessEqual, Token::Bang, Token::Identifier("y".to_string())]);
+        assert_eq!(tokenize("pub stateful f"),
+            vec![Token::Pub, Token::Stateful, Token::Identifier("f".to_string())]);
+        assert_eq!(tokenize("nil and x or y * 2"),
+            vec![Token::Nil, Token::And, Token::Identifier("x".to_string()), Token::Or,
+                 Token::Identifier("y".to_string()), Token::Star, Token::Number(2.0)]);
         assert_eq!(
             tokenize("\"test\""),
             vec![Token::String("test".to_string())]
         );
         assert_eq!(tokenize("["), vec![Token::LeftBracket]);
         assert_eq!(tokenize("]"), vec![Token::RightBracket]);
-        assert_eq!(tokenize("spub179f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03aba"),
-            vec![Token::PublicKey("spub179f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03aba".to_string())]);
-        assert_eq!(tokenize("url pk0 : spub179f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03aba;"),
+        assert_eq!(tokenize("spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp06"),
+            vec![Token::PublicKey("spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp06".to_string())]);
+        assert_eq!(tokenize("url pk0 : spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp06;"),
             vec![
                 Token::Url,
                 Token::Identifier("pk0".to_string()),
                 Token::Colon,
-                Token::PublicKey("spub179f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03aba".to_string()),
+                Token::PublicKey("spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp06".to_string()),
                 Token::Semicolon
             ]
         );
@@ -323,7 +354,7 @@ mod tests {
             "brigadier brig::Brigadier {
                 majors {
                     app1::Major1,
-                    spub179f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03aba::\"app3\"::Major3,
+                    spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp06::\"app3\"::Major3,
                     //app1::Commented,
                     app2,
                 }
@@ -340,7 +371,7 @@ mod tests {
                 Token::ColonColon,
                 Token::Identifier("Major1".to_string()),
                 Token::Comma,
-                Token::PublicKey("spub179f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03aba".to_string()),
+                Token::PublicKey("spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp06".to_string()),
                 Token::ColonColon,
                 Token::String("app3".to_string()),
                 Token::ColonColon,
@@ -356,7 +387,7 @@ mod tests {
             "major maj::Major {
                 corporals {
                     app1::Corporal1,
-                    spub179f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03aba::\"app3\"::Corporal2,
+                    spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp06::\"app3\"::Corporal2,
                     //app1::Commented,
                     app3,
                 }
@@ -373,7 +404,7 @@ mod tests {
                 Token::ColonColon,
                 Token::Identifier("Corporal1".to_string()),
                 Token::Comma,
-                Token::PublicKey("spub179f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03aba".to_string()),
+                Token::PublicKey("spub108ms3sj6y0knvassltxpgq66m3a6fvdl4yjjaa2udsj0rwds82aqtudp06".to_string()),
                 Token::ColonColon,
                 Token::String("app3".to_string()),
                 Token::ColonColon,
@@ -1,59 +1,351 @@
-use crate::frontend::hierarchical_name::HierarchicalName;
-use crate::frontend::position::{WithSpan};
 use alloc::format;
 use alloc::string::String;
-use crate::alloc::string::ToString;
 use alloc::vec::Vec;
+use werbolg_core::{Ident, Namespace};
+use crate::frontend::ast::{Ast, Module, Stmt, UrlComponent, UseItem};
+use crate::frontend::position::{Diagnostic, Span, WithSpan};
+
+// `url app : key::"src"::name;` makes `app` an alias for the path it resolves
+// to, within the file or the module it is declared in. The aliases of a scope
+// may refer to each other in any order, as long as they do not go round in a
+// circle, and to those of the enclosing scopes. In a url of several
+// components every identifier is an alias, strings and public keys being the
+// literal components, while a url of a single identifier not naming an alias
+// is the literal name. In a module name only the first component can be an
+// alias, the others naming the module, and the same goes for the paths of
+// `use` statements.
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    Unresolved,
+    Resolving,
+    Resolved(Vec<String>),
+    Failed,
+}
+
+#[derive(Debug)]
+struct Alias {
+    name: WithSpan<String>,
+    url: Vec<WithSpan<UrlComponent>>,
+    state: State,
+}
+
 #[derive(Debug)]
 pub struct UrlResolver {
-    hierarchical_name: HierarchicalName,
+    // innermost scope last
+    scopes: Vec<Vec<Alias>>,
+    // the aliases being resolved, for the message of a cycle
+    resolving: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// A name a `use` brings in, with its path resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    /// the module, or the module and one of its functions
+    pub path: Vec<String>,
+    /// `use path::*`, every public function of the module
+    pub glob: bool,
+    pub span: Span,
+}
+
+/// The urls of a file, resolved.
+#[derive(Debug, Clone, Default)]
+pub struct Urls {
+    /// the fully qualified url of each module the file declares, in order
+    pub modules: Vec<Vec<String>>,
+    /// what each module the file declares imports, in order
+    pub imports: Vec<Vec<Import>>,
+}
+
+pub fn resolve(ast: &Ast) -> Result<Urls, Vec<Diagnostic>> {
+    let mut resolver = UrlResolver::new();
+    resolver.declare(ast);
+    let mut urls = Urls::default();
+    for stmt in ast {
+        if let Stmt::Module(module) = &stmt.value {
+            let path = resolver.module_path(module);
+            resolver.push_scope();
+            resolver.declare(module.stmts());
+            let mut imports = Vec::new();
+            for stmt in module.stmts() {
+                if let Stmt::Use(prefix, items) = &stmt.value {
+                    imports.extend(resolver.imports(prefix, items, stmt.span));
+                }
+            }
+            urls.imports.push(imports);
+            resolver.pop_scope();
+            if let Some(path) = path {
+                urls.modules.push(path);
+            }
+        }
+    }
+    if resolver.diagnostics.is_empty() {
+        Ok(urls)
+    } else {
+        Err(resolver.diagnostics)
+    }
+}
+
+pub fn namespace(path: &[String]) -> Namespace {
+    path.iter()
+        .fold(Namespace::root(), |namespace, component| namespace.append(Ident::from(component.as_str())))
+}
+
+// the paths of the items of a `use`, and whether each one is a glob
+fn flatten(prefix: &[WithSpan<String>], items: &[UseItem], paths: &mut Vec<(Vec<WithSpan<String>>, bool)>) {
+    let joined = |path: &[WithSpan<String>]| prefix.iter().chain(path).cloned().collect::<Vec<_>>();
+    for item in items {
+        match item {
+            UseItem::Simple { path } => paths.push((joined(path), false)),
+            UseItem::Nested { path, items } => flatten(&joined(path), items, paths),
+            UseItem::Glob { path } => paths.push((joined(path), true)),
+        }
+    }
 }
 
 impl UrlResolver {
     pub fn new() -> Self {
-        UrlResolver {
-            hierarchical_name: HierarchicalName::new(),
-        }
+        UrlResolver { scopes: alloc::vec![Vec::new()], resolving: Vec::new(), diagnostics: Vec::new() }
     }
 
     pub fn push_scope(&mut self) {
-        self.hierarchical_name.push_scope();
+        self.scopes.push(Vec::new());
     }
 
     pub fn pop_scope(&mut self) {
-        self.hierarchical_name.pop_scope();
+        self.scopes.pop();
     }
 
-    pub fn resolve_url(&mut self, key: &WithSpan<String>, url: &WithSpan<String>) {
-        let path = WithSpan::new(url.value.clone(), url.span);
-        self.hierarchical_name.add_path(key, &path);
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
     }
 
-    pub fn extract_path_from_url(&self, url: &str) -> String {
-        url.to_string()
+    fn error(&mut self, message: String, name: &WithSpan<String>) {
+        self.diagnostics.push(Diagnostic { message, span: name.span });
     }
 
-    pub fn get_path_for_key(&self, key: &WithSpan<String>) -> Option<WithSpan<String>> {
-        let iter = self.hierarchical_name.into_iter().collect::<Vec<_>>(); // Convert into Vec
-        for (k, v) in iter.into_iter().rev() { // Convert back to iterator and use rev
-            if k == key {
-                return Some(v.clone());
+    /// Adds the aliases `stmts` declare to the innermost scope, then resolves
+    /// them, so that the unused ones are checked as well.
+    pub fn declare(&mut self, stmts: &[WithSpan<Stmt>]) {
+        for stmt in stmts {
+            if let Stmt::Url(name, url) = &stmt.value {
+                self.add_alias(name, url);
             }
         }
-        None
+        let scope = self.scopes.len() - 1;
+        for index in 0..self.scopes[scope].len() {
+            let name = self.scopes[scope][index].name.clone();
+            let _ = self.resolve_alias(scope, &name);
+        }
+    }
+
+    fn add_alias(&mut self, name: &WithSpan<String>, url: &[WithSpan<UrlComponent>]) {
+        let (current, outer) = self.scopes.split_last().expect("the file scope");
+        let declared = current.iter().any(|alias| alias.name.value == name.value);
+        let shadowed = outer.iter().flatten().any(|alias| alias.name.value == name.value);
+        if declared {
+            self.error(format!("url alias {} is already declared", name.value), name);
+            return;
+        }
+        if shadowed {
+            self.error(format!("url alias {} shadows the one of the enclosing scope", name.value), name);
+            return;
+        }
+        let alias = Alias { name: name.clone(), url: url.to_vec(), state: State::Unresolved };
+        self.scopes.last_mut().expect("the file scope").push(alias);
     }
 
-    pub fn flatten_source(&self, source_code: &str) -> String {
-        let mut result = source_code.to_string();
-        let iter = self.hierarchical_name.into_iter().collect::<Vec<_>>(); // Convert into Vec
-    
-        for (key, value) in iter.into_iter() { // Convert back to iterator
-            if let Some(composite_value) = self.hierarchical_name.resolve_composite_key(value) {
-                result = result.replace(&format!("{}::", key), &format!("{}::", composite_value));
-            } else {
-                result = result.replace(&format!("{}::", key), &format!("{}::", value));
+    // the innermost scope, up to `scope`, declaring `name`, and where
+    fn find(&self, scope: usize, name: &str) -> Option<(usize, usize)> {
+        (0..=scope)
+            .rev()
+            .find_map(|s| Some((s, self.scopes[s].iter().position(|alias| alias.name.value == name)?)))
+    }
+
+    // None when no alias is called `name`, Some(Err) when it is one that
+    // could not be resolved, which has been told already
+    fn resolve_alias(&mut self, scope: usize, name: &WithSpan<String>) -> Option<Result<Vec<String>, ()>> {
+        let (s, index) = self.find(scope, &name.value)?;
+        match self.scopes[s][index].state.clone() {
+            State::Resolved(path) => Some(Ok(path)),
+            State::Failed => Some(Err(())),
+            State::Resolving => {
+                let start = self.resolving.iter().position(|n| *n == name.value).unwrap_or(0);
+                let mut cycle = self.resolving[start..].join(" -> ");
+                cycle.push_str(" -> ");
+                cycle.push_str(&name.value);
+                let declared = self.scopes[s][index].name.clone();
+                self.error(format!("url alias {} refers to itself: {}", name.value, cycle), &declared);
+                self.scopes[s][index].state = State::Failed;
+                Some(Err(()))
+            }
+            State::Unresolved => {
+                self.scopes[s][index].state = State::Resolving;
+                self.resolving.push(name.value.clone());
+                let url = self.scopes[s][index].url.clone();
+                let resolved = self.resolve_url(s, &url);
+                self.resolving.pop();
+                // a cycle through this alias has marked it failed already
+                if self.scopes[s][index].state == State::Resolving {
+                    self.scopes[s][index].state = match &resolved {
+                        Ok(path) => State::Resolved(path.clone()),
+                        Err(()) => State::Failed,
+                    };
+                }
+                Some(if self.scopes[s][index].state == State::Failed { Err(()) } else { resolved })
+            }
+        }
+    }
+
+    fn resolve_url(&mut self, scope: usize, url: &[WithSpan<UrlComponent>]) -> Result<Vec<String>, ()> {
+        if let [WithSpan { value: UrlComponent::Identifier(name), .. }] = url {
+            return match self.resolve_alias(scope, name) {
+                Some(path) => path,
+                None => Ok(alloc::vec![name.value.clone()]),
+            };
+        }
+        let mut path = Vec::new();
+        let mut failed = false;
+        for component in url {
+            match &component.value {
+                UrlComponent::Identifier(name) => match self.resolve_alias(scope, name) {
+                    Some(Ok(resolved)) => path.extend(resolved),
+                    Some(Err(())) => failed = true,
+                    None => {
+                        self.error(format!("url alias {} is not declared", name.value), name);
+                        failed = true;
+                    }
+                },
+                UrlComponent::String(literal) | UrlComponent::PublicKey(literal) => path.push(literal.value.clone()),
             }
         }
-        result
+        if failed {
+            Err(())
+        } else {
+            Ok(path)
+        }
+    }
+
+    /// The imports of `use prefix { items };`, in the innermost scope.
+    pub fn imports(&mut self, prefix: &[WithSpan<String>], items: &[UseItem], span: Span) -> Vec<Import> {
+        let mut paths = Vec::new();
+        if items.is_empty() {
+            paths.push((prefix.to_vec(), false));
+        } else {
+            flatten(prefix, items, &mut paths);
+        }
+        let scope = self.scopes.len() - 1;
+        let mut imports = Vec::new();
+        for (mut path, glob) in paths {
+            // `a::b::{self}` is `a::b`
+            if !glob && path.len() > 1 && path.last().is_some_and(|component| component.value == "self") {
+                path.pop();
+            }
+            let Some((first, rest)) = path.split_first() else {
+                self.diagnostics.push(Diagnostic { message: "nothing to import".into(), span });
+                continue;
+            };
+            let span = Span::union(first, path.last().unwrap_or(first));
+            let mut resolved = match self.resolve_alias(scope, first) {
+                Some(Ok(resolved)) => resolved,
+                Some(Err(())) => continue,
+                None => alloc::vec![first.value.clone()],
+            };
+            resolved.extend(rest.iter().map(|component| component.value.clone()));
+            imports.push(Import { path: resolved, glob, span });
+        }
+        imports
+    }
+
+    /// The path `module` is declared under, None when it uses an alias that
+    /// could not be resolved.
+    pub fn module_path(&mut self, module: &Module) -> Option<Vec<String>> {
+        let scope = self.scopes.len() - 1;
+        let mut path = Vec::new();
+        for (index, component) in module.name().iter().enumerate() {
+            match &component.value {
+                UrlComponent::Identifier(name) if index == 0 => match self.resolve_alias(scope, name) {
+                    Some(resolved) => path.extend(resolved.ok()?),
+                    None => path.push(name.value.clone()),
+                },
+                UrlComponent::Identifier(literal) | UrlComponent::String(literal) | UrlComponent::PublicKey(literal) => {
+                    path.push(literal.value.clone())
+                }
+            }
+        }
+        Some(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use crate::frontend::parse;
+
+    fn paths(src: &str) -> Result<Vec<String>, Vec<String>> {
+        let ast = parse(src).map_err(|diagnostics| diagnostics.into_iter().map(|d| d.message).collect::<Vec<_>>())?;
+        let mut resolver = UrlResolver::new();
+        resolver.declare(&ast);
+        let modules = ast
+            .iter()
+            .filter_map(|stmt| match &stmt.value {
+                Stmt::Module(module) => resolver.module_path(module).map(|path| path.join("::")),
+                _ => None,
+            })
+            .collect();
+        if resolver.diagnostics().is_empty() {
+            Ok(modules)
+        } else {
+            Err(resolver.diagnostics().iter().map(|d| d.message.clone()).collect())
+        }
+    }
+
+    #[test]
+    fn aliases_resolve_in_any_order() {
+        assert_eq!(
+            paths(
+                "url app : public_key::type::name;
+                url public_key : sio79f7;
+                url type : \"src\";
+                url name : app_name;
+                corporal app::Corporal { main :: () { 1; } }
+                corporal apps::Other { main :: () { 1; } }"
+            ),
+            Ok(alloc::vec!["sio79f7::src::app_name::Corporal".into(), "apps::Other".into()])
+        );
+    }
+
+    #[test]
+    fn bad_aliases_are_reported() {
+        assert_eq!(
+            paths("url app : key::\"src\"; corporal app::A { main :: () { 1; } }"),
+            Err(alloc::vec!["url alias key is not declared".into()])
+        );
+        assert_eq!(
+            paths("url a : b::\"x\"; url b : a::\"y\";"),
+            Err(alloc::vec!["url alias a refers to itself: a -> b -> a".into()])
+        );
+        assert_eq!(
+            paths("url a : \"x\"; url a : \"y\";"),
+            Err(alloc::vec!["url alias a is already declared".into()])
+        );
+    }
+
+    #[test]
+    fn module_scopes_see_the_file_aliases() {
+        let ast = parse(
+            "url root : \"r\";
+            corporal root::A {
+                url app : root::\"a\";
+                url root : \"shadow\";
+                main :: () { 1; }
+            }",
+        )
+        .expect("parses");
+        let diagnostics = super::resolve(&ast).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "url alias root shadows the one of the enclosing scope");
     }
 }
@@ -1,6 +1,10 @@
 #![no_std]
 //#![feature(error_in_core)]
 extern crate alloc;
+// the scheduler runs on smol, which needs an operating system: the pool of
+// worker threads, the system clock and TCP links use it directly
+#[cfg(feature = "std")]
+extern crate std;
 //use alloc::format;
 // use alloc::string::String;
 // use werbolg_lang_common::Source;
@@ -13,4 +17,5 @@ extern crate alloc;
 pub mod scheduler;
 pub mod frontend;
 pub mod compiler;
+pub mod public_key;
 
@@ -0,0 +1,265 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+// Public keys are written the way bech32m (BIP 350) writes data: a human
+// readable prefix, the separator `1`, then the key in groups of 5 bits and a
+// checksum of 6 groups, each group being one character of CHARSET. The
+// checksum catches any mistyping of up to 4 characters, which the
+// alphabet, lacking `1`, `b`, `i` and `o`, makes less likely to begin with.
+// A key is written in lowercase, all uppercase being accepted as well.
+
+pub const PREFIX: &str = "spub";
+pub const KEY_LENGTH: usize = 32;
+const SEPARATOR: char = '1';
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const CHECKSUM_LENGTH: usize = 6;
+const BECH32M: u32 = 0x2bc8_30a3;
+// 256 bits make 52 groups, the last one padded with 4 zero bits
+const DATA_LENGTH: usize = (KEY_LENGTH * 8 + 4) / 5;
+/// The number of characters a public key is written with.
+pub const ENCODED_LENGTH: usize = PREFIX.len() + 1 + DATA_LENGTH + CHECKSUM_LENGTH;
+
+// the forms keys were written in before, as 64 hexadecimal digits after the
+// prefix, longest prefix first
+const LEGACY_PREFIXES: [&str; 4] = ["siopub001", "s007sio", "spub1", "sio"];
+
+/// An Ed25519 public key naming a node, the root of the urls of its modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PublicKey([u8; KEY_LENGTH]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublicKeyError {
+    /// Does not start with `spub1`.
+    Prefix,
+    MixedCase,
+    /// The number of characters, which is not `ENCODED_LENGTH`.
+    Length(usize),
+    /// A character out of the alphabet, and where it is.
+    Character(usize, char),
+    Checksum,
+    /// The bits padding the last group are not zero.
+    Padding,
+    /// A key written in one of the legacy forms.
+    Legacy(PublicKey),
+}
+
+impl fmt::Display for PublicKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublicKeyError::Prefix => write!(f, "a public key starts with {}{}", PREFIX, SEPARATOR),
+            PublicKeyError::MixedCase => write!(f, "a public key is either all lowercase or all uppercase"),
+            PublicKeyError::Length(length) => {
+                write!(f, "a public key is {} characters long, not {}", ENCODED_LENGTH, length)
+            }
+            PublicKeyError::Character(position, character) => {
+                write!(f, "'{}' at {} is not a public key character", character, position)
+            }
+            PublicKeyError::Checksum => write!(f, "the checksum does not match, the key is mistyped"),
+            PublicKeyError::Padding => write!(f, "the last character does not end in zero bits"),
+            PublicKeyError::Legacy(key) => write!(f, "this form is no longer supported, write {}", key),
+        }
+    }
+}
+
+impl core::error::Error for PublicKeyError {}
+
+impl PublicKey {
+    pub fn from_bytes(bytes: [u8; KEY_LENGTH]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; KEY_LENGTH] {
+        &self.0
+    }
+
+    /// The canonical form of the key.
+    pub fn encode(&self) -> String {
+        let mut data = groups(&self.0);
+        data.extend(checksum(&data));
+        let mut encoded = String::with_capacity(ENCODED_LENGTH);
+        encoded.push_str(PREFIX);
+        encoded.push(SEPARATOR);
+        encoded.extend(data.iter().map(|&group| CHARSET[group as usize] as char));
+        encoded
+    }
+
+    /// Reads a key in its canonical form. A key in one of the legacy forms is
+    /// read as well, but given back in a `PublicKeyError::Legacy`.
+    pub fn decode(text: &str) -> Result<Self, PublicKeyError> {
+        if let Some(key) = Self::from_legacy(text) {
+            return Err(PublicKeyError::Legacy(key));
+        }
+        let lowercase = text.to_ascii_lowercase();
+        if text != lowercase && text != text.to_ascii_uppercase() {
+            return Err(PublicKeyError::MixedCase);
+        }
+        let Some(rest) = lowercase.strip_prefix(PREFIX).and_then(|rest| rest.strip_prefix(SEPARATOR)) else {
+            return Err(PublicKeyError::Prefix);
+        };
+        let length = text.chars().count();
+        if length != ENCODED_LENGTH {
+            return Err(PublicKeyError::Length(length));
+        }
+        let data = rest
+            .chars()
+            .enumerate()
+            .map(|(index, character)| match CHARSET.iter().position(|&c| c as char == character) {
+                Some(group) => Ok(group as u8),
+                None => Err(PublicKeyError::Character(PREFIX.len() + 1 + index, character)),
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        if polymod(expanded_prefix().chain(data.iter().copied())) != BECH32M {
+            return Err(PublicKeyError::Checksum);
+        }
+        let key = bytes(&data[..DATA_LENGTH]).ok_or(PublicKeyError::Padding)?;
+        Ok(Self(key))
+    }
+
+    /// Reads a key written in one of the forms used before the canonical one:
+    /// 64 hexadecimal digits after `spub1`, `sio`, `siopub001` or `s007sio`.
+    pub fn from_legacy(text: &str) -> Option<Self> {
+        let digits = LEGACY_PREFIXES
+            .iter()
+            .find_map(|prefix| text.strip_prefix(prefix))
+            .filter(|digits| digits.len() == 2 * KEY_LENGTH && digits.bytes().all(|digit| digit.is_ascii_hexdigit()))?;
+        let mut key = [0u8; KEY_LENGTH];
+        for (byte, pair) in key.iter_mut().zip(digits.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some(Self(key))
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.encode())
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = PublicKeyError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::decode(text)
+    }
+}
+
+// the key in groups of 5 bits, most significant first
+fn groups(bytes: &[u8]) -> Vec<u8> {
+    let mut groups = Vec::with_capacity(DATA_LENGTH + CHECKSUM_LENGTH);
+    let mut accumulator = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        accumulator = accumulator << 8 | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            groups.push((accumulator >> bits & 31) as u8);
+        }
+        accumulator &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        groups.push((accumulator << (5 - bits) & 31) as u8);
+    }
+    groups
+}
+
+// the inverse of `groups`, None when the padding bits are not zero
+fn bytes(groups: &[u8]) -> Option<[u8; KEY_LENGTH]> {
+    let mut key = [0u8; KEY_LENGTH];
+    let mut accumulator = 0u32;
+    let mut bits = 0;
+    let mut filled = 0;
+    for &group in groups {
+        accumulator = accumulator << 5 | group as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            key[filled] = (accumulator >> bits) as u8;
+            filled += 1;
+            accumulator &= (1 << bits) - 1;
+        }
+    }
+    (accumulator == 0).then_some(key)
+}
+
+fn expanded_prefix() -> impl Iterator<Item = u8> {
+    PREFIX
+        .bytes()
+        .map(|byte| byte >> 5)
+        .chain(core::iter::once(0))
+        .chain(PREFIX.bytes().map(|byte| byte & 31))
+}
+
+fn polymod(values: impl Iterator<Item = u8>) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x1ff_ffff) << 5 ^ value as u32;
+        for (bit, generator) in GENERATOR.iter().enumerate() {
+            if top >> bit & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn checksum(data: &[u8]) -> [u8; CHECKSUM_LENGTH] {
+    let values = expanded_prefix().chain(data.iter().copied()).chain([0; CHECKSUM_LENGTH]);
+    let polymod = polymod(values) ^ BECH32M;
+    let mut checksum = [0u8; CHECKSUM_LENGTH];
+    for (index, group) in checksum.iter_mut().enumerate() {
+        *group = (polymod >> (5 * (CHECKSUM_LENGTH - 1 - index)) & 31) as u8;
+    }
+    checksum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    const LEGACY: &str = "79f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03abd";
+
+    fn key() -> PublicKey {
+        PublicKey::from_legacy(&alloc::format!("sio{}", LEGACY)).unwrap()
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        let encoded = key().encode();
+        assert_eq!(encoded.len(), ENCODED_LENGTH);
+        assert!(encoded.starts_with("spub1"));
+        assert_eq!(PublicKey::decode(&encoded), Ok(key()));
+        assert_eq!(PublicKey::decode(&encoded.to_ascii_uppercase()), Ok(key()));
+        assert_eq!(PublicKey::from_bytes([0; KEY_LENGTH]).to_string().parse(), Ok(PublicKey::from_bytes([0; KEY_LENGTH])));
+    }
+
+    #[test]
+    fn mistakes_are_told_apart() {
+        let encoded = key().encode();
+        let mut mistyped = encoded.clone().into_bytes();
+        mistyped[20] = if mistyped[20] == b'q' { b'p' } else { b'q' };
+        let mistyped = String::from_utf8(mistyped).unwrap();
+        assert_eq!(PublicKey::decode(&mistyped), Err(PublicKeyError::Checksum));
+        assert_eq!(PublicKey::decode(&encoded[..60]), Err(PublicKeyError::Length(60)));
+        let misspelt = alloc::format!("{}b{}", &encoded[..10], &encoded[11..]);
+        assert_eq!(PublicKey::decode(&misspelt), Err(PublicKeyError::Character(10, 'b')));
+        assert_eq!(PublicKey::decode(&encoded.replacen("spub1", "SPUB1", 1)), Err(PublicKeyError::MixedCase));
+        assert_eq!(PublicKey::decode("spu1qqqq"), Err(PublicKeyError::Prefix));
+    }
+
+    #[test]
+    fn legacy_forms_are_recognised() {
+        for prefix in LEGACY_PREFIXES {
+            let legacy = alloc::format!("{}{}", prefix, LEGACY);
+            assert_eq!(PublicKey::decode(&legacy), Err(PublicKeyError::Legacy(key())));
+        }
+        assert_eq!(PublicKey::from_legacy(&alloc::format!("siopub001{}abcd", LEGACY)), None);
+        assert_eq!(key().as_bytes()[0], 0x79);
+    }
+}
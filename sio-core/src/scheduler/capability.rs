@@ -0,0 +1,105 @@
+use core::fmt;
+use crate::scheduler::config::ProcessRole;
+
+/// What the code of a process may do beyond computing with its own threads,
+/// depending on its role: the higher the rank, the more it can do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// start other processes, `spawn_process`
+    SpawnProcess,
+    /// watch other processes end, `supervise`
+    Supervise,
+    /// deploy processes to other nodes, `deploy` and `deploy_to`
+    Deploy,
+}
+
+impl Capability {
+    /// The capability the operation `name` needs, if it is a privileged one.
+    pub fn of(name: &str) -> Option<Self> {
+        match name {
+            "spawn_process" => Some(Capability::SpawnProcess),
+            "supervise" => Some(Capability::Supervise),
+            "deploy" | "deploy_to" => Some(Capability::Deploy),
+            _ => None,
+        }
+    }
+
+    /// The lowest role that has it.
+    pub fn rank(self) -> ProcessRole {
+        match self {
+            Capability::SpawnProcess => ProcessRole::Major,
+            Capability::Supervise => ProcessRole::Brigadier,
+            Capability::Deploy => ProcessRole::General,
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capability::SpawnProcess => write!(f, "spawn processes"),
+            Capability::Supervise => write!(f, "supervise processes"),
+            Capability::Deploy => write!(f, "deploy processes"),
+        }
+    }
+}
+
+impl ProcessRole {
+    pub fn can(self, capability: Capability) -> bool {
+        self >= capability.rank()
+    }
+
+    /// The role of the processes it starts, one rank below its own: majors
+    /// start corporals, brigadiers majors, which makes supervising a major
+    /// the business of a brigadier. Corporals start none.
+    pub fn below(self) -> Self {
+        match self {
+            ProcessRole::Corporal | ProcessRole::Major => ProcessRole::Corporal,
+            ProcessRole::Brigadier => ProcessRole::Major,
+            ProcessRole::General => ProcessRole::Brigadier,
+        }
+    }
+}
+
+/// Code tried an operation its role does not allow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilityDenied {
+    pub role: ProcessRole,
+    pub capability: Capability,
+}
+
+impl fmt::Display for CapabilityDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a {} cannot {}, only a {} or above can",
+            self.role,
+            self.capability,
+            self.capability.rank()
+        )
+    }
+}
+
+impl core::error::Error for CapabilityDenied {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn higher_ranks_can_do_more() {
+        assert!(!ProcessRole::Corporal.can(Capability::SpawnProcess));
+        assert!(ProcessRole::Major.can(Capability::SpawnProcess));
+        assert!(!ProcessRole::Major.can(Capability::Supervise));
+        assert!(ProcessRole::Brigadier.can(Capability::Supervise));
+        assert!(!ProcessRole::Brigadier.can(Capability::Deploy));
+        assert!(ProcessRole::General.can(Capability::Deploy));
+        let denied = CapabilityDenied { role: ProcessRole::Major, capability: Capability::Supervise };
+        assert_eq!(denied.to_string(), "a major cannot supervise processes, only a brigadier or above can");
+        // a brigadier supervises the majors it starts, and none of them can
+        // start a brigadier
+        assert_eq!(ProcessRole::Brigadier.below(), ProcessRole::Major);
+        assert!(ProcessRole::General.below() < ProcessRole::General);
+    }
+}
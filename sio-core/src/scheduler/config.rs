@@ -0,0 +1,82 @@
+use core::fmt;
+use crate::frontend::ast::ModuleKind;
+use crate::scheduler::quota::Quota;
+
+/// The role of a process, from the rank of the module it runs. Roles are
+/// ordered by rank, corporals first.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum ProcessRole {
+    Corporal,
+    Major,
+    Brigadier,
+    General,
+}
+
+impl From<ModuleKind> for ProcessRole {
+    fn from(kind: ModuleKind) -> Self {
+        match kind {
+            ModuleKind::Corporal => ProcessRole::Corporal,
+            ModuleKind::Major => ProcessRole::Major,
+            ModuleKind::Brigadier => ProcessRole::Brigadier,
+            ModuleKind::General => ProcessRole::General,
+        }
+    }
+}
+
+impl fmt::Display for ProcessRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessRole::Corporal => write!(f, "corporal"),
+            ProcessRole::Major => write!(f, "major"),
+            ProcessRole::Brigadier => write!(f, "brigadier"),
+            ProcessRole::General => write!(f, "general"),
+        }
+    }
+}
+
+/// The settings that depend on the role of a process.
+#[derive(Debug, Clone)]
+pub struct RoleConfig {
+    /// How many steps a thread runs before it yields to the executor, so a
+    /// busy thread cannot starve the others.
+    pub reductions: u32,
+    /// How many operations the threads can queue up for their process
+    /// before they suspend, `None` for no bound.
+    pub mailbox: Option<usize>,
+    /// How many messages sent to each process can wait to be received
+    /// before the senders suspend, `None` for no bound.
+    pub messages: Option<usize>,
+    /// What each process can use up before it is killed.
+    pub quota: Quota,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessConfig {
+    pub corporal: RoleConfig,
+    pub major: RoleConfig,
+    pub brigadier: RoleConfig,
+    pub general: RoleConfig,
+}
+
+impl ProcessConfig {
+    pub fn role(&self, role: ProcessRole) -> &RoleConfig {
+        match role {
+            ProcessRole::Corporal => &self.corporal,
+            ProcessRole::Major => &self.major,
+            ProcessRole::Brigadier => &self.brigadier,
+            ProcessRole::General => &self.general,
+        }
+    }
+}
+
+// higher ranks coordinate the lower ones and get longer slices
+impl Default for ProcessConfig {
+    fn default() -> Self {
+        Self {
+            corporal: RoleConfig { reductions: 1000, mailbox: Some(64), messages: Some(64), quota: Quota::default() },
+            major: RoleConfig { reductions: 2000, mailbox: Some(128), messages: Some(128), quota: Quota::default() },
+            brigadier: RoleConfig { reductions: 4000, mailbox: Some(256), messages: Some(256), quota: Quota::default() },
+            general: RoleConfig { reductions: 4000, mailbox: Some(256), messages: Some(256), quota: Quota::default() },
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+use crate::compiler::value::VariableId;
+use crate::scheduler::ThreadId;
+
+/// What a suspended thread is waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suspension {
+    /// the variable to be bound
+    Bound(VariableId),
+    /// another thread to wait on the variable
+    Needed(VariableId),
+}
+
+impl Suspension {
+    pub fn variable(&self) -> VariableId {
+        match self {
+            Suspension::Bound(variable) | Suspension::Needed(variable) => *variable,
+        }
+    }
+}
+
+/// Where a variable was declared, as a span of the process source and the
+/// line and column it starts at, both counted from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    pub span: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Declaration {
+    pub fn new(content: &str, span: Range<usize>) -> Self {
+        let before = &content[..span.start.min(content.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        Self { span, line, column }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuspendedThread {
+    pub thread_id: ThreadId,
+    pub suspension: Suspension,
+    pub declared: Option<Declaration>,
+}
+
+/// Every thread of a process is suspended and none of them can resume: no
+/// running thread is left to bind or need the variables they wait on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deadlock {
+    pub threads: Vec<SuspendedThread>,
+}
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadlock: all {} threads are suspended", self.threads.len())?;
+        for thread in self.threads.iter() {
+            match thread.suspension {
+                Suspension::Bound(variable) => {
+                    write!(f, "\n  thread {} waits on variable {}", thread.thread_id, variable)?
+                }
+                Suspension::Needed(variable) => {
+                    write!(f, "\n  thread {} waits for variable {} to be needed", thread.thread_id, variable)?
+                }
+            }
+            if let Some(declared) = &thread.declared {
+                write!(f, ", declared at {}:{}", declared.line, declared.column)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for Deadlock {}
@@ -0,0 +1,218 @@
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+use core::fmt;
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll, Wake, Waker};
+use async_channel::{unbounded, Receiver, Sender};
+
+// A single-threaded executor that decides itself which task runs next. Every
+// step it polls one of the ready tasks, picked by a seeded generator or read
+// from a recorded schedule, so the interleaving of the threads of a process
+// only depends on the seed.
+
+/// Tasks are numbered in the order they are spawned, the future given to
+/// `block_on` being task 0.
+pub type TaskId = u32;
+
+pub(crate) type BoxedTask = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// How the next task is chosen.
+#[derive(Debug, Clone)]
+pub enum ScheduleMode {
+    /// pseudo-randomly among the ready tasks
+    Seeded(u64),
+    /// as recorded by an earlier run
+    Replay(Schedule),
+}
+
+/// The tasks polled by a run, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schedule {
+    pub steps: Vec<TaskId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// the recorded task was not ready at that step, the program changed
+    Diverged { step: usize, expected: TaskId },
+    /// the recorded schedule ended before the run did
+    Exhausted { step: usize },
+    /// no task is ready and none can be woken from inside the run, for
+    /// instance when they wait on a system clock
+    Stalled { step: usize },
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::Diverged { step, expected } => {
+                write!(f, "schedule diverged at step {}: task {} is not ready", step, expected)
+            }
+            ScheduleError::Exhausted { step } => write!(f, "schedule exhausted at step {}", step),
+            ScheduleError::Stalled { step } => write!(f, "no task can run at step {}", step),
+        }
+    }
+}
+
+impl core::error::Error for ScheduleError {}
+
+// splitmix64, good enough to shuffle schedules and trivially reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+struct TaskWaker {
+    task: TaskId,
+    woken: Sender<TaskId>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        let _ = self.woken.try_send(self.task);
+    }
+}
+
+pub struct DeterministicExecutor {
+    mode: ScheduleMode,
+    rng: Rng,
+    spawner: Sender<BoxedTask>,
+    spawned: Receiver<BoxedTask>,
+    woken_sender: Sender<TaskId>,
+    woken: Receiver<TaskId>,
+    schedule: Schedule,
+}
+
+impl DeterministicExecutor {
+    pub fn new(mode: ScheduleMode) -> Self {
+        let seed = match &mode {
+            ScheduleMode::Seeded(seed) => *seed,
+            ScheduleMode::Replay(_) => 0,
+        };
+        let (spawner, spawned) = unbounded();
+        let (woken_sender, woken) = unbounded();
+        Self { mode, rng: Rng(seed), spawner, spawned, woken_sender, woken, schedule: Schedule::default() }
+    }
+
+    /// Where to send the tasks to run next to the future given to `block_on`.
+    pub(crate) fn spawner(&self) -> Sender<BoxedTask> {
+        self.spawner.clone()
+    }
+
+    /// The schedule of the last `block_on`.
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    pub fn block_on<F: Future>(&mut self, future: F) -> Result<F::Output, ScheduleError> {
+        let mut main = pin!(future);
+        let mut tasks: Vec<Option<BoxedTask>> = Vec::new();
+        let mut wakers = vec![self.waker(0)];
+        let mut ready = vec![true];
+        self.schedule.steps.clear();
+        loop {
+            while let Ok(task) = self.spawned.try_recv() {
+                tasks.push(Some(task));
+                wakers.push(self.waker(tasks.len() as TaskId));
+                ready.push(true);
+            }
+            while let Ok(task) = self.woken.try_recv() {
+                ready[task as usize] = true;
+            }
+            let step = self.schedule.steps.len();
+            let candidates: Vec<TaskId> = (0..ready.len() as TaskId).filter(|task| ready[*task as usize]).collect();
+            let task = match &self.mode {
+                ScheduleMode::Seeded(_) => {
+                    if candidates.is_empty() {
+                        return Err(ScheduleError::Stalled { step });
+                    }
+                    candidates[(self.rng.next() % candidates.len() as u64) as usize]
+                }
+                ScheduleMode::Replay(schedule) => match schedule.steps.get(step) {
+                    Some(task) if candidates.contains(task) => *task,
+                    Some(task) => return Err(ScheduleError::Diverged { step, expected: *task }),
+                    None => return Err(ScheduleError::Exhausted { step }),
+                },
+            };
+            self.schedule.steps.push(task);
+            ready[task as usize] = false;
+            let mut cx = Context::from_waker(&wakers[task as usize]);
+            if task == 0 {
+                if let Poll::Ready(output) = main.as_mut().poll(&mut cx) {
+                    return Ok(output);
+                }
+            } else if let Some(future) = tasks[task as usize - 1].as_mut() {
+                if future.as_mut().poll(&mut cx).is_ready() {
+                    tasks[task as usize - 1] = None;
+                }
+            }
+        }
+    }
+
+    fn waker(&self, task: TaskId) -> Waker {
+        Waker::from(Arc::new(TaskWaker { task, woken: self.woken_sender.clone() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    // three tasks appending their number, yielding in between
+    fn interleaving(executor: &mut DeterministicExecutor) -> Vec<u32> {
+        let (sender, receiver) = unbounded();
+        let done = Arc::new(AtomicU32::new(0));
+        for task in 0..3 {
+            let sender = sender.clone();
+            let done = done.clone();
+            let _ = executor.spawner().try_send(Box::pin(async move {
+                for _ in 0..3 {
+                    let _ = sender.send(task).await;
+                    smol::future::yield_now().await;
+                }
+                done.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        executor
+            .block_on(async {
+                while done.load(Ordering::SeqCst) < 3 {
+                    smol::future::yield_now().await;
+                }
+            })
+            .unwrap();
+        let mut order = Vec::new();
+        while let Ok(task) = receiver.try_recv() {
+            order.push(task);
+        }
+        order
+    }
+
+    #[test]
+    fn a_seed_fixes_the_interleaving() {
+        let first = interleaving(&mut DeterministicExecutor::new(ScheduleMode::Seeded(7)));
+        assert_eq!(first, interleaving(&mut DeterministicExecutor::new(ScheduleMode::Seeded(7))));
+        let seeds: Vec<Vec<u32>> = (0..16)
+            .map(|seed| interleaving(&mut DeterministicExecutor::new(ScheduleMode::Seeded(seed))))
+            .collect();
+        assert!(seeds.iter().any(|order| *order != first), "seeds pick different interleavings");
+    }
+
+    #[test]
+    fn a_recorded_schedule_replays_exactly() {
+        let mut recorder = DeterministicExecutor::new(ScheduleMode::Seeded(42));
+        let recorded = interleaving(&mut recorder);
+        let schedule = recorder.schedule().clone();
+        let mut replayer = DeterministicExecutor::new(ScheduleMode::Replay(schedule.clone()));
+        assert_eq!(interleaving(&mut replayer), recorded);
+        assert_eq!(replayer.schedule(), &schedule);
+    }
+}
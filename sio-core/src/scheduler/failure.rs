@@ -0,0 +1,72 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use core::ops::Range;
+use werbolg_compile::InstructionAddress;
+use werbolg_core::Ident;
+use crate::scheduler::{Declaration, ThreadId};
+
+/// One call of a failed thread's stack, innermost first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub ip: InstructionAddress,
+    /// the function the instruction belongs to, with its declaration
+    pub function: Option<(Ident, Option<Declaration>)>,
+}
+
+/// A thread stopped on an execution error. It fails its whole process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadFailure {
+    pub thread_id: ThreadId,
+    pub error: String,
+    pub trace: Vec<Frame>,
+}
+
+impl ThreadFailure {
+    /// The instruction the thread failed on.
+    pub fn ip(&self) -> Option<InstructionAddress> {
+        self.trace.first().map(|frame| frame.ip)
+    }
+}
+
+impl fmt::Display for ThreadFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        write!(f, "\n  in thread {}", self.thread_id)?;
+        for frame in self.trace.iter() {
+            match &frame.function {
+                Some((name, Some(declared))) => {
+                    write!(f, "\n  at {} in {} declared at {}:{}", frame.ip, name, declared.line, declared.column)?
+                }
+                Some((name, None)) => write!(f, "\n  at {} in {}", frame.ip, name)?,
+                None => write!(f, "\n  at {}", frame.ip)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for ThreadFailure {}
+
+/// Maps instruction addresses back to the functions of the source they were
+/// compiled from.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    // sorted by the address the function code starts at
+    functions: Vec<(InstructionAddress, Ident, Option<Range<usize>>)>,
+}
+
+impl SourceMap {
+    pub fn new(mut functions: Vec<(InstructionAddress, Ident, Option<Range<usize>>)>) -> Self {
+        functions.sort_by_key(|(code_pos, _, _)| *code_pos);
+        Self { functions }
+    }
+
+    pub fn frame(&self, content: &str, ip: InstructionAddress) -> Frame {
+        let index = self.functions.partition_point(|(code_pos, _, _)| *code_pos <= ip);
+        let function = index.checked_sub(1).map(|index| {
+            let (_, name, span) = &self.functions[index];
+            (name.clone(), span.clone().map(|span| Declaration::new(content, span)))
+        });
+        Frame { ip, function }
+    }
+}
@@ -0,0 +1,140 @@
+use alloc::{vec, vec::Vec};
+use werbolg_compile::{CallArity, CompilationUnit, InstructionAddress};
+use werbolg_core::{id::IdF, ValueFun};
+use werbolg_exec::{LocalStackSize, StackPointer};
+use crate::compiler::{nifs::nif_table, ThreadExecutionMachine, ThreadLiteral, ThreadValue as Value};
+use crate::scheduler::snapshot::SnapshotError;
+
+// The only code depending on how werbolg lays out an execution machine: its
+// stack, its instruction and stack pointers, and the return addresses of the
+// calls in progress. The scheduler goes through these functions, so a change
+// of layout stays here.
+
+/// The state of a machine, as a snapshot keeps it.
+#[derive(Debug, Clone)]
+pub(crate) struct MachineSnapshot {
+    pub(crate) ip: u64,
+    pub(crate) sp: u64,
+    pub(crate) current_arity: u64,
+    pub(crate) stack: Vec<Value>,
+    // return address, stack pointer, local stack size and arity of each call
+    pub(crate) rets: Vec<(u64, u64, u64, u64)>,
+}
+
+/// The values on the stack of the machine, which hold whatever its thread
+/// still refers to.
+pub(crate) fn stack(em: &ThreadExecutionMachine) -> impl Iterator<Item = &Value> + '_ {
+    em.stack.iter_pos().map(|(_, value)| value)
+}
+
+/// Where the thread is, followed by the return addresses of the calls it is
+/// in, innermost first.
+pub(crate) fn trace(em: &ThreadExecutionMachine) -> Vec<InstructionAddress> {
+    let mut trace = vec![em.ip];
+    trace.extend(em.rets.iter().rev().map(|ret| ret.0));
+    trace
+}
+
+pub(crate) fn capture(em: &ThreadExecutionMachine) -> MachineSnapshot {
+    MachineSnapshot {
+        ip: em.ip.as_index() as u64,
+        sp: em.sp.0 as u64,
+        current_arity: em.current_arity.0 as u64,
+        stack: stack(em).cloned().collect(),
+        rets: em
+            .rets
+            .iter()
+            .map(|(ip, sp, locals, arity)| (ip.as_index() as u64, sp.0 as u64, locals.0 as u64, arity.0 as u64))
+            .collect(),
+    }
+}
+
+// puts the captured state back into a machine that was not initialized, once
+// `Code::check` accepted it
+pub(crate) fn resume(em: &mut ThreadExecutionMachine, machine: &MachineSnapshot) -> Result<(), SnapshotError> {
+    let arity = |arity: u64| CallArity::try_from(arity as usize).map_err(|_| SnapshotError::Invalid("call arity"));
+    for value in machine.stack.iter() {
+        em.stack.push_value(value.clone());
+    }
+    em.rets = machine
+        .rets
+        .iter()
+        .map(|(ip, sp, locals, call_arity)| {
+            Ok((
+                InstructionAddress::from_collection_len(*ip as usize),
+                StackPointer(*sp as usize),
+                LocalStackSize(*locals as u32),
+                arity(*call_arity)?,
+            ))
+        })
+        .collect::<Result<_, SnapshotError>>()?;
+    em.ip = InstructionAddress::from_collection_len(machine.ip as usize);
+    em.sp = StackPointer(machine.sp as usize);
+    em.current_arity = arity(machine.current_arity)?;
+    Ok(())
+}
+
+/// How much code a source compiled to: what a machine or a value coming
+/// from elsewhere may point into.
+pub(crate) struct Code {
+    pub(crate) instructions: usize,
+    pub(crate) funs: usize,
+    pub(crate) nifs: usize,
+}
+
+impl Code {
+    pub(crate) fn of(cu: &CompilationUnit<ThreadLiteral>) -> Self {
+        Self {
+            instructions: cu.code.next_id().as_index(),
+            funs: cu.funs.next_id().as_index(),
+            nifs: nif_table().len(),
+        }
+    }
+
+    pub(crate) fn fun(&self, fun: &ValueFun) -> Result<(), SnapshotError> {
+        match fun {
+            ValueFun::Fun(fun) if fun.as_index() >= self.funs => Err(SnapshotError::Invalid("function")),
+            ValueFun::Native(nif) if nif.as_index() >= self.nifs => Err(SnapshotError::Invalid("native function")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the functions in `value` are in this code. A cell of another
+    /// process is not anywhere here either.
+    pub(crate) fn value(&self, value: &Value) -> Result<(), SnapshotError> {
+        let mut value = value;
+        // the elements of a list, its spine walked without recursing
+        while let Value::Cons(_, cell) = value {
+            self.value(&cell.0)?;
+            value = &cell.1;
+        }
+        match value {
+            Value::Fun(_, fun) => self.fun(fun),
+            Value::Closure(_, closure) => {
+                self.fun(&closure.fun)?;
+                closure.env.iter().try_for_each(|value| self.value(value))
+            }
+            Value::Map(_, map) => map.iter().try_for_each(|(key, value)| {
+                self.value(key)?;
+                self.value(value)
+            }),
+            Value::Record(_, record) => record.fields.iter().try_for_each(|value| self.value(value)),
+            Value::Cell(..) => Err(SnapshotError::Invalid("cell")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the instruction and stack pointers of `machine` hold in this
+    /// code and on its own stack.
+    pub(crate) fn check(&self, machine: &MachineSnapshot) -> Result<(), SnapshotError> {
+        let instruction = |ip: u64| ip < self.instructions as u64;
+        if !instruction(machine.ip) || !machine.rets.iter().all(|ret| instruction(ret.0)) {
+            return Err(SnapshotError::Invalid("instruction address"));
+        }
+        let depth = machine.stack.len() as u64;
+        if machine.sp > depth || machine.rets.iter().any(|ret| ret.1 > depth) {
+            return Err(SnapshotError::Invalid("stack pointer"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use alloc::sync::Arc;
+use async_channel::{Receiver, Sender};
+use smol::future;
+use crate::compiler::value::{ThreadValue as Value, VariableId, NO_VARIABLE};
+use crate::scheduler::process::ProcessId;
+use crate::scheduler::runtime::ProcessTable;
+use crate::scheduler::store::Store;
+use crate::scheduler::time::{Clock, Millis};
+
+// Every process has a mailbox other processes `send` messages to, and its
+// threads `receive` them from, in the order they were sent. Messages are
+// copied: only what is determined can be sent. A mailbox is bounded, a
+// sender suspends while it is full.
+
+/// What a thread sees of the mailboxes: the one of its process, to receive
+/// from, and those of the other processes of the runtime, to send to.
+#[derive(Clone)]
+pub struct Mailboxes {
+    process_id: ProcessId,
+    inbox: (Sender<Value>, Receiver<Value>),
+    table: Arc<ProcessTable>,
+}
+
+impl Mailboxes {
+    pub(crate) fn new(process_id: ProcessId, inbox: (Sender<Value>, Receiver<Value>), table: Arc<ProcessTable>) -> Self {
+        Self { process_id, inbox, table }
+    }
+
+    pub fn process_id(&self) -> ProcessId {
+        self.process_id
+    }
+
+    /// The mailbox of `process_id`, `None` for a process the runtime does
+    /// not know.
+    pub(crate) fn mailbox(&self, process_id: ProcessId) -> Option<Sender<Value>> {
+        if process_id == self.process_id {
+            return Some(self.inbox.0.clone());
+        }
+        self.table.mailbox(process_id)
+    }
+}
+
+/// Set by `send` and `receive`: what the thread waits on a mailbox for once
+/// the step is done, its machine unlocked in the meantime.
+#[derive(Clone)]
+pub enum Mail {
+    /// post the message, waiting for room in a full mailbox
+    Send(Sender<Value>, Value),
+    /// bind the variable to the next message; with a deadline, to the list
+    /// of that message, or to nil once the deadline passed
+    Receive(VariableId, Option<Millis>),
+}
+
+impl Mail {
+    /// Waits on the mailbox and binds what was received. `false` once the
+    /// mailbox of the process is closed, which happens when it stops.
+    pub(crate) async fn deliver(self, mailboxes: &Mailboxes, store: &Store, clock: &dyn Clock) -> bool {
+        let (variable, received) = match self {
+            // a process that ended drops what is sent to it
+            Mail::Send(mailbox, message) => {
+                let _ = mailbox.send(message).await;
+                return true;
+            }
+            Mail::Receive(variable, None) => match mailboxes.inbox.1.recv().await {
+                Ok(message) => (variable, message),
+                Err(_) => return false,
+            },
+            Mail::Receive(variable, Some(deadline)) => {
+                // a message already there wins over a deadline already past
+                let message = future::or(async { Some(mailboxes.inbox.1.recv().await) }, async {
+                    clock.sleep_until(deadline).await;
+                    None
+                })
+                .await;
+                match message {
+                    Some(Ok(message)) => (variable, Value::Cons(NO_VARIABLE, Arc::new((message, Value::Unit)))),
+                    Some(Err(_)) => return false,
+                    None => (variable, Value::Unit),
+                }
+            }
+        };
+        // the variable is fresh, binding it cannot fail
+        let _ = store.unify(&Value::Unbound(variable), &received);
+        true
+    }
+}
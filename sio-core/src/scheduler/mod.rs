@@ -1,3 +1,69 @@
+// Only the dataflow store and what the frontend needs from the scheduler, the
+// roles and their capabilities, build without `std`.
+mod capability;
+mod config;
+#[cfg(feature = "std")]
+mod deadlock;
+#[cfg(feature = "std")]
+mod deterministic;
+#[cfg(feature = "std")]
+mod failure;
+#[cfg(feature = "std")]
+mod machine;
+#[cfg(feature = "std")]
+mod mailbox;
+#[cfg(feature = "std")]
+mod modules;
+#[cfg(feature = "std")]
+mod node;
+#[cfg(feature = "std")]
 mod process;
+mod quota;
+#[cfg(feature = "std")]
+mod runtime;
+#[cfg(feature = "std")]
+mod pool;
+pub mod store;
+#[cfg(feature = "std")]
+mod script;
+#[cfg(feature = "std")]
+mod snapshot;
+#[cfg(feature = "std")]
+mod time;
+#[cfg(feature = "std")]
+mod transport;
+mod wakers;
 
-pub use process::{Process, Thread, Operation};
\ No newline at end of file
+pub use capability::{Capability, CapabilityDenied};
+pub use config::{ProcessConfig, ProcessRole, RoleConfig};
+#[cfg(feature = "std")]
+pub use deadlock::{Deadlock, Declaration, SuspendedThread, Suspension};
+#[cfg(feature = "std")]
+pub use deterministic::{DeterministicExecutor, Schedule, ScheduleError, ScheduleMode, TaskId};
+#[cfg(feature = "std")]
+pub use failure::{Frame, SourceMap, ThreadFailure};
+#[cfg(feature = "std")]
+pub use mailbox::{Mail, Mailboxes};
+#[cfg(feature = "std")]
+pub use modules::{ExternalCall, LoadedModule, Modules};
+#[cfg(feature = "std")]
+pub use node::{Message, Network, NodeId};
+#[cfg(feature = "std")]
+pub use script::{Event, Trace};
+#[cfg(feature = "std")]
+pub use time::SystemClock;
+#[cfg(feature = "std")]
+pub use time::{Clock, Millis, VirtualClock};
+#[cfg(feature = "std")]
+pub use pool::ExecutorPool;
+pub use quota::{Quota, QuotaExceeded, Resource, Usage};
+#[cfg(feature = "std")]
+pub use process::{Process, Thread, Operation, ThreadEntry, ThreadId, ProcessId};
+#[cfg(feature = "std")]
+pub use runtime::{ProcessExit, ProcessFailure, ProcessInfo, Runtime};
+#[cfg(feature = "std")]
+pub use snapshot::{Snapshot, SnapshotError};
+#[cfg(feature = "std")]
+pub use transport::{MemoryTransport, Transport, TransportError, TransportFuture};
+#[cfg(feature = "std")]
+pub use transport::TcpTransport;
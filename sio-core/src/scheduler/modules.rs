@@ -0,0 +1,350 @@
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use core::{error::Error, fmt};
+use hashbrown::HashMap;
+use smol::lock::Mutex;
+use werbolg_compile::CompilationUnit;
+use werbolg_core::{ir::{Privacy, Statement}, AbsPath, FunId, Ident, Namespace};
+use werbolg_exec::WerRefCount;
+use werbolg_lang_common::Source;
+use crate::compiler::{create_thread_env, prelude::prelude, ThreadExecutionEnviron, ThreadLiteral, ThreadValue as Value};
+use crate::compiler::value::VariableId;
+use crate::frontend::{imports::Interface, Lowered};
+use crate::scheduler::config::ProcessRole;
+use crate::scheduler::failure::SourceMap;
+use crate::scheduler::process::{compile_thread, function_spans, lower_parsed, main_namespace, run_parsing, source_map};
+
+// Code is loaded the way Erlang does it: a module has a current version, which
+// external calls (`B.x()`) run, and possibly an old one, which the threads
+// started before the last load keep running, their local calls staying on the
+// code they started in. Loading a new version makes the current one old, and
+// the old one can only be purged once no thread runs it anymore.
+//
+// A program split over several files is compiled in one go, each file
+// importing from the others as well as from the modules already loaded.
+//
+// An external call runs on the thread making it, in a machine of its own for
+// the code of the module, and returns to the caller's machine. Functions only
+// have a meaning in the code they were compiled in, so none can be passed from
+// one version of a module to another.
+
+/// A compiled version of a module, or the code of a process.
+pub struct LoadedModule {
+    pub(crate) name: String,
+    pub(crate) version: u64,
+    pub(crate) role: ProcessRole,
+    pub(crate) source: Arc<Source>,
+    // where its functions are compiled: its url, or `root::main` for a
+    // process that declares no module
+    pub(crate) namespace: Namespace,
+    pub(crate) cu: WerRefCount<CompilationUnit<ThreadLiteral>>,
+    pub(crate) ee: WerRefCount<ThreadExecutionEnviron>,
+    pub(crate) source_map: SourceMap,
+    // the public functions, which external calls can run
+    exports: HashMap<String, FunId>,
+    // the module and function of each external call of the code
+    pub(crate) externals: Vec<(String, String)>,
+    // what the module offers to those importing it, `None` for a process
+    // that declares no module, which cannot be loaded
+    pub(crate) interface: Option<Interface>,
+}
+
+impl fmt::Debug for LoadedModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.version)
+    }
+}
+
+/// Set by `external`: the call the thread makes once the step is done, to
+/// the external call of that index in its code, binding the variable to
+/// what the call returns.
+#[derive(Clone)]
+pub struct ExternalCall {
+    pub external: usize,
+    pub args: Vec<Value>,
+    pub result: VariableId,
+}
+
+impl LoadedModule {
+    /// Compiles the first module `src` declares. Its version is the one it
+    /// is declared with, or the next one when loaded without.
+    pub fn compile(src: String, path: String) -> Result<Self, Box<dyn Error>> {
+        let mut modules = Self::compile_all(alloc::vec![(src, path)], &[])?;
+        Ok(modules.remove(0))
+    }
+
+    /// Compiles files, given as source and path, which may import each other
+    /// and the modules of `loaded`. A module of the files replaces the loaded
+    /// one at the same url.
+    pub fn compile_all(files: Vec<(String, String)>, loaded: &[Interface]) -> Result<Vec<Self>, Box<dyn Error>> {
+        let parsed = files
+            .into_iter()
+            .map(|(src, path)| run_parsing(src, path))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut interfaces = Vec::new();
+        for (_, parsed) in parsed.iter() {
+            let Some(interface) = parsed.interface() else {
+                return Err("only a declared module can be loaded".into());
+            };
+            interfaces.push(interface);
+        }
+        let kept = loaded
+            .iter()
+            .filter(|old| !interfaces.iter().any(|new: &Interface| new.path == old.path))
+            .cloned()
+            .collect::<Vec<_>>();
+        interfaces.extend(kept);
+        parsed
+            .into_iter()
+            .map(|(source, parsed)| {
+                let lowered = lower_parsed(&source, parsed, &interfaces)?;
+                Self::from_lowered(Arc::new(source), lowered)
+            })
+            .collect()
+    }
+
+    pub(crate) fn from_lowered(source: Arc<Source>, lowered: Lowered) -> Result<Self, Box<dyn Error>> {
+        let exported: Vec<Ident> = lowered
+            .module
+            .statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Function(_, def, _) if matches!(def.privacy, Privacy::Public) => Some(def.name.clone()),
+                _ => None,
+            })
+            .collect();
+        let mut env = create_thread_env();
+        let prelude = prelude(&source.file_unit.content)?;
+        let functions = function_spans(&lowered.module);
+        let library = function_spans(&prelude);
+        let namespace = lowered.url.unwrap_or_else(main_namespace);
+        let cu = compile_thread(&mut env, source.clone(), prelude, &namespace, lowered.module)?;
+        let source_map = source_map(&cu, &namespace, functions, library);
+        let exports = exported
+            .into_iter()
+            .filter_map(|name| {
+                let fun_id = cu.funs_tbl.get(&AbsPath::new(&namespace, &name))?;
+                Some((format!("{}", name), fun_id))
+            })
+            .collect();
+        let ee = werbolg_exec::ExecutionEnviron::from_compile_environment(env.finalize());
+        Ok(Self {
+            name: lowered.name,
+            version: lowered.version.unwrap_or(0),
+            role: ProcessRole::from(lowered.kind),
+            source,
+            namespace,
+            cu: WerRefCount::new(cu),
+            ee: WerRefCount::new(ee),
+            source_map,
+            exports,
+            externals: lowered.externals,
+            interface: lowered.interface,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The fully qualified url of the module, its name last.
+    pub fn url(&self) -> &[String] {
+        self.interface.as_ref().map_or(&[][..], |interface| interface.path.as_slice())
+    }
+
+    // what the table keeps the module under and external calls name it by
+    fn key(&self) -> String {
+        self.url().join("::")
+    }
+
+    pub(crate) fn export(&self, function: &str) -> Option<FunId> {
+        self.exports.get(function).copied()
+    }
+}
+
+struct Versions {
+    current: Arc<LoadedModule>,
+    old: Option<Arc<LoadedModule>>,
+}
+
+// the table holds one reference to each version, the threads running it the
+// others
+fn in_use(module: &Arc<LoadedModule>) -> bool {
+    Arc::strong_count(module) > 1
+}
+
+// the version `module` is to be loaded as, next to the `loaded` ones
+fn next_version(loaded: Option<&Versions>, module: &LoadedModule) -> Result<u64, Box<dyn Error>> {
+    let Some(versions) = loaded else {
+        return Ok(module.version.max(1));
+    };
+    if versions.old.as_ref().is_some_and(in_use) {
+        return Err(format!("the old version of {} is still running", module.name).into());
+    }
+    let current = versions.current.version;
+    if module.version == 0 {
+        Ok(current + 1)
+    } else if module.version <= current {
+        Err(format!("{} {} is not newer than the loaded version {}", module.name, module.version, current).into())
+    } else {
+        Ok(module.version)
+    }
+}
+
+/// The modules loaded in a runtime, shared by its processes, by url: two
+/// modules of the same name at different urls are different modules.
+#[derive(Default)]
+pub struct Modules {
+    modules: Mutex<HashMap<String, Versions>>,
+}
+
+impl Modules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `module` the current version of its url, and the current one
+    /// old. Fails while the old version is still running, as there are only
+    /// two of them, or when `module` is declared with a version that is not
+    /// newer than the current one. Returns the version it was loaded as.
+    pub async fn load(&self, module: LoadedModule) -> Result<u64, Box<dyn Error>> {
+        let versions = self.load_all(alloc::vec![module]).await?;
+        Ok(versions[0])
+    }
+
+    /// Loads modules compiled together, either all of them or, when one of
+    /// them cannot be, none. Returns the versions they were loaded as.
+    pub async fn load_all(&self, mut loading: Vec<LoadedModule>) -> Result<Vec<u64>, Box<dyn Error>> {
+        let mut modules = self.modules.lock().await;
+        for (index, module) in loading.iter().enumerate() {
+            if module.interface.is_none() {
+                return Err("only a declared module can be loaded".into());
+            }
+            if loading[..index].iter().any(|other| other.key() == module.key()) {
+                return Err(format!("{} is loaded twice", module.key()).into());
+            }
+        }
+        let versions = loading
+            .iter()
+            .map(|module| next_version(modules.get(&module.key()), module))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (mut module, &version) in loading.drain(..).zip(versions.iter()) {
+            module.version = version;
+            match modules.get_mut(&module.key()) {
+                Some(versions) => {
+                    versions.old = Some(core::mem::replace(&mut versions.current, Arc::new(module)));
+                }
+                None => {
+                    modules.insert(module.key(), Versions { current: Arc::new(module), old: None });
+                }
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Drops the old version of the module at `url`. Fails while a thread
+    /// still runs it.
+    pub async fn purge(&self, url: &str) -> Result<(), Box<dyn Error>> {
+        let mut modules = self.modules.lock().await;
+        let Some(versions) = modules.get_mut(url) else {
+            return Err(format!("{} is not loaded", url).into());
+        };
+        if let Some(old) = &versions.old {
+            if in_use(old) {
+                return Err(format!("{} {} is still running", url, old.version).into());
+            }
+        }
+        versions.old = None;
+        Ok(())
+    }
+
+    /// Makes the code of a process the current version of the module it
+    /// declares, unless a version of that module is already loaded. Calls
+    /// of the process to its own module then go through the table, and run
+    /// the version loaded after it.
+    pub(crate) async fn provide(&self, code: Arc<LoadedModule>) {
+        if code.interface.is_none() {
+            return;
+        }
+        let mut modules = self.modules.lock().await;
+        modules.entry(code.key()).or_insert_with(|| Versions { current: code, old: None });
+    }
+
+    /// The version external calls to the module at `url` run.
+    pub async fn current(&self, url: &str) -> Option<Arc<LoadedModule>> {
+        Some(self.modules.lock().await.get(url)?.current.clone())
+    }
+
+    /// What the current version of each module offers to the modules
+    /// importing it.
+    pub async fn interfaces(&self) -> Vec<Interface> {
+        let modules = self.modules.lock().await;
+        modules.values().filter_map(|versions| versions.current.interface.clone()).collect()
+    }
+
+    /// The versions of the module at `url` loaded, old one first.
+    pub async fn versions(&self, url: &str) -> Vec<u64> {
+        match self.modules.lock().await.get(url) {
+            Some(versions) => versions.old.iter().chain(Some(&versions.current)).map(|module| module.version).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn b(version: &str, x: u64) -> LoadedModule {
+        let src = format!("corporal lib::B {} {{ pub x :: () {{ {}; }} }}", version, x);
+        LoadedModule::compile(src, "b.sio".to_string()).expect("B compiles")
+    }
+
+    #[test]
+    fn loading_keeps_one_old_version() {
+        let modules = Modules::new();
+        smol::block_on(async {
+            assert_eq!(modules.load(b("", 1)).await.unwrap(), 1);
+            assert_eq!(modules.load(b("", 2)).await.unwrap(), 2);
+            assert_eq!(modules.versions("lib::B").await, [1, 2]);
+            let error = modules.load(b("2", 3)).await.unwrap_err();
+            assert_eq!(error.to_string(), "B 2 is not newer than the loaded version 2");
+
+            // a thread still running version 2 once it is old
+            let running = modules.current("lib::B").await.unwrap();
+            assert_eq!(modules.load(b("100", 3)).await.unwrap(), 100);
+            assert!(modules.load(b("", 4)).await.is_err());
+            assert!(modules.purge("lib::B").await.is_err());
+            drop(running);
+            modules.purge("lib::B").await.unwrap();
+            assert_eq!(modules.versions("lib::B").await, [100]);
+        });
+    }
+
+    #[test]
+    fn modules_of_the_same_name_are_kept_by_url() {
+        let modules = Modules::new();
+        let other = LoadedModule::compile("corporal app::B { pub x :: () { 5; } }".to_string(), "app/b.sio".to_string())
+            .expect("app::B compiles");
+        smol::block_on(async {
+            assert_eq!(modules.load(b("", 1)).await.unwrap(), 1);
+            assert_eq!(modules.load(other).await.unwrap(), 1);
+            assert_eq!(modules.versions("lib::B").await, [1]);
+            assert_eq!(modules.versions("app::B").await, [1]);
+            assert!(modules.current("B").await.is_none());
+            assert_eq!(modules.interfaces().await.len(), 2);
+        });
+    }
+
+    #[test]
+    fn modules_are_compiled_under_their_url() {
+        let module = b("", 1);
+        let x = |namespace: &Namespace| module.cu.funs_tbl.get(&AbsPath::new(namespace, &Ident::from("x")));
+        assert!(x(&module.namespace).is_some());
+        assert!(x(&main_namespace()).is_none());
+    }
+}
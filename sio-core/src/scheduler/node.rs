@@ -0,0 +1,873 @@
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec, vec::Vec};
+use core::error::Error;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use async_channel::{unbounded, Receiver, Sender};
+use hashbrown::HashMap;
+use smol::{lock::Mutex, Executor, Task};
+use crate::compiler::{map::ValueMap, ThreadValue as Value, value::{Closure, Record, VariableId}};
+use crate::scheduler::config::{ProcessConfig, ProcessRole};
+use crate::scheduler::machine::Code;
+use crate::scheduler::modules::Modules;
+use crate::scheduler::process::{Operation, Process, ProcessId, ThreadId};
+use crate::scheduler::runtime::{Ids, ProcessTable};
+use crate::scheduler::snapshot::{Reader, SnapshotError, Writer};
+use crate::scheduler::store::Store;
+use crate::scheduler::time::Clock;
+use crate::scheduler::transport::{Transport, TransportError};
+
+// A process deploys another one to a node it is connected to, like it spawns
+// a child process, except that the closure may capture variables that are
+// not bound yet. Those are shared: each side has its own variable, and a
+// bridge forwards to the other side when its variable is needed and what it
+// is bound to, so either side can bind it and wait on it as on any other.
+// What is left unbound in a binding is shared the same way from then on.
+// The variables are named on the wire by their id on the node that shared
+// them first, from `DEPLOYED_NAME` on for the deployed end. Thread ids on
+// the wire are those of the sender, the receiver puts its own.
+//
+// A frame holds a message: the protocol version, then the message written
+// like the values of a snapshot. Nothing tells who is at the other end: what
+// it deploys runs no higher than the role of the link, and a value in a
+// frame that does not hold in the code it is for drops the link, as a
+// garbled frame does.
+
+pub type NodeId = u64;
+// numbers a deployment on both ends of a link
+pub type DeploymentId = u64;
+
+const PROTOCOL: u64 = 1;
+
+// the names of the variables the deployed end shares first, apart from those
+// of the deploying end
+const DEPLOYED_NAME: VariableId = 1 << 63;
+
+/// What nodes send each other.
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// starts a process on the receiving node running the closure, compiled
+    /// from the same source as the process deploying it
+    Spawn {
+        deployment: DeploymentId,
+        path: String,
+        source: String,
+        closure: Value,
+    },
+    /// an operation for the process on the receiving end of the deployment:
+    /// `Bind` binds a shared variable, `SynchVar` of an unbound variable needs
+    /// it, and `ChildExit` tells how the deployed process ended
+    Operation(DeploymentId, Operation),
+    /// a message sent to the deployed process
+    Mail(DeploymentId, Value),
+}
+
+const SPAWN: u64 = 0;
+const OPERATION: u64 = 1;
+const MAIL: u64 = 2;
+const SYNCH_VAR: u64 = 0;
+const BIND: u64 = 1;
+const CHILD_EXIT: u64 = 2;
+
+impl Message {
+    /// `None` for an operation that does not go to another node.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        let mut out = Writer { bytes: Vec::new() };
+        out.uint(PROTOCOL);
+        match self {
+            Message::Spawn { deployment, path, source, closure } => {
+                out.uint(SPAWN);
+                out.uint(*deployment);
+                out.string(path);
+                out.string(source);
+                out.value(closure);
+            }
+            Message::Operation(deployment, operation) => {
+                out.uint(OPERATION);
+                out.uint(*deployment);
+                match operation {
+                    Operation::SynchVar(thread_id, value) => {
+                        out.uint(SYNCH_VAR);
+                        out.uint(*thread_id);
+                        out.value(value);
+                    }
+                    Operation::Bind(thread_id, variable, value) => {
+                        out.uint(BIND);
+                        out.uint(*thread_id);
+                        out.uint(*variable);
+                        out.value(value);
+                    }
+                    Operation::ChildExit(thread_id, process_id, completed) => {
+                        out.uint(CHILD_EXIT);
+                        out.uint(*thread_id);
+                        out.uint(*process_id);
+                        out.uint(*completed as u64);
+                    }
+                    _ => return None,
+                }
+            }
+            Message::Mail(deployment, message) => {
+                out.uint(MAIL);
+                out.uint(*deployment);
+                out.value(message);
+            }
+        }
+        Some(out.bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut input = Reader { bytes, at: 0 };
+        let version = input.uint()?;
+        if version != PROTOCOL {
+            return Err(SnapshotError::Version(version as u16));
+        }
+        let message = match input.uint()? {
+            SPAWN => Message::Spawn {
+                deployment: input.uint()?,
+                path: input.string()?,
+                source: input.string()?,
+                closure: input.value()?,
+            },
+            OPERATION => {
+                let deployment = input.uint()?;
+                let operation = match input.uint()? {
+                    SYNCH_VAR => Operation::SynchVar(input.uint()?, input.value()?),
+                    BIND => Operation::Bind(input.uint()?, input.uint()?, input.value()?),
+                    CHILD_EXIT => Operation::ChildExit(input.uint()?, input.uint()?, input.uint()? != 0),
+                    _ => return Err(SnapshotError::Invalid("operation")),
+                };
+                Message::Operation(deployment, operation)
+            }
+            MAIL => Message::Mail(input.uint()?, input.value()?),
+            _ => return Err(SnapshotError::Invalid("message")),
+        };
+        if input.at != bytes.len() {
+            return Err(SnapshotError::Invalid("trailing bytes"));
+        }
+        Ok(message)
+    }
+}
+
+// the process of a deployment on one end of a link
+struct Endpoint {
+    // the thread that deployed it, or the main thread of the deployed process
+    thread_id: ThreadId,
+    // on the deploying node the id its process knows the deployed one by
+    process_id: ProcessId,
+    store: Arc<Store>,
+    mailbox: Sender<Operation>,
+    // what the values coming from the other end may point into
+    code: Code,
+    // creates the variables the other end shares
+    next_variable: Arc<AtomicU64>,
+    // whether this is the deployed end
+    deployed: bool,
+    // the shared variables, from their name on the wire to their id here,
+    // and back
+    variables: HashMap<VariableId, VariableId>,
+    names: HashMap<VariableId, VariableId>,
+    // on the deployed end, where the mail for its process goes
+    mail: Option<Sender<Value>>,
+    // on the deploying end, which routes the mail of the deployed process
+    // here
+    table: Option<Arc<ProcessTable>>,
+    // the bridges and whatever carries the mail, dropped with the endpoint
+    tasks: Vec<Task<()>>,
+}
+
+impl Endpoint {
+    // The value with the variables named as on the wire. Those not shared
+    // yet are from now on, and are added to `shared`.
+    fn to_wire(&mut self, value: &Value, shared: &mut Vec<(VariableId, VariableId)>) -> Value {
+        rename(value, &mut |variable| match self.names.get(&variable) {
+            Some(name) => *name,
+            None => {
+                let name = if self.deployed { DEPLOYED_NAME | variable } else { variable };
+                self.variables.insert(name, variable);
+                self.names.insert(variable, name);
+                shared.push((name, variable));
+                name
+            }
+        })
+    }
+
+    // The value with the variables named as here. Those the other end just
+    // shared get a variable of their own, and are added to `shared`.
+    fn from_wire(&mut self, value: &Value, shared: &mut Vec<(VariableId, VariableId)>) -> Value {
+        rename(value, &mut |name| match self.variables.get(&name) {
+            Some(variable) => *variable,
+            None => {
+                let variable = self.next_variable.fetch_add(1, Ordering::SeqCst);
+                self.variables.insert(name, variable);
+                self.names.insert(variable, name);
+                shared.push((name, variable));
+                variable
+            }
+        })
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        if let Some(table) = &self.table {
+            table.undeployed(self.process_id);
+        }
+    }
+}
+
+pub(crate) struct Link {
+    transport: Arc<dyn Transport>,
+    // the highest role a process deployed from the other end runs as
+    role: ProcessRole,
+    endpoints: Mutex<HashMap<DeploymentId, Endpoint>>,
+}
+
+impl Link {
+    async fn send(&self, message: Message) -> Result<(), TransportError> {
+        match message.to_bytes() {
+            Some(frame) => self.transport.send(frame).await,
+            None => Ok(()),
+        }
+    }
+
+    // Hands the operation to the process it is for, in its own terms. A
+    // value that does not hold in its code is an error, which drops the link.
+    async fn deliver<'a>(
+        self: &Arc<Self>,
+        executor: &Arc<Executor<'a>>,
+        deployment: DeploymentId,
+        operation: Operation,
+    ) -> Result<(), SnapshotError> {
+        let mut endpoints = self.endpoints.lock().await;
+        let Some(endpoint) = endpoints.get_mut(&deployment) else {
+            return Ok(());
+        };
+        let mut shared = Vec::new();
+        let delivered = match operation {
+            Operation::Bind(_, name, value) => match endpoint.variables.get(&name).copied() {
+                Some(variable) => {
+                    endpoint.code.value(&value)?;
+                    let value = endpoint.from_wire(&value, &mut shared);
+                    Some(Operation::Bind(endpoint.thread_id, variable, value))
+                }
+                None => None,
+            },
+            Operation::SynchVar(_, Value::Unbound(name)) => {
+                if let Some(variable) = endpoint.variables.get(&name) {
+                    endpoint.store.need(*variable);
+                }
+                None
+            }
+            Operation::ChildExit(_, _, completed) => {
+                let exit = Operation::ChildExit(endpoint.thread_id, endpoint.process_id, completed);
+                let mailbox = endpoint.mailbox.clone();
+                endpoints.remove(&deployment);
+                drop(endpoints);
+                let _ = mailbox.send(exit).await;
+                return Ok(());
+            }
+            _ => None,
+        };
+        let mailbox = endpoint.mailbox.clone();
+        drop(endpoints);
+        if let Some(operation) = delivered {
+            let _ = mailbox.send(operation).await;
+        }
+        self.share(executor, deployment, shared).await;
+        Ok(())
+    }
+
+    // posts the message to the deployed process at this end
+    async fn post(&self, deployment: DeploymentId, message: Value) -> Result<(), SnapshotError> {
+        let endpoints = self.endpoints.lock().await;
+        let Some(Endpoint { code, mail: Some(mail), .. }) = endpoints.get(&deployment) else {
+            return Ok(());
+        };
+        code.value(&message)?;
+        // unbounded, closed once the process ended, which drops its mail
+        let _ = mail.try_send(message);
+        Ok(())
+    }
+
+    // bridges the variables newly shared over the deployment
+    async fn share<'a>(
+        self: &Arc<Self>,
+        executor: &Arc<Executor<'a>>,
+        deployment: DeploymentId,
+        shared: Vec<(VariableId, VariableId)>,
+    ) {
+        if shared.is_empty() {
+            return;
+        }
+        let mut endpoints = self.endpoints.lock().await;
+        let Some(endpoint) = endpoints.get_mut(&deployment) else {
+            return;
+        };
+        for (name, variable) in shared {
+            let bridge = bridge(executor.clone(), self.clone(), deployment, name, variable);
+            endpoint.tasks.push(executor.spawn(bridge));
+        }
+    }
+}
+
+/// The nodes a runtime is connected to.
+pub struct Network {
+    links: Mutex<HashMap<NodeId, Arc<Link>>>,
+    next_node: AtomicU64,
+    next_deployment: AtomicU64,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self {
+            links: Mutex::new(HashMap::new()),
+            next_node: AtomicU64::new(1),
+            next_deployment: AtomicU64::new(1),
+        }
+    }
+
+    /// The nodes connected, by id.
+    pub async fn nodes(&self) -> Vec<NodeId> {
+        let mut nodes: Vec<NodeId> = self.links.lock().await.keys().copied().collect();
+        nodes.sort();
+        nodes
+    }
+
+    pub(crate) async fn add(&self, transport: Arc<dyn Transport>, role: ProcessRole) -> (NodeId, Arc<Link>) {
+        let node = self.next_node.fetch_add(1, Ordering::SeqCst);
+        let link = Arc::new(Link { transport, role, endpoints: Mutex::new(HashMap::new()) });
+        self.links.lock().await.insert(node, link.clone());
+        (node, link)
+    }
+
+    pub(crate) async fn close(&self, node: NodeId) -> bool {
+        match self.links.lock().await.get(&node) {
+            Some(link) => {
+                link.transport.close();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sends the closure to `node`, or to the first node connected, to run as
+    /// a process there. The variables it captured that are not bound yet are
+    /// shared with it from then on, and the mail sent to it goes there.
+    pub(crate) async fn deploy<'a>(
+        &self,
+        executor: &Arc<Executor<'a>>,
+        node: Option<NodeId>,
+        deploying: Deploying,
+    ) -> Result<NodeId, Box<dyn Error>> {
+        let chosen = {
+            let links = self.links.lock().await;
+            match node {
+                Some(node) => links.get(&node).map(|link| (node, link.clone())),
+                None => links.iter().min_by_key(|(node, _)| **node).map(|(node, link)| (*node, link.clone())),
+            }
+        };
+        let Some((node_id, link)) = chosen else {
+            return Err(match node {
+                Some(node) => format!("node {} is not connected", node).into(),
+                None => "there is no node to deploy to".into(),
+            });
+        };
+        let deployment = self.next_deployment.fetch_add(1, Ordering::SeqCst);
+        let shared: Vec<(VariableId, VariableId)> =
+            unbound_variables(&deploying.closure).into_iter().map(|variable| (variable, variable)).collect();
+        let (mail, outbox) = unbounded();
+        deploying.table.deployed(deploying.process_id, mail);
+        let forward = executor.spawn(forward(link.clone(), deployment, outbox));
+        link.endpoints.lock().await.insert(
+            deployment,
+            Endpoint {
+                thread_id: deploying.thread_id,
+                process_id: deploying.process_id,
+                store: deploying.store,
+                mailbox: deploying.mailbox,
+                code: deploying.code,
+                next_variable: deploying.next_variable,
+                deployed: false,
+                variables: shared.iter().copied().collect(),
+                names: shared.iter().map(|(name, variable)| (*variable, *name)).collect(),
+                mail: None,
+                table: Some(deploying.table),
+                tasks: vec![forward],
+            },
+        );
+        let spawn = Message::Spawn {
+            deployment,
+            path: deploying.path,
+            source: deploying.source,
+            closure: deploying.closure,
+        };
+        if let Err(error) = link.send(spawn).await {
+            link.endpoints.lock().await.remove(&deployment);
+            return Err(error.into());
+        }
+        // what the bridges send goes to a process the other end knows by now
+        link.share(executor, deployment, shared).await;
+        Ok(node_id)
+    }
+
+    async fn remove(&self, node: NodeId) {
+        self.links.lock().await.remove(&node);
+    }
+}
+
+/// What a process deploys, see `Network::deploy`.
+pub(crate) struct Deploying {
+    pub(crate) thread_id: ThreadId,
+    pub(crate) process_id: ProcessId,
+    pub(crate) store: Arc<Store>,
+    pub(crate) mailbox: Sender<Operation>,
+    pub(crate) code: Code,
+    pub(crate) next_variable: Arc<AtomicU64>,
+    pub(crate) table: Arc<ProcessTable>,
+    pub(crate) path: String,
+    pub(crate) source: String,
+    pub(crate) closure: Value,
+}
+
+// Tells the other end when the variable is needed here, then what it is bound
+// to. The other end does the same, so whichever side binds it first, the
+// binding ends up on both. What is left unbound in the binding gets a bridge
+// of its own once sent.
+fn bridge<'a>(
+    executor: Arc<Executor<'a>>,
+    link: Arc<Link>,
+    deployment: DeploymentId,
+    name: VariableId,
+    variable: VariableId,
+) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+    Box::pin(async move {
+        let endpoint = {
+            let endpoints = link.endpoints.lock().await;
+            endpoints.get(&deployment).map(|endpoint| (endpoint.store.clone(), endpoint.thread_id, endpoint.mailbox.clone()))
+        };
+        let Some((store, thread_id, mailbox)) = endpoint else {
+            return;
+        };
+        store.wait_needed(variable).await;
+        if matches!(store.deref(&Value::Unbound(variable)), Value::Unbound(_)) {
+            let need = Operation::SynchVar(thread_id, Value::Unbound(name));
+            let _ = link.send(Message::Operation(deployment, need)).await;
+        }
+        let value = store.determined(variable).await;
+        let Some(value) = store.resolve(&value) else {
+            let error = String::from("cells cannot be sent to another node");
+            let _ = mailbox.send(Operation::ThreadFailure(thread_id, error, Vec::new())).await;
+            return;
+        };
+        let mut shared = Vec::new();
+        let value = match link.endpoints.lock().await.get_mut(&deployment) {
+            Some(endpoint) => endpoint.to_wire(&value, &mut shared),
+            None => return,
+        };
+        let _ = link.send(Message::Operation(deployment, Operation::Bind(thread_id, name, value))).await;
+        link.share(&executor, deployment, shared).await;
+    })
+}
+
+// sends the mail for the deployed process over the link, in order
+async fn forward(link: Arc<Link>, deployment: DeploymentId, outbox: Receiver<Value>) {
+    while let Ok(message) = outbox.recv().await {
+        if link.send(Message::Mail(deployment, message)).await.is_err() {
+            break;
+        }
+    }
+}
+
+// Puts the mail that came over the link in the mailbox of the process, in
+// order. Senders on the other node do not wait for room in it.
+async fn receive_mail(mail: Receiver<Value>, mailbox: Sender<Value>) {
+    while let Ok(message) = mail.recv().await {
+        if mailbox.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// What a runtime shares with the processes deployed to it.
+#[derive(Clone)]
+pub(crate) struct Node<'a> {
+    pub(crate) executor: Arc<Executor<'a>>,
+    pub(crate) ids: Arc<Ids>,
+    pub(crate) config: ProcessConfig,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) modules: Arc<Modules>,
+    pub(crate) network: Arc<Network>,
+    pub(crate) table: Arc<ProcessTable>,
+}
+
+impl<'a> Node<'a> {
+    /// Handles what comes from the node at the other end of the link, until
+    /// it is closed. The processes with a deployment over the link are then
+    /// told the node is down: for a deploying process, its child failed, and
+    /// the threads waiting on what it shares with it fail.
+    pub(crate) async fn serve(self, node: NodeId, link: Arc<Link>) {
+        // a garbled frame drops the link too
+        while let Ok(frame) = link.transport.recv().await {
+            let Ok(message) = Message::from_bytes(&frame) else {
+                break;
+            };
+            let delivered = match message {
+                Message::Spawn { deployment, path, source, closure } => {
+                    if self.spawn(&link, deployment, path, source, closure).await.is_err() {
+                        let exit = Operation::ChildExit(0, 0, false);
+                        let _ = link.send(Message::Operation(deployment, exit)).await;
+                    }
+                    Ok(())
+                }
+                Message::Operation(deployment, operation) => link.deliver(&self.executor, deployment, operation).await,
+                Message::Mail(deployment, message) => link.post(deployment, message).await,
+            };
+            if delivered.is_err() {
+                break;
+            }
+        }
+        link.transport.close();
+        self.network.remove(node).await;
+        let endpoints: Vec<Endpoint> = link.endpoints.lock().await.drain().map(|(_, endpoint)| endpoint).collect();
+        for endpoint in endpoints {
+            let shared = endpoint.variables.values().copied().collect();
+            let down = Operation::NodeDown(endpoint.thread_id, node, endpoint.process_id, shared);
+            let _ = endpoint.mailbox.send(down).await;
+        }
+    }
+
+    // the shared variables get a variable of their own in the new process,
+    // which runs no higher than the link allows
+    async fn spawn(
+        &self,
+        link: &Arc<Link>,
+        deployment: DeploymentId,
+        path: String,
+        source: String,
+        closure: Value,
+    ) -> Result<(), Box<dyn Error>> {
+        let interfaces = self.modules.interfaces().await;
+        let process = Process::new(self.executor.clone(), self.ids.clone(), self.config.clone(), source, path, &interfaces)?
+            .with_clock(self.clock.clone())
+            .with_modules(self.modules.clone())
+            .with_network(self.network.clone());
+        let mut variables = HashMap::new();
+        let closure = rename(&closure, &mut |name| *variables.entry(name).or_insert_with(|| process.fresh_variable()));
+        let mut process = process
+            .deployed(closure, variables.values().copied().collect(), link.role)?
+            .with_table(self.table.clone());
+        let store = process.store();
+        let (mail, received) = unbounded();
+        let shared: Vec<(VariableId, VariableId)> = variables.iter().map(|(name, variable)| (*name, *variable)).collect();
+        link.endpoints.lock().await.insert(
+            deployment,
+            Endpoint {
+                thread_id: process.main_thread(),
+                process_id: process.process_id(),
+                store: store.clone(),
+                mailbox: process.mailbox(),
+                code: process.code(),
+                next_variable: process.next_variable(),
+                deployed: true,
+                names: shared.iter().map(|(name, variable)| (*variable, *name)).collect(),
+                variables,
+                mail: Some(mail),
+                table: None,
+                tasks: vec![self.executor.spawn(receive_mail(received, process.inbox()))],
+            },
+        );
+        link.share(&self.executor, deployment, shared).await;
+        let link = link.clone();
+        self.executor
+            .spawn(async move {
+                let completed = process.run().await.is_ok();
+                // what the process bound goes out before its exit, the
+                // deploying side stops listening then
+                let Some(mut endpoint) = link.endpoints.lock().await.remove(&deployment) else {
+                    return;
+                };
+                let thread_id = endpoint.thread_id;
+                let bound: Vec<(VariableId, VariableId)> =
+                    endpoint.variables.iter().map(|(name, variable)| (*name, *variable)).collect();
+                for (name, variable) in bound {
+                    if matches!(store.deref(&Value::Unbound(variable)), Value::Unbound(_)) {
+                        continue;
+                    }
+                    if let Some(value) = store.resolve(&Value::Unbound(variable)) {
+                        let value = endpoint.to_wire(&value, &mut Vec::new());
+                        let _ = link.send(Message::Operation(deployment, Operation::Bind(thread_id, name, value))).await;
+                    }
+                }
+                let _ = link.send(Message::Operation(deployment, Operation::ChildExit(thread_id, 0, completed))).await;
+            })
+            .detach();
+        Ok(())
+    }
+}
+
+// the unbound variables in a resolved value
+pub(crate) fn unbound_variables(value: &Value) -> Vec<VariableId> {
+    let mut variables = Vec::new();
+    rename(value, &mut |variable| {
+        if !variables.contains(&variable) {
+            variables.push(variable);
+        }
+        variable
+    });
+    variables
+}
+
+// a copy of a resolved value, with each unbound variable renamed
+fn rename(value: &Value, rename_variable: &mut impl FnMut(VariableId) -> VariableId) -> Value {
+    // the elements of a list, its spine walked without recursing
+    let mut heads = Vec::new();
+    let mut value = value;
+    while let Value::Cons(tag, cell) = value {
+        heads.push((*tag, rename(&cell.0, rename_variable)));
+        value = &cell.1;
+    }
+    let mut renamed = match value {
+        Value::Unbound(variable) => Value::Unbound(rename_variable(*variable)),
+        Value::Map(tag, map) => {
+            let mut renamed = ValueMap::new();
+            for (key, value) in map.iter() {
+                renamed = renamed.insert(key.clone(), rename(value, rename_variable));
+            }
+            Value::Map(*tag, renamed)
+        }
+        Value::Closure(tag, closure) => {
+            let env = closure.env.iter().map(|value| rename(value, rename_variable)).collect();
+            Value::Closure(*tag, Arc::new(Closure { fun: closure.fun, env }))
+        }
+        Value::Record(tag, record) => {
+            let fields = record.fields.iter().map(|value| rename(value, rename_variable)).collect();
+            Value::Record(*tag, Arc::new(Record { label: record.label.clone(), fields }))
+        }
+        value => value.clone(),
+    };
+    while let Some((tag, head)) = heads.pop() {
+        renamed = Value::Cons(tag, Arc::new((head, renamed)));
+    }
+    renamed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{format, string::ToString};
+    use crate::compiler::value::NO_VARIABLE;
+    use crate::scheduler::runtime::{ProcessExit, ProcessFailure, Runtime};
+    use crate::scheduler::transport::MemoryTransport;
+
+    #[test]
+    fn messages_read_back_what_was_written() {
+        let closure = Value::Closure(
+            NO_VARIABLE,
+            Arc::new(Closure {
+                fun: werbolg_core::ValueFun::Fun(<werbolg_core::FunId as werbolg_core::id::IdF>::from_collection_len(3)),
+                env: alloc::vec![Value::Unbound(4), Value::Integer(NO_VARIABLE, 2)],
+            }),
+        );
+        let spawn = Message::Spawn {
+            deployment: 1,
+            path: "a.sio".to_string(),
+            source: "general app::A {}".to_string(),
+            closure: closure.clone(),
+        };
+        match Message::from_bytes(&spawn.to_bytes().unwrap()).unwrap() {
+            Message::Spawn { deployment, path, source, closure: read } => {
+                assert_eq!((deployment, path.as_str(), source.as_str()), (1, "a.sio", "general app::A {}"));
+                assert!(read.equals(&closure));
+            }
+            message => panic!("a spawn, got {:?}", message),
+        }
+        assert_eq!(unbound_variables(&closure), [4]);
+        let bind = Message::Operation(1, Operation::Bind(2, 4, Value::Integer(NO_VARIABLE, 5)));
+        assert!(matches!(
+            Message::from_bytes(&bind.to_bytes().unwrap()).unwrap(),
+            Message::Operation(1, Operation::Bind(2, 4, Value::Integer(_, 5)))
+        ));
+        let mail = Message::Mail(1, Value::Integer(NO_VARIABLE, 6));
+        assert!(matches!(
+            Message::from_bytes(&mail.to_bytes().unwrap()).unwrap(),
+            Message::Mail(1, Value::Integer(_, 6))
+        ));
+        assert!(Message::Operation(1, Operation::ThreadTerminate(2)).to_bytes().is_none());
+        assert!(Message::from_bytes(&[PROTOCOL as u8 + 1]).is_err());
+    }
+
+    // x is bound on the first node and read on the second, which binds y
+    // for the first one to read, and l to a list whose tail it binds once
+    // the first one needs it
+    static SHARES: &str = "
+        general app::A {
+            pub main :: () {
+                let x, y, l;
+                let child = deploy(() {
+                    y = x + 1;
+                    let t;
+                    l = [y | t];
+                    wait_needed(t);
+                    t = [3];
+                });
+                x = 1;
+                let z = y + 0;
+                z = 2;
+                let h = head(tail(l));
+                h = 3;
+                let completed = supervise(child);
+                completed = true;
+            }
+        }";
+
+    // the deployed process waits on x forever
+    static WAITS: &str = "
+        general app::A {
+            pub main :: () {
+                let x;
+                let child = deploy(() { let y = x + 0; });
+                let completed = supervise(child);
+                completed = false;
+            }
+        }";
+
+    // the deployed process gets what is sent to it, and runs as a corporal
+    // unless the link allows more
+    static MAILS: &str = "
+        general app::A {
+            pub main :: () {
+                let child = deploy(() {
+                    let got = receive();
+                    got = 1;
+                    spawn_process(() { 2; });
+                });
+                send(child, 1);
+                let completed = supervise(child);
+            }
+        }";
+
+    fn connected<'a>(
+        ex: &Arc<Executor<'a>>,
+        first: impl Transport + 'static,
+        second: impl Transport + 'static,
+    ) -> (Runtime<'a>, Runtime<'a>) {
+        connected_as(ex, first, second, ProcessRole::Corporal)
+    }
+
+    // the second runtime lets the first deploy processes up to `role`
+    fn connected_as<'a>(
+        ex: &Arc<Executor<'a>>,
+        first: impl Transport + 'static,
+        second: impl Transport + 'static,
+        role: ProcessRole,
+    ) -> (Runtime<'a>, Runtime<'a>) {
+        let mut a = Runtime::new(ex.clone());
+        let mut b = Runtime::new(ex.clone());
+        smol::block_on(async {
+            a.connect(first).await;
+            b.connect_as(second, role).await;
+        });
+        (a, b)
+    }
+
+    #[test]
+    fn deployed_processes_share_dataflow_variables() {
+        let ex = Arc::new(Executor::new());
+        let (first, second) = MemoryTransport::pair();
+        let (mut a, _b) = connected(&ex, first, second);
+        let process_id = smol::block_on(a.spawn(SHARES.to_string(), "shares.sio".to_string())).unwrap();
+        let exit = smol::block_on(ex.run(a.join(process_id)));
+        assert_eq!(exit, Some(ProcessExit::Completed));
+    }
+
+    #[test]
+    fn a_node_going_down_fails_the_threads_sharing_with_it() {
+        let ex = Arc::new(Executor::new());
+        let (first, second) = MemoryTransport::pair();
+        let (mut a, b) = connected(&ex, first, second);
+        let process_id = smol::block_on(a.spawn(WAITS.to_string(), "waits.sio".to_string())).unwrap();
+        let (exit, deployed) = smol::block_on(ex.run(async {
+            // the link goes down once the process is deployed, before or
+            // after it waits on x
+            while b.processes().is_empty() {
+                smol::future::yield_now().await;
+            }
+            assert!(b.disconnect(1).await);
+            let exit = a.join(process_id).await;
+            while b.processes()[0].exit.is_none() {
+                smol::future::yield_now().await;
+            }
+            (exit, b.processes()[0].exit.clone())
+        }));
+        // the deploying process sees its child fail, and completes
+        assert_eq!(exit, Some(ProcessExit::Completed));
+        let Some(ProcessExit::Failed(ProcessFailure::Thread(failure))) = deployed else {
+            panic!("a thread failure, got {:?}", deployed);
+        };
+        assert_eq!(failure.error, format!("node {} disconnected", 1));
+    }
+
+    #[test]
+    fn deployed_processes_get_mail_and_run_as_the_link_allows() {
+        let ex = Arc::new(Executor::new());
+        let (first, second) = MemoryTransport::pair();
+        let (mut a, b) = connected(&ex, first, second);
+        let process_id = smol::block_on(a.spawn(MAILS.to_string(), "mails.sio".to_string())).unwrap();
+        assert_eq!(smol::block_on(ex.run(a.join(process_id))), Some(ProcessExit::Completed));
+        // the message came, then the corporal could not spawn a process
+        let failed = b.processes()[0].exit.clone();
+        let Some(ProcessExit::Failed(ProcessFailure::Thread(failure))) = failed else {
+            panic!("a thread failure, got {:?}", failed);
+        };
+        assert!(failure.error.contains("a corporal cannot spawn processes"), "{}", failure.error);
+
+        let (first, second) = MemoryTransport::pair();
+        let (mut a, b) = connected_as(&ex, first, second, ProcessRole::General);
+        let process_id = smol::block_on(a.spawn(MAILS.to_string(), "mails.sio".to_string())).unwrap();
+        assert_eq!(smol::block_on(ex.run(a.join(process_id))), Some(ProcessExit::Completed));
+        assert_eq!(b.processes()[0].exit, Some(ProcessExit::Completed));
+    }
+
+    #[test]
+    fn processes_deploy_to_connected_nodes_only() {
+        let ex = Arc::new(Executor::new());
+        let (first, second) = MemoryTransport::pair();
+        let (mut a, _b) = connected(&ex, first, second);
+        let process_id = smol::block_on(a.spawn("
+            general app::A {
+                pub main :: () {
+                    deploy_to(2, () { 1; });
+                }
+            }".to_string(), "elsewhere.sio".to_string()))
+            .unwrap();
+        let exit = smol::block_on(ex.run(a.join(process_id)));
+        let Some(ProcessExit::Failed(ProcessFailure::Thread(failure))) = exit else {
+            panic!("a thread failure, got {:?}", exit);
+        };
+        assert_eq!(failure.error, "node 2 is not connected");
+    }
+
+    #[test]
+    fn nodes_connect_over_tcp() {
+        use crate::scheduler::transport::TcpTransport;
+        let ex = Arc::new(Executor::new());
+        let (first, second) = smol::block_on(async {
+            let listener = smol::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let accepted = TcpTransport::accept(&listener);
+            let connecting = TcpTransport::connect(address);
+            let (first, second) = smol::future::zip(accepted, connecting).await;
+            (first.unwrap(), second.unwrap())
+        });
+        let (mut a, _b) = connected(&ex, first, second);
+        let process_id = smol::block_on(a.spawn(SHARES.to_string(), "shares.sio".to_string())).unwrap();
+        let exit = smol::block_on(ex.run(a.join(process_id)));
+        assert_eq!(exit, Some(ProcessExit::Completed));
+    }
+}
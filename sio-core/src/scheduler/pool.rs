@@ -0,0 +1,110 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::future::Future;
+use std::thread::{self, JoinHandle};
+use async_channel::{bounded, Receiver, Sender};
+use smol::Executor;
+
+/// Runs one executor from several OS threads. Every worker drives the same
+/// `Executor`, which keeps a local queue per worker and lets idle workers
+/// steal from busy ones, so the sio threads of a process, and the processes
+/// themselves, spread over all the cores. The dataflow semantics do not
+/// depend on it: threads only meet through their process.
+pub struct ExecutorPool {
+    executor: Arc<Executor<'static>>,
+    workers: Vec<JoinHandle<()>>,
+    // closing it stops the workers
+    shutdown: Sender<()>,
+}
+
+impl ExecutorPool {
+    pub fn new(workers: usize) -> Self {
+        let executor = Arc::new(Executor::new());
+        let (shutdown, stop): (Sender<()>, Receiver<()>) = bounded(1);
+        let workers = (0..workers.max(1))
+            .map(|worker| {
+                let executor = executor.clone();
+                let stop = stop.clone();
+                thread::Builder::new()
+                    .name(alloc::format!("sio-worker-{}", worker))
+                    .spawn(move || {
+                        let _ = smol::block_on(executor.run(stop.recv()));
+                    })
+                    .expect("spawning a worker thread")
+            })
+            .collect();
+        Self { executor, workers, shutdown }
+    }
+
+    /// One worker per available core.
+    pub fn with_available_parallelism() -> Self {
+        Self::new(thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+    }
+
+    pub fn executor(&self) -> Arc<Executor<'static>> {
+        self.executor.clone()
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs `future` to completion on the calling thread, which helps the
+    /// workers with the other tasks in the meantime.
+    pub fn block_on<T>(&self, future: impl Future<Output = T>) -> T {
+        smol::block_on(self.executor.run(future))
+    }
+
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.shutdown.close();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for ExecutorPool {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{boxed::Box, string::ToString};
+    use core::error::Error;
+    use crate::scheduler::Runtime;
+
+    static PARALLEL: &str = "
+        corporal app::Corporal {
+            pub main :: () {
+                let a, b, c;
+                thread { a = count(200); }
+                thread { b = count(200); }
+                thread { c = count(200); }
+                let total = a + b + c;
+                total = 600;
+            }
+            count :: (n) {
+                if n == 0 { 0; } else { 1 + count(n - 1); }
+            }
+        }";
+
+    #[test]
+    fn processes_run_on_several_workers() {
+        let pool = ExecutorPool::new(4);
+        assert_eq!(pool.workers(), 4);
+        let runtime = Runtime::new(pool.executor());
+        let mut first = pool.block_on(runtime.create(PARALLEL.to_string(), "first.sio".to_string())).unwrap();
+        let mut second = pool.block_on(runtime.create(PARALLEL.to_string(), "second.sio".to_string())).unwrap();
+        let (first, second): (Result<(), Box<dyn Error>>, Result<(), Box<dyn Error>>) =
+            pool.block_on(smol::future::zip(first.run(), second.run()));
+        first.expect("the first process completes");
+        second.expect("the second process completes");
+        pool.shutdown();
+    }
+}
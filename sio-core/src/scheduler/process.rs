@@ -1,283 +1,1244 @@
-use alloc::{format, vec, vec::Vec, boxed::Box, string::String, sync::Arc};
-const MSGSIZE: usize = 10;
+use alloc::{format, vec, vec::Vec, boxed::Box, string::{String, ToString}, sync::Arc, collections::BinaryHeap};
+use core::cmp::Reverse;
 //use log::*;
 use hashbrown::HashMap;
 use core::sync::atomic::{AtomicU64, Ordering};
-use async_channel::{unbounded, Receiver, Sender};
-use smol::{Executor};
+use async_channel::{bounded, unbounded, Receiver, Sender};
+use smol::{Executor, Task, lock::Mutex};
 use werbolg_exec::{
-    ExecutionEnviron, 
-    //ExecutionError, 
+    ExecutionError,
     ExecutionMachine, ExecutionParams, WerRefCount, step
 };
-use crate::frontend;
+use crate::frontend::{self, imports::Interface, position::Diagnostic};
 use crate::compiler::{
     //process::run_frontend,
-    ThreadExecutionMachine, ThreadEnvironment, ThreadAllocator, ThreadLiteral, RunningThreadState, ThreadValue as Value, thread_literal_mapper, thread_literal_to_value
+    ThreadExecutionMachine, ThreadExecutionEnviron, ThreadEnvironment, ThreadAllocator, ThreadLiteral, RunningThreadState, ThreadValue as Value, thread_literal_mapper, thread_literal_to_value
 };
-use werbolg_core::{AbsPath, Ident, Namespace, ir::Module};
-use werbolg_compile::{compile};
+use werbolg_core::{AbsPath, FunId, Ident, Namespace, ValueFun, ir::{Module, Statement}};
+use werbolg_compile::{compile, CompilationUnit, InstructionAddress};
 use werbolg_lang_common::{Report, ReportKind, Source};
-use crate::compiler::create_thread_env;
-use crate::compiler::value::VariableId;
+use crate::compiler::value::{CellId, VariableId, FIRST_VARIABLE, NO_VARIABLE};
+use crate::scheduler::store::Store;
+use crate::scheduler::config::{ProcessConfig, ProcessRole};
+use crate::scheduler::quota::{Meter, Quota, QuotaExceeded, Usage};
+use crate::scheduler::deadlock::{Deadlock, Declaration, Suspension, SuspendedThread};
+use crate::scheduler::failure::{SourceMap, ThreadFailure};
+use crate::scheduler::time::{Clock, Millis};
+use crate::scheduler::deterministic::{BoxedTask, DeterministicExecutor, Schedule, ScheduleMode};
+use crate::scheduler::script::{self, Event, Trace};
+use crate::scheduler::runtime::{Ids, ProcessExit, ProcessTable};
+use crate::scheduler::machine::{self, Code, MachineSnapshot};
+use crate::scheduler::snapshot::{Snapshot, VariableSnapshot};
+use crate::scheduler::mailbox::{Mailboxes, Mail};
+use crate::scheduler::modules::{ExternalCall, LoadedModule, Modules};
+use crate::scheduler::node::{unbound_variables, Deploying, Network, NodeId};
+use core::future::Future;
+use crate::frontend::ast::ModuleKind;
+use smol::future::yield_now;
 use core::error::Error;
 //use log::info;
-static NEXT_PROCESS_ID: AtomicU64 = AtomicU64::new(0);
-type ThreadId = u64;
-type ProcessId = u64;
+pub type ThreadId = u64;
+
+// variables created between two sweeps of the store of a quiescent process
+const SWEEP_INTERVAL: VariableId = 4096;
+pub type ProcessId = u64;
 #[derive(Debug, Clone)]
 pub enum Operation {
     SynchVar(ThreadId, Value),
-    ThreadSpawn(ThreadId, Vec<Operation>),
+    Bind(ThreadId, VariableId, Value),
+    Unify(ThreadId, Value, Value),
+    NewCell(ThreadId, CellId, Value),
+    // replaces the content when there is a new one, and binds the variable
+    // to the content the cell had before
+    Exchange(ThreadId, CellId, Option<Value>, VariableId),
+    ThreadSpawn(ThreadId, ThreadEntry),
     WaitNeeded(ThreadId, VariableId),
+    // binds the variable to nil once the clock of the process reached the
+    // deadline, or fails the process for `NO_VARIABLE`
+    Timer(ThreadId, Millis, VariableId),
     ThreadTerminate(ThreadId),
+    // the thread stopped on an error, which fails the process, along with the
+    // instruction it failed on followed by the return addresses of its stack
+    ThreadFailure(ThreadId, String, Vec<InstructionAddress>),
+    // the thread took the process over its quota, which kills it
+    QuotaExceeded(ThreadId, QuotaExceeded),
+    // starts a child process running the closure, and binds the variable to
+    // its id
+    ProcessSpawn(ThreadId, Value, VariableId),
+    // binds the variable once the child process ended, to whether it completed
+    Supervise(ThreadId, ProcessId, VariableId),
+    // the child process the thread started ended, and whether it completed
+    ChildExit(ThreadId, ProcessId, bool),
+    // the thread made an external call, and runs the module until it
+    // returns
+    Call(ThreadId, Arc<LoadedModule>),
+    // the last external call the thread made returned
+    Return(ThreadId),
+    // starts a process running the closure on the node given, or on any,
+    // and binds the variable to its id
+    Deploy(ThreadId, Option<NodeId>, Value, VariableId),
+    // the node at the other end of a deployment is gone, with the process
+    // there and the variables shared with it
+    NodeDown(ThreadId, NodeId, ProcessId, Vec<VariableId>),
     //Portcullis(ThreadId, Operation),
 }
+/// What a spawned thread runs.
+#[derive(Debug, Clone)]
+pub enum ThreadEntry {
+    /// A `thread { ... }` block: the closure it was lowered to, holding the
+    /// lifted function and the variables the block captured.
+    Closure(Value),
+    /// A scripted thread, as used to describe a schedule of operations.
+    Script(Vec<Operation>),
+}
+// a process started by a thread of another one
+struct Child {
+    // dropping it cancels the child
+    task: Option<Task<()>>,
+    // whether it completed, once it ended
+    exit: Option<bool>,
+    // the variables `supervise` is waiting to bind to the exit
+    supervisors: Vec<VariableId>,
+}
 impl Operation {
+    pub fn spawn(thread_id: ThreadId, script: Vec<Operation>) -> Self {
+        Self::ThreadSpawn(thread_id, ThreadEntry::Script(script))
+    }
     pub fn unbound(thread_id: ThreadId, id: VariableId) -> Self {
         Self::SynchVar(thread_id, Value::Unbound(id))
     }
     pub fn int(thread_id: ThreadId, id: VariableId, value: u64) -> Self {
-        Self::SynchVar(thread_id, Value::Integer(id, value))
+        Self::Bind(thread_id, id, Value::Integer(NO_VARIABLE, value))
     }
 }
-enum ProcessRole {
-    Corporal,
-    Major,
-    Brigadier,
-}
-static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(0);
-enum ThreadState {
-    Running,
-    Waiting(Operation),
-    WaitNeeded(VariableId),
-}
 pub struct Thread {
     thread_id: ThreadId,
     thread_to_process_sender: Sender<Operation>,
-    process_to_thread_receiver: Receiver<Operation>,
-    state: ThreadState,
-    em: ThreadExecutionMachine,
+    // steps left before yielding, and the budget it is reset to
+    reductions: u32,
+    budget: u32,
+    // the usage of the process, which the thread counts its steps in
+    meter: Arc<Meter>,
+    quota: Quota,
+    // locked while the thread steps, free while it is suspended, which is
+    // when a snapshot reads it; during an external call, the machine of the
+    // module called
+    em: Arc<Mutex<ThreadExecutionMachine>>,
+    // the code `em` runs, and the modules its external calls go to
+    code: Arc<LoadedModule>,
+    modules: Arc<Modules>,
+    // the role the thread started with, no module it calls runs above it
+    role: ProcessRole,
+    // the external calls in progress, innermost last
+    callers: Vec<Caller>,
+}
+// an external call in progress, which returns to the machine that made it
+struct Caller {
+    em: Arc<Mutex<ThreadExecutionMachine>>,
+    code: Arc<LoadedModule>,
+    // bound to what the call returns
+    result: VariableId,
 }
 impl<'a> Thread {
     pub fn new(
         thread_id: ThreadId,
         thread_to_process_sender: Sender<Operation>, 
-        process_to_thread_receiver: Receiver<Operation>, 
-        em: ThreadExecutionMachine) -> Self {
+        budget: u32,
+        meter: Arc<Meter>,
+        quota: Quota,
+        em: ThreadExecutionMachine,
+        code: Arc<LoadedModule>,
+        modules: Arc<Modules>) -> Self {
+        let budget = budget.max(1);
         Self {
             thread_id,
             thread_to_process_sender,
-            state: ThreadState::Running,
-            process_to_thread_receiver,
-            em,
+            reductions: budget,
+            budget,
+            meter,
+            quota,
+            role: em.userdata.role,
+            em: Arc::new(Mutex::new(em)),
+            code,
+            modules,
+            callers: Vec::new(),
         }
     }
-    fn werbolg_exec(&mut self) -> Option<Operation> {
-        match step(&mut self.em).ok()? {
-            None => None,
-            Some(v) => Some(Operation::SynchVar(self.thread_id, v)),
+    pub(crate) fn machine(&self) -> Arc<Mutex<ThreadExecutionMachine>> {
+        self.em.clone()
+    }
+    async fn send(&self, operation: Operation) {
+        let _ = self.thread_to_process_sender.send(operation).await;
+    }
+    // Looks the function up in the current version of the module, and runs
+    // it on a machine of its own until it returns to the one calling it.
+    async fn call(&mut self, mut state: RunningThreadState, call: ExternalCall) -> Result<(), String> {
+        let Some((module, function)) = self.code.externals.get(call.external).cloned() else {
+            return Err(format!("there is no external call {}", call.external));
+        };
+        let Some(callee) = self.modules.current(&module).await else {
+            return Err(format!("module {} is not loaded", module));
+        };
+        let Some(entry_point) = callee.export(&function) else {
+            return Err(format!("{} {} has no public function {}", callee.name(), callee.version(), function));
+        };
+        if !Arc::ptr_eq(&callee, &self.code) && call.args.iter().any(|arg| state.store.holds_code(arg)) {
+            return Err(format!("a function cannot be passed to {} {}, which runs other code", callee.name(), callee.version()));
         }
+        state.role = self.role.min(callee.role);
+        let em = build_thread_machine(callee.ee.clone(), callee.cu.clone(), state, entry_point, &call.args)
+            .map_err(|error| error.to_string())?;
+        self.send(Operation::Call(self.thread_id, callee.clone())).await;
+        self.callers.push(Caller {
+            em: core::mem::replace(&mut self.em, Arc::new(Mutex::new(em))),
+            code: core::mem::replace(&mut self.code, callee),
+            result: call.result,
+        });
+        Ok(())
+    }
+    // back to the machine that made the call, with what it returns
+    async fn ret(&mut self, caller: Caller, store: &Store, value: Value) -> Result<(), String> {
+        if !Arc::ptr_eq(&caller.code, &self.code) && store.holds_code(&value) {
+            return Err(format!("a function cannot be returned to {}, which runs other code", caller.code.name()));
+        }
+        store.unify(&Value::Unbound(caller.result), &value).map_err(|error| format!("{}", error))?;
+        self.em = caller.em;
+        self.code = caller.code;
+        self.send(Operation::Return(self.thread_id)).await;
+        Ok(())
     }
     async fn run(&mut self) {
+        let mut em = self.em.lock_arc().await;
         loop {
-            if let Some(operation) = self.werbolg_exec() {
-                match operation {
-                    Operation::SynchVar(ref _thread_id, ref value) => {
-                        match value {
-                            Value::Unbound(_index) => {
-                                //info!("thread {} sent to process: {:?}", thread.thread_id, operation.clone());
-                                self.state = ThreadState::Waiting(operation.clone());
-                                let _ = self.thread_to_process_sender.send(operation.clone()).await;
-                            },
-                            Value::Integer(_index, _value) => {
-                                //info!("thread {} sent to process: {:?}", thread.thread_id, operation.clone());
-                                self.state = ThreadState::Running;
-                                let _ = self.thread_to_process_sender.send(operation.clone()).await;
-                            }
-                            &Value::Unit | &Value::Bool(_,_) | &Value::Fun(_,_) => todo!()
-                        }
-                    },
-                    Operation::ThreadSpawn(_,_) => {
-                        //info!("thread {} sent to process: {:?}", thread.thread_id, operation.clone());
-                        let _ = self.thread_to_process_sender.send(operation.clone()).await;
-                    },
-                    Operation::WaitNeeded(thread_id, variable_index) => {
-                        //info!("thread {} sent to process: {:?}", thread.thread_id, operation.clone());
-                        self.state = ThreadState::WaitNeeded(variable_index);
-                        let _ = self.thread_to_process_sender.send(Operation::WaitNeeded(thread_id, variable_index)).await;
-                    },
-                    Operation::ThreadTerminate(_thread_id) => {
-                        let _ = self.thread_to_process_sender.send(Operation::ThreadTerminate(self.thread_id)).await;
-                        break;
+            if self.reductions == 0 {
+                self.reductions = self.budget;
+                drop(em);
+                yield_now().await;
+                em = self.em.lock_arc().await;
+            }
+            // the step is counted before it runs, one over the limit kills
+            // the process instead
+            self.meter.reduced();
+            if let Some(exceeded) = self.quota.exceeded(&self.meter.usage()) {
+                drop(em);
+                self.send(Operation::QuotaExceeded(self.thread_id, exceeded)).await;
+                return;
+            }
+            self.reductions -= 1;
+            let result = step(&mut em);
+            let pending = em.userdata.pending.take();
+            // the process is only told of the suspension to detect deadlocks,
+            // binding or needing the variable wakes the thread directly
+            if let Some(suspension) = em.userdata.suspension.take() {
+                let store = em.userdata.store.clone();
+                drop(em);
+                if let Some(operation) = pending {
+                    self.send(operation).await;
+                }
+                match suspension {
+                    Suspension::Bound(variable) => {
+                        self.send(Operation::unbound(self.thread_id, variable)).await;
+                        store.determined(variable).await;
+                    }
+                    Suspension::Needed(variable) => {
+                        self.send(Operation::WaitNeeded(self.thread_id, variable)).await;
+                        store.wait_needed(variable).await;
                     }
                 }
-            } else {
-                // There are no more operations to execute.
-                let _ = self.thread_to_process_sender.send(Operation::ThreadTerminate(self.thread_id)).await;
-                break;
+                em = self.em.lock_arc().await;
+                continue;
             }
-            match self.state {
-                ThreadState::Running => {
-                },
-                ThreadState::Waiting(ref unbound) => {
-                    //info!("thread {} is waiting for unbound variable {:?}", thread.thread_id, unbound);
-                    match self.process_to_thread_receiver.recv().await {
-                        Ok(bound) => {
-                            if let (Operation::SynchVar(_, unbound_value), Operation::SynchVar(_, bound_value)) = (unbound, bound) {
-                                if let (Value::Unbound(unbound_id), Value::Integer(bound_id, _)) = (unbound_value, bound_value) {
-                                    if *unbound_id == bound_id {
-                                        //info!("waiting thread {} found {:?} and will start running", thread.thread_id, bound_id);
-                                        self.state = ThreadState::Running;
-                                    }
-                                }
-                            }
-                        },
-                        Err(_e) => {
-                            //info!("break");
-                            // Channel was closed, which means all threads have terminated
+            // a full mailbox suspends the thread in `send`, an empty one in
+            // `receive`, which must not hold the machine while they wait
+            match (result, pending) {
+                (Ok(None), None) => {
+                    if let Some(call) = em.userdata.call.take() {
+                        let state = em.userdata.clone();
+                        let trace = machine::trace(&em);
+                        drop(em);
+                        if let Err(error) = self.call(state, call).await {
+                            self.send(Operation::ThreadFailure(self.thread_id, error, trace)).await;
                             break;
                         }
-                    }
-                },
-                ThreadState::WaitNeeded(variable_index) => {
-                    //info!("thread {} is waiting on wait_needed variable_index {}", thread.thread_id, variable_index);
-                    let operation = self.process_to_thread_receiver.recv().await;
-                    match operation {
-                        Ok(bound) => {
-                            if let Operation::SynchVar(_, Value::Integer(bound_index, _)) = bound {
-                                if variable_index == bound_index {
-                                    self.state = ThreadState::Running;
-                                }
-                            }
+                        em = self.em.lock_arc().await;
+                    } else if let Some(mail) = em.userdata.mail.take() {
+                        let mailboxes = em.userdata.mailboxes.clone();
+                        let store = em.userdata.store.clone();
+                        let clock = em.userdata.clock.clone();
+                        drop(em);
+                        // the process stopped
+                        if !mail.deliver(&mailboxes, &store, clock.as_ref()).await {
+                            return;
                         }
-                        Err(_e) => break,
+                        em = self.em.lock_arc().await;
+                    }
+                }
+                (Ok(None), Some(operation)) => {
+                    drop(em);
+                    self.send(operation).await;
+                    em = self.em.lock_arc().await;
+                }
+                (Ok(Some(value)), pending) => {
+                    let store = em.userdata.store.clone();
+                    let trace = machine::trace(&em);
+                    drop(em);
+                    if let Some(operation) = pending {
+                        self.send(operation).await;
                     }
+                    let Some(caller) = self.callers.pop() else {
+                        break;
+                    };
+                    if let Err(error) = self.ret(caller, &store, value).await {
+                        self.send(Operation::ThreadFailure(self.thread_id, error, trace)).await;
+                        break;
+                    }
+                    em = self.em.lock_arc().await;
+                }
+                (Err(error), _) => {
+                    let message = match error {
+                        ExecutionError::UserPanic { message } => message,
+                        error => format!("{:?}", error),
+                    };
+                    let trace = machine::trace(&em);
+                    drop(em);
+                    self.send(Operation::ThreadFailure(self.thread_id, message, trace)).await;
+                    break;
                 }
             }
         }
+        self.send(Operation::ThreadTerminate(self.thread_id)).await;
     }
 }
 pub struct Process<'a> {
     process_id: ProcessId,
+    path: String,
+    // allocates the thread ids, shared by the processes of a runtime
+    ids: Arc<Ids>,
     executor: Arc<Executor<'a>>,
-    //role: ProcessRole,
-    process_to_thread_senders: HashMap<ThreadId, Sender<Operation>>,
+    // set while the threads run on a deterministic executor instead
+    deterministic: Option<Sender<BoxedTask>>,
+    role: ProcessRole,
+    config: ProcessConfig,
+    // the running threads, with their task unless it is deterministic;
+    // dropping a task cancels its thread
+    threads: HashMap<ThreadId, Option<Task<()>>>,
+    // the machines of the compiled threads, for snapshots
+    machines: HashMap<ThreadId, Arc<Mutex<ThreadExecutionMachine>>>,
+    // the last suspension each thread reported, stale once it resumed
+    suspended: HashMap<ThreadId, Suspension>,
     thread_to_process_sender: Sender<Operation>,
     thread_to_process_receiver: Receiver<Operation>,
-    wait_needed_threads: HashMap<VariableId, ThreadId>,
-    waiting_threads: HashMap<VariableId, Vec<ThreadId>>,
-    bound_variables: HashMap<VariableId, Operation>,
-    source: Arc<Source>,
-    module: Module,
-    env: ThreadEnvironment,
+    // shared with the threads, which bind and wait on it directly
+    store: Arc<Store>,
+    cells: HashMap<CellId, Value>,
+    clock: Arc<dyn Clock>,
+    // deadlines of the pending timers, earliest first
+    timers: BinaryHeap<Reverse<(Millis, VariableId)>>,
+    next_variable: Arc<AtomicU64>,
+    meter: Arc<Meter>,
+    // compiled once, every thread of the process runs the same code, and
+    // its children too
+    code: Arc<LoadedModule>,
+    // played as the main thread instead of the compiled `main`
+    main_script: Option<Vec<Operation>>,
+    // run as the main thread of a child process instead of `main`
+    main_entry: Option<(FunId, Vec<Value>)>,
+    // the id of the main thread, once known
+    main_thread: Option<ThreadId>,
+    children: HashMap<ProcessId, Child>,
+    // the threads of a restored snapshot, run instead of `main`
+    restored: Vec<MachineSnapshot>,
+    // set by `run_to_snapshot`, the process stops once quiescent
+    pausing: bool,
+    // the variables created when the store was last swept
+    swept_at: VariableId,
+    // the modules loaded in the runtime, which external calls run
+    modules: Arc<Modules>,
+    // the threads running a loaded module rather than the process code from
+    // their own machine: the version they started in, if any, then those of
+    // the external calls they are in, innermost last, which they keep from
+    // being purged
+    thread_modules: HashMap<ThreadId, Vec<Arc<LoadedModule>>>,
+    // the nodes the runtime is connected to
+    network: Arc<Network>,
+    // for a process deployed from another node, the variables it shares
+    // with it, which that node can still bind
+    remote_variables: Vec<VariableId>,
+    // the variables shared with a node that went down while they were still
+    // unbound, by root
+    lost: HashMap<VariableId, NodeId>,
+    // the processes of the runtime, where this one records how it ended and
+    // its threads find the mailboxes they send to
+    table: Arc<ProcessTable>,
+    // the messages sent to the process, which its threads receive
+    inbox: (Sender<Value>, Receiver<Value>),
+    // what scripted threads did, drained by `trace`
+    events: (Sender<Event>, Receiver<Event>),
     //em: Vec<Operation>,
 }
 impl<'a> Process<'a> {
-    pub fn new(
+    // processes are created by a `Runtime`, which hands out their ids
+    pub(crate) fn new(
         executor: Arc<Executor<'a>>, 
-        //em: Vec<Operation>,
+        ids: Arc<Ids>,
+        config: ProcessConfig,
         src: String,
         path: String,
+        interfaces: &[Interface],
     ) -> Result<Self, Box<dyn Error>> {
-        let (thread_to_process_sender, thread_to_process_receiver): (Sender<Operation>, Receiver<Operation>) = unbounded();
-        let process_to_thread_senders = HashMap::<ThreadId, Sender<Operation>>::new();
-        let env = create_thread_env();
-        let (source, module) = run_frontend(src, path)?;
-        let source = Arc::new(source);
+        let (source, lowered) = run_lowering(src, path.clone(), interfaces)?;
+        let role = ProcessRole::from(lowered.kind);
+        let mut code = LoadedModule::from_lowered(Arc::new(source), lowered)?;
+        // the first version of its module, unless declared with another one
+        code.version = code.version.max(1);
+        let (thread_to_process_sender, thread_to_process_receiver) = mailbox(&config, role);
+        let inbox = inbox(&config, role);
+        let next_variable = Arc::new(AtomicU64::new(FIRST_VARIABLE));
+        let store = Arc::new(Store::new());
         Ok(Self {
-            process_id: NEXT_PROCESS_ID.fetch_add(1, Ordering::SeqCst),
+            process_id: ids.process(),
+            path,
+            ids,
             executor,
-            process_to_thread_senders,
+            deterministic: None,
+            role,
+            config,
+            threads: HashMap::new(),
+            machines: HashMap::new(),
+            suspended: HashMap::new(),
             thread_to_process_sender,
             thread_to_process_receiver,
-            waiting_threads: HashMap::<VariableId, Vec<ThreadId>>::new(),
-            wait_needed_threads: HashMap::<VariableId, ThreadId>::new(),
-            bound_variables: HashMap::<VariableId, Operation>::new(),
-            source, 
-            module, 
-            env
+            store: store.clone(),
+            cells: HashMap::<CellId, Value>::new(),
+            clock: default_clock(),
+            timers: BinaryHeap::new(),
+            meter: Arc::new(Meter::new(store)),
+            next_variable,
+            code: Arc::new(code),
+            main_script: None,
+            main_entry: None,
+            main_thread: None,
+            children: HashMap::new(),
+            restored: Vec::new(),
+            pausing: false,
+            swept_at: FIRST_VARIABLE,
+            modules: Arc::new(Modules::new()),
+            thread_modules: HashMap::new(),
+            network: Arc::new(Network::new()),
+            remote_variables: Vec::new(),
+            lost: HashMap::new(),
+            table: Arc::new(ProcessTable::default()),
+            inbox,
+            events: unbounded(),
         })
     }
-    pub async fn run(&mut self) -> Result<(), Box<dyn Error>> {
-        let (process_to_thread_sender, process_to_thread_receiver): (Sender<Operation>, Receiver<Operation>) = unbounded();
-        let thread_id = NEXT_THREAD_ID.fetch_add(1, Ordering::SeqCst);
-        self.process_to_thread_senders.insert(thread_id, process_to_thread_sender);
-        let mut env = create_thread_env();
-        let cu = compile_thread(&mut env, self.source.clone(), self.module.clone())?;
-        let ee = werbolg_exec::ExecutionEnviron::from_compile_environment(env.finalize());
-        let em = build_thread_machine(ee, cu)?;
+    // A process sharing the code of this one, with a store, threads and
+    // usage of its own, whose main thread runs `entry_point` a rank below.
+    fn child(&self, entry_point: FunId, args: Vec<Value>) -> Self {
+        let role = self.role.below();
+        let (thread_to_process_sender, thread_to_process_receiver) = mailbox(&self.config, role);
+        let next_variable = Arc::new(AtomicU64::new(FIRST_VARIABLE));
+        let store = Arc::new(Store::new());
+        Self {
+            process_id: self.ids.process(),
+            path: self.path.clone(),
+            ids: self.ids.clone(),
+            executor: self.executor.clone(),
+            deterministic: None,
+            role,
+            config: self.config.clone(),
+            threads: HashMap::new(),
+            machines: HashMap::new(),
+            suspended: HashMap::new(),
+            thread_to_process_sender,
+            thread_to_process_receiver,
+            store: store.clone(),
+            cells: HashMap::<CellId, Value>::new(),
+            clock: self.clock.clone(),
+            timers: BinaryHeap::new(),
+            meter: Arc::new(Meter::new(store)),
+            next_variable,
+            code: self.code.clone(),
+            main_script: None,
+            main_entry: Some((entry_point, args)),
+            main_thread: None,
+            children: HashMap::new(),
+            restored: Vec::new(),
+            pausing: false,
+            swept_at: FIRST_VARIABLE,
+            modules: self.modules.clone(),
+            thread_modules: HashMap::new(),
+            network: self.network.clone(),
+            remote_variables: Vec::new(),
+            lost: HashMap::new(),
+            table: self.table.clone(),
+            inbox: inbox(&self.config, role),
+            events: unbounded(),
+        }
+    }
+    /// A process without a program, whose main thread plays `script`. Its
+    /// scripted threads log events, see `trace`.
+    pub(crate) fn scripted(
+        executor: Arc<Executor<'a>>,
+        ids: Arc<Ids>,
+        config: ProcessConfig,
+        script: Vec<Operation>,
+    ) -> Result<Self, Box<dyn Error>> {
+        script::check(&script)?;
+        let mut process = Self::new(executor, ids, config, String::new(), "script".to_string(), &[])?;
+        process.main_script = Some(script);
+        Ok(process)
+    }
+    /// The process saved in `snapshot`, compiled again from its source. Its
+    /// threads resume where they were suspended, and its timers fire after
+    /// the time they had left.
+    pub(crate) fn restore(
+        executor: Arc<Executor<'a>>,
+        ids: Arc<Ids>,
+        config: ProcessConfig,
+        snapshot: &Snapshot,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut process = Self::new(executor, ids, config, snapshot.source.clone(), snapshot.path.clone(), &[])?;
+        snapshot.check(&Code::of(&process.code.cu))?;
+        for variable in snapshot.variables.iter() {
+            process.store.restore(variable.variable, variable.binding.clone(), variable.needed);
+            if let Some(span) = variable.declared.clone() {
+                process.store.declare(variable.variable, span);
+            }
+        }
+        process.next_variable.store(snapshot.next_variable, Ordering::SeqCst);
+        process.cells = snapshot.cells.iter().cloned().collect();
+        process.meter.set_cells(process.cells.len());
+        process.restored = snapshot.threads.clone();
+        // the timers are set against the clock of the process once it runs
+        process.timers = snapshot.timers.iter().map(|(left, variable)| Reverse((*left, *variable))).collect();
+        Ok(process)
+    }
+    pub fn process_id(&self) -> ProcessId {
+        self.process_id
+    }
+    /// The path of the source the process was compiled from.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn quota(&self) -> &Quota {
+        &self.config.role(self.role).quota
+    }
+    pub fn usage(&self) -> Usage {
+        self.meter.usage()
+    }
+    pub(crate) fn meter(&self) -> Arc<Meter> {
+        self.meter.clone()
+    }
+    /// The events of the scripted threads since the last call.
+    pub fn trace(&self) -> Trace {
+        let mut trace = Trace::default();
+        while let Ok(event) = self.events.1.try_recv() {
+            trace.events.push(event);
+        }
+        trace
+    }
+    /// Uses `clock` for the timers of this process instead of the default one.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+    /// Resolves the external calls of this process in `modules` instead of
+    /// a table of its own.
+    pub fn with_modules(mut self, modules: Arc<Modules>) -> Self {
+        self.modules = modules;
+        self
+    }
+    /// Deploys to the nodes of `network` instead of having none to deploy
+    /// to.
+    pub fn with_network(mut self, network: Arc<Network>) -> Self {
+        self.network = network;
+        self
+    }
+    /// Lists the process in `table`, where it records how it ended.
+    pub(crate) fn with_table(mut self, table: Arc<ProcessTable>) -> Self {
+        table.insert(self.process_id, &self.path, self.meter.clone(), self.inbox());
+        self.table = table;
+        self
+    }
+    // The process deployed from another node: its main thread runs the
+    // closure, which shares `remote_variables` with that node, and which
+    // came over a link allowing processes up to `role`.
+    pub(crate) fn deployed(mut self, closure: Value, remote_variables: Vec<VariableId>, role: ProcessRole) -> Result<Self, Box<dyn Error>> {
+        self.code().value(&closure)?;
+        let ValueFun::Fun(entry_point) = closure.closure().map(|(_, closure)| closure.fun).map_err(|e| format!("{:?}", e))? else {
+            return Err("a process cannot start in a native function".into());
+        };
+        self.main_entry = Some((entry_point, vec![closure]));
+        self.remote_variables = remote_variables;
+        // a rank below the process that deployed it, as any child
+        self.role = self.role.below().min(role);
+        (self.thread_to_process_sender, self.thread_to_process_receiver) = mailbox(&self.config, self.role);
+        self.inbox = inbox(&self.config, self.role);
+        Ok(self)
+    }
+    pub(crate) fn store(&self) -> Arc<Store> {
+        self.store.clone()
+    }
+    pub(crate) fn mailbox(&self) -> Sender<Operation> {
+        self.thread_to_process_sender.clone()
+    }
+    /// Where the messages sent to the process go.
+    pub(crate) fn inbox(&self) -> Sender<Value> {
+        self.inbox.0.clone()
+    }
+    fn mailboxes(&self) -> Mailboxes {
+        Mailboxes::new(self.process_id, self.inbox.clone(), self.table.clone())
+    }
+    pub(crate) fn fresh_variable(&self) -> VariableId {
+        self.next_variable.fetch_add(1, Ordering::SeqCst)
+    }
+    pub(crate) fn next_variable(&self) -> Arc<AtomicU64> {
+        self.next_variable.clone()
+    }
+    /// What the values of the process may point into.
+    pub(crate) fn code(&self) -> Code {
+        Code::of(&self.code.cu)
+    }
+    /// The id of the main thread, given out before the process runs when
+    /// asked for.
+    pub(crate) fn main_thread(&mut self) -> ThreadId {
+        *self.main_thread.get_or_insert_with(|| self.ids.thread())
+    }
+    fn spawn_thread(&mut self, entry_point: FunId, args: Vec<Value>) -> Result<(), Box<dyn Error>> {
+        let thread_id = self.main_thread();
+        self.spawn_thread_in(thread_id, None, entry_point, args)
+    }
+    // runs the process code, or `module` when given, at the lower of the
+    // ranks of the process and of the code
+    fn spawn_thread_in(
+        &mut self,
+        thread_id: ThreadId,
+        module: Option<Arc<LoadedModule>>,
+        entry_point: FunId,
+        args: Vec<Value>,
+    ) -> Result<(), Box<dyn Error>> {
+        let code = module.clone().unwrap_or_else(|| self.code.clone());
+        let state = RunningThreadState::new(
+            thread_id,
+            self.role.min(code.role),
+            self.next_variable.clone(),
+            self.store.clone(),
+            self.clock.clone(),
+            self.mailboxes(),
+        );
+        let em = build_thread_machine(code.ee.clone(), code.cu.clone(), state, entry_point, &args)?;
+        if let Some(module) = module {
+            self.thread_modules.insert(thread_id, vec![module]);
+        }
+        self.start_thread(thread_id, em, code);
+        Ok(())
+    }
+    // the step a restored thread was suspended on is replayed first, so it
+    // suspends again until what it waited for is there
+    fn resume_thread(&mut self, machine: &MachineSnapshot) -> Result<(), Box<dyn Error>> {
+        let thread_id = self.ids.thread();
+        let state = RunningThreadState::new(
+            thread_id,
+            self.role,
+            self.next_variable.clone(),
+            self.store.clone(),
+            self.clock.clone(),
+            self.mailboxes(),
+        );
+        let mut em = new_thread_machine(self.code.ee.clone(), self.code.cu.clone(), state);
+        machine::resume(&mut em, machine)?;
+        self.start_thread(thread_id, em, self.code.clone());
+        Ok(())
+    }
+    fn start_thread(&mut self, thread_id: ThreadId, em: ThreadExecutionMachine, code: Arc<LoadedModule>) {
         let mut thread = Thread::new(
             thread_id, 
             self.thread_to_process_sender.clone(), 
-            process_to_thread_receiver, 
-            em);
-        self.executor.spawn(async move { thread.run().await }).detach();
+            self.config.role(self.role).reductions,
+            self.meter.clone(),
+            self.quota().clone(),
+            em,
+            code,
+            self.modules.clone());
+        self.machines.insert(thread_id, thread.machine());
+        let task = self.spawn_task(async move { thread.run().await });
+        self.threads.insert(thread_id, task);
+        self.meter.set_threads(self.threads.len());
+    }
+    fn spawn_script(&mut self, script: Vec<Operation>) {
+        let thread_id = self.ids.thread();
+        let task = script::play(
+            thread_id,
+            script,
+            self.store.clone(),
+            self.thread_to_process_sender.clone(),
+            self.events.0.clone(),
+        );
+        let task = self.spawn_task(task);
+        self.threads.insert(thread_id, task);
+        self.meter.set_threads(self.threads.len());
+    }
+    fn spawn_task(&self, task: impl Future<Output = ()> + Send + 'static) -> Option<Task<()>> {
+        match &self.deterministic {
+            Some(spawner) => {
+                let _ = spawner.try_send(Box::pin(task));
+                None
+            }
+            None => Some(self.executor.spawn(task)),
+        }
+    }
+    /// Runs the process and its threads on the current OS thread, one task at
+    /// a time, in an order given by `mode`. Returns the schedule that was
+    /// followed, which replays the same run with `ScheduleMode::Replay`.
+    /// Timers only fire with a clock that does not need another thread, such
+    /// as a `VirtualClock` advanced by the program's own tasks.
+    pub fn run_deterministic(&mut self, mode: ScheduleMode) -> (Result<(), Box<dyn Error>>, Schedule) {
+        let mut executor = DeterministicExecutor::new(mode);
+        self.deterministic = Some(executor.spawner());
+        let result = match executor.block_on(self.run()) {
+            Ok(result) => result,
+            Err(error) => Err(error.into()),
+        };
+        self.deterministic = None;
+        (result, executor.schedule().clone())
+    }
+    fn check_quota(&self) -> Result<(), QuotaExceeded> {
+        match self.quota().exceeded(&self.meter.usage()) {
+            Some(exceeded) => Err(exceeded),
+            None => Ok(()),
+        }
+    }
+    // the thread about to be spawned must fit in the quota
+    fn admit(&self) -> Result<(), QuotaExceeded> {
+        let mut usage = self.meter.usage();
+        usage.threads += 1;
+        match self.quota().exceeded(&usage) {
+            Some(exceeded) => Err(exceeded),
+            None => Ok(()),
+        }
+    }
+    // counts the child process about to be started, which must fit in the
+    // quota
+    fn admit_child(&self) -> Result<(), QuotaExceeded> {
+        self.meter.child();
+        self.check_quota()
+    }
+    // The failure of a thread, located in the code it runs. The trace is empty
+    // for what a thread asked of the process and the process could not do.
+    fn thread_failure(&self, thread_id: ThreadId, error: String, trace: Vec<InstructionAddress>) -> ThreadFailure {
+        let code = self.thread_modules.get(&thread_id).and_then(|modules| modules.last()).unwrap_or(&self.code);
+        let content = &code.source.file_unit.content;
+        let trace = trace.into_iter().map(|ip| code.source_map.frame(content, ip)).collect();
+        ThreadFailure { thread_id, error, trace }
+    }
+    // The closure and what it captured are copied to the child, which runs on
+    // the executor of the process with nothing shared but the code. Its exit
+    // comes back as an operation from the thread that started it. The closure
+    // was grounded by `spawn_process`.
+    fn spawn_process(&mut self, thread_id: ThreadId, closure: Value) -> Result<ProcessId, Box<dyn Error>> {
+        if self.deterministic.is_some() {
+            return Err("child processes cannot run on a deterministic executor".into());
+        }
+        if self.thread_modules.contains_key(&thread_id) {
+            return Err("a process can only be started from the code of its parent".into());
+        }
+        let ValueFun::Fun(entry_point) = closure.closure().map(|(_, closure)| closure.fun).map_err(|e| format!("{:?}", e))? else {
+            return Err("a process cannot start in a native function".into());
+        };
+        let child = self.child(entry_point, vec![closure]).with_table(self.table.clone());
+        let process_id = child.process_id();
+        let task = start_child(&self.executor, child, thread_id, self.thread_to_process_sender.clone());
+        self.children.insert(process_id, Child { task: Some(task), exit: None, supervisors: Vec::new() });
+        Ok(process_id)
+    }
+    // Like `spawn_process`, on another node, where the closure is compiled
+    // from the same source. The variables it captured that are not bound yet
+    // stay shared with the deployed process. It is supervised like a child.
+    async fn deploy(&mut self, thread_id: ThreadId, node: Option<NodeId>, closure: Value) -> Result<ProcessId, Box<dyn Error>> {
+        if self.deterministic.is_some() {
+            return Err("deployed processes cannot run on a deterministic executor".into());
+        }
+        if self.thread_modules.contains_key(&thread_id) {
+            return Err("a process can only be deployed from the code of its parent".into());
+        }
+        let Some(closure) = self.store.resolve(&closure) else {
+            return Err("cells cannot be sent to another node".into());
+        };
+        let process_id = self.ids.process();
+        let deploying = Deploying {
+            thread_id,
+            process_id,
+            store: self.store.clone(),
+            mailbox: self.thread_to_process_sender.clone(),
+            code: self.code(),
+            next_variable: self.next_variable.clone(),
+            table: self.table.clone(),
+            path: self.path.clone(),
+            source: self.code.source.file_unit.content.to_string(),
+            closure,
+        };
+        self.network.deploy(&self.executor, node, deploying).await?;
+        self.children.insert(process_id, Child { task: None, exit: None, supervisors: Vec::new() });
+        Ok(process_id)
+    }
+    // whether another node may still bind a variable of this process: one
+    // it shares, or one left unbound in what those are bound to, which is
+    // shared along with the binding
+    fn remote_bindings(&self) -> bool {
+        self.remote_variables.iter().any(|variable| match self.store.resolve(&Value::Unbound(*variable)) {
+            Some(value) => !unbound_variables(&value).is_empty(),
+            None => false,
+        })
+    }
+    // The node at the other end of a deployment went down: the deployed
+    // process failed, as far as its parent knows, and the variables shared
+    // with it will not be bound from there anymore. The thread waiting on
+    // one of them fails.
+    fn node_down(&mut self, node: NodeId, process_id: ProcessId, shared: Vec<VariableId>) -> Result<Option<ThreadId>, Box<dyn Error>> {
+        self.child_exited(process_id, false)?;
+        self.remote_variables.retain(|variable| !shared.contains(variable));
+        for variable in shared {
+            if let Some(value) = self.store.resolve(&Value::Unbound(variable)) {
+                self.lost.extend(unbound_variables(&value).into_iter().map(|root| (root, node)));
+            }
+        }
+        let waiting = self.suspended.iter().find_map(|(thread_id, suspension)| match *suspension {
+            Suspension::Bound(variable) => self.lost_node(variable).map(|_| *thread_id),
+            Suspension::Needed(_) => None,
+        });
+        Ok(waiting)
+    }
+    // the node the variable was shared with when it went down, if any
+    fn lost_node(&self, variable: VariableId) -> Option<NodeId> {
+        match self.store.deref(&Value::Unbound(variable)) {
+            Value::Unbound(root) => self.lost.get(&root).copied(),
+            _ => None,
+        }
+    }
+    fn supervise(&mut self, process_id: ProcessId, exit: VariableId) -> Result<(), Box<dyn Error>> {
+        let Some(child) = self.children.get_mut(&process_id) else {
+            return Err(format!("process {} is not a child of process {}", process_id, self.process_id).into());
+        };
+        match child.exit {
+            Some(completed) => self.unify(Value::Unbound(exit), Value::Bool(NO_VARIABLE, completed)),
+            None => {
+                child.supervisors.push(exit);
+                Ok(())
+            }
+        }
+    }
+    fn child_exited(&mut self, process_id: ProcessId, completed: bool) -> Result<(), Box<dyn Error>> {
+        let Some(child) = self.children.get_mut(&process_id) else {
+            return Ok(());
+        };
+        child.task = None;
+        child.exit = Some(completed);
+        for exit in core::mem::take(&mut child.supervisors) {
+            self.unify(Value::Unbound(exit), Value::Bool(NO_VARIABLE, completed))?;
+        }
+        Ok(())
+    }
+    fn children_running(&self) -> bool {
+        self.children.values().any(|child| child.exit.is_none())
+    }
+    fn unify(&self, left: Value, right: Value) -> Result<(), Box<dyn Error>> {
+        match self.store.unify(&left, &right) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(format!("{}", error).into()),
+        }
+    }
+    // A thread resumes only once its variable is bound or needed, which no
+    // suspended thread can do, or a timer does. So when every thread reported
+    // a suspension that still holds, on a variable no timer binds, none of
+    // them will ever resume.
+    fn deadlock(&self) -> Option<Deadlock> {
+        if self.threads.is_empty()
+            || self.suspended.len() < self.threads.len()
+            || self.children_running()
+            || self.remote_bindings()
+        {
+            return None;
+        }
+        let timed = self
+            .timers
+            .iter()
+            .filter(|Reverse((_, variable))| *variable != NO_VARIABLE)
+            .filter_map(|Reverse((_, variable))| match self.store.deref(&Value::Unbound(*variable)) {
+                Value::Unbound(root) => Some(root),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut threads = Vec::new();
+        for (thread_id, suspension) in self.suspended.iter() {
+            let suspension = match *suspension {
+                Suspension::Bound(variable) => match self.store.deref(&Value::Unbound(variable)) {
+                    // the variable the thread waits on now
+                    Value::Unbound(root) if !timed.contains(&root) => Suspension::Bound(root),
+                    _ => return None,
+                },
+                Suspension::Needed(variable) => match self.store.deref(&Value::Unbound(variable)) {
+                    Value::Unbound(root) if !self.store.is_needed(variable) && !timed.contains(&root) => {
+                        Suspension::Needed(root)
+                    }
+                    _ => return None,
+                },
+            };
+            let declared = self
+                .store
+                .declaration(suspension.variable())
+                .map(|span| Declaration::new(&self.code.source.file_unit.content, span));
+            threads.push(SuspendedThread { thread_id: *thread_id, suspension, declared });
+        }
+        threads.sort_by_key(|thread| thread.thread_id);
+        Some(Deadlock { threads })
+    }
+    // Every thread is suspended on something no thread can provide, nothing
+    // is left in the mailbox and the process only waits for its timers.
+    fn quiescent(&self) -> bool {
+        !self.threads.is_empty()
+            && self.suspended.len() == self.threads.len()
+            && self.children.is_empty()
+            && self.thread_to_process_receiver.is_empty()
+            && self.suspended.values().all(|suspension| match *suspension {
+                Suspension::Bound(variable) => matches!(self.store.deref(&Value::Unbound(variable)), Value::Unbound(_)),
+                Suspension::Needed(variable) => {
+                    !self.store.is_needed(variable)
+                        && matches!(self.store.deref(&Value::Unbound(variable)), Value::Unbound(_))
+                }
+            })
+    }
+    // whether serving is over for now: deadlocked, or paused for a snapshot
+    fn stopped(&self) -> Option<Result<(), Box<dyn Error>>> {
+        if let Some(deadlock) = self.deadlock() {
+            return Some(Err(deadlock.into()));
+        }
+        if self.pausing && self.quiescent() {
+            return Some(Ok(()));
+        }
+        None
+    }
+    fn snapshot(&self) -> Result<Snapshot, Box<dyn Error>> {
+        if self.machines.len() < self.threads.len() {
+            return Err("scripted threads cannot be saved".into());
+        }
+        if !self.thread_modules.is_empty() {
+            return Err("threads running loaded modules cannot be saved".into());
+        }
+        if !self.remote_variables.is_empty() {
+            return Err("processes sharing variables with another node cannot be saved".into());
+        }
+        let mut thread_ids = self.machines.keys().copied().collect::<Vec<_>>();
+        thread_ids.sort();
+        let mut threads = Vec::new();
+        for thread_id in thread_ids {
+            let Some(em) = self.machines[&thread_id].try_lock() else {
+                return Err(format!("thread {} is running", thread_id).into());
+            };
+            threads.push(machine::capture(&em));
+        }
+        let next_variable = self.next_variable.load(Ordering::SeqCst);
+        let variables = (FIRST_VARIABLE..next_variable)
+            .map(|variable| VariableSnapshot {
+                variable,
+                binding: self.store.binding(variable),
+                needed: self.store.is_needed(variable),
+                declared: self.store.declaration(variable),
+            })
+            .filter(|variable| variable.binding.is_some() || variable.needed || variable.declared.is_some())
+            .collect();
+        let mut cells = self.cells.iter().map(|(cell, content)| (*cell, content.clone())).collect::<Vec<_>>();
+        cells.sort_by_key(|(cell, _)| *cell);
+        let now = self.clock.now();
+        let mut timers = self
+            .timers
+            .iter()
+            .map(|Reverse((deadline, variable))| (deadline.saturating_sub(now), *variable))
+            .collect::<Vec<_>>();
+        timers.sort();
+        Ok(Snapshot {
+            path: self.path.clone(),
+            source: self.code.source.file_unit.content.to_string(),
+            next_variable,
+            variables,
+            cells,
+            timers,
+            threads,
+        })
+    }
+    // Drops the values nothing refers to anymore, once enough variables were
+    // created since the last time, or as soon as the process holds half the
+    // values of its quota. Only while quiescent, and only when the
+    // process knows everything that holds its variables: the stacks of its
+    // compiled threads, its cells, its timers. Children, loaded modules and
+    // other nodes keep the store as it is.
+    fn sweep(&mut self) {
+        let end = self.next_variable.load(Ordering::SeqCst);
+        let pressed = end > self.swept_at && self.quota().values.is_some_and(|limit| self.store.values() > limit / 2);
+        if (end < self.swept_at.saturating_add(SWEEP_INTERVAL) && !pressed)
+            || self.machines.len() < self.threads.len()
+            || !self.thread_modules.is_empty()
+            || !self.remote_variables.is_empty()
+            || !self.quiescent()
+        {
+            return;
+        }
+        {
+            // no thread steps while its machine is held
+            let mut machines = Vec::new();
+            for machine in self.machines.values() {
+                let Some(em) = machine.try_lock() else {
+                    return;
+                };
+                machines.push(em);
+            }
+            let mut roots = Vec::new();
+            for em in machines.iter() {
+                roots.extend(machine::stack(em).cloned());
+            }
+            roots.extend(self.cells.values().cloned());
+            roots.extend(self.timers.iter().map(|Reverse((_, variable))| Value::Unbound(*variable)));
+            roots.extend(self.suspended.values().map(|suspension| Value::Unbound(suspension.variable())));
+            let live = self.store.reachable(roots);
+            // SAFETY: every thread is suspended on a variable of `suspended`
+            // and only steps again through its machine, the mailbox is empty
+            // and the process has no children nor remote variables: whatever
+            // can use a variable from now on holds one of the roots
+            unsafe { self.store.sweep(&live, end) };
+        }
+        self.swept_at = end;
+    }
+    // binds the variables of the timers whose deadline passed
+    fn fire_timers(&mut self) -> Result<(), Box<dyn Error>> {
+        let now = self.clock.now();
+        while let Some(Reverse((deadline, variable))) = self.timers.peek().copied() {
+            if deadline > now {
+                break;
+            }
+            self.timers.pop();
+            if variable == NO_VARIABLE {
+                return Err("the process reached the deadline set by `die_after`".into());
+            }
+            self.unify(Value::Unbound(variable), Value::Unit)?;
+        }
+        Ok(())
+    }
+    /// Runs the process until all its threads terminated and its children
+    /// ended, or one of the threads failed or they deadlocked, which cancels
+    /// the threads and children left. A failing child does not fail its
+    /// parent, which can `supervise` it.
+    pub async fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        let result = self.serve().await;
+        self.stop();
+        self.table.exited(self.process_id, ProcessExit::of(&result));
+        result
+    }
+    /// Runs the process until it is quiescent, every thread waiting on a
+    /// timer or on another thread, and saves it. The process is stopped
+    /// afterwards, `Runtime::restore` carries on from the snapshot. Fails when
+    /// the process ends first, or has child processes.
+    pub async fn run_to_snapshot(&mut self) -> Result<Snapshot, Box<dyn Error>> {
+        self.pausing = true;
+        let result = self.serve().await;
+        self.pausing = false;
+        let exit = match &result {
+            Ok(()) if !self.threads.is_empty() => ProcessExit::Shutdown,
+            result => ProcessExit::of(result),
+        };
+        let snapshot = match result {
+            Ok(()) if self.threads.is_empty() => Err("the process ended before it could be saved".into()),
+            Ok(()) => self.snapshot(),
+            Err(error) => Err(error),
+        };
+        self.stop();
+        self.table.exited(self.process_id, exit);
+        snapshot
+    }
+    fn stop(&mut self) {
+        self.threads.clear();
+        self.machines.clear();
+        self.thread_modules.clear();
+        self.children.clear();
+        self.meter.set_threads(0);
+        self.suspended.clear();
+        // what is sent from now on is dropped
+        self.inbox.1.close();
+    }
+    async fn serve(&mut self) -> Result<(), Box<dyn Error>> {
+        // calls to its own module go through the table like any other
+        self.modules.provide(self.code.clone()).await;
+        let restored = core::mem::take(&mut self.restored);
+        if !restored.is_empty() {
+            let now = self.clock.now();
+            self.timers = self.timers.drain().map(|Reverse((left, variable))| Reverse((now.saturating_add(left), variable))).collect();
+            for machine in restored.iter() {
+                self.resume_thread(machine)?;
+            }
+        } else {
+            match (self.main_script.take(), self.main_entry.take()) {
+                (Some(script), _) => self.spawn_script(script),
+                (None, Some((entry_point, args))) => self.spawn_thread(entry_point, args)?,
+                (None, None) => self.spawn_thread(main_entry_point(&self.code), Vec::new())?,
+            }
+        }
         loop {
-            match self.thread_to_process_receiver.recv().await {
+            self.fire_timers()?;
+            self.sweep();
+            let received = match self.timers.peek() {
+                Some(Reverse((deadline, _))) => {
+                    let clock = self.clock.clone();
+                    let deadline = *deadline;
+                    let receiver = &self.thread_to_process_receiver;
+                    smol::future::or(async { Some(receiver.recv().await) }, async {
+                        clock.sleep_until(deadline).await;
+                        None
+                    })
+                    .await
+                }
+                None => Some(self.thread_to_process_receiver.recv().await),
+            };
+            let Some(received) = received else {
+                // a deadline passed, which may leave the process with only
+                // threads nothing can wake anymore
+                self.fire_timers()?;
+                if let Some(result) = self.stopped() {
+                    break result;
+                }
+                continue;
+            };
+            match received {
                 Ok(operation) => {
+                    // variables are created by the threads on their own
+                    self.check_quota()?;
                     match operation {
-                        Operation::SynchVar(thread_id, ref value) => {
-                            match value {
-                                Value::Unbound(variable_index) => {
-                                    //info!("thread_id {} with {:?}",thread_id, operation);
-                                    // if already bound, notify waiting thread the value is bound
-                                    if let Some(_bound_variable_index) = self.bound_variables.get(variable_index) {
-                                        if let Some(sender) = self.process_to_thread_senders.get(&thread_id) {
-                                            let _ = sender.send(operation.clone()).await;
-                                        }
-                                    } else {
-                                        self.waiting_threads.entry(*variable_index).or_insert_with(Vec::new).push(thread_id);
-                                    }
-                                    // notify wait needed threads to start work, enabling laziness
-                                    if let Some(wait_needed_thread_id) = self.wait_needed_threads.get(variable_index) {
-                                        if let Some(sender) = self.process_to_thread_senders.get(wait_needed_thread_id) {
-                                            let _ = sender.send(operation.clone()).await;
-                                        }
-                                        self.wait_needed_threads.remove(variable_index);
-                                    }
-                                },
-                                Value::Integer(variable_index, _value) => {
-                                    //info!("thread_id {} with {:?}",thread_id, operation);
-                                    self.bound_variables.insert(*variable_index, operation.clone());
-                                    if let Some(notify_threads) = self.waiting_threads.get(variable_index) {
-                                        for thread_id in notify_threads {
-                                            if let Some(sender) = self.process_to_thread_senders.get(thread_id) {
-                                                let _ = sender.send(operation.clone()).await;
-                                            }
-                                        }
-                                        self.waiting_threads.remove(variable_index);
-                                    }
-                                },
-                                &Value::Unit | &Value::Bool(_, _) | &Value::Fun(_, _) => todo!()
-                            }   
+                        Operation::SynchVar(thread_id, Value::Unbound(variable_index)) => {
+                            if let Some(node) = self.lost_node(variable_index) {
+                                break Err(self.thread_failure(thread_id, format!("node {} disconnected", node), Vec::new()).into());
+                            }
+                            // asking for a variable needs it, the producer wakes up
+                            self.store.need(variable_index);
+                            self.suspended.insert(thread_id, Suspension::Bound(variable_index));
+                            if let Some(result) = self.stopped() {
+                                break result;
+                            }
+                        },
+                        // a determined value is nothing to wait on
+                        Operation::SynchVar(_thread_id, _value) => {},
+                        Operation::Bind(_thread_id, variable_index, value) => {
+                            //info!("thread_id {} with {:?}",thread_id, operation);
+                            self.unify(Value::Unbound(variable_index), value)?;
+                        },
+                        Operation::Unify(_thread_id, left, right) => {
+                            self.unify(left, right)?;
+                        },
+                        Operation::NewCell(_thread_id, cell, content) => {
+                            self.cells.insert(cell, content);
+                            self.meter.set_cells(self.cells.len());
+                        },
+                        Operation::Exchange(_thread_id, cell, content, old) => {
+                            let Some(current) = self.cells.get_mut(&cell) else {
+                                return Err(format!("cell {} does not exist", cell).into());
+                            };
+                            let previous = match content {
+                                Some(content) => core::mem::replace(current, content),
+                                None => current.clone(),
+                            };
+                            self.unify(Value::Unbound(old), previous)?;
                         },
-                        Operation::ThreadSpawn(_thread_id, ref _em) => {
+                        Operation::ThreadSpawn(thread_id, entry) => {
                             //info!("thread_id {} with {:?}",thread_id, operation);
-                            let (process_to_thread_sender, process_to_thread_receiver): (Sender<Operation>, Receiver<Operation>) = unbounded();
-                            let thread_id = NEXT_THREAD_ID.fetch_add(1, Ordering::SeqCst);
-                            self.process_to_thread_senders.insert(thread_id, process_to_thread_sender);
-                            let env = create_thread_env();
-                            let cu = compile_thread(&mut self.env, self.source.clone(), self.module.clone())?;
-                            let ee = werbolg_exec::ExecutionEnviron::from_compile_environment(env.finalize());
-                            let em = build_thread_machine(ee, cu)?;
-                            let mut thread = Thread::new(
-                                thread_id, 
-                                self.thread_to_process_sender.clone(), 
-                                process_to_thread_receiver, 
-                                em);
-                            self.executor.spawn(async move { thread.run().await }).detach();
+                            self.admit()?;
+                            // the lifted block takes its closure as first argument
+                            let closure = match entry {
+                                ThreadEntry::Closure(closure) => closure,
+                                ThreadEntry::Script(script) => {
+                                    self.spawn_script(script);
+                                    continue;
+                                }
+                            };
+                            let ValueFun::Fun(entry_point) = closure.closure().map(|(_, closure)| closure.fun).map_err(|e| format!("{:?}", e))? else {
+                                break Err("a thread cannot start in a native function".into());
+                            };
+                            // a thread of a loaded module spawns in its code
+                            let module = self.thread_modules.get(&thread_id).and_then(|modules| modules.last()).cloned();
+                            let spawned = self.ids.thread();
+                            self.spawn_thread_in(spawned, module, entry_point, vec![closure])?;
                         }
                         Operation::WaitNeeded(thread_id, variable_index) => {
-                            //info!("thread_id {} WaitNeeded({})",thread_id, variable_index);
-                            self.wait_needed_threads.entry(variable_index).or_insert(thread_id);
+                            self.suspended.insert(thread_id, Suspension::Needed(variable_index));
+                            if let Some(result) = self.stopped() {
+                                break result;
+                            }
+                        }
+                        Operation::ThreadFailure(thread_id, error, trace) => {
+                            break Err(self.thread_failure(thread_id, error, trace).into());
+                        }
+                        Operation::QuotaExceeded(_thread_id, exceeded) => {
+                            break Err(exceeded.into());
+                        }
+                        Operation::ProcessSpawn(thread_id, closure, variable) => {
+                            self.admit_child()?;
+                            let process_id = match self.spawn_process(thread_id, closure) {
+                                Ok(process_id) => process_id,
+                                Err(error) => break Err(self.thread_failure(thread_id, error.to_string(), Vec::new()).into()),
+                            };
+                            self.unify(Value::Unbound(variable), Value::Integer(NO_VARIABLE, process_id))?;
+                        }
+                        Operation::Call(thread_id, module) => {
+                            self.thread_modules.entry(thread_id).or_default().push(module);
+                        }
+                        Operation::Return(thread_id) => {
+                            if let Some(modules) = self.thread_modules.get_mut(&thread_id) {
+                                modules.pop();
+                                if modules.is_empty() {
+                                    self.thread_modules.remove(&thread_id);
+                                }
+                            }
+                        }
+                        Operation::Deploy(thread_id, node, closure, variable) => {
+                            self.admit_child()?;
+                            let process_id = match self.deploy(thread_id, node, closure).await {
+                                Ok(process_id) => process_id,
+                                Err(error) => break Err(self.thread_failure(thread_id, error.to_string(), Vec::new()).into()),
+                            };
+                            self.unify(Value::Unbound(variable), Value::Integer(NO_VARIABLE, process_id))?;
+                        }
+                        Operation::NodeDown(_thread_id, node, process_id, shared) => {
+                            if let Some(thread_id) = self.node_down(node, process_id, shared)? {
+                                break Err(self.thread_failure(thread_id, format!("node {} disconnected", node), Vec::new()).into());
+                            }
+                            if self.threads.is_empty() && !self.children_running() {
+                                break Ok(());
+                            }
+                            if let Some(result) = self.stopped() {
+                                break result;
+                            }
+                        }
+                        Operation::Supervise(_thread_id, process_id, variable) => {
+                            self.supervise(process_id, variable)?;
+                        }
+                        Operation::ChildExit(_thread_id, process_id, completed) => {
+                            self.child_exited(process_id, completed)?;
+                            if self.threads.is_empty() && !self.children_running() {
+                                break Ok(());
+                            }
+                        }
+                        Operation::Timer(_thread_id, deadline, variable) => {
+                            self.timers.push(Reverse((deadline, variable)));
                         }
                         Operation::ThreadTerminate(thread_id) => {
                             //info!("thread_id {} ThreadTerminate",thread_id);
-                            self.process_to_thread_senders.remove(&thread_id);
-                            if self.process_to_thread_senders.is_empty() {
+                            self.threads.remove(&thread_id);
+                            self.machines.remove(&thread_id);
+                            self.thread_modules.remove(&thread_id);
+                            self.meter.set_threads(self.threads.len());
+                            self.suspended.remove(&thread_id);
+                            if self.threads.is_empty() && !self.children_running() {
                                 break Ok(());
                             }
+                            if let Some(result) = self.stopped() {
+                                break result;
+                            }
                         }
                     }
                 },
@@ -287,23 +1248,62 @@ impl<'a> Process<'a> {
                     break Ok(());
                 }
             }
-            // info!("     unbound_variables: {:?}", self.waiting_threads);
-            // info!("     bound_variables: {:?}", self.bound_variables);
-            // info!("     wait_needed_variables: {:?}", self.wait_needed_threads);
         }
     }
 }
 
 
-fn compile_thread(
+// a full mailbox suspends the threads sending to it until the process
+// catches up
+fn mailbox(config: &ProcessConfig, role: ProcessRole) -> (Sender<Operation>, Receiver<Operation>) {
+    match config.role(role).mailbox {
+        Some(capacity) => bounded(capacity.max(1)),
+        None => unbounded(),
+    }
+}
+
+fn inbox(config: &ProcessConfig, role: ProcessRole) -> (Sender<Value>, Receiver<Value>) {
+    match config.role(role).messages {
+        Some(capacity) => bounded(capacity.max(1)),
+        None => unbounded(),
+    }
+}
+
+// outside of `Process::run`, which cannot spawn a future holding itself
+fn start_child<'a>(
+    executor: &Executor<'a>,
+    mut child: Process<'a>,
+    thread_id: ThreadId,
+    parent: Sender<Operation>,
+) -> Task<()> {
+    executor.spawn(async move {
+        let process_id = child.process_id();
+        let completed = child.run().await.is_ok();
+        let _ = parent.send(Operation::ChildExit(thread_id, process_id, completed)).await;
+    })
+}
+
+pub(crate) fn default_clock() -> Arc<dyn Clock> {
+    Arc::new(crate::scheduler::time::SystemClock::new())
+}
+
+/// Where the functions of a file declaring no module are compiled.
+pub(crate) fn main_namespace() -> Namespace {
+    Namespace::root().append(Ident::from("main"))
+}
+
+// compiles the module under its namespace
+pub(crate) fn compile_thread(
     //params: SioParams,
     env: &mut ThreadEnvironment,
     source: Arc<Source>,
+    prelude: Module,
+    namespace: &Namespace,
     module: Module,
-) -> Result<werbolg_compile::CompilationUnit<ThreadLiteral>, Box<dyn Error>> {
+) -> Result<CompilationUnit<ThreadLiteral>, Box<dyn Error>> {
     //let (source, module) = run_frontend(src, path).unwrap();
-    let module_ns = Namespace::root().append(Ident::from("main"));
-    let modules = vec![(module_ns.clone(), module)];
+    // the prelude goes next to the NIFs, where unqualified calls end up
+    let modules = vec![(Namespace::root(), prelude), (namespace.clone(), module)];
     let compilation_params = werbolg_compile::CompilationParams {
         literal_mapper: thread_literal_mapper,
         sequence_constructor: None,
@@ -327,47 +1327,120 @@ fn compile_thread(
     Ok(cu)
 }
 
-pub fn build_thread_machine (
-    ee: ExecutionEnviron<ThreadAllocator, ThreadLiteral, RunningThreadState, Value>,
-    cu: werbolg_compile::CompilationUnit<ThreadLiteral>,
-) -> Result<ThreadExecutionMachine, Box<dyn Error>> {
-    let module_ns = Namespace::root().append(Ident::from("main"));
-    let entry_point = cu
+// where each function of the module was declared, before it is compiled
+pub(crate) fn function_spans(module: &Module) -> Vec<(Ident, werbolg_core::Span)> {
+    module
+        .statements
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Function(span, def, _) => Some((def.name.clone(), span.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+pub(crate) fn source_map(
+    cu: &CompilationUnit<ThreadLiteral>,
+    namespace: &Namespace,
+    functions: Vec<(Ident, werbolg_core::Span)>,
+    library: Vec<(Ident, werbolg_core::Span)>,
+) -> SourceMap {
+    // prelude functions have their own source, they are only named
+    let functions = functions
+        .into_iter()
+        .map(|(name, span)| (namespace.clone(), name, Some(span)))
+        .chain(library.into_iter().map(|(name, _)| (Namespace::root(), name, None)))
+        .filter_map(|(namespace, name, span)| {
+            let fun_id = cu.funs_tbl.get(&AbsPath::new(&namespace, &name))?;
+            Some((cu.funs[fun_id].code_pos, name, span))
+        })
+        .collect();
+    SourceMap::new(functions)
+}
+
+fn main_entry_point(code: &LoadedModule) -> FunId {
+    code
+        .cu
         .funs_tbl
-        .get(&AbsPath::new(&module_ns, &Ident::from("main")))
-        .expect("existing function as entry point");
+        .get(&AbsPath::new(&code.namespace, &Ident::from("main")))
+        .expect("existing function as entry point")
+}
+
+fn new_thread_machine(
+    ee: WerRefCount<ThreadExecutionEnviron>,
+    cu: WerRefCount<CompilationUnit<ThreadLiteral>>,
+    state: RunningThreadState,
+) -> ThreadExecutionMachine {
     let execution_params = ExecutionParams {
         literal_to_value: thread_literal_to_value,
     };
-    let state = RunningThreadState {};
     let allocator = ThreadAllocator {};
-    let mut em = ExecutionMachine::new(
-        WerRefCount::new(cu),
-        WerRefCount::new(ee),
-        execution_params, allocator, state);
-    werbolg_exec::initialize(&mut em, entry_point, &[]).unwrap();
+    ExecutionMachine::new(cu, ee, execution_params, allocator, state)
+}
+
+pub fn build_thread_machine (
+    ee: WerRefCount<ThreadExecutionEnviron>,
+    cu: WerRefCount<CompilationUnit<ThreadLiteral>>,
+    state: RunningThreadState,
+    entry_point: FunId,
+    args: &[Value],
+) -> Result<ThreadExecutionMachine, Box<dyn Error>> {
+    let mut em = new_thread_machine(ee, cu, state);
+    if let Err(e) = werbolg_exec::initialize(&mut em, entry_point, args) {
+        return Err(format!("cannot start thread: {:?}", e).into());
+    }
     Ok(em)
 }
 
-pub fn run_frontend(src: String, path: String) -> Result<(Source, Module), Box<dyn Error>> {
+pub fn run_frontend(src: String, path: String) -> Result<(Source, ModuleKind, Module), Box<dyn Error>> {
+    let (source, lowered) = run_lowering(src, path, &[])?;
+    Ok((source, lowered.kind, lowered.module))
+}
+
+/// Parses and lowers `src`, the modules it imports being among `interfaces`.
+pub(crate) fn run_lowering(
+    src: String,
+    path: String,
+    interfaces: &[Interface],
+) -> Result<(Source, frontend::Lowered), Box<dyn Error>> {
+    let (source, parsed) = run_parsing(src, path)?;
+    let lowered = lower_parsed(&source, parsed, interfaces)?;
+    Ok((source, lowered))
+}
+
+pub(crate) fn run_parsing(src: String, path: String) -> Result<(Source, frontend::Parsed), Box<dyn Error>> {
     let source = Source::from_string(path, src);
-    let parsing_res = frontend::module(&source.file_unit);
-    let module = match parsing_res {
-        Err(es) => {
-            for e in es.into_iter() {
-                let report = Report::new(ReportKind::Error, format!("Parse Error: {:?}", e.message))
-                    .lines_before(1)
-                    .lines_after(1)
-                    .highlight(e.span.start.0 as usize .. e.span.end.0 as usize, e.message);
+    match frontend::parse_file(&source.file_unit) {
+        Err(es) => Err(report_diagnostics(&source, "parse error", es)),
+        Ok(parsed) => Ok((source, parsed)),
+    }
+}
 
-                report_print(&source, report)?;
-            }
-            return Err(format!("parse error").into());
+pub(crate) fn lower_parsed(
+    source: &Source,
+    parsed: frontend::Parsed,
+    interfaces: &[Interface],
+) -> Result<frontend::Lowered, Box<dyn Error>> {
+    frontend::lower_parsed(parsed, interfaces).map_err(|unlowered| match unlowered {
+        frontend::Unlowered::Resolution(es) => report_diagnostics(source, "resolution error", es),
+        frontend::Unlowered::Lowering(es) => report_diagnostics(source, "parse error", es),
+    })
+}
+
+// `kind` names what failed, in the report and in the error returned
+fn report_diagnostics(source: &Source, kind: &str, es: Vec<Diagnostic>) -> Box<dyn Error> {
+    for e in es.into_iter() {
+        let report = Report::new(ReportKind::Error, format!("{}: {:?}", kind, e.message))
+            .lines_before(1)
+            .lines_after(1)
+            .highlight(e.span.start.0 as usize .. e.span.end.0 as usize, e.message);
+
+        if let Err(error) = report_print(source, report) {
+            return error;
         }
-        Ok(module) => module,
-    };
-    Ok((source, module))
-} 
+    }
+    kind.into()
+}
 
 pub fn report_print(source: &Source, report: Report) -> Result<(), Box<dyn Error>> {
     let mut s = String::new();
@@ -378,23 +1451,25 @@ pub fn report_print(source: &Source, report: Report) -> Result<(), Box<dyn Error
 
 #[cfg(test)]
 mod tests {
-    //use alloc::vec::Vec;
-    //use alloc::vec;
     use alloc::string::ToString;
-    use sio::compiler::create_thread_env;
     use super::*;
-    static src: &str =
+    use crate::scheduler::Runtime;
+    use crate::scheduler::time::VirtualClock;
+    // a variable is only bound once a thread needs it: both wait until the
+    // comparison reads them
+    static LAZY_DATAFLOW: &str =
         "
         url public_key : sio79f708c25a23ed367610facc14035adc7ba4b1bfa9252ef55c6c24f1b9b03abd;
         url type : src;
         url name : app_name;
         url app : public_key::type::name;
         corporal app::Corporal {
-            pub master :: () {
+            pub main :: () {
                 let x, y;
                 let z;
                 thread {
-                    let assign_x = lazy (x) {
+                    let assign_x = (x) {
+                        wait_needed(x);
                         x = 0;
                     };
                     assign_x(x);
@@ -402,23 +1477,759 @@ mod tests {
                 thread {
                     assign_y(y);
                 }
-                if x == y {
-                    z = 1;
-                }
+                z = x == y;
+                z = true;
             }
-            lazy assign_y :: (y) {
+            assign_y :: (y) {
+                wait_needed(y);
                 y = 0;
             }
         }";
+    fn run(src: &str) -> Result<(), Box<dyn Error>> {
+        let ex = Arc::new(Executor::new());
+        let mut process = smol::block_on(Runtime::new(ex.clone()).create(src.to_string(), "test.sio".to_string()))?;
+        smol::block_on(ex.run(process.run()))
+    }
     #[test]
     fn basic_lazy_concurrent_dataflow() {
-        let env = create_thread_env();
-        use sio_vm_std::run_frontend;
+        run(LAZY_DATAFLOW).expect("x and y are bound once needed, and equal");
+    }
+    #[test]
+    fn variables_bind_once() {
+        run("
+        corporal app::Corporal {
+            pub main :: () {
+                let x, y;
+                x = 1;
+                y = x + 1;
+                y = 2;
+                x == 1;
+            }
+        }").expect("binding to the same value again unifies");
+    }
+    #[test]
+    fn print_waits_for_what_it_prints() {
+        run("
+        corporal app::Corporal {
+            pub main :: () {
+                let x;
+                thread {
+                    x = [1, Point(2, 3)];
+                }
+                print x;
+                print nil;
+            }
+        }").expect("print compiles to a NIF and runs once x is bound");
+    }
+    #[test]
+    fn rebinding_to_a_different_value_fails() {
+        let error = run("
+        corporal app::Corporal {
+            pub main :: () {
+                let x;
+                x = 1;
+                x = 2;
+            }
+        }").unwrap_err();
+        assert!(error.to_string().starts_with("unification failure"));
+    }
+    #[test]
+    fn procedures_take_and_return_procedures() {
+        run(include_str!("../../../scratch/higher_order_procedure_test.sio"))
+            .expect("every result unifies with what it is expected to be");
+    }
+    #[test]
+    fn partial_values_unify_structurally() {
+        run("
+        corporal app::Corporal {
+            pub main :: () {
+                let x, y, a, rest;
+                x = y;
+                x = [a | rest];
+                y = [1];
+                a = 1;
+            }
+        }").expect("a is bound through y");
+    }
+    #[test]
+    fn partial_values_with_other_items_do_not_unify() {
+        let error = run("
+        corporal app::Corporal {
+            pub main :: () {
+                let x, y, a, rest;
+                x = y;
+                x = [a | rest];
+                y = [1];
+                a = 2;
+            }
+        }").unwrap_err();
+        assert!(error.to_string().starts_with("unification failure"));
+    }
+    #[test]
+    fn variables_and_records_are_bound_by_unification() {
+        run(include_str!("../../../scratch/var_to_var_binding_test.sio"))
+            .expect("every variable is bound through another one or a record");
+    }
+    #[test]
+    fn records_of_other_fields_do_not_unify() {
+        let error = run("
+        corporal app::Corporal {
+            pub main :: () {
+                let x;
+                let p = Point(1, x);
+                p = Point(1, 2);
+                p = Point(x, 2);
+            }
+        }").unwrap_err();
+        assert!(error.to_string().starts_with("unification failure"));
+    }
+    #[test]
+    fn cells_exchange_their_content() {
+        run("
+        corporal app::Corporal {
+            pub stateful main :: () {
+                let counter = cell(0);
+                let old = exchange(counter, 1);
+                old = 0;
+                cell_set(counter, cell_get(counter) + 1);
+                let current = cell_get(counter);
+                current = 2;
+            }
+        }").expect("the counter was incremented twice");
+    }
+    #[test]
+    fn a_cell_holds_its_last_content_only() {
+        let error = run("
+        corporal app::Corporal {
+            pub stateful main :: () {
+                let counter = cell(0);
+                cell_set(counter, 1);
+                cell_set(counter, 2);
+                let current = cell_get(counter);
+                current = 1;
+            }
+        }").unwrap_err();
+        assert!(error.to_string().starts_with("unification failure"));
+    }
+    #[test]
+    fn threads_share_dataflow_variables() {
+        run("
+        corporal app::Corporal {
+            pub main :: () {
+                let x, y;
+                thread {
+                    y = x + 1;
+                }
+                thread {
+                    x = 1;
+                }
+                y = 2;
+            }
+        }").expect("the first thread waits for the second one");
+    }
+    #[test]
+    fn a_thread_reads_what_another_one_bound() {
+        let error = run("
+        corporal app::Corporal {
+            pub main :: () {
+                let x, y;
+                thread {
+                    y = x + 1;
+                }
+                thread {
+                    x = 1;
+                }
+                y = 3;
+            }
+        }").unwrap_err();
+        assert!(error.to_string().starts_with("unification failure"));
+    }
+    #[test]
+    fn several_consumers_of_one_lazy_producer() {
+        run("
+        corporal app::Corporal {
+            pub main :: () {
+                let xs, a, b, c;
+                thread {
+                    wait_needed(xs);
+                    xs = [1, 2];
+                }
+                thread {
+                    a = xs[0];
+                }
+                thread {
+                    b = xs[1];
+                }
+                c = xs[0] + xs[1];
+                a = 1;
+                b = 2;
+                c = 3;
+            }
+        }").expect("every consumer gets the list");
+    }
+    #[test]
+    fn several_producers_wait_on_the_same_variable() {
+        run("
+        corporal app::Corporal {
+            pub main :: () {
+                let x, y;
+                thread {
+                    wait_needed(x);
+                    x = 1;
+                }
+                thread {
+                    wait_needed(x);
+                    y = 2;
+                }
+                let z = x + y;
+                z = 3;
+            }
+        }").expect("both producers wake up");
+    }
+    #[test]
+    fn philosophers_waiting_on_each_other_deadlock() {
+        let error = run("
+        corporal app::Corporal {
+            pub main :: () {
+                let left, middle, right;
+                thread {
+                    left = middle + 1;
+                }
+                thread {
+                    middle = right + 1;
+                }
+                thread {
+                    right = left + 1;
+                }
+            }
+        }").unwrap_err();
+        let deadlock = error.downcast_ref::<Deadlock>().expect("a deadlock report");
+        assert_eq!(deadlock.threads.len(), 3);
+        for thread in deadlock.threads.iter() {
+            assert!(matches!(thread.suspension, Suspension::Bound(_)));
+            // `let left, middle, right;`
+            assert_eq!(thread.declared.as_ref().map(|declared| declared.line), Some(4));
+        }
+        assert!(error.to_string().starts_with("deadlock: all 3 threads are suspended"));
+    }
+    #[test]
+    fn a_producer_nobody_needs_deadlocks() {
+        let error = run("
+        corporal app::Corporal {
+            pub main :: () {
+                let x;
+                wait_needed(x);
+                x = 1;
+            }
+        }").unwrap_err();
+        let deadlock = error.downcast_ref::<Deadlock>().expect("a deadlock report");
+        assert_eq!(deadlock.threads.len(), 1);
+        let thread = &deadlock.threads[0];
+        assert!(matches!(thread.suspension, Suspension::Needed(_)));
+        let declared = thread.declared.as_ref().expect("x has a declaration");
+        assert_eq!((declared.line, declared.column), (4, 21));
+    }
+    #[test]
+    fn a_failing_thread_fails_the_process_with_a_trace() {
+        let error = run("
+        corporal app::Corporal {
+            pub main :: () {
+                thread {
+                    let r = divide(1);
+                    r;
+                }
+            }
+            divide :: (n) {
+                n / 0;
+            }
+        }").unwrap_err();
+        let failure = error.downcast_ref::<ThreadFailure>().expect("a thread failure");
+        assert_eq!(failure.error, "division by zero");
+        assert!(failure.ip().is_some());
+        let functions: Vec<String> = failure
+            .trace
+            .iter()
+            .filter_map(|frame| frame.function.as_ref().map(|(name, _)| format!("{}", name)))
+            .collect();
+        assert_eq!(functions[0], "divide");
+        assert!(functions.contains(&"main$lambda0".to_string()));
+        let (_, declared) = failure.trace[0].function.as_ref().unwrap();
+        assert_eq!(declared.as_ref().map(|declared| declared.line), Some(9));
+    }
+    #[test]
+    fn sleeping_threads_resume_when_virtual_time_advances() {
         let ex = Arc::new(Executor::new());
-        //let (source, module) = run_frontend(src, path)?;
-        let mut process = Process::new(src.to_string(), "/".to_string(), env).expect("Corporal failure reason:");
-        smol::block_on(ex.run(process.run()));
-        assert_eq!(4, 4);
-        Ok(())
+        let clock = Arc::new(VirtualClock::new());
+        let mut process = smol::block_on(Runtime::new(ex.clone()).create("
+        corporal app::Corporal {
+            pub main :: () {
+                let x;
+                thread {
+                    sleep(1000);
+                    x = 1;
+                }
+                sleep(500);
+                x = 1;
+            }
+        }".to_string(), "sleep.sio".to_string())).unwrap().with_clock(clock.clone());
+        let settle = || async {
+            for _ in 0..100 {
+                yield_now().await;
+            }
+        };
+        smol::block_on(ex.run(async {
+            let run = ex.spawn(async move { process.run().await.map_err(|e| e.to_string()) });
+            settle().await;
+            clock.advance(999);
+            settle().await;
+            // both timers pending is not a deadlock, the first one fired
+            assert!(!run.is_finished());
+            clock.advance(1);
+            run.await.expect("both threads woke up");
+        }));
+    }
+    #[test]
+    fn a_process_dies_at_the_deadline_it_set_unless_it_ended() {
+        let ex = Arc::new(Executor::new());
+        let clock = Arc::new(VirtualClock::new());
+        let runtime = Runtime::new(ex.clone());
+        let mut dying = smol::block_on(runtime.create("
+        corporal app::Corporal {
+            pub main :: () {
+                die_after(100);
+                wait(1000);
+            }
+        }".to_string(), "die.sio".to_string())).unwrap().with_clock(clock.clone());
+        let mut ending = smol::block_on(runtime.create("
+        corporal app::Corporal {
+            pub main :: () {
+                die_after(100);
+            }
+        }".to_string(), "end.sio".to_string())).unwrap().with_clock(clock.clone());
+        smol::block_on(ex.run(async {
+            ending.run().await.expect("the process ended before its deadline");
+            let run = ex.spawn(async move { dying.run().await.map_err(|e| e.to_string()) });
+            for _ in 0..100 {
+                yield_now().await;
+            }
+            clock.advance(100);
+            let error = run.await.unwrap_err();
+            assert!(error.contains("die_after"), "{}", error);
+        }));
+    }
+    #[test]
+    fn a_timer_nobody_waits_on_does_not_hide_a_deadlock() {
+        let ex = Arc::new(Executor::new());
+        let mut process = smol::block_on(Runtime::new(ex.clone()).create("
+        corporal app::Corporal {
+            pub main :: () {
+                let t = timer(1000);
+                let x;
+                x + 1;
+            }
+        }".to_string(), "deadlock.sio".to_string())).unwrap().with_clock(Arc::new(VirtualClock::new()));
+        smol::block_on(ex.run(async {
+            let run = ex.spawn(async move { process.run().await.map_err(|e| e.to_string()) });
+            for _ in 0..100 {
+                yield_now().await;
+            }
+            assert!(run.is_finished(), "the process still waits on its timer");
+            let error = run.await.unwrap_err();
+            assert!(error.starts_with("deadlock: all 1 threads are suspended"), "{}", error);
+        }));
+    }
+    #[test]
+    fn a_quiescent_process_drops_the_values_it_cannot_reach() {
+        let ex = Arc::new(Executor::new());
+        let clock = Arc::new(VirtualClock::new());
+        let mut process = smol::block_on(Runtime::new(ex.clone()).create("
+        corporal app::Corporal {
+            pub main :: () {
+                let kept = build(0, 3);
+                let total = sum(build(0, 5000));
+                sleep(10);
+                total = 12497500;
+                let left = sum(kept);
+                left = 3;
+            }
+            build :: (from, to) {
+                let xs;
+                if from < to {
+                    xs = [from | build(from + 1, to)];
+                } else {
+                    xs = nil;
+                }
+                xs;
+            }
+            sum :: (xs) {
+                if is_cons(xs) {
+                    head(xs) + sum(tail(xs));
+                } else {
+                    0;
+                }
+            }
+        }".to_string(), "sweep.sio".to_string())).unwrap().with_clock(clock.clone());
+        let (store, next_variable) = (process.store.clone(), process.next_variable.clone());
+        smol::block_on(ex.run(async {
+            let run = ex.spawn(async move { process.run().await.map_err(|e| e.to_string()) });
+            for _ in 0..100 {
+                yield_now().await;
+            }
+            clock.advance(10);
+            run.await.expect("the kept list is still there");
+        }));
+        // the long list went while the process slept
+        let bound = (FIRST_VARIABLE..next_variable.load(Ordering::SeqCst))
+            .filter(|variable| store.is_bound(*variable))
+            .count();
+        assert!(bound < 100, "{} variables still bound", bound);
+    }
+    #[test]
+    fn messages_are_received_in_the_order_they_were_sent() {
+        run("
+        corporal app::Corporal {
+            pub main :: () {
+                send(self(), [1, 2]);
+                send(self(), 3);
+                receive first {
+                    let two = first[1];
+                    two = 2;
+                }
+                let got;
+                receive second {
+                    got = second;
+                } after 0 {
+                    got = 0;
+                }
+                got = 3;
+                let late;
+                receive third {
+                    late = third;
+                } after 0 {
+                    late = 0;
+                }
+                late = 0;
+            }
+        }").expect("a message already there is received even past the deadline");
+        let error = run("
+        corporal app::Corporal {
+            pub main :: () {
+                let x;
+                send(self(), [x]);
+            }
+        }").unwrap_err();
+        assert!(error.to_string().starts_with("a message has to be determined"), "{}", error);
+    }
+    #[test]
+    fn a_sender_waits_while_the_mailbox_is_full() {
+        let ex = Arc::new(Executor::new());
+        let clock = Arc::new(VirtualClock::new());
+        let mut config = ProcessConfig::default();
+        config.corporal.messages = Some(1);
+        let mut process = smol::block_on(Runtime::with_config(ex.clone(), config).create("
+        corporal app::Corporal {
+            pub main :: () {
+                let me = self();
+                thread {
+                    send(me, 1);
+                    send(me, 2);
+                }
+                sleep(10);
+                receive a {
+                    a = 1;
+                }
+                receive b {
+                    b = 2;
+                }
+            }
+        }".to_string(), "full.sio".to_string())).unwrap().with_clock(clock.clone());
+        let meter = process.meter();
+        smol::block_on(ex.run(async {
+            let run = ex.spawn(async move { process.run().await.map_err(|e| e.to_string()) });
+            for _ in 0..100 {
+                yield_now().await;
+            }
+            assert_eq!(meter.usage().threads, 2, "the sender waits for room for its second message");
+            clock.advance(10);
+            run.await.expect("the messages come through once received");
+        }));
+    }
+    fn racing_exchanges(mode: ScheduleMode) -> (Result<(), Box<dyn Error>>, Schedule) {
+        let ex = Arc::new(Executor::new());
+        let mut process = smol::block_on(Runtime::new(ex).create("
+        corporal app::Corporal {
+            pub stateful main :: () {
+                let c = cell(0);
+                let a, b;
+                thread {
+                    exchange(c, 1);
+                    a = 1;
+                }
+                thread {
+                    exchange(c, 2);
+                    b = 1;
+                }
+                let both = a + b;
+                let last = cell_get(c);
+                last = 1;
+            }
+        }".to_string(), "race.sio".to_string())).unwrap();
+        process.run_deterministic(mode)
+    }
+    #[test]
+    fn seeded_schedules_are_reproducible() {
+        let mut outcomes = Vec::new();
+        for seed in 0..32 {
+            let (result, schedule) = racing_exchanges(ScheduleMode::Seeded(seed));
+            let (again, same) = racing_exchanges(ScheduleMode::Seeded(seed));
+            assert_eq!(schedule, same);
+            assert_eq!(result.is_ok(), again.is_ok());
+            let (replayed, replay) = racing_exchanges(ScheduleMode::Replay(schedule.clone()));
+            assert_eq!(schedule, replay);
+            assert_eq!(result.is_ok(), replayed.is_ok());
+            outcomes.push(result.is_ok());
+        }
+        // the race goes both ways depending on the seed
+        assert!(outcomes.contains(&true) && outcomes.contains(&false));
+    }
+    #[test]
+    fn a_full_mailbox_suspends_the_threads_sending_to_it() {
+        let mut config = ProcessConfig::default();
+        config.corporal.mailbox = Some(1);
+        for seed in 0..8 {
+            let runtime = Runtime::with_config(Arc::new(Executor::new()), config.clone());
+            let mut process = smol::block_on(runtime.create("
+            corporal app::Corporal {
+                pub stateful main :: () {
+                    let c = cell(0);
+                    let a, b, d;
+                    thread { exchange(c, 1); exchange(c, 2); exchange(c, 3); a = 1; }
+                    thread { exchange(c, 4); exchange(c, 5); exchange(c, 6); b = 1; }
+                    thread { exchange(c, 7); exchange(c, 8); exchange(c, 9); d = 1; }
+                    let total = a + b + d;
+                    total = 3;
+                }
+            }".to_string(), "mailbox.sio".to_string())).unwrap();
+            let (result, _) = process.run_deterministic(ScheduleMode::Seeded(seed));
+            result.expect("the threads wait for room in the mailbox");
+        }
     }
-}
\ No newline at end of file
+    #[test]
+    fn buffered_streams_keep_the_producer_a_few_elements_ahead() {
+        run("
+        corporal app::Corporal {
+            pub stateful main :: () {
+                let produced = cell(0);
+                let xs = stream_buffer(counted(0, 20, produced), 3);
+                let total = check(xs, 0, produced);
+                total = 190;
+            }
+            stateful counted :: (from, to, produced) {
+                let xs;
+                thread {
+                    wait_needed(xs);
+                    if from < to {
+                        cell_set(produced, from + 1);
+                        xs = [from | counted(from + 1, to, produced)];
+                    } else {
+                        xs = nil;
+                    }
+                }
+                xs;
+            }
+            // the sum of xs, checking the producer never runs more than the
+            // 3 buffered elements ahead of the one being read
+            stateful check :: (xs, consumed, produced) {
+                if is_cons(xs) {
+                    let ahead = cell_get(produced) - consumed;
+                    let bounded = ahead <= 4;
+                    bounded = true;
+                    head(xs) + check(tail(xs), consumed + 1, produced);
+                } else {
+                    0;
+                }
+            }
+        }").expect("the buffered stream is read to the end");
+    }
+    #[test]
+    fn lazy_ranges_only_produce_what_is_read() {
+        run("
+        corporal app::Corporal {
+            pub main :: () {
+                let xs = stream_range(0, 5);
+                let rest = stream_drop(xs, 2);
+                let third = rest[0];
+                third = 2;
+                let total = xs[0] + xs[1] + xs[2] + xs[3] + xs[4];
+                total = 10;
+                let empty = stream_drop(xs, 5);
+                empty = nil;
+            }
+        }").expect("the range is read to the end");
+    }
+    #[test]
+    fn supervisors_see_how_their_children_ended() {
+        run("
+        brigadier app::Brigadier {
+            pub main :: () {
+                let n = 2;
+                let fine = spawn_process(() { let x = n; x = 2; });
+                let broken = spawn_process(() { let x = n; x = 3; });
+                let completed = supervise(fine);
+                completed = true;
+                let failed = supervise(broken);
+                failed = false;
+            }
+        }").expect("a failing child does not fail its parent");
+        let error = run("
+        major app::Major {
+            pub main :: () {
+                let x;
+                spawn_process(() { x; });
+            }
+        }").unwrap_err();
+        // the thread asking fails where it asked
+        let failure = error.downcast_ref::<ThreadFailure>().expect("a thread failure");
+        assert_eq!(failure.error, "a process can only be started with determined values");
+        assert!(failure.ip().is_some());
+    }
+    #[test]
+    fn children_run_a_rank_below_their_parent() {
+        // the child of a brigadier is a major, which cannot supervise
+        let error = run("
+        brigadier app::Brigadier {
+            pub main :: () {
+                let child = spawn_process(() {
+                    let grandchild = spawn_process(() { 1; });
+                    supervise(grandchild);
+                });
+                let completed = supervise(child);
+                completed = false;
+            }
+        }");
+        assert!(error.is_ok(), "the child failed, not its parent: {:?}", error.err());
+
+        let ex = Arc::new(Executor::new());
+        let mut runtime = Runtime::new(ex.clone());
+        let parent = smol::block_on(runtime.spawn("
+        brigadier app::Brigadier {
+            pub main :: () {
+                let child = spawn_process(() {
+                    let got = receive();
+                    got = 1;
+                });
+                send(child, 1);
+                let completed = supervise(child);
+                completed = true;
+            }
+        }".to_string(), "children.sio".to_string())).unwrap();
+        assert_eq!(smol::block_on(ex.run(runtime.join(parent))), Some(ProcessExit::Completed));
+        // the child is listed with the processes of the runtime, and got the
+        // message sent to its id
+        let processes = runtime.processes();
+        assert_eq!(processes.len(), 2);
+        assert_eq!(processes[1].exit, Some(ProcessExit::Completed));
+    }
+    static TICKS: &str = "
+    corporal app::Corporal {
+        pub main :: () {
+            let total = sum(ticks(0, 6));
+            total = 15;
+        }
+        // one element every 10 milliseconds
+        ticks :: (from, to) {
+            if from < to {
+                let rest;
+                thread {
+                    sleep(10);
+                    rest = ticks(from + 1, to);
+                }
+                [from | rest];
+            } else {
+                nil;
+            }
+        }
+        sum :: (xs) {
+            if is_cons(xs) {
+                head(xs) + sum(tail(xs));
+            } else {
+                0;
+            }
+        }
+    }";
+    // runs the process to its end, advancing the clock as it goes
+    fn run_ticking(ex: &Arc<Executor<'static>>, clock: &Arc<VirtualClock>, mut process: Process<'static>) -> Result<(), String> {
+        smol::block_on(ex.run(async {
+            let run = ex.spawn(async move { process.run().await.map_err(|e| e.to_string()) });
+            for _ in 0..6 {
+                for _ in 0..100 {
+                    yield_now().await;
+                }
+                clock.advance(10);
+            }
+            run.await
+        }))
+    }
+    #[test]
+    fn restored_processes_carry_on_where_they_were_saved() {
+        let ex = Arc::new(Executor::new());
+        let clock = Arc::new(VirtualClock::new());
+        let runtime = Runtime::new(ex.clone()).with_clock(clock.clone());
+        run_ticking(&ex, &clock, smol::block_on(runtime.create(TICKS.to_string(), "ticks.sio".to_string())).unwrap())
+            .expect("the uninterrupted run sums the stream");
+
+        // saved mid-stream: the first element is summed, the next one is
+        // 10 milliseconds away and the sum is waiting for it
+        let mut process = smol::block_on(runtime.create(TICKS.to_string(), "ticks.sio".to_string())).unwrap();
+        let saved = smol::block_on(ex.run(process.run_to_snapshot())).expect("the process waits on its timer");
+        let bytes = saved.to_bytes();
+
+        // restored by another runtime, with a clock of its own
+        let ex = Arc::new(Executor::new());
+        let clock = Arc::new(VirtualClock::new());
+        let runtime = Runtime::new(ex.clone()).with_clock(clock.clone());
+        let snapshot = Snapshot::from_bytes(&bytes).expect("a valid snapshot");
+        let mut restored = runtime.restore(&snapshot).unwrap();
+        let again = smol::block_on(ex.run(restored.run_to_snapshot())).expect("the restored process waits too");
+        assert_eq!(again.to_bytes(), bytes);
+        run_ticking(&ex, &clock, runtime.restore(&snapshot).unwrap())
+            .expect("the restored run sums the stream to the same total");
+    }
+    #[test]
+    fn a_busy_thread_does_not_starve_the_others_of_its_process() {
+        // the spinning thread never ends: the process only stops on the
+        // failure the main thread provokes once it summed the whole stream
+        let error = run("
+        corporal app::Corporal {
+            pub main :: () {
+                thread {
+                    spin(0);
+                }
+                let total = sum(produce(0, 10));
+                total = 0;
+            }
+            spin :: (n) {
+                spin(n + 1);
+            }
+            produce :: (from, to) {
+                let xs;
+                thread {
+                    if from < to {
+                        xs = [from | produce(from + 1, to)];
+                    } else {
+                        xs = nil;
+                    }
+                }
+                xs;
+            }
+            sum :: (xs) {
+                if is_cons(xs) {
+                    head(xs) + sum(tail(xs));
+                } else {
+                    0;
+                }
+            }
+        }").unwrap_err().to_string();
+        assert!(error.contains(", 45) does not unify with Integer(0, 0)"), "{}", error);
+    }
+}
@@ -0,0 +1,171 @@
+use alloc::sync::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::scheduler::store::Store;
+
+/// What a process can use up, `None` for no limit. A process going over one
+/// of its limits is killed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// values held at the same time, in bound variables and in cells
+    pub values: Option<u64>,
+    /// threads running at the same time
+    pub threads: Option<u64>,
+    /// child processes started, spawned or deployed
+    pub children: Option<u64>,
+    /// steps run by all the threads together
+    pub reductions: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Values,
+    Threads,
+    Children,
+    Reductions,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Values => write!(f, "values"),
+            Resource::Threads => write!(f, "threads"),
+            Resource::Children => write!(f, "child processes"),
+            Resource::Reductions => write!(f, "reductions"),
+        }
+    }
+}
+
+/// What a process used so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub values: u64,
+    pub threads: u64,
+    pub children: u64,
+    pub reductions: u64,
+}
+
+impl Usage {
+    pub fn get(&self, resource: Resource) -> u64 {
+        match resource {
+            Resource::Values => self.values,
+            Resource::Threads => self.threads,
+            Resource::Children => self.children,
+            Resource::Reductions => self.reductions,
+        }
+    }
+}
+
+impl Quota {
+    pub fn limit(&self, resource: Resource) -> Option<u64> {
+        match resource {
+            Resource::Values => self.values,
+            Resource::Threads => self.threads,
+            Resource::Children => self.children,
+            Resource::Reductions => self.reductions,
+        }
+    }
+
+    /// The first limit `usage` goes over.
+    pub fn exceeded(&self, usage: &Usage) -> Option<QuotaExceeded> {
+        [Resource::Values, Resource::Threads, Resource::Children, Resource::Reductions]
+            .into_iter()
+            .find_map(|resource| match self.limit(resource) {
+                Some(limit) if usage.get(resource) > limit => {
+                    Some(QuotaExceeded { resource, limit, usage: *usage })
+                }
+                _ => None,
+            })
+    }
+}
+
+/// The reason a process was killed for going over its quota.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub resource: Resource,
+    pub limit: u64,
+    pub usage: Usage,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "quota exceeded: {} {} used, the limit is {}",
+            self.usage.get(self.resource),
+            self.resource,
+            self.limit
+        )
+    }
+}
+
+impl core::error::Error for QuotaExceeded {}
+
+// The counters behind `Usage`, shared by a process, its threads and its
+// runtime. Threads count their steps one at a time and check the quota before
+// each, a process is stopped at the first step over a limit. Values are those
+// the store holds: a quiescent process drops what nothing refers to anymore,
+// a busy one keeps them until then.
+pub(crate) struct Meter {
+    // the store of the process
+    store: Arc<Store>,
+    cells: AtomicU64,
+    threads: AtomicU64,
+    children: AtomicU64,
+    reductions: AtomicU64,
+}
+
+impl Meter {
+    pub(crate) fn new(store: Arc<Store>) -> Self {
+        Self {
+            store,
+            cells: AtomicU64::new(0),
+            threads: AtomicU64::new(0),
+            children: AtomicU64::new(0),
+            reductions: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn usage(&self) -> Usage {
+        Usage {
+            values: self.store.values() + self.cells.load(Ordering::Relaxed),
+            threads: self.threads.load(Ordering::Relaxed),
+            children: self.children.load(Ordering::Relaxed),
+            reductions: self.reductions.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn set_cells(&self, cells: usize) {
+        self.cells.store(cells as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_threads(&self, threads: usize) {
+        self.threads.store(threads as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn child(&self) {
+        self.children.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reduced(&self) {
+        self.reductions.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn the_first_limit_gone_over_is_reported() {
+        let quota = Quota { threads: Some(4), reductions: Some(100), ..Quota::default() };
+        let mut usage = Usage { values: 1000, threads: 4, children: 12, reductions: 100 };
+        assert_eq!(quota.exceeded(&usage), None);
+        usage.reductions = 101;
+        usage.threads = 5;
+        let exceeded = quota.exceeded(&usage).expect("over two limits");
+        assert_eq!(exceeded.resource, Resource::Threads);
+        assert_eq!(exceeded.to_string(), "quota exceeded: 5 threads used, the limit is 4");
+    }
+}
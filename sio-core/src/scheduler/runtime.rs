@@ -0,0 +1,818 @@
+use alloc::{boxed::Box, format, string::{String, ToString}, sync::Arc, vec::Vec};
+use core::error::Error;
+use core::fmt;
+use async_channel::Sender;
+use std::sync::Mutex;
+use core::sync::atomic::{AtomicU64, Ordering};
+use hashbrown::HashMap;
+use smol::{Executor, Task};
+use crate::compiler::value::ThreadValue as Value;
+use crate::scheduler::config::{ProcessConfig, ProcessRole};
+use crate::scheduler::deadlock::Deadlock;
+use crate::scheduler::failure::ThreadFailure;
+use crate::scheduler::modules::{LoadedModule, Modules};
+use crate::scheduler::node::{Network, Node, NodeId};
+use crate::scheduler::process::{default_clock, Operation, Process, ProcessId, ThreadId};
+use crate::scheduler::quota::{Meter, QuotaExceeded, Usage};
+use crate::scheduler::snapshot::Snapshot;
+use crate::scheduler::time::Clock;
+use crate::scheduler::transport::Transport;
+
+// A runtime is a node: it owns what the processes running on it share, the
+// executor their threads run on, the id counters, the clock of their timers,
+// the modules their external calls run, the nodes they deploy to and the
+// table of its processes. Two runtimes share nothing, so tests and
+// embedders can run several side by side.
+
+/// Allocates the ids of the processes of a runtime and of their threads.
+#[derive(Debug, Default)]
+pub(crate) struct Ids {
+    process: AtomicU64,
+    thread: AtomicU64,
+}
+
+impl Ids {
+    pub(crate) fn process(&self) -> ProcessId {
+        self.process.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub(crate) fn thread(&self) -> ThreadId {
+        self.thread.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+/// How a process ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessExit {
+    /// all its threads terminated
+    Completed,
+    /// a thread failed or the threads deadlocked
+    Failed(ProcessFailure),
+    /// shut down before it ended
+    Shutdown,
+    /// killed, for going over its quota or by `Runtime::kill`, with the
+    /// reason
+    Killed(String),
+}
+
+impl ProcessExit {
+    // how a process that ran to `result` ended
+    pub(crate) fn of(result: &Result<(), Box<dyn Error>>) -> Self {
+        match result {
+            Ok(()) => ProcessExit::Completed,
+            Err(error) => match error.downcast_ref::<QuotaExceeded>() {
+                Some(exceeded) => ProcessExit::Killed(exceeded.to_string()),
+                None => ProcessExit::Failed(ProcessFailure::of(error.as_ref())),
+            },
+        }
+    }
+}
+
+/// Why a process failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessFailure {
+    Deadlock(Deadlock),
+    Thread(ThreadFailure),
+    /// any other error, such as a value it cannot bind
+    Other(String),
+}
+
+impl ProcessFailure {
+    fn of(error: &(dyn Error + 'static)) -> Self {
+        if let Some(deadlock) = error.downcast_ref::<Deadlock>() {
+            ProcessFailure::Deadlock(deadlock.clone())
+        } else if let Some(failure) = error.downcast_ref::<ThreadFailure>() {
+            ProcessFailure::Thread(failure.clone())
+        } else {
+            ProcessFailure::Other(error.to_string())
+        }
+    }
+}
+
+impl fmt::Display for ProcessFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessFailure::Deadlock(deadlock) => write!(f, "{}", deadlock),
+            ProcessFailure::Thread(failure) => write!(f, "{}", failure),
+            ProcessFailure::Other(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for ProcessFailure {}
+
+/// A process of a runtime, as listed by `Runtime::processes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub process_id: ProcessId,
+    pub path: String,
+    /// the names it is registered under
+    pub names: Vec<String>,
+    /// once it ended
+    pub exit: Option<ProcessExit>,
+    pub usage: Usage,
+}
+
+struct Entry {
+    path: String,
+    meter: Arc<Meter>,
+    mailbox: Sender<Value>,
+    exit: Option<ProcessExit>,
+}
+
+/// The processes of a runtime, from when they are created, whether they
+/// are started or run by hand. The processes record how they ended.
+#[derive(Default)]
+pub(crate) struct ProcessTable {
+    entries: Mutex<HashMap<ProcessId, Entry>>,
+    // the processes deployed to other nodes, whose mail goes over the link
+    deployed: Mutex<HashMap<ProcessId, Sender<Value>>>,
+}
+
+impl ProcessTable {
+    pub(crate) fn insert(&self, process_id: ProcessId, path: &str, meter: Arc<Meter>, mailbox: Sender<Value>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.entry(process_id).or_insert_with(|| Entry { path: path.to_string(), meter, mailbox, exit: None });
+    }
+
+    /// The first exit recorded is the one the process keeps.
+    pub(crate) fn exited(&self, process_id: ProcessId, exit: ProcessExit) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(&process_id) {
+            entry.exit.get_or_insert(exit);
+        }
+    }
+
+    pub(crate) fn mailbox(&self, process_id: ProcessId) -> Option<Sender<Value>> {
+        if let Some(entry) = self.entries.lock().unwrap().get(&process_id) {
+            return Some(entry.mailbox.clone());
+        }
+        self.deployed.lock().unwrap().get(&process_id).cloned()
+    }
+
+    /// Mail for `process_id`, which runs on another node, goes to `mailbox`
+    /// until it is `undeployed`.
+    pub(crate) fn deployed(&self, process_id: ProcessId, mailbox: Sender<Value>) {
+        self.deployed.lock().unwrap().insert(process_id, mailbox);
+    }
+
+    pub(crate) fn undeployed(&self, process_id: ProcessId) {
+        self.deployed.lock().unwrap().remove(&process_id);
+    }
+
+    fn contains(&self, process_id: ProcessId) -> bool {
+        self.entries.lock().unwrap().contains_key(&process_id)
+    }
+
+    fn exit(&self, process_id: ProcessId) -> Option<ProcessExit> {
+        self.entries.lock().unwrap().get(&process_id)?.exit.clone()
+    }
+
+    fn usage(&self, process_id: ProcessId) -> Option<Usage> {
+        Some(self.entries.lock().unwrap().get(&process_id)?.meter.usage())
+    }
+
+    // id, path, exit and usage of every process, by id
+    fn list(&self) -> Vec<(ProcessId, String, Option<ProcessExit>, Usage)> {
+        let entries = self.entries.lock().unwrap();
+        let mut list: Vec<_> = entries
+            .iter()
+            .map(|(process_id, entry)| (*process_id, entry.path.clone(), entry.exit.clone(), entry.meter.usage()))
+            .collect();
+        list.sort_by_key(|(process_id, ..)| *process_id);
+        list
+    }
+}
+
+pub struct Runtime<'a> {
+    executor: Arc<Executor<'a>>,
+    config: ProcessConfig,
+    clock: Arc<dyn Clock>,
+    ids: Arc<Ids>,
+    modules: Arc<Modules>,
+    network: Arc<Network>,
+    // serve the links to the other nodes
+    links: Vec<Task<()>>,
+    table: Arc<ProcessTable>,
+    // the processes started and not joined or shut down yet
+    tasks: HashMap<ProcessId, Task<ProcessExit>>,
+    // names processes are registered under
+    registry: HashMap<String, ProcessId>,
+}
+
+impl<'a> Runtime<'a> {
+    pub fn new(executor: Arc<Executor<'a>>) -> Self {
+        Self::with_config(executor, ProcessConfig::default())
+    }
+
+    pub fn with_config(executor: Arc<Executor<'a>>, config: ProcessConfig) -> Self {
+        Self {
+            executor,
+            config,
+            clock: default_clock(),
+            ids: Arc::new(Ids::default()),
+            modules: Arc::new(Modules::new()),
+            network: Arc::new(Network::new()),
+            links: Vec::new(),
+            table: Arc::new(ProcessTable::default()),
+            tasks: HashMap::new(),
+            registry: HashMap::new(),
+        }
+    }
+
+    /// Uses `clock` for the timers of the processes created from now on.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn executor(&self) -> Arc<Executor<'a>> {
+        self.executor.clone()
+    }
+
+    pub fn config(&self) -> &ProcessConfig {
+        &self.config
+    }
+
+    /// Compiles a process without starting it, to run it by hand or hand it
+    /// to `start` later. It can import the modules loaded.
+    pub async fn create(&self, src: String, path: String) -> Result<Process<'a>, Box<dyn Error>> {
+        let interfaces = self.modules.interfaces().await;
+        let process = Process::new(self.executor.clone(), self.ids.clone(), self.config.clone(), src, path, &interfaces)?;
+        Ok(process
+            .with_clock(self.clock.clone())
+            .with_modules(self.modules.clone())
+            .with_network(self.network.clone())
+            .with_table(self.table.clone()))
+    }
+
+    /// A process without a program, whose main thread plays `script`.
+    pub fn create_scripted(&self, script: Vec<Operation>) -> Result<Process<'a>, Box<dyn Error>> {
+        let process = Process::scripted(self.executor.clone(), self.ids.clone(), self.config.clone(), script)?;
+        Ok(process
+            .with_clock(self.clock.clone())
+            .with_modules(self.modules.clone())
+            .with_network(self.network.clone())
+            .with_table(self.table.clone()))
+    }
+
+    /// A process carrying on from `snapshot`, which may have been saved by
+    /// another runtime. It gets a new id, and is run like a created one.
+    pub fn restore(&self, snapshot: &Snapshot) -> Result<Process<'a>, Box<dyn Error>> {
+        let process = Process::restore(self.executor.clone(), self.ids.clone(), self.config.clone(), snapshot)?;
+        Ok(process
+            .with_clock(self.clock.clone())
+            .with_modules(self.modules.clone())
+            .with_network(self.network.clone())
+            .with_table(self.table.clone()))
+    }
+
+    /// Compiles the module `src` declares and makes it the current version
+    /// at its url, which the external calls made from now on run. Returns
+    /// the version it was loaded as.
+    pub async fn load(&self, src: String, path: String) -> Result<u64, Box<dyn Error>> {
+        let versions = self.load_all(alloc::vec![(src, path)]).await?;
+        Ok(versions[0])
+    }
+
+    /// Compiles the modules of a program split over several files, given as
+    /// source and path, which can import each other as well as the modules
+    /// loaded, and loads them all, or none when one of them fails to.
+    pub async fn load_all(&self, files: Vec<(String, String)>) -> Result<Vec<u64>, Box<dyn Error>> {
+        let modules = LoadedModule::compile_all(files, &self.modules.interfaces().await)?;
+        self.modules.load_all(modules).await
+    }
+
+    /// Drops the old version of the module at `url`, once no thread runs it
+    /// anymore.
+    pub async fn purge(&self, url: &str) -> Result<(), Box<dyn Error>> {
+        self.modules.purge(url).await
+    }
+
+    pub fn modules(&self) -> Arc<Modules> {
+        self.modules.clone()
+    }
+
+    /// Connects to the node at the other end of `transport`, which processes
+    /// can deploy to from now on, and which can deploy to this one. Nothing
+    /// tells who is at the other end, so what it deploys here runs as a
+    /// corporal, see `connect_as`.
+    pub async fn connect(&mut self, transport: impl Transport + 'static) -> NodeId {
+        self.connect_as(transport, ProcessRole::Corporal).await
+    }
+
+    /// Like `connect`, for a node the embedder trusts with processes up to
+    /// `role`: what it deploys runs a rank below the process deploying it,
+    /// and never above `role`.
+    pub async fn connect_as(&mut self, transport: impl Transport + 'static, role: ProcessRole) -> NodeId {
+        let (node_id, link) = self.network.add(Arc::new(transport), role).await;
+        let node = Node {
+            executor: self.executor.clone(),
+            ids: self.ids.clone(),
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            modules: self.modules.clone(),
+            network: self.network.clone(),
+            table: self.table.clone(),
+        };
+        self.links.push(self.executor.spawn(node.serve(node_id, link)));
+        node_id
+    }
+
+    /// Closes the connection to the node. The processes deployed over it
+    /// count as failed for their supervisors, and the threads waiting on a
+    /// variable shared over it fail, on both ends. `false` for a node that
+    /// is not connected.
+    pub async fn disconnect(&self, node: NodeId) -> bool {
+        self.network.close(node).await
+    }
+
+    pub fn network(&self) -> Arc<Network> {
+        self.network.clone()
+    }
+
+    /// Compiles a process and starts it.
+    pub async fn spawn(&mut self, src: String, path: String) -> Result<ProcessId, Box<dyn Error>> {
+        let process = self.create(src, path).await?;
+        Ok(self.start(process))
+    }
+
+    /// Runs `process` on the executor of the runtime.
+    pub fn start(&mut self, mut process: Process<'a>) -> ProcessId {
+        let process_id = process.process_id();
+        // a process created by another runtime is listed by this one too
+        self.table.insert(process_id, process.path(), process.meter(), process.inbox());
+        let task = self.executor.spawn(async move {
+            let result = process.run().await;
+            ProcessExit::of(&result)
+        });
+        self.tasks.insert(process_id, task);
+        process_id
+    }
+
+    /// Waits for the process to end. `None` for a process the runtime does not
+    /// know, or that was not started and did not end.
+    pub async fn join(&mut self, process_id: ProcessId) -> Option<ProcessExit> {
+        if let Some(task) = self.tasks.remove(&process_id) {
+            let exit = task.await;
+            self.exited(process_id, exit);
+        }
+        self.table.exit(process_id)
+    }
+
+    /// Waits for every process started so far to end.
+    pub async fn join_all(&mut self) -> Vec<(ProcessId, ProcessExit)> {
+        let mut exits = Vec::new();
+        for process_id in self.process_ids() {
+            if let Some(exit) = self.join(process_id).await {
+                exits.push((process_id, exit));
+            }
+        }
+        exits
+    }
+
+    /// Stops the process and cancels its threads. A process that already
+    /// ended keeps the exit it ended with.
+    pub async fn shutdown(&mut self, process_id: ProcessId) -> Option<ProcessExit> {
+        self.stop(process_id, ProcessExit::Shutdown).await
+    }
+
+    /// Like `shutdown`, the process ending as killed for `reason`.
+    pub async fn kill(&mut self, process_id: ProcessId, reason: &str) -> Option<ProcessExit> {
+        self.stop(process_id, ProcessExit::Killed(reason.to_string())).await
+    }
+
+    async fn stop(&mut self, process_id: ProcessId, exit: ProcessExit) -> Option<ProcessExit> {
+        if let Some(task) = self.tasks.remove(&process_id) {
+            let exit = task.cancel().await.unwrap_or(exit);
+            self.exited(process_id, exit);
+        }
+        self.table.exit(process_id)
+    }
+
+    pub async fn shutdown_all(&mut self) {
+        for process_id in self.process_ids() {
+            self.shutdown(process_id).await;
+        }
+    }
+
+    /// What the process used so far, or used in total once it ended.
+    pub fn usage(&self, process_id: ProcessId) -> Option<Usage> {
+        self.table.usage(process_id)
+    }
+
+    /// Names the process, so it can be found with `whereis`. The name is
+    /// released when the process ends.
+    pub fn register(&mut self, name: &str, process_id: ProcessId) -> Result<(), Box<dyn Error>> {
+        if !self.table.contains(process_id) {
+            return Err(format!("process {} does not exist", process_id).into());
+        }
+        if self.table.exit(process_id).is_some() {
+            return Err(format!("process {} has ended", process_id).into());
+        }
+        if let Some(registered) = self.registry.get(name) {
+            return Err(format!("{} is already the name of process {}", name, registered).into());
+        }
+        self.registry.insert(name.to_string(), process_id);
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, name: &str) -> Option<ProcessId> {
+        self.registry.remove(name)
+    }
+
+    pub fn whereis(&self, name: &str) -> Option<ProcessId> {
+        self.registry.get(name).copied()
+    }
+
+    /// The processes created so far, by id.
+    pub fn processes(&self) -> Vec<ProcessInfo> {
+        self.table
+            .list()
+            .into_iter()
+            .map(|(process_id, path, exit, usage)| {
+                let mut names: Vec<String> = self
+                    .registry
+                    .iter()
+                    .filter(|(_, registered)| **registered == process_id)
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                names.sort();
+                ProcessInfo { process_id, path, names, exit, usage }
+            })
+            .collect()
+    }
+
+    fn process_ids(&self) -> Vec<ProcessId> {
+        self.table.list().into_iter().map(|(process_id, ..)| process_id).collect()
+    }
+
+    fn exited(&mut self, process_id: ProcessId, exit: ProcessExit) {
+        self.table.exited(process_id, exit);
+        self.registry.retain(|_, registered| *registered != process_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::quota::Quota;
+    use crate::scheduler::time::VirtualClock;
+
+    static COMPLETES: &str = "
+        corporal app::Corporal {
+            pub main :: () {
+                let x, y;
+                thread {
+                    y = x + 1;
+                }
+                x = 1;
+                y = 2;
+            }
+        }";
+
+    static FAILS: &str = "
+        corporal app::Corporal {
+            pub main :: () {
+                let x;
+                x = 1;
+                x = 2;
+            }
+        }";
+
+    // waits on a virtual clock nobody advances
+    static SLEEPS: &str = "
+        corporal app::Corporal {
+            pub main :: () {
+                sleep(1000);
+            }
+        }";
+
+    #[test]
+    fn processes_get_their_own_ids_and_are_joined() {
+        let ex = Arc::new(Executor::new());
+        let mut runtime = Runtime::new(ex.clone());
+        let first = smol::block_on(runtime.spawn(COMPLETES.to_string(), "first.sio".to_string())).unwrap();
+        let second = smol::block_on(runtime.spawn(FAILS.to_string(), "second.sio".to_string())).unwrap();
+        assert_ne!(first, second);
+        let exits = smol::block_on(ex.run(runtime.join_all()));
+        assert_eq!(exits[0], (first, ProcessExit::Completed));
+        match &exits[1] {
+            (process_id, ProcessExit::Failed(ProcessFailure::Thread(failure))) => {
+                assert_eq!(*process_id, second);
+                assert!(failure.error.starts_with("unification failure"));
+            }
+            exit => panic!("the second process fails, got {:?}", exit),
+        }
+        let paths: Vec<String> = runtime.processes().into_iter().map(|info| info.path).collect();
+        assert_eq!(paths, ["first.sio", "second.sio"]);
+    }
+
+    #[test]
+    fn created_processes_are_listed_and_record_how_they_ended() {
+        let ex = Arc::new(Executor::new());
+        let runtime = Runtime::new(ex.clone());
+        let mut process = smol::block_on(runtime.create(FAILS.to_string(), "fails.sio".to_string())).unwrap();
+        assert_eq!(runtime.processes()[0].process_id, process.process_id());
+        assert_eq!(runtime.processes()[0].exit, None);
+        assert!(smol::block_on(ex.run(process.run())).is_err());
+        match runtime.processes()[0].exit.clone() {
+            Some(ProcessExit::Failed(ProcessFailure::Thread(failure))) => {
+                assert!(failure.error.starts_with("unification failure"))
+            }
+            exit => panic!("the process fails, got {:?}", exit),
+        }
+    }
+
+    #[test]
+    fn runtimes_do_not_share_ids() {
+        let ex = Arc::new(Executor::new());
+        let first = smol::block_on(Runtime::new(ex.clone()).create(COMPLETES.to_string(), "a.sio".to_string())).unwrap();
+        let second = smol::block_on(Runtime::new(ex.clone()).create(COMPLETES.to_string(), "b.sio".to_string())).unwrap();
+        assert_eq!(first.process_id(), second.process_id());
+    }
+
+    #[test]
+    fn shutting_down_stops_a_waiting_process() {
+        let ex = Arc::new(Executor::new());
+        let mut runtime = Runtime::new(ex.clone()).with_clock(Arc::new(VirtualClock::new()));
+        let sleeper = smol::block_on(runtime.spawn(SLEEPS.to_string(), "sleeper.sio".to_string())).unwrap();
+        runtime.register("sleeper", sleeper).unwrap();
+        assert!(runtime.register("sleeper", sleeper).is_err());
+        smol::block_on(ex.run(async {
+            for _ in 0..100 {
+                smol::future::yield_now().await;
+            }
+            assert_eq!(runtime.processes()[0].exit, None);
+            assert_eq!(runtime.whereis("sleeper"), Some(sleeper));
+            assert_eq!(runtime.processes()[0].names, ["sleeper"]);
+            assert_eq!(runtime.shutdown(sleeper).await, Some(ProcessExit::Shutdown));
+        }));
+        assert_eq!(runtime.whereis("sleeper"), None);
+        assert_eq!(runtime.processes()[0].exit, Some(ProcessExit::Shutdown));
+        assert!(runtime.register("sleeper", sleeper).is_err());
+    }
+
+    fn run_with_quota(quota: Quota, reductions: u32, src: &str) -> (Option<ProcessExit>, Usage) {
+        let mut config = ProcessConfig::default();
+        for role in [&mut config.corporal, &mut config.major] {
+            role.quota = quota.clone();
+            role.reductions = reductions;
+        }
+        let ex = Arc::new(Executor::new());
+        let mut runtime = Runtime::with_config(ex.clone(), config);
+        let process_id = smol::block_on(runtime.spawn(src.to_string(), "quota.sio".to_string())).unwrap();
+        let exit = smol::block_on(ex.run(runtime.join(process_id)));
+        (exit, runtime.usage(process_id).unwrap())
+    }
+
+    #[test]
+    fn going_over_a_quota_kills_the_process() {
+        let spin = "
+        corporal app::Corporal {
+            pub main :: () {
+                spin(0);
+            }
+            spin :: (n) {
+                spin(n + 1);
+            }
+        }";
+        let (exit, usage) = run_with_quota(Quota { reductions: Some(10_000), ..Quota::default() }, 100, spin);
+        assert_eq!(
+            exit,
+            Some(ProcessExit::Killed("quota exceeded: 10001 reductions used, the limit is 10000".to_string()))
+        );
+        // the step over the limit is counted, and nothing after it
+        assert_eq!(usage.reductions, 10_001);
+
+        let bomb = "
+        corporal app::Corporal {
+            pub main :: () {
+                bomb(0);
+            }
+            bomb :: (n) {
+                thread {
+                    let x;
+                    let y = x + n;
+                }
+                bomb(n + 1);
+            }
+        }";
+        let (exit, usage) = run_with_quota(Quota { threads: Some(50), ..Quota::default() }, 1000, bomb);
+        assert_eq!(
+            exit,
+            Some(ProcessExit::Killed("quota exceeded: 51 threads used, the limit is 50".to_string()))
+        );
+        // the process cancelled its threads on its way out
+        assert_eq!(usage.threads, 0);
+
+        let forks = "
+        major app::Major {
+            pub main :: () {
+                fork(0);
+            }
+            fork :: (n) {
+                spawn_process(() { n; });
+                fork(n + 1);
+            }
+        }";
+        let (exit, usage) = run_with_quota(Quota { children: Some(3), ..Quota::default() }, 1000, forks);
+        assert_eq!(
+            exit,
+            Some(ProcessExit::Killed("quota exceeded: 4 child processes used, the limit is 3".to_string()))
+        );
+        assert_eq!(usage.children, 4);
+
+        let hoard = "
+        corporal app::Corporal {
+            pub main :: () {
+                hoard(0, nil);
+            }
+            hoard :: (n, kept) {
+                let more = [n | kept];
+                hoard(n + 1, more);
+            }
+        }";
+        let (exit, usage) = run_with_quota(Quota { values: Some(1000), ..Quota::default() }, 1000, hoard);
+        let Some(ProcessExit::Killed(reason)) = exit else {
+            panic!("the process is killed, not {:?}", exit);
+        };
+        assert!(reason.ends_with("values used, the limit is 1000"), "{}", reason);
+        // everything the list holds is still referred to
+        assert!(usage.values > 1000);
+    }
+
+    static B1: &str = "
+        corporal lib::B 1 {
+            pub x :: () {
+                1;
+            }
+            // stays on the version it started in
+            pub slow :: () {
+                sleep(10);
+                x();
+            }
+        }";
+
+    static B2: &str = "
+        corporal lib::B 2 {
+            pub x :: () {
+                2;
+            }
+        }";
+
+    #[test]
+    fn external_calls_run_the_current_version() {
+        let ex = Arc::new(Executor::new());
+        let clock = Arc::new(VirtualClock::new());
+        let mut runtime = Runtime::new(ex.clone()).with_clock(clock.clone());
+        let app = "
+        corporal app::A {
+            use lib::B;
+            pub main :: () {
+                let before = B.x();
+                before = 1;
+                // waits for `slow`, which waits for the clock
+                let slow = B.slow() + 0;
+                slow = 1;
+                let after = B.x();
+                after = 2;
+            }
+        }";
+        smol::block_on(ex.run(async {
+            assert_eq!(runtime.load(B1.to_string(), "b.sio".to_string()).await.unwrap(), 1);
+            let process_id = runtime.spawn(app.to_string(), "a.sio".to_string()).await.unwrap();
+            for _ in 0..100 {
+                smol::future::yield_now().await;
+            }
+            assert_eq!(runtime.load(B2.to_string(), "b.sio".to_string()).await.unwrap(), 2);
+            // `slow` still runs version 1
+            assert!(runtime.purge("lib::B").await.is_err());
+            clock.advance(10);
+            assert_eq!(runtime.join(process_id).await, Some(ProcessExit::Completed));
+            runtime.purge("lib::B").await.unwrap();
+            assert_eq!(runtime.modules().versions("lib::B").await, [2]);
+        }));
+    }
+
+    #[test]
+    fn a_process_calling_its_own_module_runs_the_current_version() {
+        let ex = Arc::new(Executor::new());
+        let clock = Arc::new(VirtualClock::new());
+        let mut runtime = Runtime::new(ex.clone()).with_clock(clock.clone());
+        let a = |version: u64| {
+            format!(
+                "corporal lib::A {} {{
+                    pub main :: () {{
+                        sleep(10);
+                        let r = A.version();
+                        r = 2;
+                    }}
+                    pub version :: () {{
+                        {};
+                    }}
+                }}",
+                version, version
+            )
+        };
+        smol::block_on(ex.run(async {
+            let process_id = runtime.spawn(a(1), "a.sio".to_string()).await.unwrap();
+            for _ in 0..100 {
+                smol::future::yield_now().await;
+            }
+            assert_eq!(runtime.modules().versions("lib::A").await, [1]);
+            assert_eq!(runtime.load(a(2), "a.sio".to_string()).await.unwrap(), 2);
+            clock.advance(10);
+            assert_eq!(runtime.join(process_id).await, Some(ProcessExit::Completed));
+        }));
+    }
+
+    #[test]
+    fn external_calls_fail_the_thread_making_them() {
+        let ex = Arc::new(Executor::new());
+        let mut runtime = Runtime::new(ex.clone());
+        let b = "
+        corporal lib::B {
+            pub apply :: (f) {
+                f(1);
+            }
+        }";
+        let app = |call: &str| {
+            format!(
+                "corporal app::A {{
+                    use lib::B;
+                    pub main :: () {{
+                        {};
+                    }}
+                }}",
+                call
+            )
+        };
+        smol::block_on(ex.run(async {
+            runtime.load(b.to_string(), "b.sio".to_string()).await.unwrap();
+            for (call, error) in [
+                ("B.apply((x) { x; })", "a function cannot be passed to B 1, which runs other code"),
+                ("B.missing()", "B 1 has no public function missing"),
+            ] {
+                let process_id = runtime.spawn(app(call), "a.sio".to_string()).await.unwrap();
+                match runtime.join(process_id).await {
+                    Some(ProcessExit::Failed(ProcessFailure::Thread(failure))) => {
+                        assert_eq!(failure.error, error);
+                        // where the call was made, in the code of the process
+                        assert!(failure.ip().is_some());
+                    }
+                    exit => panic!("the thread calling B fails, got {:?}", exit),
+                }
+            }
+        }));
+    }
+
+    #[test]
+    fn programs_of_several_files_import_each_other() {
+        let ex = Arc::new(Executor::new());
+        let mut runtime = Runtime::new(ex.clone()).with_clock(Arc::new(VirtualClock::new()));
+        let c = "
+        corporal lib::C {
+            use lib::D::y;
+            pub z :: () {
+                y();
+            }
+        }";
+        let d = "
+        corporal lib::D {
+            pub y :: () {
+                3;
+            }
+        }";
+        let app = "
+        corporal app::A {
+            use lib::C::*;
+            pub main :: () {
+                let r = z();
+                r = 3;
+            }
+        }";
+        let files = |sources: &[(&str, &str)]| {
+            sources.iter().map(|(src, path)| (src.to_string(), path.to_string())).collect::<Vec<_>>()
+        };
+        smol::block_on(ex.run(async {
+            let error = runtime.load_all(files(&[(c, "c.sio")])).await.unwrap_err();
+            assert!(error.to_string().contains("resolution error"));
+            assert!(runtime.modules().current("lib::C").await.is_none());
+
+            let versions = runtime.load_all(files(&[(c, "c.sio"), (d, "d.sio")])).await.unwrap();
+            assert_eq!(versions, [1, 1]);
+            let process_id = runtime.spawn(app.to_string(), "a.sio".to_string()).await.unwrap();
+            assert_eq!(runtime.join(process_id).await, Some(ProcessExit::Completed));
+        }));
+    }
+
+    #[test]
+    fn processes_can_be_killed_with_a_reason() {
+        let ex = Arc::new(Executor::new());
+        let mut runtime = Runtime::new(ex.clone()).with_clock(Arc::new(VirtualClock::new()));
+        let sleeper = smol::block_on(runtime.spawn(SLEEPS.to_string(), "sleeper.sio".to_string())).unwrap();
+        let exit = smol::block_on(ex.run(runtime.kill(sleeper, "maintenance")));
+        assert_eq!(exit, Some(ProcessExit::Killed("maintenance".to_string())));
+        assert_eq!(runtime.processes()[0].exit, exit);
+    }
+}
@@ -0,0 +1,352 @@
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use async_channel::Sender;
+use core::error::Error;
+use crate::compiler::{ThreadValue as Value, value::{VariableId, FIRST_VARIABLE, NO_VARIABLE}};
+use crate::scheduler::{Operation, ThreadEntry, ThreadId};
+use crate::scheduler::store::Store;
+
+// Scripted threads stand in for compiled ones: they play a list of
+// operations against the store of their process, as a compiled thread would
+// while running, and log what happens so tests can check the order of events
+// without going through the compiler. The thread ids in a script are the ones
+// its events carry.
+
+/// Something a scripted thread did or that happened to it.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// the thread spawned another thread
+    Spawned(ThreadId),
+    Bound(ThreadId, VariableId, Value),
+    Waiting(ThreadId, VariableId),
+    /// the variable the thread waited on is determined
+    Resumed(ThreadId, VariableId, Value),
+    WaitingNeeded(ThreadId, VariableId),
+    /// another thread waits on the variable the thread produces
+    Needed(ThreadId, VariableId),
+    Terminated(ThreadId),
+    Failed(ThreadId, String),
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Event::Spawned(a), Event::Spawned(b)) | (Event::Terminated(a), Event::Terminated(b)) => a == b,
+            (Event::Bound(a, x, v), Event::Bound(b, y, w)) | (Event::Resumed(a, x, v), Event::Resumed(b, y, w)) => {
+                a == b && x == y && v.equals(w)
+            }
+            (Event::Waiting(a, x), Event::Waiting(b, y))
+            | (Event::WaitingNeeded(a, x), Event::WaitingNeeded(b, y))
+            | (Event::Needed(a, x), Event::Needed(b, y)) => a == b && x == y,
+            (Event::Failed(a, m), Event::Failed(b, n)) => a == b && m == n,
+            _ => false,
+        }
+    }
+}
+
+/// The events of a run, in the order they happened.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    pub events: Vec<Event>,
+}
+
+impl Trace {
+    pub fn position(&self, event: &Event) -> Option<usize> {
+        self.events.iter().position(|e| e == event)
+    }
+
+    pub fn contains(&self, event: &Event) -> bool {
+        self.position(event).is_some()
+    }
+
+    /// Whether all the events happened, in this order, maybe with other
+    /// events in between.
+    pub fn in_order(&self, events: &[Event]) -> bool {
+        let mut expected = events.iter().peekable();
+        for event in self.events.iter() {
+            if expected.peek() == Some(&event) {
+                expected.next();
+            }
+        }
+        expected.peek().is_none()
+    }
+
+    pub fn assert_order(&self, events: &[Event]) {
+        assert!(self.in_order(events), "expected {:#?} in order, got {:#?}", events, self.events);
+    }
+}
+
+impl Operation {
+    /// The thread doing the operation.
+    pub fn thread_id(&self) -> ThreadId {
+        match self {
+            Operation::SynchVar(thread_id, _)
+            | Operation::Bind(thread_id, _, _)
+            | Operation::Unify(thread_id, _, _)
+            | Operation::NewCell(thread_id, _, _)
+            | Operation::Exchange(thread_id, _, _, _)
+            | Operation::ThreadSpawn(thread_id, _)
+            | Operation::WaitNeeded(thread_id, _)
+            | Operation::Timer(thread_id, _, _)
+            | Operation::ThreadTerminate(thread_id)
+            | Operation::ThreadFailure(thread_id, _, _)
+            | Operation::QuotaExceeded(thread_id, _)
+            | Operation::ProcessSpawn(thread_id, _, _)
+            | Operation::Supervise(thread_id, _, _)
+            | Operation::ChildExit(thread_id, _, _)
+            | Operation::Call(thread_id, _)
+            | Operation::Return(thread_id)
+            | Operation::Deploy(thread_id, _, _, _)
+            | Operation::NodeDown(thread_id, _, _, _) => *thread_id,
+        }
+    }
+}
+
+/// Fails when `script`, or a script it spawns, uses `NO_VARIABLE` as a
+/// variable: the variables of a script are numbered from `FIRST_VARIABLE`.
+pub(crate) fn check(script: &[Operation]) -> Result<(), Box<dyn Error>> {
+    for operation in script {
+        let variable = match operation {
+            Operation::SynchVar(_, Value::Unbound(variable))
+            | Operation::Bind(_, variable, _)
+            | Operation::WaitNeeded(_, variable) => *variable,
+            Operation::ThreadSpawn(_, ThreadEntry::Script(script)) => {
+                check(script)?;
+                continue;
+            }
+            _ => continue,
+        };
+        if variable == NO_VARIABLE {
+            return Err(format!(
+                "variable {} of a script is no variable, they are numbered from {}",
+                NO_VARIABLE, FIRST_VARIABLE
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Plays `script` as the thread `thread_id` of a process. The process is
+/// told about suspensions and termination like for a compiled thread, and
+/// gets the operations only it can do.
+pub(crate) async fn play(
+    thread_id: ThreadId,
+    script: Vec<Operation>,
+    store: Arc<Store>,
+    to_process: Sender<Operation>,
+    events: Sender<Event>,
+) {
+    let log = |event: Event| {
+        let _ = events.try_send(event);
+    };
+    let scripted_id = script.first().map(|operation| operation.thread_id()).unwrap_or(thread_id);
+    for operation in script {
+        let actor = operation.thread_id();
+        let bind = |variable: VariableId, value: Value| match store.unify(&Value::Unbound(variable), &value) {
+            Ok(_) => Ok(Event::Bound(actor, variable, value)),
+            Err(error) => Err(format!("{}", error)),
+        };
+        let result = match operation {
+            Operation::SynchVar(_, Value::Unbound(variable)) => {
+                log(Event::Waiting(actor, variable));
+                let _ = to_process.send(Operation::unbound(thread_id, variable)).await;
+                let value = store.determined(variable).await;
+                Ok(Event::Resumed(actor, variable, value))
+            }
+            Operation::SynchVar(_, _) => Err("a script can only wait on an unbound variable".into()),
+            Operation::Bind(_, variable, value) => bind(variable, value),
+            Operation::Unify(_, left, right) => match store.unify(&left, &right) {
+                Ok(_) => continue,
+                Err(error) => Err(format!("{}", error)),
+            },
+            Operation::WaitNeeded(_, variable) => {
+                log(Event::WaitingNeeded(actor, variable));
+                let _ = to_process.send(Operation::WaitNeeded(thread_id, variable)).await;
+                store.wait_needed(variable).await;
+                Ok(Event::Needed(actor, variable))
+            }
+            Operation::ThreadSpawn(_, ThreadEntry::Script(script)) => {
+                // logged first, the new thread may run right away
+                log(Event::Spawned(actor));
+                let _ = to_process.send(Operation::ThreadSpawn(thread_id, ThreadEntry::Script(script))).await;
+                continue;
+            }
+            Operation::ThreadSpawn(_, ThreadEntry::Closure(_)) => Err("a script cannot spawn a closure".into()),
+            Operation::ThreadTerminate(_) => break,
+            Operation::ThreadFailure(_, error, _) => Err(error),
+            operation @ (Operation::NewCell(..)
+            | Operation::Exchange(..)
+            | Operation::Timer(..)
+            | Operation::QuotaExceeded(..)
+            | Operation::ProcessSpawn(..)
+            | Operation::Supervise(..)
+            | Operation::ChildExit(..)
+            | Operation::Call(..)
+            | Operation::Return(..)
+            | Operation::Deploy(..)
+            | Operation::NodeDown(..)) => {
+                let _ = to_process.send(operation).await;
+                continue;
+            }
+        };
+        match result {
+            Ok(event) => log(event),
+            Err(error) => {
+                log(Event::Failed(actor, error.clone()));
+                let _ = to_process.send(Operation::ThreadFailure(thread_id, error, Vec::new())).await;
+                return;
+            }
+        }
+    }
+    log(Event::Terminated(scripted_id));
+    let _ = to_process.send(Operation::ThreadTerminate(thread_id)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::ToString, vec};
+    use smol::Executor;
+    use crate::compiler::value::NO_VARIABLE;
+    use crate::scheduler::{Deadlock, Runtime, ScheduleMode};
+
+    fn int(n: u64) -> Value {
+        Value::Integer(NO_VARIABLE, n)
+    }
+
+    // the ordering a scenario guarantees has to hold whatever the schedule
+    fn traces(script: Vec<Operation>) -> Vec<Trace> {
+        (0..8)
+            .map(|seed| {
+                let mut process = Runtime::new(Arc::new(Executor::new())).create_scripted(script.clone()).unwrap();
+                let (result, _) = process.run_deterministic(ScheduleMode::Seeded(seed));
+                result.expect("the script runs");
+                process.trace()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_waiting_thread_resumes_after_the_binding() {
+        let script = vec![
+            Operation::spawn(0, vec![Operation::int(1, 1, 0)]),
+            Operation::unbound(0, 1),
+        ];
+        for trace in traces(script) {
+            trace.assert_order(&[Event::Bound(1, 1, int(0)), Event::Resumed(0, 1, int(0)), Event::Terminated(0)]);
+        }
+    }
+
+    #[test]
+    fn a_thread_reads_what_was_bound_before_it_started() {
+        let script = vec![
+            Operation::int(0, 1, 0),
+            Operation::spawn(0, vec![Operation::unbound(1, 1)]),
+        ];
+        for trace in traces(script) {
+            trace.assert_order(&[Event::Bound(0, 1, int(0)), Event::Spawned(0), Event::Resumed(1, 1, int(0))]);
+        }
+    }
+
+    #[test]
+    fn lazy_producers_run_once_needed() {
+        let script = vec![
+            Operation::spawn(0, vec![
+                Operation::WaitNeeded(1, 1),
+                Operation::int(1, 1, 1),
+                Operation::unbound(1, 2),
+            ]),
+            Operation::spawn(0, vec![
+                Operation::unbound(2, 1),
+                Operation::int(2, 2, 2),
+                Operation::int(2, 3, 3),
+            ]),
+            Operation::unbound(0, 3),
+        ];
+        for trace in traces(script) {
+            trace.assert_order(&[
+                Event::Waiting(2, 1),
+                Event::Needed(1, 1),
+                Event::Bound(1, 1, int(1)),
+                Event::Resumed(2, 1, int(1)),
+                Event::Bound(2, 2, int(2)),
+                Event::Resumed(1, 2, int(2)),
+            ]);
+            trace.assert_order(&[Event::Bound(2, 3, int(3)), Event::Resumed(0, 3, int(3))]);
+        }
+    }
+
+    #[test]
+    fn a_producer_needed_by_several_threads_binds_once() {
+        let script = vec![
+            Operation::spawn(0, vec![
+                Operation::unbound(1, 1),
+                Operation::int(1, 3, 3),
+            ]),
+            Operation::spawn(0, vec![
+                Operation::WaitNeeded(2, 2),
+                Operation::int(2, 1, 1),
+                Operation::int(2, 2, 2),
+            ]),
+            Operation::spawn(0, vec![
+                Operation::unbound(3, 2),
+                Operation::int(3, 3, 3),
+            ]),
+            Operation::unbound(0, 3),
+        ];
+        for trace in traces(script) {
+            trace.assert_order(&[
+                Event::Waiting(3, 2),
+                Event::Needed(2, 2),
+                Event::Bound(2, 1, int(1)),
+                Event::Resumed(1, 1, int(1)),
+            ]);
+            trace.assert_order(&[Event::Bound(2, 2, int(2)), Event::Resumed(3, 2, int(2))]);
+            assert!(trace.contains(&Event::Resumed(0, 3, int(3))));
+        }
+    }
+
+    #[test]
+    fn nested_spawns_run_in_order() {
+        let script = vec![
+            Operation::spawn(0, vec![
+                Operation::spawn(1, vec![
+                    Operation::spawn(2, vec![
+                        Operation::spawn(3, vec![Operation::int(4, 4, 4)]),
+                    ]),
+                ]),
+            ]),
+            Operation::unbound(0, 4),
+        ];
+        for trace in traces(script) {
+            trace.assert_order(&[
+                Event::Spawned(0),
+                Event::Spawned(1),
+                Event::Spawned(2),
+                Event::Spawned(3),
+                Event::Bound(4, 4, int(4)),
+                Event::Resumed(0, 4, int(4)),
+            ]);
+        }
+    }
+
+    #[test]
+    fn scripted_failures_and_deadlocks_fail_the_process() {
+        let runtime = Runtime::new(Arc::new(Executor::new()));
+        let mut process = runtime
+            .create_scripted(vec![Operation::int(0, 1, 0), Operation::int(0, 1, 1)])
+            .unwrap();
+        let (result, _) = process.run_deterministic(ScheduleMode::Seeded(0));
+        assert!(result.unwrap_err().to_string().starts_with("unification failure"));
+        assert!(matches!(process.trace().events.last(), Some(Event::Failed(0, _))));
+
+        let mut process = runtime.create_scripted(vec![Operation::unbound(0, 5)]).unwrap();
+        let (result, _) = process.run_deterministic(ScheduleMode::Seeded(0));
+        assert!(result.unwrap_err().downcast_ref::<Deadlock>().is_some());
+        process.trace().assert_order(&[Event::Waiting(0, 5)]);
+
+        let nested = vec![Operation::spawn(0, vec![Operation::unbound(1, NO_VARIABLE)])];
+        let error = runtime.create_scripted(nested).err().expect("variable 0 is rejected");
+        assert!(error.to_string().contains("numbered from 1"), "{}", error);
+    }
+}
@@ -0,0 +1,574 @@
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::fmt;
+use core::ops::Range;
+use hashbrown::HashSet;
+use werbolg_core::{id::IdF, FunId, NifId, ValueFun};
+use crate::compiler::{fingerprint, ThreadValue as Value};
+use crate::compiler::map::{MapKey, ValueMap};
+use crate::compiler::value::{CellId, Closure, Record, VariableId, FIRST_VARIABLE, NO_VARIABLE};
+use crate::scheduler::machine::Code;
+pub(crate) use crate::scheduler::machine::MachineSnapshot;
+use crate::scheduler::time::Millis;
+
+// A snapshot is the state of a quiescent process, every thread suspended:
+// its store, cells and pending timers, and the machine of each thread. The
+// source goes along, a restored process compiles it again to the same code,
+// so the instruction addresses and function ids of the machines still hold.
+//
+// The binary format starts with `MAGIC`, the version and the fingerprint of
+// the prelude and NIFs the code was compiled with, followed by unsigned LEB128
+// integers, length-prefixed strings and tagged values. A change to the format
+// bumps `VERSION`: snapshots are only read by the version that wrote them, and
+// only restored next to the same prelude and NIFs. What they refer to is
+// checked against the code they are restored into all the same.
+
+const MAGIC: &[u8; 4] = b"SIOS";
+pub const VERSION: u16 = 2;
+// the magic, the version and the fingerprint
+const HEADER: usize = MAGIC.len() + 2 + 8;
+
+/// The saved state of a process, see `Process::run_to_snapshot` and
+/// `Runtime::restore`.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub(crate) path: String,
+    pub(crate) source: String,
+    pub(crate) next_variable: VariableId,
+    pub(crate) variables: Vec<VariableSnapshot>,
+    pub(crate) cells: Vec<(CellId, Value)>,
+    // the time left before each timer fires
+    pub(crate) timers: Vec<(Millis, VariableId)>,
+    // in the order the threads were spawned
+    pub(crate) threads: Vec<MachineSnapshot>,
+}
+
+// the variables with something to keep, the others are fresh
+#[derive(Debug, Clone)]
+pub(crate) struct VariableSnapshot {
+    pub(crate) variable: VariableId,
+    // what the variable is bound to, which can be another variable
+    pub(crate) binding: Option<Value>,
+    pub(crate) needed: bool,
+    pub(crate) declared: Option<Range<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// not a snapshot at all
+    NotASnapshot,
+    /// written by another version of the format
+    Version(u16),
+    /// the bytes end in the middle of the snapshot
+    Truncated,
+    /// written next to another prelude or other NIFs, which compile the
+    /// source to other code
+    Code,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::NotASnapshot => write!(f, "not a process snapshot"),
+            SnapshotError::Version(version) => {
+                write!(f, "snapshot format version {} is not supported, only version {} is", version, VERSION)
+            }
+            SnapshotError::Truncated => write!(f, "truncated snapshot"),
+            SnapshotError::Code => write!(f, "snapshot of code compiled with another prelude or other native functions"),
+            SnapshotError::Invalid(what) => write!(f, "invalid snapshot: {}", what),
+        }
+    }
+}
+
+impl core::error::Error for SnapshotError {}
+
+impl Snapshot {
+    /// The path of the source the process was compiled from.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer { bytes: Vec::new() };
+        out.bytes.extend_from_slice(MAGIC);
+        out.bytes.extend_from_slice(&VERSION.to_le_bytes());
+        out.bytes.extend_from_slice(&fingerprint().to_le_bytes());
+        out.string(&self.path);
+        out.string(&self.source);
+        out.uint(self.next_variable);
+        out.uint(self.variables.len() as u64);
+        for variable in self.variables.iter() {
+            out.uint(variable.variable);
+            match &variable.binding {
+                Some(value) => {
+                    out.uint(1);
+                    out.value(value);
+                }
+                None => out.uint(0),
+            }
+            out.uint(variable.needed as u64);
+            match &variable.declared {
+                Some(span) => {
+                    out.uint(1);
+                    out.uint(span.start as u64);
+                    out.uint(span.end as u64);
+                }
+                None => out.uint(0),
+            }
+        }
+        out.uint(self.cells.len() as u64);
+        for (cell, content) in self.cells.iter() {
+            out.uint(*cell);
+            out.value(content);
+        }
+        out.uint(self.timers.len() as u64);
+        for (left, variable) in self.timers.iter() {
+            out.uint(*left);
+            out.uint(*variable);
+        }
+        out.uint(self.threads.len() as u64);
+        for machine in self.threads.iter() {
+            out.uint(machine.ip);
+            out.uint(machine.sp);
+            out.uint(machine.current_arity);
+            out.uint(machine.stack.len() as u64);
+            for value in machine.stack.iter() {
+                out.value(value);
+            }
+            out.uint(machine.rets.len() as u64);
+            for (ip, sp, locals, arity) in machine.rets.iter() {
+                out.uint(*ip);
+                out.uint(*sp);
+                out.uint(*locals);
+                out.uint(*arity);
+            }
+        }
+        out.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        if bytes.len() < MAGIC.len() + 2 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let version = u16::from_le_bytes([bytes[MAGIC.len()], bytes[MAGIC.len() + 1]]);
+        if version != VERSION {
+            return Err(SnapshotError::Version(version));
+        }
+        let written = bytes.get(MAGIC.len() + 2..HEADER).ok_or(SnapshotError::Truncated)?;
+        if written != fingerprint().to_le_bytes() {
+            return Err(SnapshotError::Code);
+        }
+        let mut input = Reader { bytes, at: HEADER };
+        let path = input.string()?;
+        let source = input.string()?;
+        let next_variable = input.uint()?;
+        let mut variables = Vec::new();
+        for _ in 0..input.uint()? {
+            let variable = input.uint()?;
+            let binding = match input.uint()? {
+                0 => None,
+                _ => Some(input.value()?),
+            };
+            let needed = input.uint()? != 0;
+            let declared = match input.uint()? {
+                0 => None,
+                _ => Some(input.uint()? as usize..input.uint()? as usize),
+            };
+            variables.push(VariableSnapshot { variable, binding, needed, declared });
+        }
+        let mut cells = Vec::new();
+        for _ in 0..input.uint()? {
+            cells.push((input.uint()?, input.value()?));
+        }
+        let mut timers = Vec::new();
+        for _ in 0..input.uint()? {
+            timers.push((input.uint()?, input.uint()?));
+        }
+        let mut threads = Vec::new();
+        for _ in 0..input.uint()? {
+            let (ip, sp, current_arity) = (input.uint()?, input.uint()?, input.uint()?);
+            let mut stack = Vec::new();
+            for _ in 0..input.uint()? {
+                stack.push(input.value()?);
+            }
+            let mut rets = Vec::new();
+            for _ in 0..input.uint()? {
+                rets.push((input.uint()?, input.uint()?, input.uint()?, input.uint()?));
+            }
+            threads.push(MachineSnapshot { ip, sp, current_arity, stack, rets });
+        }
+        if input.at != bytes.len() {
+            return Err(SnapshotError::Invalid("trailing bytes"));
+        }
+        Ok(Snapshot { path, source, next_variable, variables, cells, timers, threads })
+    }
+
+    /// Checks that what the snapshot refers to exists: the variables it
+    /// numbered, its cells, and in `code`, the code its source compiled to
+    /// again, the functions and instructions.
+    pub(crate) fn check(&self, code: &Code) -> Result<(), SnapshotError> {
+        if self.variables.iter().any(|variable| !self.numbered(variable.variable)) {
+            return Err(SnapshotError::Invalid("variable"));
+        }
+        // the deadline of `die_after` binds no variable
+        if self.timers.iter().any(|(_, variable)| *variable != NO_VARIABLE && !self.numbered(*variable)) {
+            return Err(SnapshotError::Invalid("timer"));
+        }
+        for machine in self.threads.iter() {
+            code.check(machine)?;
+        }
+        let cells: HashSet<CellId> = self.cells.iter().map(|(cell, _)| *cell).collect();
+        let mut terms: Vec<Value> = self.variables.iter().filter_map(|variable| variable.binding.clone()).collect();
+        terms.extend(self.cells.iter().map(|(_, content)| content.clone()));
+        terms.extend(self.threads.iter().flat_map(|machine| machine.stack.iter().cloned()));
+        while let Some(term) = terms.pop() {
+            match term {
+                Value::Unbound(variable) if !self.numbered(variable) => return Err(SnapshotError::Invalid("variable")),
+                Value::Cell(_, cell) if !cells.contains(&cell) => return Err(SnapshotError::Invalid("cell")),
+                Value::Fun(_, fun) => code.fun(&fun)?,
+                Value::Closure(_, closure) => {
+                    code.fun(&closure.fun)?;
+                    terms.extend(closure.env.iter().cloned());
+                }
+                Value::Cons(_, cell) => {
+                    terms.push(cell.0.clone());
+                    terms.push(cell.1.clone());
+                }
+                Value::Map(_, map) => terms.extend(map.iter().map(|(_, value)| value.clone())),
+                Value::Record(_, record) => terms.extend(record.fields.iter().cloned()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn numbered(&self, variable: VariableId) -> bool {
+        (FIRST_VARIABLE..self.next_variable).contains(&variable)
+    }
+}
+
+// also used for the messages between nodes
+pub(crate) struct Writer {
+    pub(crate) bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn uint(&mut self, mut n: u64) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    pub(crate) fn string(&mut self, s: &str) {
+        self.uint(s.len() as u64);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn fun(&mut self, fun: &ValueFun) {
+        match fun {
+            ValueFun::Native(nif) => {
+                self.uint(0);
+                self.uint(nif.as_index() as u64);
+            }
+            ValueFun::Fun(fun) => {
+                self.uint(1);
+                self.uint(fun.as_index() as u64);
+            }
+        }
+    }
+
+    pub(crate) fn value(&mut self, value: &Value) {
+        // lists are written spine first, long ones do not recurse
+        let mut value = value;
+        let mut elements = Vec::new();
+        while let Value::Cons(tag, cell) = value {
+            elements.push((*tag, &cell.0));
+            value = &cell.1;
+        }
+        if !elements.is_empty() {
+            self.uint(TAG_LIST);
+            self.uint(elements.len() as u64);
+            for (tag, head) in elements {
+                self.uint(tag);
+                self.value(head);
+            }
+        }
+        match value {
+            Value::Unit => self.uint(TAG_UNIT),
+            Value::Unbound(variable) => {
+                self.uint(TAG_UNBOUND);
+                self.uint(*variable);
+            }
+            Value::Bool(tag, b) => {
+                self.uint(TAG_BOOL);
+                self.uint(*tag);
+                self.uint(*b as u64);
+            }
+            Value::Integer(tag, n) => {
+                self.uint(TAG_INTEGER);
+                self.uint(*tag);
+                self.uint(*n);
+            }
+            Value::Fun(tag, fun) => {
+                self.uint(TAG_FUN);
+                self.uint(*tag);
+                self.fun(fun);
+            }
+            Value::Map(tag, map) => {
+                self.uint(TAG_MAP);
+                self.uint(*tag);
+                self.uint(map.len() as u64);
+                for (key, value) in map.iter() {
+                    self.value(&key.to_value());
+                    self.value(value);
+                }
+            }
+            Value::Closure(tag, closure) => {
+                self.uint(TAG_CLOSURE);
+                self.uint(*tag);
+                self.fun(&closure.fun);
+                self.uint(closure.env.len() as u64);
+                for value in closure.env.iter() {
+                    self.value(value);
+                }
+            }
+            Value::Cell(tag, cell) => {
+                self.uint(TAG_CELL);
+                self.uint(*tag);
+                self.uint(*cell);
+            }
+            Value::Record(tag, record) => {
+                self.uint(TAG_RECORD);
+                self.uint(*tag);
+                self.string(&record.label);
+                self.uint(record.fields.len() as u64);
+                for value in record.fields.iter() {
+                    self.value(value);
+                }
+            }
+            Value::Cons(_, _) => unreachable!("the spine was written"),
+        }
+    }
+}
+
+const TAG_UNIT: u64 = 0;
+const TAG_UNBOUND: u64 = 1;
+const TAG_BOOL: u64 = 2;
+const TAG_INTEGER: u64 = 3;
+const TAG_FUN: u64 = 4;
+const TAG_MAP: u64 = 5;
+const TAG_CLOSURE: u64 = 6;
+const TAG_CELL: u64 = 7;
+// the elements of a list and their tags, followed by its last tail
+const TAG_LIST: u64 = 8;
+const TAG_RECORD: u64 = 9;
+
+// how deep maps, closures, records and list elements may nest in what is read,
+// so that bytes from a peer cannot overflow the stack of the reader; the
+// spine of a list is read without nesting
+const MAX_DEPTH: usize = 256;
+
+pub(crate) struct Reader<'b> {
+    pub(crate) bytes: &'b [u8],
+    pub(crate) at: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, SnapshotError> {
+        let byte = *self.bytes.get(self.at).ok_or(SnapshotError::Truncated)?;
+        self.at += 1;
+        Ok(byte)
+    }
+
+    pub(crate) fn uint(&mut self) -> Result<u64, SnapshotError> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            // the last byte only has room for the top bit
+            if shift == 63 && byte > 1 {
+                return Err(SnapshotError::Invalid("integer too large"));
+            }
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(SnapshotError::Invalid("integer too large"))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, SnapshotError> {
+        let len = self.uint()? as usize;
+        let end = self.at.checked_add(len).filter(|end| *end <= self.bytes.len()).ok_or(SnapshotError::Truncated)?;
+        let s = core::str::from_utf8(&self.bytes[self.at..end]).map_err(|_| SnapshotError::Invalid("string"))?;
+        self.at = end;
+        Ok(String::from(s))
+    }
+
+    fn fun(&mut self) -> Result<ValueFun, SnapshotError> {
+        match self.uint()? {
+            0 => Ok(ValueFun::Native(NifId::from_collection_len(self.uint()? as usize))),
+            1 => Ok(ValueFun::Fun(FunId::from_collection_len(self.uint()? as usize))),
+            _ => Err(SnapshotError::Invalid("function")),
+        }
+    }
+
+    pub(crate) fn value(&mut self) -> Result<Value, SnapshotError> {
+        self.nested(0)
+    }
+
+    // a value inside `depth` others
+    fn nested(&mut self, depth: usize) -> Result<Value, SnapshotError> {
+        if depth > MAX_DEPTH {
+            return Err(SnapshotError::Invalid("value nested too deeply"));
+        }
+        let inner = depth + 1;
+        let mut tag = self.uint()?;
+        let mut elements = Vec::new();
+        if tag == TAG_LIST {
+            for _ in 0..self.uint()? {
+                let element_tag = self.uint()?;
+                elements.push((element_tag, self.nested(inner)?));
+            }
+            tag = self.uint()?;
+        }
+        let mut value = match tag {
+            TAG_UNIT => Value::Unit,
+            TAG_UNBOUND => Value::Unbound(self.uint()?),
+            TAG_BOOL => Value::Bool(self.uint()?, self.uint()? != 0),
+            TAG_INTEGER => Value::Integer(self.uint()?, self.uint()?),
+            TAG_FUN => Value::Fun(self.uint()?, self.fun()?),
+            TAG_MAP => {
+                let tag = self.uint()?;
+                let mut map = ValueMap::new();
+                for _ in 0..self.uint()? {
+                    let key = MapKey::from_value(&self.nested(inner)?).map_err(|_| SnapshotError::Invalid("map key"))?;
+                    map = map.insert(key, self.nested(inner)?);
+                }
+                Value::Map(tag, map)
+            }
+            TAG_CLOSURE => {
+                let tag = self.uint()?;
+                let fun = self.fun()?;
+                let mut env = Vec::new();
+                for _ in 0..self.uint()? {
+                    env.push(self.nested(inner)?);
+                }
+                Value::Closure(tag, Arc::new(Closure { fun, env }))
+            }
+            TAG_CELL => Value::Cell(self.uint()?, self.uint()?),
+            TAG_RECORD => {
+                let tag = self.uint()?;
+                let label = self.string()?;
+                let mut fields = Vec::new();
+                for _ in 0..self.uint()? {
+                    fields.push(self.nested(inner)?);
+                }
+                Value::Record(tag, Arc::new(Record { label: label.into(), fields }))
+            }
+            _ => return Err(SnapshotError::Invalid("value")),
+        };
+        while let Some((tag, head)) = elements.pop() {
+            value = Value::Cons(tag, Arc::new((head, value)));
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::ToString, vec};
+    use crate::compiler::value::NO_VARIABLE;
+
+    fn int(n: u64) -> Value {
+        Value::Integer(NO_VARIABLE, n)
+    }
+
+    #[test]
+    fn snapshots_read_back_what_was_written() {
+        let list = (0..1000).rev().fold(Value::Unbound(3), |tail, n| Value::Cons(NO_VARIABLE, Arc::new((int(n), tail))));
+        let map = ValueMap::new().insert(MapKey::Integer(1), Value::Bool(2, true));
+        let snapshot = Snapshot {
+            path: "app.sio".to_string(),
+            source: "corporal app::Corporal {}".to_string(),
+            next_variable: 4,
+            variables: vec![
+                VariableSnapshot { variable: 1, binding: Some(list), needed: true, declared: Some(10..20) },
+                VariableSnapshot { variable: 2, binding: Some(Value::Map(1, map)), needed: false, declared: None },
+                VariableSnapshot { variable: 3, binding: None, needed: true, declared: None },
+            ],
+            cells: vec![(5, Value::Cell(NO_VARIABLE, 6))],
+            timers: vec![(250, 3)],
+            threads: Vec::new(),
+        };
+        let bytes = snapshot.to_bytes();
+        let read = Snapshot::from_bytes(&bytes).expect("a valid snapshot");
+        assert_eq!(read.to_bytes(), bytes);
+        assert_eq!(read.path(), "app.sio");
+        assert!(read.variables[0].binding.as_ref().unwrap().equals(snapshot.variables[0].binding.as_ref().unwrap()));
+        assert_eq!(read.timers, vec![(250, 3)]);
+
+        assert_eq!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(), SnapshotError::Truncated);
+        assert_eq!(Snapshot::from_bytes(b"sio?").unwrap_err(), SnapshotError::NotASnapshot);
+        let mut newer = bytes.clone();
+        newer[4] = 3;
+        assert_eq!(Snapshot::from_bytes(&newer).unwrap_err(), SnapshotError::Version(3));
+        let mut other = bytes.clone();
+        other[6] ^= 1;
+        assert_eq!(Snapshot::from_bytes(&other).unwrap_err(), SnapshotError::Code);
+    }
+
+    #[test]
+    fn snapshots_only_refer_to_what_they_hold() {
+        let code = Code { instructions: 10, funs: 2, nifs: 0 };
+        let mut snapshot = Snapshot {
+            path: "app.sio".to_string(),
+            source: "corporal app::Corporal {}".to_string(),
+            next_variable: 4,
+            variables: vec![VariableSnapshot { variable: 1, binding: Some(Value::Unbound(3)), needed: false, declared: None }],
+            cells: Vec::new(),
+            // the deadline of `die_after`
+            timers: vec![(250, NO_VARIABLE)],
+            threads: Vec::new(),
+        };
+        assert_eq!(snapshot.check(&code), Ok(()));
+        snapshot.variables[0].binding = Some(Value::Unbound(4));
+        assert_eq!(snapshot.check(&code), Err(SnapshotError::Invalid("variable")));
+        snapshot.variables[0].binding = Some(Value::Fun(NO_VARIABLE, ValueFun::Fun(FunId::from_collection_len(2))));
+        assert_eq!(snapshot.check(&code), Err(SnapshotError::Invalid("function")));
+        snapshot.variables[0].binding = Some(Value::Cell(NO_VARIABLE, 7));
+        assert_eq!(snapshot.check(&code), Err(SnapshotError::Invalid("cell")));
+        snapshot.variables[0].binding = None;
+        let machine = MachineSnapshot { ip: 10, sp: 0, current_arity: 0, stack: Vec::new(), rets: Vec::new() };
+        snapshot.threads.push(machine);
+        assert_eq!(snapshot.check(&code), Err(SnapshotError::Invalid("instruction address")));
+        snapshot.threads[0].ip = 9;
+        snapshot.threads[0].sp = 1;
+        assert_eq!(snapshot.check(&code), Err(SnapshotError::Invalid("stack pointer")));
+    }
+
+    #[test]
+    fn values_read_are_bounded() {
+        // records of one field, each holding the next
+        let mut bytes = Vec::new();
+        for _ in 0..100_000 {
+            bytes.extend([TAG_RECORD as u8, 0, 0, 1]);
+        }
+        bytes.push(TAG_UNIT as u8);
+        let mut input = Reader { bytes: &bytes, at: 0 };
+        assert_eq!(input.value().unwrap_err(), SnapshotError::Invalid("value nested too deeply"));
+
+        let mut max = Vec::new();
+        max.extend([0xff; 9]);
+        max.push(0x01);
+        assert_eq!(Reader { bytes: &max, at: 0 }.uint(), Ok(u64::MAX));
+        *max.last_mut().unwrap() = 0x02;
+        assert_eq!(Reader { bytes: &max, at: 0 }.uint(), Err(SnapshotError::Invalid("integer too large")));
+    }
+}
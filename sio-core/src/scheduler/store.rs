@@ -0,0 +1,821 @@
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+use core::ptr;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use crate::compiler::{ThreadValue as Value, map::ValueMap, value::{Closure, Record, VariableId, NO_VARIABLE}};
+use crate::scheduler::wakers::WakerList;
+use hashbrown::HashSet;
+use werbolg_core::ValueFun;
+
+// The single-assignment store of a process, shared by all its threads. A
+// variable is bound at most once, either to a value, which may itself contain
+// unbound variables (`[a | tail]`), or to another variable, in which case it
+// shares whatever that variable is bound to later.
+//
+// Nothing here takes a lock. Every variable is a slot with an atomic state,
+// and binding it wakes the threads waiting on it directly through their
+// `Waker`, without going through the process.
+//
+// Variables are never renumbered, and a slot stays in place as long as the
+// store lives. Only a quiescent process can drop the values nothing refers to
+// anymore, see `Store::sweep`.
+
+#[derive(Debug, Clone)]
+pub enum UnificationError {
+    Mismatch(Value, Value),
+    Occurs(VariableId, Value),
+}
+
+impl fmt::Display for UnificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnificationError::Mismatch(left, right) => {
+                write!(f, "unification failure: {:?} does not unify with {:?}", left, right)
+            }
+            UnificationError::Occurs(variable, value) => {
+                write!(f, "unification failure: variable {} occurs in {:?}", variable, value)
+            }
+        }
+    }
+}
+
+const UNBOUND: u8 = 0;
+const BINDING: u8 = 1;
+const BOUND: u8 = 2;
+
+struct Slot {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<Value>>,
+    needed: AtomicBool,
+    waiters: WakerList,
+    needers: WakerList,
+    // source span of the declaration, empty when unknown
+    declared_start: AtomicUsize,
+    declared_end: AtomicUsize,
+}
+
+impl Slot {
+    fn new() -> Self {
+        Self {
+            state: AtomicU8::new(UNBOUND),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            needed: AtomicBool::new(false),
+            waiters: WakerList::new(),
+            needers: WakerList::new(),
+            declared_start: AtomicUsize::new(0),
+            declared_end: AtomicUsize::new(0),
+        }
+    }
+
+    fn get(&self) -> Option<Value> {
+        // sequentially consistent, as `need`: binding a variable to another
+        // one either sees it needed or is seen by whoever needs it
+        if self.state.load(Ordering::SeqCst) == BOUND {
+            // written once before the state became BOUND, never after
+            Some(unsafe { (*self.value.get()).assume_init_ref() }.clone())
+        } else {
+            None
+        }
+    }
+
+    // the value the variable already had when it was bound first
+    fn bind(&self, value: Value) -> Result<(), Value> {
+        if self.state.compare_exchange(UNBOUND, BINDING, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            loop {
+                if let Some(existing) = self.get() {
+                    return Err(existing);
+                }
+                core::hint::spin_loop();
+            }
+        }
+        unsafe { (*self.value.get()).write(value) };
+        self.state.store(BOUND, Ordering::SeqCst);
+        self.waiters.close();
+        // bound to a value nobody has to produce it anymore, bound to another
+        // variable the needers go on waiting on that one
+        self.needers.close();
+        Ok(())
+    }
+
+    fn need(&self) {
+        if !self.needed.swap(true, Ordering::SeqCst) {
+            self.needers.close();
+        }
+    }
+
+    fn is_needed(&self) -> bool {
+        self.needed.load(Ordering::SeqCst)
+    }
+}
+
+// SAFETY: `value` is only written by the thread that moved `state` from
+// UNBOUND to BINDING, once, before the release to BOUND, and it is only read
+// by reference after an acquire of BOUND. Sharing a slot thus shares the value
+// between threads, which must be fine on its own.
+unsafe impl Sync for Slot where Value: Send + Sync {}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == BOUND {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+// Slots live in buckets of growing size, bucket `b` holding 2^b slots, which
+// are allocated the first time one of their variables is used and never move.
+const BUCKETS: usize = 65;
+
+fn location(variable: VariableId) -> (usize, usize) {
+    let position = variable as u128 + 1;
+    let bucket = 127 - position.leading_zeros() as usize;
+    (bucket, (position - (1u128 << bucket)) as usize)
+}
+
+pub struct Store {
+    buckets: [AtomicPtr<Slot>; BUCKETS],
+    // the variables bound and not swept yet, what the store holds on to
+    values: AtomicU64,
+    // the store owns the slots behind the pointers, and is only `Send` or
+    // `Sync` when they are
+    _slots: PhantomData<Box<[Slot]>>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Self { buckets: [const { AtomicPtr::new(ptr::null_mut()) }; BUCKETS], values: AtomicU64::new(0), _slots: PhantomData }
+    }
+
+    // the slot of `variable` when its bucket was allocated, reading a
+    // variable does not allocate
+    fn existing(&self, variable: VariableId) -> Option<&Slot> {
+        let (bucket, index) = location(variable);
+        let slots = self.buckets[bucket].load(Ordering::Acquire);
+        if slots.is_null() {
+            None
+        } else {
+            Some(unsafe { &*slots.add(index) })
+        }
+    }
+
+    fn slot(&self, variable: VariableId) -> &Slot {
+        let (bucket, index) = location(variable);
+        let mut slots = self.buckets[bucket].load(Ordering::Acquire);
+        if slots.is_null() {
+            let fresh: Box<[Slot]> = (0..1usize << bucket).map(|_| Slot::new()).collect();
+            let fresh = Box::into_raw(fresh) as *mut Slot;
+            match self.buckets[bucket].compare_exchange(ptr::null_mut(), fresh, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => slots = fresh,
+                Err(current) => {
+                    // another thread allocated the bucket first
+                    drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(fresh, 1usize << bucket)) });
+                    slots = current;
+                }
+            }
+        }
+        unsafe { &*slots.add(index) }
+    }
+
+    /// How many variables are bound, until a sweep drops those nothing
+    /// refers to anymore.
+    pub fn values(&self) -> u64 {
+        self.values.load(Ordering::Relaxed)
+    }
+
+    // binds the slot of `variable`, counting the value it now holds
+    fn bind(&self, variable: VariableId, value: Value) -> Result<(), Value> {
+        self.slot(variable).bind(value)?;
+        self.values.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn is_bound(&self, variable: VariableId) -> bool {
+        self.binding(variable).is_some()
+    }
+
+    /// Whether some thread waits on `variable`, or on a variable it is bound
+    /// to: both are the same variable from then on.
+    pub fn is_needed(&self, variable: VariableId) -> bool {
+        let mut variable = variable;
+        loop {
+            let Some(slot) = self.existing(variable) else {
+                return false;
+            };
+            if slot.is_needed() {
+                return true;
+            }
+            match slot.get() {
+                Some(Value::Unbound(next)) => variable = next,
+                _ => return false,
+            }
+        }
+    }
+
+    /// What `variable` is bound to, without following the chain.
+    pub fn binding(&self, variable: VariableId) -> Option<Value> {
+        self.existing(variable).and_then(Slot::get)
+    }
+
+    /// Binds `variable` as it was in another store, when restoring a
+    /// snapshot: nothing is unified and nobody is woken up.
+    pub(crate) fn restore(&self, variable: VariableId, binding: Option<Value>, needed: bool) {
+        if let Some(value) = binding {
+            let _ = self.bind(variable, value);
+        }
+        if needed {
+            self.slot(variable).need();
+        }
+    }
+
+    /// Records where `variable` was declared, for diagnostics.
+    pub fn declare(&self, variable: VariableId, span: Range<usize>) {
+        let slot = self.slot(variable);
+        slot.declared_start.store(span.start, Ordering::Relaxed);
+        slot.declared_end.store(span.end, Ordering::Relaxed);
+    }
+
+    pub fn declaration(&self, variable: VariableId) -> Option<Range<usize>> {
+        let slot = self.existing(variable)?;
+        let span = slot.declared_start.load(Ordering::Relaxed)..slot.declared_end.load(Ordering::Relaxed);
+        if span.is_empty() {
+            None
+        } else {
+            Some(span)
+        }
+    }
+
+    /// Follows variable to variable bindings: the result is a determined value
+    /// or the unbound variable at the end of the chain.
+    pub fn deref(&self, value: &Value) -> Value {
+        let mut value = value.clone();
+        while let Value::Unbound(variable) = value {
+            match self.binding(variable) {
+                Some(bound) => value = bound,
+                None => break,
+            }
+        }
+        value
+    }
+
+    /// Resolves to what `variable` is bound to, which can be another
+    /// variable. Waiting on a variable also marks it as needed.
+    pub fn wait(&self, variable: VariableId) -> Wait<'_> {
+        Wait { store: self, variable }
+    }
+
+    /// Resolves to the determined value of `variable`, following the
+    /// variables it is bound to and waiting on each of them in turn.
+    pub async fn determined(&self, variable: VariableId) -> Value {
+        let mut variable = variable;
+        loop {
+            let bound = self.wait(variable).await;
+            match self.deref(&bound) {
+                Value::Unbound(next) => variable = next,
+                value => return value,
+            }
+        }
+    }
+
+    /// Marks `variable` as needed, and the variables it is bound to.
+    pub fn need(&self, variable: VariableId) {
+        let mut variable = variable;
+        loop {
+            let slot = self.slot(variable);
+            slot.need();
+            match slot.get() {
+                Some(Value::Unbound(next)) => variable = next,
+                _ => return,
+            }
+        }
+    }
+
+    /// Resolves once some thread waits on `variable`, or it got bound.
+    pub fn wait_needed(&self, variable: VariableId) -> WaitNeeded<'_> {
+        WaitNeeded { store: self, variable }
+    }
+
+    /// Unifies both terms, binding the unbound variables of either side.
+    /// Returns the variables bound in the process, in the order they were
+    /// bound. On failure the bindings made so far are kept: the process fails
+    /// as a whole, nothing observes the store afterwards.
+    pub fn unify(&self, left: &Value, right: &Value) -> Result<Vec<VariableId>, UnificationError> {
+        let mut bound = Vec::new();
+        let mut pairs = vec![(left.clone(), right.clone())];
+        while let Some((left, right)) = pairs.pop() {
+            match (self.deref(&left), self.deref(&right)) {
+                (Value::Unbound(x), Value::Unbound(y)) if x == y => {}
+                // always bind the younger variable to the older one, two
+                // threads binding x to y and y to x at once cannot make a cycle
+                (Value::Unbound(x), Value::Unbound(y)) => {
+                    let (younger, older) = if x > y { (x, y) } else { (y, x) };
+                    match self.bind(younger, Value::Unbound(older)) {
+                        Ok(()) => {
+                            // needing either variable needs both
+                            if self.slot(younger).is_needed() {
+                                self.need(older);
+                            }
+                            bound.push(younger);
+                        }
+                        Err(existing) => pairs.push((existing, Value::Unbound(older))),
+                    }
+                }
+                (Value::Unbound(x), value) | (value, Value::Unbound(x)) => {
+                    if self.occurs(x, &value) {
+                        return Err(UnificationError::Occurs(x, value));
+                    }
+                    match self.bind(x, value.clone().with_variable(x)) {
+                        Ok(()) => bound.push(x),
+                        // bound by another thread meanwhile
+                        Err(existing) => pairs.push((existing, value)),
+                    }
+                }
+                (Value::Cons(_, a), Value::Cons(_, b)) => {
+                    pairs.push((a.1.clone(), b.1.clone()));
+                    pairs.push((a.0.clone(), b.0.clone()));
+                }
+                (Value::Record(i1, a), Value::Record(i2, b)) => {
+                    if Arc::ptr_eq(&a, &b) {
+                        continue;
+                    }
+                    if a.label != b.label || a.fields.len() != b.fields.len() {
+                        return Err(UnificationError::Mismatch(Value::Record(i1, a), Value::Record(i2, b)));
+                    }
+                    pairs.extend(a.fields.iter().cloned().zip(b.fields.iter().cloned()));
+                }
+                (Value::Map(i1, a), Value::Map(i2, b)) => {
+                    if a.ptr_eq(&b) {
+                        continue;
+                    }
+                    if a.len() != b.len() {
+                        return Err(UnificationError::Mismatch(Value::Map(i1, a), Value::Map(i2, b)));
+                    }
+                    for (key, value) in a.iter() {
+                        match b.get(key) {
+                            Some(other) => pairs.push((value.clone(), other.clone())),
+                            None => {
+                                return Err(UnificationError::Mismatch(Value::Map(i1, a.clone()), Value::Map(i2, b.clone())))
+                            }
+                        }
+                    }
+                }
+                (left, right) => {
+                    if !left.equals(&right) {
+                        return Err(UnificationError::Mismatch(left, right));
+                    }
+                }
+            }
+        }
+        Ok(bound)
+    }
+
+    /// A copy of `value` that does not refer to this store anymore, with every
+    /// variable in it replaced by its value. `None` when some part of it is not
+    /// determined yet, or is a cell, whose content belongs to the process.
+    pub fn ground(&self, value: &Value) -> Option<Value> {
+        self.copy(value, false)
+    }
+
+    /// Like `ground`, keeping the variables that are not bound yet, each one
+    /// as the last variable of its chain. `None` when some part of it is a
+    /// cell.
+    pub fn resolve(&self, value: &Value) -> Option<Value> {
+        self.copy(value, true)
+    }
+
+    fn copy(&self, value: &Value, keep_unbound: bool) -> Option<Value> {
+        // the elements of a list, its spine walked without recursing
+        let mut heads = Vec::new();
+        let mut value = self.deref(value);
+        while let Value::Cons(_, cell) = value {
+            heads.push(self.copy(&cell.0, keep_unbound)?);
+            value = self.deref(&cell.1);
+        }
+        let mut copy = match value {
+            Value::Unbound(variable) if keep_unbound => Value::Unbound(variable),
+            Value::Unbound(_) | Value::Cell(_, _) => return None,
+            Value::Unit => Value::Unit,
+            Value::Bool(_, b) => Value::Bool(NO_VARIABLE, b),
+            Value::Integer(_, n) => Value::Integer(NO_VARIABLE, n),
+            Value::Fun(_, fun) => Value::Fun(NO_VARIABLE, fun),
+            Value::Map(_, map) => {
+                let mut copy = ValueMap::new();
+                for (key, value) in map.iter() {
+                    copy = copy.insert(key.clone(), self.copy(value, keep_unbound)?);
+                }
+                Value::Map(NO_VARIABLE, copy)
+            }
+            Value::Closure(_, closure) => {
+                let env = closure
+                    .env
+                    .iter()
+                    .map(|value| self.copy(value, keep_unbound))
+                    .collect::<Option<Vec<_>>>()?;
+                Value::Closure(NO_VARIABLE, Arc::new(Closure { fun: closure.fun, env }))
+            }
+            Value::Record(_, record) => {
+                let fields = record
+                    .fields
+                    .iter()
+                    .map(|value| self.copy(value, keep_unbound))
+                    .collect::<Option<Vec<_>>>()?;
+                Value::Record(NO_VARIABLE, Arc::new(Record { label: record.label.clone(), fields }))
+            }
+            Value::Cons(_, _) => unreachable!("the spine was walked"),
+        };
+        while let Some(head) = heads.pop() {
+            copy = Value::Cons(NO_VARIABLE, Arc::new((head, copy)));
+        }
+        Some(copy)
+    }
+
+    // whether binding `variable` to `value` would make an infinite term
+    fn occurs(&self, variable: VariableId, value: &Value) -> bool {
+        let mut terms = vec![value.clone()];
+        while let Some(term) = terms.pop() {
+            match self.deref(&term) {
+                Value::Unbound(other) => {
+                    if other == variable {
+                        return true;
+                    }
+                }
+                Value::Cons(_, cell) => {
+                    terms.push(cell.0.clone());
+                    terms.push(cell.1.clone());
+                }
+                Value::Map(_, map) => terms.extend(map.iter().map(|(_, value)| value.clone())),
+                Value::Closure(_, closure) => terms.extend(closure.env.iter().cloned()),
+                Value::Record(_, record) => terms.extend(record.fields.iter().cloned()),
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Whether `value`, or what its variables are bound to, holds a function
+    /// of the compiled code, which is meaningless in other code. Native
+    /// functions are the same everywhere.
+    pub(crate) fn holds_code(&self, value: &Value) -> bool {
+        let mut terms = vec![value.clone()];
+        while let Some(term) = terms.pop() {
+            match self.deref(&term) {
+                Value::Fun(_, ValueFun::Fun(_)) | Value::Closure(_, _) => return true,
+                Value::Cons(_, cell) => {
+                    terms.push(cell.0.clone());
+                    terms.push(cell.1.clone());
+                }
+                Value::Map(_, map) => terms.extend(map.iter().map(|(_, value)| value.clone())),
+                Value::Record(_, record) => terms.extend(record.fields.iter().cloned()),
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// The variables `roots` refer to, directly or through what they are
+    /// bound to, and the variables their values are tagged with.
+    pub(crate) fn reachable(&self, roots: impl IntoIterator<Item = Value>) -> HashSet<VariableId> {
+        let mut live = HashSet::new();
+        // terms shared between values are walked once
+        let mut seen = HashSet::new();
+        let mut terms = roots.into_iter().collect::<Vec<_>>();
+        while let Some(term) = terms.pop() {
+            if let Some(variable) = term.variable() {
+                if live.insert(variable) {
+                    terms.extend(self.binding(variable));
+                }
+            }
+            match term {
+                Value::Cons(_, cell) => {
+                    if seen.insert(Arc::as_ptr(&cell) as *const () as usize) {
+                        terms.push(cell.0.clone());
+                        terms.push(cell.1.clone());
+                    }
+                }
+                Value::Closure(_, closure) => {
+                    if seen.insert(Arc::as_ptr(&closure) as *const () as usize) {
+                        terms.extend(closure.env.iter().cloned());
+                    }
+                }
+                Value::Record(_, record) => {
+                    if seen.insert(Arc::as_ptr(&record) as *const () as usize) {
+                        terms.extend(record.fields.iter().cloned());
+                    }
+                }
+                Value::Map(_, map) => terms.extend(map.iter().map(|(_, value)| value.clone())),
+                _ => {}
+            }
+        }
+        live
+    }
+
+    /// Drops the values of the variables below `end` that are not `live`, and
+    /// frees the buckets left without a live variable. Returns how many values
+    /// were dropped.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may use a variable that is not live, during the sweep or after
+    /// it: every thread of the process is suspended on live variables, and
+    /// whatever else holds variables of the store only holds live ones.
+    pub(crate) unsafe fn sweep(&self, live: &HashSet<VariableId>, end: VariableId) -> usize {
+        let mut dropped = 0;
+        for (bucket, slots) in self.buckets.iter().enumerate() {
+            let first = (1u128 << bucket) - 1;
+            if first >= end as u128 {
+                break;
+            }
+            let pointer = slots.load(Ordering::Acquire);
+            if pointer.is_null() {
+                continue;
+            }
+            let last = ((1u128 << (bucket + 1)) - 1).min(end as u128);
+            let dead = (first..last).filter(|variable| !live.contains(&(*variable as VariableId)));
+            let mut emptied = 0;
+            for variable in dead {
+                let slot = unsafe { &mut *pointer.add((variable - first) as usize) };
+                if *slot.state.get_mut() == BOUND {
+                    dropped += 1;
+                }
+                *slot = Slot::new();
+                emptied += 1;
+            }
+            // variables of a freed bucket read as unbound and undeclared, as
+            // they did before it was allocated
+            if emptied == 1usize << bucket {
+                slots.store(ptr::null_mut(), Ordering::Release);
+                drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(pointer, 1usize << bucket)) });
+            }
+        }
+        self.values.fetch_sub(dropped as u64, Ordering::Relaxed);
+        dropped
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        for (bucket, slots) in self.buckets.iter_mut().enumerate() {
+            let slots = *slots.get_mut();
+            if !slots.is_null() {
+                drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(slots, 1usize << bucket)) });
+            }
+        }
+    }
+}
+
+pub struct Wait<'a> {
+    store: &'a Store,
+    variable: VariableId,
+}
+
+impl Future for Wait<'_> {
+    type Output = Value;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Value> {
+        let slot = self.store.slot(self.variable);
+        if let Some(value) = slot.get() {
+            return Poll::Ready(value);
+        }
+        slot.need();
+        // the list is closed right after the value is written: either the
+        // push fails and the value is there, or the bind wakes us up
+        if !slot.waiters.push(cx.waker()) {
+            if let Some(value) = slot.get() {
+                return Poll::Ready(value);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+pub struct WaitNeeded<'a> {
+    store: &'a Store,
+    variable: VariableId,
+}
+
+impl Future for WaitNeeded<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let store = self.store;
+        loop {
+            let slot = store.slot(self.variable);
+            if slot.is_needed() {
+                return Poll::Ready(());
+            }
+            match slot.get() {
+                // whoever needs that one needs this one
+                Some(Value::Unbound(next)) => {
+                    self.variable = next;
+                    continue;
+                }
+                Some(_) => return Poll::Ready(()),
+                None => {}
+            }
+            // closed when the variable got needed or bound meanwhile
+            if slot.needers.push(cx.waker()) {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use crate::compiler::value::NO_VARIABLE;
+    use core::pin::pin;
+    use smol::future::poll_once;
+
+    fn int(n: u64) -> Value {
+        Value::Integer(NO_VARIABLE, n)
+    }
+
+    fn cons(head: Value, tail: Value) -> Value {
+        Value::Cons(NO_VARIABLE, Arc::new((head, tail)))
+    }
+
+    #[test]
+    fn variables_bound_to_variables_share_their_value() {
+        let store = Store::new();
+        // the younger variable points to the older one
+        assert_eq!(store.unify(&Value::Unbound(1), &Value::Unbound(2)).unwrap(), vec![2]);
+        assert!(matches!(store.deref(&Value::Unbound(2)), Value::Unbound(1)));
+        store.unify(&Value::Unbound(1), &int(3)).unwrap();
+        assert!(store.deref(&Value::Unbound(2)).equals(&int(3)));
+        // binding the chain the other way round is a no-op
+        assert!(store.unify(&Value::Unbound(2), &Value::Unbound(1)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn partial_values_are_refined_structurally() {
+        let store = Store::new();
+        // x = [a | t]
+        store.unify(&Value::Unbound(1), &cons(Value::Unbound(2), Value::Unbound(3))).unwrap();
+        // x = [1 | nil]
+        let bound = store.unify(&Value::Unbound(1), &cons(int(1), Value::Unit)).unwrap();
+        assert_eq!(bound, vec![2, 3]);
+        assert!(store.deref(&Value::Unbound(2)).equals(&int(1)));
+        assert!(store.deref(&Value::Unbound(3)).equals(&Value::Unit));
+    }
+
+    #[test]
+    fn records_unify_by_label_and_fields() {
+        let store = Store::new();
+        let point = |x: Value, y: Value| {
+            Value::Record(NO_VARIABLE, Arc::new(Record { label: "Point".into(), fields: vec![x, y] }))
+        };
+        // p = Point(a, 2), then p = Point(1, b)
+        store.unify(&Value::Unbound(1), &point(Value::Unbound(2), int(2))).unwrap();
+        let bound = store.unify(&Value::Unbound(1), &point(int(1), Value::Unbound(3))).unwrap();
+        assert_eq!(bound, vec![2, 3]);
+        assert!(store.ground(&Value::Unbound(1)).expect("p is determined").equals(&point(int(1), int(2))));
+        // another label, or another number of fields, does not unify
+        let other = Value::Record(NO_VARIABLE, Arc::new(Record { label: "Pair".into(), fields: vec![int(1), int(2)] }));
+        assert!(matches!(store.unify(&Value::Unbound(1), &other), Err(UnificationError::Mismatch(_, _))));
+        let atom = Value::Record(NO_VARIABLE, Arc::new(Record::atom("Point")));
+        assert!(matches!(store.unify(&Value::Unbound(1), &atom), Err(UnificationError::Mismatch(_, _))));
+        let error = store.unify(&Value::Unbound(4), &point(Value::Unbound(4), int(1))).unwrap_err();
+        assert!(matches!(error, UnificationError::Occurs(4, _)));
+    }
+
+    #[test]
+    fn grounding_copies_determined_values_only() {
+        let store = Store::new();
+        // x = [1 | t], t = [y], y unbound
+        store.unify(&Value::Unbound(1), &cons(int(1), Value::Unbound(2))).unwrap();
+        store.unify(&Value::Unbound(2), &cons(Value::Unbound(3), Value::Unit)).unwrap();
+        assert!(store.ground(&Value::Unbound(1)).is_none());
+        // resolving keeps y, through t
+        let resolved = store.resolve(&Value::Unbound(1)).expect("no cell in x");
+        assert!(resolved.equals(&cons(int(1), cons(Value::Unbound(3), Value::Unit))));
+        store.unify(&Value::Unbound(3), &int(2)).unwrap();
+        let ground = store.ground(&Value::Unbound(1)).expect("x is determined");
+        assert!(ground.equals(&cons(int(1), cons(int(2), Value::Unit))));
+        assert_eq!(ground.variable(), None);
+        assert!(store.ground(&Value::Cell(NO_VARIABLE, 4)).is_none());
+    }
+
+    #[test]
+    fn mismatches_and_cycles_fail() {
+        let store = Store::new();
+        store.unify(&Value::Unbound(1), &cons(int(1), Value::Unit)).unwrap();
+        let error = store.unify(&Value::Unbound(1), &cons(int(2), Value::Unit)).unwrap_err();
+        assert!(matches!(error, UnificationError::Mismatch(_, _)));
+        let error = store.unify(&Value::Unbound(2), &cons(int(1), Value::Unbound(2))).unwrap_err();
+        assert!(matches!(error, UnificationError::Occurs(2, _)));
+    }
+
+    #[test]
+    fn binding_wakes_every_waiter() {
+        let store = Arc::new(Store::new());
+        let ex = smol::LocalExecutor::new();
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let store = store.clone();
+                ex.spawn(async move { store.determined(7).await })
+            })
+            .collect();
+        let needed = {
+            let store = store.clone();
+            ex.spawn(async move { store.wait_needed(7).await })
+        };
+        smol::block_on(ex.run(async {
+            needed.await;
+            // variables far apart land in different buckets
+            store.unify(&Value::Unbound(7), &Value::Unbound(1000)).unwrap();
+            store.unify(&Value::Unbound(7), &int(5)).unwrap();
+            for waiter in waiters {
+                assert!(waiter.await.equals(&int(5)));
+            }
+        }));
+        assert!(store.is_bound(1000));
+    }
+
+    #[test]
+    fn every_needer_wakes_on_the_first_wait() {
+        let store = Arc::new(Store::new());
+        let ex = smol::LocalExecutor::new();
+        let needers: Vec<_> = (0..3)
+            .map(|_| {
+                let store = store.clone();
+                ex.spawn(async move { store.wait_needed(4).await })
+            })
+            .collect();
+        smol::block_on(ex.run(async {
+            assert!(!store.is_needed(4));
+            let waiter = {
+                let store = store.clone();
+                ex.spawn(async move { store.determined(4).await })
+            };
+            for needer in needers {
+                needer.await;
+            }
+            assert!(store.is_needed(4));
+            let list = cons(int(1), Value::Unit);
+            store.unify(&Value::Unbound(4), &list).unwrap();
+            assert!(waiter.await.equals(&list));
+        }));
+        // needing a bound variable does not wait
+        smol::block_on(store.wait_needed(4));
+    }
+
+    #[test]
+    fn variables_bound_to_each_other_are_needed_together() {
+        let store = Store::new();
+        smol::block_on(async {
+            let mut needed = pin!(store.wait_needed(2));
+            assert!(poll_once(&mut needed).await.is_none());
+            // binding 2 to 1 needs neither of them
+            store.unify(&Value::Unbound(1), &Value::Unbound(2)).unwrap();
+            assert!(poll_once(&mut needed).await.is_none());
+            assert!(!store.is_needed(2));
+            // waiting on 1 needs 2 as well
+            assert!(poll_once(store.wait(1)).await.is_none());
+            assert!(store.is_needed(2));
+            assert!(poll_once(&mut needed).await.is_some());
+        });
+        // and a variable needed first passes it on when it is bound
+        store.need(4);
+        store.unify(&Value::Unbound(3), &Value::Unbound(4)).unwrap();
+        assert!(store.is_needed(3));
+    }
+
+    #[test]
+    fn sweeping_drops_what_nothing_refers_to() {
+        let store = Store::new();
+        // x = [a | t], a = 1, and variables nothing refers to
+        store.unify(&Value::Unbound(1), &cons(Value::Unbound(2), Value::Unbound(3))).unwrap();
+        store.unify(&Value::Unbound(2), &int(1)).unwrap();
+        for variable in 4..16 {
+            store.unify(&Value::Unbound(variable), &int(variable)).unwrap();
+        }
+        let live = store.reachable([Value::Unbound(1)]);
+        assert_eq!(live, [1, 2, 3].into_iter().collect());
+        assert_eq!(store.values(), 14);
+        assert_eq!(unsafe { store.sweep(&live, 16) }, 12);
+        assert_eq!(store.values(), 2);
+        assert!(!store.is_bound(4) && !store.is_bound(15));
+        // variables 7 to 14 share a bucket, which is freed
+        assert!(store.buckets[3].load(Ordering::Acquire).is_null());
+        assert!(!store.buckets[2].load(Ordering::Acquire).is_null());
+        let resolved = store.resolve(&Value::Unbound(1)).expect("no cell in x");
+        assert!(resolved.equals(&cons(int(1), Value::Unbound(3))));
+        // binding into a freed bucket allocates it again
+        store.unify(&Value::Unbound(8), &int(8)).unwrap();
+        assert!(store.deref(&Value::Unbound(8)).equals(&int(8)));
+    }
+}
@@ -0,0 +1,162 @@
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use crate::scheduler::wakers::WakerList;
+
+/// Milliseconds since the origin of a clock.
+pub type Millis = u64;
+
+/// Where the timers of a process get their time from.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Millis;
+
+    /// Resolves once `now()` reached `deadline`.
+    fn sleep_until(&self, deadline: Millis) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+/// Time that only moves when told to, so tests do not have to sleep.
+pub struct VirtualClock {
+    now: AtomicU64,
+    sleepers: WakerList,
+    // counts the `advance` calls, each one empties `sleepers`
+    advances: AtomicU64,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self { now: AtomicU64::new(0), sleepers: WakerList::new(), advances: AtomicU64::new(0) }
+    }
+
+    pub fn advance(&self, by: Millis) {
+        self.now.fetch_add(by, Ordering::AcqRel);
+        self.sleepers.wake_all();
+        // counted once the sleepers are woken: a sleeper that saw the count
+        // before it registered registers again after this
+        self.advances.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Millis {
+        self.now.load(Ordering::Acquire)
+    }
+
+    fn sleep_until(&self, deadline: Millis) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(VirtualSleep { clock: self, deadline, registered: None })
+    }
+}
+
+struct VirtualSleep<'a> {
+    clock: &'a VirtualClock,
+    deadline: Millis,
+    // the waker pushed last, and the advances counted before
+    registered: Option<(u64, Waker)>,
+}
+
+impl Future for VirtualSleep<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.clock.now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        // a waker stays in the list until the next advance, polling again in
+        // between does not push it again
+        let advances = self.clock.advances.load(Ordering::Acquire);
+        let registered = matches!(&self.registered, Some((at, waker)) if *at == advances && waker.will_wake(cx.waker()));
+        if !registered {
+            self.clock.sleepers.push(cx.waker());
+            self.registered = Some((advances, cx.waker().clone()));
+        }
+        // checked again once registered, `advance` may have run in between
+        if self.clock.now() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// The wall clock, counting from its creation.
+pub struct SystemClock {
+    origin: std::time::Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self { origin: std::time::Instant::now() }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Millis {
+        self.origin.elapsed().as_millis() as Millis
+    }
+
+    fn sleep_until(&self, deadline: Millis) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let at = self.origin + std::time::Duration::from_millis(deadline);
+        Box::pin(async move {
+            smol::Timer::at(at).await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+
+    #[test]
+    fn virtual_sleepers_wake_at_their_deadline() {
+        let clock = Arc::new(VirtualClock::new());
+        let ex = smol::LocalExecutor::new();
+        let sleeper = {
+            let clock = clock.clone();
+            ex.spawn(async move { clock.sleep_until(100).await })
+        };
+        smol::block_on(ex.run(async {
+            for _ in 0..10 {
+                smol::future::yield_now().await;
+            }
+            clock.advance(99);
+            for _ in 0..10 {
+                smol::future::yield_now().await;
+            }
+            assert!(!sleeper.is_finished());
+            clock.advance(1);
+            sleeper.await;
+        }));
+        assert_eq!(clock.now(), 100);
+    }
+
+    #[test]
+    fn a_sleeper_polled_again_registers_once() {
+        let clock = VirtualClock::new();
+        smol::block_on(async {
+            let mut sleep = clock.sleep_until(10);
+            for _ in 0..5 {
+                assert!(smol::future::poll_once(&mut sleep).await.is_none());
+            }
+            assert_eq!(clock.sleepers.len(), 1);
+            clock.advance(5);
+            assert!(smol::future::poll_once(&mut sleep).await.is_none());
+            assert_eq!(clock.sleepers.len(), 1);
+            clock.advance(5);
+            assert!(smol::future::poll_once(&mut sleep).await.is_some());
+        });
+    }
+}
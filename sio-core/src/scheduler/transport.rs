@@ -0,0 +1,170 @@
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use async_channel::{unbounded, Receiver, Sender};
+
+// A transport carries the frames of the protocol between two nodes, in
+// order, each one whole. What a frame holds is up to `node`, a transport only
+// has to tell when the other end is gone.
+
+pub type TransportFuture<'t, T> = Pin<Box<dyn Future<Output = Result<T, TransportError>> + Send + 't>>;
+
+/// A connection between two nodes.
+pub trait Transport: Send + Sync {
+    fn send(&self, frame: Vec<u8>) -> TransportFuture<'_, ()>;
+    /// Resolves to the next frame from the other end.
+    fn recv(&self) -> TransportFuture<'_, Vec<u8>>;
+    /// Closes the connection, both ends see it closed afterwards.
+    fn close(&self);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportError {
+    Closed,
+    Io(String),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Closed => write!(f, "the connection is closed"),
+            TransportError::Io(error) => write!(f, "connection error: {}", error),
+        }
+    }
+}
+
+impl core::error::Error for TransportError {}
+
+/// Both ends of a connection within the same program, for tests and for
+/// nodes sharing an OS process.
+pub struct MemoryTransport {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl MemoryTransport {
+    pub fn pair() -> (Self, Self) {
+        let (to_second, from_first) = unbounded();
+        let (to_first, from_second) = unbounded();
+        (
+            Self { sender: to_second, receiver: from_second },
+            Self { sender: to_first, receiver: from_first },
+        )
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send(&self, frame: Vec<u8>) -> TransportFuture<'_, ()> {
+        Box::pin(async move { self.sender.send(frame).await.map_err(|_| TransportError::Closed) })
+    }
+
+    fn recv(&self) -> TransportFuture<'_, Vec<u8>> {
+        Box::pin(async move { self.receiver.recv().await.map_err(|_| TransportError::Closed) })
+    }
+
+    fn close(&self) {
+        self.sender.close();
+        self.receiver.close();
+    }
+}
+
+pub use tcp::TcpTransport;
+
+mod tcp {
+    use alloc::{boxed::Box, string::ToString, vec, vec::Vec};
+    use smol::io::{AsyncReadExt, AsyncWriteExt};
+    use smol::lock::Mutex;
+    use smol::net::{AsyncToSocketAddrs, TcpListener, TcpStream};
+    use super::{Transport, TransportError, TransportFuture};
+
+    // frames are sent with their length first, as 4 big endian bytes
+    const MAX_FRAME: usize = 64 << 20;
+
+    fn io(error: std::io::Error) -> TransportError {
+        match error.kind() {
+            std::io::ErrorKind::UnexpectedEof
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::BrokenPipe => TransportError::Closed,
+            _ => TransportError::Io(error.to_string()),
+        }
+    }
+
+    /// A connection over TCP.
+    pub struct TcpTransport {
+        stream: TcpStream,
+        // a frame is read and written in several steps, which must not
+        // interleave with those of another one
+        reader: Mutex<TcpStream>,
+        writer: Mutex<TcpStream>,
+    }
+
+    impl TcpTransport {
+        pub fn new(stream: TcpStream) -> Self {
+            Self { reader: Mutex::new(stream.clone()), writer: Mutex::new(stream.clone()), stream }
+        }
+
+        pub async fn connect<A: AsyncToSocketAddrs>(address: A) -> Result<Self, TransportError> {
+            let stream = TcpStream::connect(address).await.map_err(io)?;
+            Ok(Self::new(stream))
+        }
+
+        /// Waits for the next node to connect to `listener`.
+        pub async fn accept(listener: &TcpListener) -> Result<Self, TransportError> {
+            let (stream, _) = listener.accept().await.map_err(io)?;
+            Ok(Self::new(stream))
+        }
+    }
+
+    impl Transport for TcpTransport {
+        fn send(&self, frame: Vec<u8>) -> TransportFuture<'_, ()> {
+            Box::pin(async move {
+                if frame.len() > MAX_FRAME {
+                    return Err(TransportError::Io("frame too large".to_string()));
+                }
+                let mut writer = self.writer.lock().await;
+                writer.write_all(&(frame.len() as u32).to_be_bytes()).await.map_err(io)?;
+                writer.write_all(&frame).await.map_err(io)?;
+                writer.flush().await.map_err(io)
+            })
+        }
+
+        fn recv(&self) -> TransportFuture<'_, Vec<u8>> {
+            Box::pin(async move {
+                let mut reader = self.reader.lock().await;
+                let mut len = [0u8; 4];
+                reader.read_exact(&mut len).await.map_err(io)?;
+                let len = u32::from_be_bytes(len) as usize;
+                if len > MAX_FRAME {
+                    return Err(TransportError::Io("frame too large".to_string()));
+                }
+                let mut frame = vec![0u8; len];
+                reader.read_exact(&mut frame).await.map_err(io)?;
+                Ok(frame)
+            })
+        }
+
+        fn close(&self) {
+            let _ = self.stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn closing_either_end_closes_the_connection() {
+        let (first, second) = MemoryTransport::pair();
+        smol::block_on(async {
+            first.send(vec![1, 2]).await.unwrap();
+            assert_eq!(second.recv().await.unwrap(), [1, 2]);
+            second.close();
+            assert_eq!(first.send(vec![3]).await, Err(TransportError::Closed));
+            assert_eq!(first.recv().await, Err(TransportError::Closed));
+        });
+    }
+}
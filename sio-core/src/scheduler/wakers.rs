@@ -0,0 +1,92 @@
+use alloc::boxed::Box;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use core::task::Waker;
+
+// A stack of wakers, pushed with a compare and swap and emptied all at once,
+// without a lock. Once closed nothing can be pushed anymore, which tells the
+// pusher that the event it wanted to wait for already happened.
+pub(crate) struct WakerList {
+    head: AtomicPtr<WakerNode>,
+}
+
+struct WakerNode {
+    waker: Waker,
+    next: *mut WakerNode,
+}
+
+// never dereferenced, only compared against
+const CLOSED: *mut WakerNode = 1 as *mut WakerNode;
+
+impl WakerList {
+    pub(crate) const fn new() -> Self {
+        Self { head: AtomicPtr::new(ptr::null_mut()) }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.head.load(Ordering::Acquire) == CLOSED
+    }
+
+    // false when the list is already closed
+    pub(crate) fn push(&self, waker: &Waker) -> bool {
+        let node = Box::into_raw(Box::new(WakerNode { waker: waker.clone(), next: ptr::null_mut() }));
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            if head == CLOSED {
+                drop(unsafe { Box::from_raw(node) });
+                return false;
+            }
+            unsafe { (*node).next = head };
+            match self.head.compare_exchange_weak(head, node, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return true,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    pub(crate) fn close(&self) {
+        Self::wake(self.head.swap(CLOSED, Ordering::AcqRel));
+    }
+
+    // wakes the wakers pushed so far and keeps the list open, for events that
+    // happen more than once
+    pub(crate) fn wake_all(&self) {
+        let mut head = self.head.load(Ordering::Acquire);
+        while head != CLOSED {
+            match self.head.compare_exchange_weak(head, ptr::null_mut(), Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Self::wake(head),
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    // only read while nothing pushes nor wakes
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        let mut node = self.head.load(Ordering::Acquire);
+        let mut len = 0;
+        while !node.is_null() && node != CLOSED {
+            len += 1;
+            node = unsafe { (*node).next };
+        }
+        len
+    }
+
+    fn wake(mut node: *mut WakerNode) {
+        while !node.is_null() && node != CLOSED {
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next;
+            boxed.waker.wake();
+        }
+    }
+}
+
+impl Drop for WakerList {
+    fn drop(&mut self) {
+        let mut node = *self.head.get_mut();
+        while !node.is_null() && node != CLOSED {
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next;
+        }
+    }
+}
@@ -144,6 +144,10 @@ mod function {
     fn public_lazy_args() {
         harness(include_str!("function/public_lazy_args.sio"));
     }
+    #[test]
+    fn higher_order() {
+        harness(include_str!("function/higher_order.sio"));
+    }
 }
 
 mod list {